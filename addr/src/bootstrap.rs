@@ -0,0 +1,372 @@
+// Internet2 addresses with support for Tor v3
+//
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//     Martin Habovstiak <martin.habovstiak@gmail.com>
+//
+// To the extent possible under law, the author(s) have dedicated all copyright
+// and related and neighboring rights to this software to the public domain
+// worldwide. This software is distributed without any warranty.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Offline parsing and writing of peer lists in the textual form of BOLT-10
+//! DNS bootstrap records:
+//!
+//! ```text
+//! ln1qfumuen7l8wthtz45p3ftn58pvrs9xlumvkuu2xet8egzkcklqtes332zfp.127.0.0.1. SRV 9735
+//! ```
+//!
+//! Each record consists of a bech32-encoded node id label (with `ln`
+//! human-readable part) followed by the node IP address, `SRV` record type and
+//! the node port. Empty lines and lines starting with `;` or `#` are ignored.
+//! Onion addresses are not supported, since DNS bootstrap resolves nodes to
+//! `A` and `AAAA` records only. No DNS queries are performed by this module.
+
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::{InetSocketAddr, NodeAddr, NodeId};
+
+/// Human-readable part of bech32-encoded node ids
+pub const NODE_ID_HRP: &str = "ln";
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CHECKSUM_LEN: usize = 6;
+
+/// Errors parsing DNS bootstrap record
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BootstrapError {
+    /// record does not match `<node-id>.<ip>. SRV <port>` format
+    Malformed,
+
+    /// node id label is not a valid bech32 string
+    InvalidBech32,
+
+    /// node id label has human-readable part '{0}' instead of 'ln'
+    InvalidHrp(String),
+
+    /// node id label does not encode a valid public key
+    InvalidNodeId,
+
+    /// '{0}' is not a valid IP address
+    InvalidHost(String),
+
+    /// '{0}' is not a valid port for a remote node
+    InvalidPort(String),
+}
+
+/// Record which was skipped during [`parse_records`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("line {line}: {error}")]
+pub struct BootstrapWarning {
+    /// Line number, starting from 1
+    pub line: usize,
+
+    /// Skipped record
+    pub record: String,
+
+    /// Reason for skipping the record
+    pub error: BootstrapError,
+}
+
+/// Parses all records from the provided text, skipping empty lines and
+/// comments. Records which can't be parsed are skipped and reported in the
+/// returned list of warnings.
+pub fn parse_records(text: &str) -> (Vec<NodeAddr>, Vec<BootstrapWarning>) {
+    let mut addrs = vec![];
+    let mut warnings = vec![];
+    for (no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        match parse_record(line) {
+            Ok(addr) => addrs.push(addr),
+            Err(error) => warnings.push(BootstrapWarning {
+                line: no + 1,
+                record: line.to_owned(),
+                error,
+            }),
+        }
+    }
+    (addrs, warnings)
+}
+
+/// Parses single `<node-id>.<ip>. SRV <port>` record.
+pub fn parse_record(record: &str) -> Result<NodeAddr, BootstrapError> {
+    let mut tokens = record.split_whitespace();
+    let (name, port) = match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some(name), Some(kind), Some(port))
+            if kind.eq_ignore_ascii_case("SRV") && tokens.next().is_none() =>
+        {
+            (name, port)
+        }
+        _ => return Err(BootstrapError::Malformed),
+    };
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let (label, host) =
+        name.split_once('.').ok_or(BootstrapError::Malformed)?;
+
+    let id = decode_node_id(label)?;
+    let ip = IpAddr::from_str(host)
+        .map_err(|_| BootstrapError::InvalidHost(host.to_owned()))?;
+    let port = match u16::from_str(port) {
+        Ok(port) if port > 0 => port,
+        _ => return Err(BootstrapError::InvalidPort(port.to_owned())),
+    };
+    Ok(NodeAddr::new(id, InetSocketAddr::socket(ip, port)))
+}
+
+/// Writes node addresses as DNS bootstrap records, one per line. Onion
+/// addresses are skipped.
+pub fn write_records<'a>(
+    addrs: impl IntoIterator<Item = &'a NodeAddr>,
+) -> String {
+    addrs
+        .into_iter()
+        .filter_map(|addr| Record::try_from(*addr).ok())
+        .map(|record| format!("{}\n", record))
+        .collect()
+}
+
+/// DNS bootstrap record for a node with an IP address; displays as
+/// `<node-id>.<ip>. SRV <port>`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Record(NodeAddr);
+
+impl TryFrom<NodeAddr> for Record {
+    type Error = NodeAddr;
+
+    /// Fails for onion addresses, returning the original address back.
+    fn try_from(addr: NodeAddr) -> Result<Self, Self::Error> {
+        match addr.addr {
+            InetSocketAddr::IPv4(_) | InetSocketAddr::IPv6(_) => {
+                Ok(Record(addr))
+            }
+            #[cfg(feature = "tor")]
+            InetSocketAddr::Tor(_) => Err(addr),
+        }
+    }
+}
+
+impl From<Record> for NodeAddr {
+    #[inline]
+    fn from(record: Record) -> Self { record.0 }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}. SRV {}",
+            encode_node_id(self.0.id),
+            self.0.addr.address(),
+            self.0.addr.port().unwrap_or_default()
+        )
+    }
+}
+
+impl FromStr for Record {
+    type Err = BootstrapError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_record(s).map(Record)
+    }
+}
+
+/// Encodes node id as bech32 string with `ln` human-readable part.
+pub fn encode_node_id(id: NodeId) -> String {
+    let data = convert_bits(&id.public_key().serialize(), 8, 5)
+        .expect("conversion to 5 bits with padding never fails");
+    let checksum = bech32_checksum(NODE_ID_HRP, &data);
+    let mut s = String::with_capacity(
+        NODE_ID_HRP.len() + 1 + data.len() + BECH32_CHECKSUM_LEN,
+    );
+    s.push_str(NODE_ID_HRP);
+    s.push('1');
+    s.extend(
+        data.iter()
+            .chain(&checksum)
+            .map(|d| BECH32_CHARSET[*d as usize] as char),
+    );
+    s
+}
+
+/// Decodes node id from bech32 string with `ln` human-readable part.
+pub fn decode_node_id(s: &str) -> Result<NodeId, BootstrapError> {
+    if s.chars().any(|c| c.is_ascii_lowercase())
+        && s.chars().any(|c| c.is_ascii_uppercase())
+    {
+        return Err(BootstrapError::InvalidBech32);
+    }
+    let s = s.to_ascii_lowercase();
+    let (hrp, data) =
+        s.rsplit_once('1').ok_or(BootstrapError::InvalidBech32)?;
+    if hrp != NODE_ID_HRP {
+        return Err(BootstrapError::InvalidHrp(hrp.to_owned()));
+    }
+    if data.len() < BECH32_CHECKSUM_LEN {
+        return Err(BootstrapError::InvalidBech32);
+    }
+    let data = data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|d| *d == c).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(BootstrapError::InvalidBech32)?;
+    let mut values = hrp_expand(hrp);
+    values.extend(&data);
+    if bech32_polymod(&values) != 1 {
+        return Err(BootstrapError::InvalidBech32);
+    }
+
+    let data = &data[..data.len() - BECH32_CHECKSUM_LEN];
+    let key = convert_bits(data, 5, 8).ok_or(BootstrapError::InvalidNodeId)?;
+    secp256k1::PublicKey::from_slice(&key)
+        .map(NodeId::from)
+        .map_err(|_| BootstrapError::InvalidNodeId)
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    hrp.bytes()
+        .map(|c| c >> 5)
+        .chain([0])
+        .chain(hrp.bytes().map(|c| c & 0x1f))
+        .collect()
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] =
+        [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    values.iter().fold(1u32, |chk, value| {
+        let top = chk >> 25;
+        let chk = ((chk & 0x1ffffff) << 5) ^ *value as u32;
+        GEN.iter()
+            .enumerate()
+            .filter(|(i, _)| (top >> i) & 1 == 1)
+            .fold(chk, |chk, (_, gen)| chk ^ gen)
+    })
+}
+
+fn bech32_checksum(hrp: &str, data: &[u8]) -> [u8; BECH32_CHECKSUM_LEN] {
+    let mut values = hrp_expand(hrp);
+    values.extend(data);
+    values.extend([0u8; BECH32_CHECKSUM_LEN]);
+    let polymod = bech32_polymod(&values) ^ 1;
+    let mut checksum = [0u8; BECH32_CHECKSUM_LEN];
+    for (i, c) in checksum.iter_mut().enumerate() {
+        *c = ((polymod >> (5 * (5 - i))) & 0x1f) as u8;
+    }
+    checksum
+}
+
+/// Regroups bits; pads the output when converting to the smaller groups and
+/// fails on non-zero padding when converting to the larger ones.
+fn convert_bits(data: &[u8], from: u32, to: u32) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut ret = vec![];
+    let max = (1u32 << to) - 1;
+    let max_acc = (1u32 << (from + to - 1)) - 1;
+    for value in data {
+        acc = ((acc << from) | *value as u32) & max_acc;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            ret.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if to < from {
+        if bits > 0 {
+            ret.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return None;
+    }
+    Some(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/bootstrap.txt");
+    const NODE_ID: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const NODE_ID_BECH32: &str =
+        "ln1qfumuen7l8wthtz45p3ftn58pvrs9xlumvkuu2xet8egzkcklqtes332zfp";
+
+    #[test]
+    fn node_id_bech32() {
+        let id = NodeId::from_str(NODE_ID).unwrap();
+        assert_eq!(encode_node_id(id), NODE_ID_BECH32);
+        assert_eq!(decode_node_id(NODE_ID_BECH32), Ok(id));
+        assert_eq!(
+            decode_node_id(&NODE_ID_BECH32.to_ascii_uppercase()),
+            Ok(id)
+        );
+        assert_eq!(
+            decode_node_id(&NODE_ID_BECH32.replacen('q', "Q", 1)),
+            Err(BootstrapError::InvalidBech32)
+        );
+        assert_eq!(decode_node_id("ln1b"), Err(BootstrapError::InvalidBech32));
+        // Valid bech32 string encoding 32 bytes instead of 33
+        assert_eq!(
+            decode_node_id(
+                "ln1qfumuen7l8wthtz45p3ftn58pvrs9xlumvkuu2xet8egzkcklqtsnnpnp8"
+            ),
+            Err(BootstrapError::InvalidNodeId)
+        );
+    }
+
+    #[test]
+    fn fixture() {
+        let (addrs, warnings) = parse_records(FIXTURE);
+
+        let ids = addrs
+            .iter()
+            .map(|addr| addr.id.to_string()[2..10].to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["79be667e", "c6047f94", "f9308a01", "5cbdf064"]);
+        assert_eq!(
+            addrs[0],
+            NodeAddr::new(
+                NodeId::from_str(NODE_ID).unwrap(),
+                InetSocketAddr::socket(Ipv4Addr::LOCALHOST.into(), 9735)
+            )
+        );
+        assert_eq!(addrs[2].addr.port(), Some(19735));
+        assert_eq!(addrs[3].addr.to_string(), "[2001:db8::1]:9735");
+
+        let warnings = warnings
+            .into_iter()
+            .map(|warning| (warning.line, warning.error))
+            .collect::<Vec<_>>();
+        assert_eq!(warnings, [
+            (9, BootstrapError::InvalidBech32),
+            (11, BootstrapError::InvalidNodeId),
+            (13, BootstrapError::InvalidHrp(s!("tb"))),
+            (15, BootstrapError::InvalidHost(s!("seed.example.com"))),
+            (16, BootstrapError::InvalidPort(s!("0"))),
+            (17, BootstrapError::Malformed),
+        ]);
+    }
+
+    #[test]
+    fn write_parse_roundtrip() {
+        let (addrs, _) = parse_records(FIXTURE);
+        let text = write_records(&addrs);
+        assert_eq!(text.lines().count(), addrs.len());
+        assert_eq!(
+            text.lines().next().unwrap(),
+            format!("{}.127.0.0.1. SRV 9735", NODE_ID_BECH32)
+        );
+        assert_eq!(parse_records(&text), (addrs, vec![]));
+    }
+}
@@ -11,16 +11,18 @@
 // You should have received a copy of the MIT License along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
 use strict_encoding::net::{
     AddrFormat, DecodeError, RawAddr, Transport, Uniform, UniformAddr,
 };
-#[cfg(feature = "tor")]
-use torut::onion::{TorPublicKeyV3, TORV3_PUBLIC_KEY_LENGTH};
+use strict_encoding::{StrictDecode, StrictEncode};
 
 use crate::inet::PartialSocketAddr;
-use crate::{InetAddr, InetSocketAddr, InetSocketAddrExt};
+#[cfg(feature = "tor")]
+use crate::onion::{OnionPublicKey, ONION_PUBKEY_LEN};
+use crate::{InetAddr, InetSocketAddr, InetSocketAddrExt, ServiceAddr};
 
 impl strict_encoding::Strategy for InetAddr {
     type Strategy = strict_encoding::strategies::UsingUniformAddr;
@@ -94,7 +96,7 @@ impl Uniform for InetAddr {
                 InetAddr::IPv6(Ipv6Addr::from_uniform_addr_lossy(addr)?)
             }
             #[cfg(feature = "tor")]
-            AddrFormat::OnionV3 => InetAddr::Tor(tor_from_raw_addr(addr.addr)?),
+            AddrFormat::OnionV3 => InetAddr::Tor(tor_from_raw_addr(addr.addr)),
             _ => return Err(DecodeError::UnsupportedAddrFormat),
         })
     }
@@ -177,7 +179,7 @@ impl Uniform for InetSocketAddr {
             ),
             #[cfg(feature = "tor")]
             AddrFormat::OnionV3 => {
-                InetSocketAddr::Tor(tor_from_raw_addr(addr.addr)?)
+                InetSocketAddr::Tor(tor_from_raw_addr(addr.addr))
             }
             _ => return Err(DecodeError::UnsupportedAddrFormat),
         })
@@ -226,16 +228,260 @@ impl Uniform for InetSocketAddrExt {
                 Transport::Quic => crate::Transport::Quic,
                 _ => return Err(DecodeError::UnknownTransport),
             };
-            Ok(InetSocketAddrExt(transport, address))
+            // Tor addresses can be used with TCP transport only
+            InetSocketAddrExt::with(transport, address)
+                .map_err(|_| DecodeError::UnsupportedAddrFormat)
         } else {
             Err(DecodeError::InsufficientData)
         }
     }
 }
 
+/// Transport is encoded as a single byte tag, matching the `u8` representation
+/// of the enum: `0x01` for TCP, `0x02` for UDP, `0x03` for MTCP and `0x04` for
+/// QUIC.
+impl StrictEncode for crate::Transport {
+    #[inline]
+    fn strict_encode<E: io::Write>(
+        &self,
+        e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        (*self as u8).strict_encode(e)
+    }
+}
+
+impl StrictDecode for crate::Transport {
+    fn strict_decode<D: io::Read>(
+        d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        Ok(match u8::strict_decode(d)? {
+            1 => crate::Transport::Tcp,
+            2 => crate::Transport::Udp,
+            3 => crate::Transport::Mtcp,
+            4 => crate::Transport::Quic,
+            unknown => {
+                return Err(strict_encoding::Error::EnumValueNotKnown(
+                    "Transport",
+                    unknown as usize,
+                ))
+            }
+        })
+    }
+}
+
 #[cfg(feature = "tor")]
-fn tor_from_raw_addr(raw: RawAddr) -> Result<TorPublicKeyV3, DecodeError> {
-    let mut a = [0u8; TORV3_PUBLIC_KEY_LENGTH];
+fn tor_from_raw_addr(raw: RawAddr) -> OnionPublicKey {
+    let mut a = [0u8; ONION_PUBKEY_LEN];
     a.copy_from_slice(&raw[1..]);
-    TorPublicKeyV3::from_bytes(&a).map_err(|_| DecodeError::InvalidPubkey)
+    OnionPublicKey::from_bytes(a)
+}
+
+/// Mirror of [`ServiceAddr`] used for decoding it in the same way it is
+/// encoded, before the validation
+#[derive(StrictDecode)]
+enum ServiceAddrUnchecked {
+    Tcp(SocketAddr),
+    Ipc(String),
+    Inproc(String),
+}
+
+impl StrictDecode for ServiceAddr {
+    fn strict_decode<D: io::Read>(
+        d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let addr = match ServiceAddrUnchecked::strict_decode(d)? {
+            ServiceAddrUnchecked::Tcp(addr) => ServiceAddr::Tcp(addr),
+            ServiceAddrUnchecked::Ipc(path) => ServiceAddr::Ipc(path),
+            ServiceAddrUnchecked::Inproc(name) => ServiceAddr::Inproc(name),
+        };
+        addr.validate().map_err(|err| {
+            strict_encoding::Error::DataIntegrityError(err.to_string())
+        })?;
+        Ok(addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use strict_encoding::net::ADDR_LEN;
+    use strict_encoding::{strict_deserialize, strict_serialize};
+
+    use super::*;
+
+    // Uniform encoding fixtures of 127.0.0.1:9735, [::1]:9735 and an onion
+    // address: address format, right-aligned address, big-endian port and
+    // transport tag
+    const IPV4_FIXTURE: &str = concat!(
+        "00",
+        "0000000000000000000000000000000000000000000000000000000000",
+        "7f000001",
+        "2607",
+        "00"
+    );
+    const IPV6_FIXTURE: &str = concat!(
+        "01",
+        "0000000000000000000000000000000000",
+        "00000000000000000000000000000001",
+        "2607",
+        "00"
+    );
+    #[cfg(feature = "tor")]
+    const TOR_FIXTURE: &str = concat!(
+        "03",
+        "00",
+        "5866666666666666666666666666666666666666666666666666666666666666",
+        "0000",
+        "00"
+    );
+
+    const TRANSPORTS: [crate::Transport; 4] = [
+        crate::Transport::Tcp,
+        crate::Transport::Udp,
+        crate::Transport::Mtcp,
+        crate::Transport::Quic,
+    ];
+
+    fn sockets() -> Vec<InetSocketAddr> {
+        #[allow(unused_mut)]
+        let mut sockets = vec![
+            InetSocketAddr::socket(IpAddr::V4(Ipv4Addr::LOCALHOST), 9735),
+            InetSocketAddr::socket(IpAddr::V6(Ipv6Addr::LOCALHOST), 9735),
+        ];
+        #[cfg(feature = "tor")]
+        {
+            // Ed25519 base point, which is a valid Tor v3 public key
+            let mut key = [0x66u8; ONION_PUBKEY_LEN];
+            key[0] = 0x58;
+            sockets.push(InetSocketAddr::Tor(OnionPublicKey::from_bytes(key)));
+        }
+        sockets
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|pos| u8::from_str_radix(&s[pos..pos + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn socket_addr_fixtures() {
+        #[allow(unused_mut)]
+        let mut fixtures = vec![IPV4_FIXTURE, IPV6_FIXTURE];
+        #[cfg(feature = "tor")]
+        fixtures.push(TOR_FIXTURE);
+
+        for (socket, fixture) in sockets().into_iter().zip(fixtures) {
+            let data = unhex(fixture);
+            assert_eq!(strict_serialize(&socket).unwrap(), data);
+            assert_eq!(
+                strict_deserialize::<InetSocketAddr>(&data).unwrap(),
+                socket
+            );
+        }
+
+        let mut data = unhex(IPV4_FIXTURE);
+        data[ADDR_LEN + 3] = 0x02;
+        let addr =
+            InetSocketAddrExt::udp(IpAddr::V4(Ipv4Addr::LOCALHOST), 9735);
+        assert_eq!(strict_serialize(&addr).unwrap(), data);
+        assert_eq!(
+            strict_deserialize::<InetSocketAddrExt>(&data).unwrap(),
+            addr
+        );
+    }
+
+    #[test]
+    fn port_byte_order() {
+        for port in [0x0001u16, 0x0100, 0x2607, 0xFFFE] {
+            let socket =
+                InetSocketAddr::socket(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+            let data = strict_serialize(&socket).unwrap();
+            // Port is always big-endian (network byte order) regardless of
+            // the host byte order
+            assert_eq!(data[ADDR_LEN + 1..ADDR_LEN + 3], [
+                (port >> 8) as u8,
+                port as u8
+            ]);
+        }
+    }
+
+    #[test]
+    fn transport_encoding() {
+        for (transport, tag) in TRANSPORTS.into_iter().zip(1u8..) {
+            assert_eq!(strict_serialize(&transport).unwrap(), vec![tag]);
+            assert_eq!(
+                strict_deserialize::<crate::Transport>(&[tag]).unwrap(),
+                transport
+            );
+        }
+        for tag in [0u8, 5, 0xFF] {
+            assert!(matches!(
+                strict_deserialize::<crate::Transport>(&[tag]),
+                Err(strict_encoding::Error::EnumValueNotKnown("Transport", t))
+                    if t == tag as usize
+            ));
+        }
+    }
+
+    #[test]
+    fn socket_addr_ext_encoding() {
+        for transport in TRANSPORTS {
+            for socket in sockets() {
+                let addr = match InetSocketAddrExt::with(transport, socket) {
+                    Ok(addr) => addr,
+                    Err(_) => {
+                        // Impossible transport and address pair must not be
+                        // decoded
+                        let mut data = strict_serialize(
+                            &InetSocketAddrExt::with(
+                                crate::Transport::Tcp,
+                                socket,
+                            )
+                            .unwrap(),
+                        )
+                        .unwrap();
+                        data[ADDR_LEN + 3] = transport as u8;
+                        assert!(strict_deserialize::<InetSocketAddrExt>(&data)
+                            .is_err());
+                        continue;
+                    }
+                };
+                let data = strict_serialize(&addr).unwrap();
+                // Uniform address layout: format byte, address, port and
+                // transport tag as the last byte
+                assert_eq!(data.len(), ADDR_LEN + 4);
+                assert_eq!(data[ADDR_LEN + 3], transport as u8);
+                assert_eq!(
+                    strict_deserialize::<InetSocketAddrExt>(&data).unwrap(),
+                    addr
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn service_addr_encoding() {
+        for addr in [
+            ServiceAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 9735))),
+            ServiceAddr::Ipc(s!("/tmp/ctl.rpc")),
+            ServiceAddr::Inproc(s!("esb-ctl")),
+        ] {
+            let data = strict_serialize(&addr).unwrap();
+            assert_eq!(strict_deserialize::<ServiceAddr>(&data).unwrap(), addr);
+        }
+
+        // Encoding does not validate the address, while decoding does
+        for addr in
+            [ServiceAddr::Ipc(s!("")), ServiceAddr::Inproc(s!("esb ctl"))]
+        {
+            let data = strict_serialize(&addr).unwrap();
+            assert!(matches!(
+                strict_deserialize::<ServiceAddr>(&data),
+                Err(strict_encoding::Error::DataIntegrityError(_))
+            ));
+        }
+    }
 }
@@ -22,7 +22,7 @@ use std::num::ParseIntError;
 use std::str::FromStr;
 
 #[cfg(feature = "tor")]
-use torut::onion::{OnionAddressV3, TorPublicKeyV3};
+use crate::onion::{OnionAddrParseError, OnionPublicKey};
 
 /// Address type do not support ONION address format and can be used only with
 /// IPv4 or IPv6 addresses
@@ -33,16 +33,57 @@ use torut::onion::{OnionAddressV3, TorPublicKeyV3};
 pub struct NoOnionSupportError;
 
 /// Errors during address string parse process
-#[derive(Debug, Display, Error, From)]
+#[derive(Debug, Display, From)]
 #[display(doc_comments)]
+#[non_exhaustive]
 pub enum AddrParseError {
     /// Wrong port number; must be a 16-bit unsigned integer number
-    #[from(ParseIntError)]
-    WrongPortNumber,
+    #[from]
+    WrongPortNumber(ParseIntError),
+
+    /// Port {_0} can't be used for connecting to a remote host; zero port
+    /// value is allowed only for local binds, where it requests the system
+    /// to assign an ephemeral port
+    InvalidPort(u16),
+
+    /// Port {_0} is out of range; it must not exceed 65535
+    PortOutOfRange(u32),
+
+    /// Port "{_0}" is not a decimal number
+    PortNotNumeric(String),
+
+    /// Socket address requires port number
+    MissingPort,
+
+    /// Invalid host in socket address: {_0}
+    InvalidHost(Box<AddrParseError>),
 
     /// Can't recognize IPv4, v6 or Onion v2/v3 address in string "{_0}"
     WrongAddrFormat(String),
 
+    /// Invalid IPv4 address "{_0}"; it must consist of four dot-separated
+    /// decimal octets in range 0-255
+    InvalidIpv4Octet(String),
+
+    /// Invalid IPv6 address "{_0}"
+    InvalidIpv6(String),
+
+    /// Host "{host}" is not a valid Tor v3 onion address: {reason};
+    /// {lookalike}
+    #[cfg(feature = "tor")]
+    InvalidOnion {
+        /// Rejected host string
+        host: String,
+        /// Reason for rejecting the host as an onion address
+        reason: OnionAddrParseError,
+        /// Kind of address the host superficially resembles
+        lookalike: HostLookalike,
+    },
+
+    /// Unrecognized address format "{_0}"; expected IPv4, IPv6 or Tor v3
+    /// onion address
+    UnrecognizedFormat(String),
+
     /// Wrong format of socket address string "{_0}"; use
     /// \<inet_address\>\[:\<port\>\]
     WrongSocketFormat(String),
@@ -54,17 +95,35 @@ pub enum AddrParseError {
     /// Unknown transport protocol "{_0}"
     UnknownProtocolError(String),
 
+    /// Transport protocol {_0} can't be used with Tor addresses, which
+    /// support only TCP
+    UnsupportedTransport(Transport),
+
     /// Error parsing onion address
     #[cfg(feature = "tor")]
     #[display(inner)]
     #[from]
-    OnionAddressError(torut::onion::OnionAddressParseError),
+    OnionAddressError(OnionAddrParseError),
 
     /// Tor addresses are not supported; consider compiling with `tor` feature
     #[from(NoOnionSupportError)]
     NeedsTorFeature,
 }
 
+impl std::error::Error for AddrParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AddrParseError::WrongPortNumber(err) => Some(err),
+            AddrParseError::InvalidHost(err) => Some(err.as_ref()),
+            #[cfg(feature = "tor")]
+            AddrParseError::OnionAddressError(err) => Some(err),
+            #[cfg(feature = "tor")]
+            AddrParseError::InvalidOnion { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+}
+
 /// A universal address covering IPv4, IPv6 and Tor in a single byte sequence
 /// of 32 bytes.
 ///
@@ -73,13 +132,13 @@ pub enum AddrParseError {
 /// * IPv6 address
 /// * Tor Onion address (V3 only)
 ///
-/// NB: we are using [`TorPublicKeyV3`] instead of `OnionAddressV3`, since
-/// `OnionAddressV3` keeps checksum and other information which can be
-/// reconstructed from [`TorPublicKeyV3`]. The 2-byte checksum in
-/// `OnionAddressV3` is designed for human-readable part that checks that the
-/// address was typed in correctly. In computer-stored digital data it may be
-/// deterministically regenerated and does not add any additional security.
-#[derive(Clone, Copy, PartialEq, Eq, Debug, From, Display)]
+/// NB: we are storing only [`OnionPublicKey`] for onion addresses, since the
+/// checksum and version present in the onion address string can be
+/// reconstructed from the key. The 2-byte checksum is designed for
+/// human-readable part that checks that the address was typed in correctly.
+/// In computer-stored digital data it may be deterministically regenerated and
+/// does not add any additional security.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, From)]
 #[cfg_attr(
     all(feature = "serde", feature = "serde_str_helpers"),
     derive(Serialize, Deserialize),
@@ -94,7 +153,6 @@ pub enum AddrParseError {
     derive(Serialize, Deserialize),
     serde(crate = "serde_crate")
 )]
-#[display(inner)]
 #[non_exhaustive] // Required since we use feature-gated enum variants
 pub enum InetAddr {
     /// IP address of V4 standard
@@ -108,7 +166,7 @@ pub enum InetAddr {
     /// Tor address of V3 standard
     #[cfg(feature = "tor")]
     #[from]
-    Tor(TorPublicKeyV3),
+    Tor(OnionPublicKey),
 }
 
 impl PartialOrd for InetAddr {
@@ -140,7 +198,7 @@ impl Ord for InetAddr {
     }
 }
 
-// We need this since TorPublicKeyV3 does not implement Hash
+// Tor keys are hashed by their bytes
 #[allow(clippy::derive_hash_xor_eq)]
 impl std::hash::Hash for InetAddr {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
@@ -198,10 +256,10 @@ impl InetAddr {
     /// Returns Onion v3 address, if any, or [`Option::None`]
     #[cfg(feature = "tor")]
     #[inline]
-    pub fn onion_address(self) -> Option<OnionAddressV3> {
+    pub fn onion_address(self) -> Option<OnionPublicKey> {
         match self {
             InetAddr::IPv4(_) | InetAddr::IPv6(_) => None,
-            InetAddr::Tor(key) => Some(OnionAddressV3::from(&key)),
+            InetAddr::Tor(key) => Some(key),
         }
     }
 }
@@ -211,6 +269,17 @@ impl Default for InetAddr {
     fn default() -> Self { InetAddr::IPv4(Ipv4Addr::from(0)) }
 }
 
+impl fmt::Display for InetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InetAddr::IPv4(addr) => fmt::Display::fmt(addr, f),
+            InetAddr::IPv6(addr) => fmt::Display::fmt(addr, f),
+            #[cfg(feature = "tor")]
+            InetAddr::Tor(key) => fmt_onion(key, f),
+        }
+    }
+}
+
 #[cfg(feature = "tor")]
 impl TryFrom<InetAddr> for IpAddr {
     type Error = NoOnionSupportError;
@@ -246,14 +315,6 @@ impl From<IpAddr> for InetAddr {
     }
 }
 
-#[cfg(feature = "tor")]
-impl From<OnionAddressV3> for InetAddr {
-    #[inline]
-    fn from(addr: OnionAddressV3) -> Self {
-        InetAddr::Tor(addr.get_public_key())
-    }
-}
-
 #[cfg(feature = "stringly_conversions")]
 impl_try_from_stringly_standard!(InetAddr);
 #[cfg(feature = "stringly_conversions")]
@@ -261,25 +322,171 @@ impl_into_stringly_standard!(InetAddr);
 
 impl FromStr for InetAddr {
     type Err = AddrParseError;
+
+    /// Parses IPv4, IPv6 or Tor v3 onion address. If the string can't be
+    /// parsed, it is classified (IPv6-like if it contains `:`, onion-like,
+    /// IPv4-like if it consists of dot-separated digits) to report a targeted
+    /// error.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        #[cfg(feature = "tor")]
-        match (IpAddr::from_str(s), OnionAddressV3::from_str(s)) {
-            (Ok(_), Ok(_)) => {
-                Err(AddrParseError::WrongAddrFormat(s.to_owned()))
-            }
-            (Ok(ip_addr), _) => Ok(Self::from(ip_addr)),
-            (_, Ok(onionv3)) => Ok(Self::from(onionv3)),
-            _ => Err(AddrParseError::WrongAddrFormat(s.to_owned())),
+        if let Ok(ip_addr) = IpAddr::from_str(s) {
+            Ok(InetAddr::from(ip_addr))
+        } else if s.contains(':') {
+            Err(AddrParseError::InvalidIpv6(s.to_owned()))
+        } else if looks_like_onion(s) {
+            parse_onion(s)
+        } else if looks_like_ipv4(s) {
+            Err(AddrParseError::InvalidIpv4Octet(s.to_owned()))
+        } else {
+            Err(AddrParseError::UnrecognizedFormat(s.to_owned()))
         }
+    }
+}
 
-        #[cfg(not(feature = "tor"))]
-        match IpAddr::from_str(s) {
-            Ok(ip_addr) => Ok(InetAddr::from(ip_addr)),
-            _ => Err(AddrParseError::NeedsTorFeature),
+/// Kind of address which a host rejected as an invalid onion address
+/// superficially resembles, helping to tell a mistyped onion address from a
+/// node public key pasted in place of the host
+#[cfg(feature = "tor")]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum HostLookalike {
+    /// Host resembles Tor v3 onion address
+    #[display("it resembles an onion address")]
+    Onion,
+
+    /// Host consists of hex digits and has length close to the one of a
+    /// public key, i.e. it is likely a (truncated) public key
+    #[display(
+        "it resembles a hex-encoded public key, which must be given before \
+         `@` in node addresses"
+    )]
+    PublicKey,
+}
+
+#[cfg(feature = "tor")]
+impl HostLookalike {
+    fn of(host: &str) -> HostLookalike {
+        if looks_like_pubkey(host) {
+            HostLookalike::PublicKey
+        } else {
+            HostLookalike::Onion
         }
     }
 }
 
+/// Number of characters in Tor v3 onion address, not counting `.onion` suffix
+const ONION_V3_LEN: usize = 56;
+
+/// Number of hex characters in a compressed public key
+const PUBKEY_HEX_LEN: usize = 66;
+
+/// Onion address suffix; matched case-insensitively
+const ONION_SUFFIX: &str = ".onion";
+
+fn strip_onion_suffix(s: &str) -> Option<&str> {
+    let split = s.len().checked_sub(ONION_SUFFIX.len())?;
+    if !s.is_char_boundary(split) {
+        return None;
+    }
+    let (addr, suffix) = s.split_at(split);
+    if suffix.eq_ignore_ascii_case(ONION_SUFFIX) {
+        Some(addr)
+    } else {
+        None
+    }
+}
+
+/// Detects onion addresses, including near misses which are one character
+/// shorter or longer, and hex strings which may be confused with them, so
+/// that they are reported with a targeted error. Public key lookalikes are
+/// reported as such only with `tor` feature, since otherwise no onion
+/// address parsing is done and they are not recognized at all.
+fn looks_like_onion(s: &str) -> bool {
+    strip_onion_suffix(s).is_some()
+        || ((ONION_V3_LEN - 1..=ONION_V3_LEN + 1).contains(&s.len())
+            && s.bytes().all(|b| b.is_ascii_alphanumeric()))
+        || (cfg!(feature = "tor") && looks_like_pubkey(s))
+}
+
+fn looks_like_pubkey(s: &str) -> bool {
+    (ONION_V3_LEN - 1..=PUBKEY_HEX_LEN).contains(&s.len())
+        && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn looks_like_ipv4(s: &str) -> bool {
+    s.contains('.') && s.bytes().all(|b| b == b'.' || b.is_ascii_digit())
+}
+
+/// Parses Tor v3 onion address in any letter case, with or without `.onion`
+/// suffix.
+#[cfg(feature = "tor")]
+pub(crate) fn parse_onion<T>(s: &str) -> Result<T, AddrParseError>
+where
+    T: From<OnionPublicKey>,
+{
+    let addr = strip_onion_suffix(s).unwrap_or(s);
+    OnionPublicKey::from_onion_str(addr)
+        .map(T::from)
+        .map_err(|reason| AddrParseError::InvalidOnion {
+            host: s.to_owned(),
+            reason,
+            lookalike: HostLookalike::of(addr),
+        })
+}
+
+#[cfg(not(feature = "tor"))]
+fn parse_onion<T>(_: &str) -> Result<T, AddrParseError> {
+    Err(AddrParseError::NeedsTorFeature)
+}
+
+/// Splits socket address string into the host and optional port parts. IPv6
+/// host followed by a port must be enclosed in square brackets.
+fn split_port(s: &str) -> (&str, Option<&str>) {
+    if let Some((host, rest)) =
+        s.strip_prefix('[').and_then(|s| s.split_once(']'))
+    {
+        return match rest {
+            "" => (host, None),
+            rest => (host, Some(rest.strip_prefix(':').unwrap_or(rest))),
+        };
+    }
+    match s.rsplit_once(':') {
+        // Several colons without brackets: IPv6 address without port
+        Some((host, _)) if host.contains(':') => (s, None),
+        Some((host, port)) => (host, Some(port)),
+        None => (s, None),
+    }
+}
+
+/// Parses port number, distinguishing non-numeric and out-of-range values.
+/// Numbers not fitting into `u32` are reported as `u32::MAX`.
+fn parse_port(s: &str) -> Result<u16, AddrParseError> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(AddrParseError::PortNotNumeric(s.to_owned()));
+    }
+    let port = s.parse::<u32>().unwrap_or(u32::MAX);
+    u16::try_from(port).map_err(|_| AddrParseError::PortOutOfRange(port))
+}
+
+/// Finds out why the socket address string can't be parsed, checking the
+/// host first and then the port.
+fn socket_addr_error(s: &str, port_required: bool) -> AddrParseError {
+    let (host, port) = split_port(s);
+    if let Err(err) = InetAddr::from_str(host) {
+        return AddrParseError::InvalidHost(Box::new(err));
+    }
+    match port.map(parse_port) {
+        None if port_required => AddrParseError::MissingPort,
+        Some(Err(err)) => err,
+        _ => AddrParseError::WrongAddrFormat(s.to_owned()),
+    }
+}
+
+/// Formats Tor v3 public key as a canonical (lowercase) onion address with
+/// `.onion` suffix.
+#[cfg(feature = "tor")]
+fn fmt_onion(key: &OnionPublicKey, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{}", key.to_onion_string(), ONION_SUFFIX)
+}
+
 // Yes, I checked that onion addresses don't need to optimize ownership of input
 // String.
 #[cfg(feature = "parse_arg")]
@@ -339,7 +546,7 @@ pub enum PartialSocketAddr {
     /// Tor address of V3 standard
     #[cfg(feature = "tor")]
     #[from]
-    Tor(TorPublicKeyV3),
+    Tor(OnionPublicKey),
 }
 
 impl PartialOrd for PartialSocketAddr {
@@ -403,7 +610,7 @@ impl Ord for PartialSocketAddr {
     }
 }
 
-// We need this since TorPublicKeyV3 does not implement Hash
+// Tor keys are hashed by their bytes
 #[allow(clippy::derive_hash_xor_eq)]
 impl std::hash::Hash for PartialSocketAddr {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
@@ -426,7 +633,7 @@ impl PartialSocketAddr {
     /// Constructs new socket address matching the provided Tor v3 address
     #[cfg(feature = "tor")]
     #[inline]
-    pub fn tor3(tor: TorPublicKeyV3) -> Self { PartialSocketAddr::Tor(tor) }
+    pub fn tor3(tor: OnionPublicKey) -> Self { PartialSocketAddr::Tor(tor) }
 
     /// Constructs new socket address from an internet address and a port
     /// information
@@ -438,6 +645,23 @@ impl PartialSocketAddr {
         }
     }
 
+    /// Constructs partial socket address from [`InetSocketAddr`], treating
+    /// zero port as an unspecified one. Unlike `From` conversion, which keeps
+    /// zero port as is, the resulting address is displayed without the port.
+    /// The original address can be recovered with
+    /// [`PartialSocketAddr::inet_socket`] using zero default port.
+    pub fn with_optional_port(addr: InetSocketAddr) -> Self {
+        match PartialSocketAddr::from(addr) {
+            PartialSocketAddr::IPv4(ip, Some(0)) => {
+                PartialSocketAddr::IPv4(ip, None)
+            }
+            PartialSocketAddr::IPv6(ip, Some(0)) => {
+                PartialSocketAddr::IPv6(ip, None)
+            }
+            addr => addr,
+        }
+    }
+
     /// Determines whether provided address is a Tor address. Always returns
     /// `false` (the library is built without `tor` feature; use it to
     /// enable Tor addresses).
@@ -459,12 +683,12 @@ impl PartialSocketAddr {
     /// Returns Onion v3 address, if any, or [`Option::None`]
     #[cfg(feature = "tor")]
     #[inline]
-    pub fn onion_address(self) -> Option<OnionAddressV3> {
+    pub fn onion_address(self) -> Option<OnionPublicKey> {
         match self {
             PartialSocketAddr::IPv4(_, _) | PartialSocketAddr::IPv6(_, _) => {
                 None
             }
-            PartialSocketAddr::Tor(key) => Some(OnionAddressV3::from(&key)),
+            PartialSocketAddr::Tor(key) => Some(key),
         }
     }
 
@@ -490,6 +714,17 @@ impl PartialSocketAddr {
         }
     }
 
+    /// Checks that the socket address can be used for connecting to a remote
+    /// host, i.e. that its port, if present, is not zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AddrParseError::InvalidPort`] if the port is zero.
+    #[inline]
+    pub fn check_remote(self) -> Result<Self, AddrParseError> {
+        check_remote_port(self.port()).map(|_| self)
+    }
+
     /// Constructs [`InetSocketAddr`] using default port information.
     pub fn inet_socket(self, default_port: u16) -> InetSocketAddr {
         match self {
@@ -574,14 +809,6 @@ impl From<SocketAddrV6> for PartialSocketAddr {
     }
 }
 
-#[cfg(feature = "tor")]
-impl From<OnionAddressV3> for PartialSocketAddr {
-    #[inline]
-    fn from(addr: OnionAddressV3) -> Self {
-        PartialSocketAddr::Tor(addr.get_public_key())
-    }
-}
-
 impl From<InetAddr> for PartialSocketAddr {
     fn from(addr: InetAddr) -> Self {
         match addr {
@@ -620,7 +847,7 @@ impl fmt::Display for PartialSocketAddr {
                 fmt::Display::fmt(&SocketAddrV6::new(*addr, *port, 0, 0), f)
             }
             #[cfg(feature = "tor")]
-            PartialSocketAddr::Tor(addr) => fmt::Display::fmt(addr, f),
+            PartialSocketAddr::Tor(key) => fmt_onion(key, f),
         }
     }
 }
@@ -633,30 +860,14 @@ impl_into_stringly_standard!(PartialSocketAddr);
 impl FromStr for PartialSocketAddr {
     type Err = AddrParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        #[cfg(not(feature = "tor"))]
-        struct OnionAddressV3;
-        #[cfg(not(feature = "tor"))]
-        impl OnionAddressV3 {
-            fn from_str(_: &str) -> Result<Self, AddrParseError> {
-                Err(AddrParseError::NeedsTorFeature)
-            }
-        }
-
-        match (
-            SocketAddr::from_str(s),
-            IpAddr::from_str(s),
-            OnionAddressV3::from_str(s),
-        ) {
-            (Ok(_), _, Ok(_)) | (_, Ok(_), Ok(_)) => {
-                Err(AddrParseError::WrongAddrFormat(s.to_owned()))
-            }
-            (Ok(socket_addr), ..) => Ok(Self::from(socket_addr)),
-            (_, Ok(ip_addr), _) => Ok(Self::from(ip_addr)),
-            #[cfg(feature = "tor")]
-            (_, _, Ok(onionv3)) => Ok(Self::from(onionv3)),
-            (_, _, Err(err)) => Err(err.into()),
-            #[cfg(not(feature = "tor"))]
-            _ => Err(AddrParseError::WrongAddrFormat(s.to_owned())),
+        if let Ok(socket_addr) = SocketAddr::from_str(s) {
+            Ok(Self::from(socket_addr))
+        } else if let Ok(ip_addr) = IpAddr::from_str(s) {
+            Ok(Self::from(ip_addr))
+        } else if looks_like_onion(s) {
+            parse_onion(s)
+        } else {
+            Err(socket_addr_error(s, false))
         }
     }
 }
@@ -739,7 +950,7 @@ impl FromStr for Transport {
 /// and a port number (without protocol specification, i.e. TCP/UDP etc). If you
 /// need to include transport-level protocol information into the socket
 /// details, pls check [`InetSocketAddrExt`]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, From)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, From)]
 #[cfg_attr(
     all(feature = "serde", feature = "serde_str_helpers"),
     derive(Serialize, Deserialize),
@@ -754,7 +965,6 @@ impl FromStr for Transport {
     derive(Serialize, Deserialize),
     serde(crate = "serde_crate")
 )]
-#[display(inner)]
 #[non_exhaustive] // Required since we use feature-gated enum variants
 pub enum InetSocketAddr {
     /// IP socket address of V4 standard
@@ -768,7 +978,7 @@ pub enum InetSocketAddr {
     /// Tor address of V3 standard
     #[cfg(feature = "tor")]
     #[from]
-    Tor(TorPublicKeyV3),
+    Tor(OnionPublicKey),
 }
 
 impl PartialOrd for InetSocketAddr {
@@ -800,7 +1010,7 @@ impl Ord for InetSocketAddr {
     }
 }
 
-// We need this since TorPublicKeyV3 does not implement Hash
+// Tor keys are hashed by their bytes
 #[allow(clippy::derive_hash_xor_eq)]
 impl std::hash::Hash for InetSocketAddr {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
@@ -820,11 +1030,22 @@ impl Default for InetSocketAddr {
     }
 }
 
+impl fmt::Display for InetSocketAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InetSocketAddr::IPv4(socket) => fmt::Display::fmt(socket, f),
+            InetSocketAddr::IPv6(socket) => fmt::Display::fmt(socket, f),
+            #[cfg(feature = "tor")]
+            InetSocketAddr::Tor(key) => fmt_onion(key, f),
+        }
+    }
+}
+
 impl InetSocketAddr {
     /// Constructs new socket address matching the provided Tor v3 address
     #[cfg(feature = "tor")]
     #[inline]
-    pub fn tor3(tor: TorPublicKeyV3) -> Self { InetSocketAddr::Tor(tor) }
+    pub fn tor3(tor: OnionPublicKey) -> Self { InetSocketAddr::Tor(tor) }
 
     /// Constructs new socket address from an internet address and a port
     /// information
@@ -850,6 +1071,33 @@ impl InetSocketAddr {
         }
     }
 
+    /// Compares two socket addresses in the canonical order: first by the
+    /// address family (IPv4, IPv6, Tor), then by the address bytes and then
+    /// by the port number.
+    ///
+    /// Unlike [`Ord`] implementation, this order does not depend on the
+    /// enum variant ordering and is used wherever a list of addresses must
+    /// have a deterministic binary representation, for instance in signed
+    /// peer announcements.
+    pub fn canonical_cmp(&self, other: &Self) -> Ordering {
+        self.canonical_key()
+            .cmp(&other.canonical_key())
+            .then_with(|| self.cmp(other))
+    }
+
+    fn canonical_key(&self) -> (u8, Vec<u8>, Option<u16>) {
+        match self {
+            InetSocketAddr::IPv4(socket) => {
+                (0, socket.ip().octets().to_vec(), Some(socket.port()))
+            }
+            InetSocketAddr::IPv6(socket) => {
+                (1, socket.ip().octets().to_vec(), Some(socket.port()))
+            }
+            #[cfg(feature = "tor")]
+            InetSocketAddr::Tor(tor) => (2, tor.as_bytes().to_vec(), None),
+        }
+    }
+
     /// Returns [`InetAddr`] address of the socket
     #[inline]
     pub fn address(self) -> InetAddr {
@@ -873,6 +1121,27 @@ impl InetSocketAddr {
             InetSocketAddr::Tor(_) => None,
         }
     }
+
+    /// Checks that the socket address can be used for connecting to a remote
+    /// host, i.e. that it does not use zero port. Zero port is meaningful only
+    /// for local binds (requesting the system to assign an ephemeral port).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AddrParseError::InvalidPort`] if the port is zero.
+    #[inline]
+    pub fn check_remote(self) -> Result<Self, AddrParseError> {
+        check_remote_port(self.port()).map(|_| self)
+    }
+}
+
+/// Zero port is meaningful only for local binds, where it requests the system
+/// to assign an ephemeral port, and can't be used to connect to a remote host
+fn check_remote_port(port: Option<u16>) -> Result<(), AddrParseError> {
+    match port {
+        Some(0) => Err(AddrParseError::InvalidPort(0)),
+        _ => Ok(()),
+    }
 }
 
 #[cfg(feature = "stringly_conversions")]
@@ -883,23 +1152,15 @@ impl_into_stringly_standard!(InetSocketAddr);
 impl FromStr for InetSocketAddr {
     type Err = AddrParseError;
 
-    #[allow(unreachable_code)]
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Ok(socket_addr) = SocketAddrV6::from_str(s) {
             Ok(InetSocketAddr::IPv6(socket_addr))
         } else if let Ok(socket_addr) = SocketAddrV4::from_str(s) {
             Ok(InetSocketAddr::IPv4(socket_addr))
+        } else if looks_like_onion(s) {
+            parse_onion(s)
         } else {
-            #[cfg(not(feature = "tor"))]
-            {
-                Err(AddrParseError::NeedsTorFeature)
-            }
-            #[cfg(feature = "tor")]
-            if let Ok(addr) = OnionAddressV3::from_str(s) {
-                Ok(InetSocketAddr::Tor(addr.get_public_key()))
-            } else {
-                Err(AddrParseError::WrongAddrFormat(s.to_owned()))
-            }
+            Err(socket_addr_error(s, true))
         }
     }
 }
@@ -969,14 +1230,14 @@ impl From<SocketAddr> for InetSocketAddr {
 #[cfg_attr(
     all(feature = "serde", not(feature = "serde_str_helpers")),
     derive(Serialize, Deserialize),
-    serde(crate = "serde_crate")
+    serde(crate = "serde_crate", try_from = "(Transport, InetSocketAddr)")
 )]
 pub struct InetSocketAddrExt(
     /// Transport-level protocol details (like TCP, UDP etc)
-    pub Transport,
+    Transport,
     /// Details of the socket address, i.e internet address and port
     /// information
-    pub InetSocketAddr,
+    InetSocketAddr,
 );
 
 #[cfg(feature = "stringly_conversions")]
@@ -998,6 +1259,63 @@ impl InetSocketAddrExt {
     pub fn udp(address: IpAddr, port: u16) -> Self {
         Self(Transport::Udp, SocketAddr::new(address, port).into())
     }
+
+    /// Constructs [`InetSocketAddrExt`] for a given transport protocol and
+    /// socket address, checking that the protocol can be used with the
+    /// address.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AddrParseError::UnsupportedTransport`] if a Tor address is
+    /// combined with a transport protocol other than TCP.
+    pub fn with(
+        transport: Transport,
+        socket: InetSocketAddr,
+    ) -> Result<Self, AddrParseError> {
+        if socket.is_tor() && transport != Transport::Tcp {
+            return Err(AddrParseError::UnsupportedTransport(transport));
+        }
+        Ok(Self(transport, socket))
+    }
+
+    /// Returns transport protocol of the address.
+    #[inline]
+    pub fn transport(self) -> Transport { self.0 }
+
+    /// Returns socket address without the transport protocol information.
+    #[inline]
+    pub fn socket_addr(self) -> InetSocketAddr { self.1 }
+
+    /// Splits the address into transport protocol and socket address.
+    #[inline]
+    pub fn into_parts(self) -> (Transport, InetSocketAddr) { (self.0, self.1) }
+
+    /// Compares two addresses in the canonical order: first by the
+    /// transport protocol tag, and then using
+    /// [`InetSocketAddr::canonical_cmp`].
+    pub fn canonical_cmp(&self, other: &Self) -> Ordering {
+        (self.0 as u8)
+            .cmp(&(other.0 as u8))
+            .then_with(|| self.1.canonical_cmp(&other.1))
+    }
+}
+
+impl TryFrom<(Transport, InetSocketAddr)> for InetSocketAddrExt {
+    type Error = AddrParseError;
+
+    /// Constructs address with [`InetSocketAddrExt::with`].
+    #[inline]
+    fn try_from(
+        (transport, socket): (Transport, InetSocketAddr),
+    ) -> Result<Self, Self::Error> {
+        Self::with(transport, socket)
+    }
+}
+
+impl From<SocketAddr> for InetSocketAddrExt {
+    /// Constructs address for TCP transport.
+    #[inline]
+    fn from(socket: SocketAddr) -> Self { Self::tcp(socket) }
 }
 
 impl fmt::Display for InetSocketAddrExt {
@@ -1014,7 +1332,7 @@ impl FromStr for InetSocketAddrExt {
         if let (Some(transport), Some(addr), None) =
             (vals.next(), vals.next(), vals.next())
         {
-            Ok(Self(transport.parse()?, addr.parse()?))
+            Self::with(transport.parse()?, addr.parse()?)
         } else {
             Err(AddrParseError::WrongSocketExtFormat(s.to_owned()))
         }
@@ -1065,6 +1383,172 @@ mod test {
         assert!(!ip6.is_tor());
     }
 
+    #[test]
+    fn test_inet_addr_errors() {
+        type ErrFn = fn(String) -> AddrParseError;
+        let ipv4: ErrFn = AddrParseError::InvalidIpv4Octet;
+        let ipv6: ErrFn = AddrParseError::InvalidIpv6;
+        let unknown: ErrFn = AddrParseError::UnrecognizedFormat;
+        for (s, err) in [
+            ("256.0.0.1", ipv4),
+            ("1.2.3", ipv4),
+            ("1.2.3.4.5", ipv4),
+            ("01.2.3.4", ipv4),
+            ("1..2.3", ipv4),
+            ("::g", ipv6),
+            ("[::1]", ipv6),
+            ("1:2:3:4:5:6:7:8:9", ipv6),
+            ("fe80::1%eth0", ipv6),
+            ("", unknown),
+            ("localhost", unknown),
+            ("example.com", unknown),
+        ] {
+            assert_eq!(
+                format!("{:?}", InetAddr::from_str(s).unwrap_err()),
+                format!("{:?}", err(s.to_owned()))
+            );
+        }
+
+        #[cfg(not(feature = "tor"))]
+        assert!(matches!(
+            InetAddr::from_str("127.0.0.1.onion"),
+            Err(AddrParseError::NeedsTorFeature)
+        ));
+
+        // Public key given in place of the host is not mistaken for an onion
+        // address requiring `tor` feature
+        #[cfg(not(feature = "tor"))]
+        {
+            let pubkey = format!("02{}", "ab".repeat(32));
+            assert_eq!(
+                format!("{:?}", InetAddr::from_str(&pubkey).unwrap_err()),
+                format!("{:?}", AddrParseError::UnrecognizedFormat(pubkey))
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tor")]
+    fn test_onion_addr_errors() {
+        const ONION: &str =
+            "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad";
+
+        let onion = InetAddr::from_str(ONION).unwrap();
+        assert!(onion.is_tor());
+        assert_eq!(
+            InetAddr::from_str(&format!("{}.onion", ONION)).unwrap(),
+            onion
+        );
+
+        let invalid = |host: &str| match InetAddr::from_str(host) {
+            Err(AddrParseError::InvalidOnion {
+                host: err_host,
+                reason,
+                lookalike,
+            }) => {
+                assert_eq!(err_host, host);
+                (reason, lookalike)
+            }
+            res => panic!("unexpected result {:?} for {}", res, host),
+        };
+        assert_eq!(
+            invalid("127.0.0.1.onion"),
+            (OnionAddrParseError::InvalidLength(9), HostLookalike::Onion)
+        );
+        assert_eq!(
+            invalid("abc.onion"),
+            (OnionAddrParseError::InvalidLength(3), HostLookalike::Onion)
+        );
+        assert_eq!(
+            invalid(&format!("{}.onion", &ONION[1..])),
+            (OnionAddrParseError::InvalidLength(55), HostLookalike::Onion)
+        );
+        // Near misses without `.onion` suffix: wrong length by one, invalid
+        // base32 character and bad checksum
+        assert_eq!(
+            invalid(&ONION[1..]),
+            (OnionAddrParseError::InvalidLength(55), HostLookalike::Onion)
+        );
+        assert_eq!(
+            invalid(&format!("{}a", ONION)),
+            (OnionAddrParseError::InvalidLength(57), HostLookalike::Onion)
+        );
+        assert_eq!(
+            invalid(&ONION.replace("duckduckgogg", "duckduckgog1")),
+            (
+                OnionAddrParseError::InvalidCharacter('1'),
+                HostLookalike::Onion
+            )
+        );
+        assert_eq!(
+            invalid(&ONION.replace("duckduckgogg", "duckduckgogh")),
+            (OnionAddrParseError::InvalidChecksum, HostLookalike::Onion)
+        );
+        // Truncated and complete public keys
+        let pubkey = "02d1780dd0e08f4d873f94faf49d878d909a1174291d3fcac3e9a4f2\
+                      f4d0b2ea3f";
+        assert_eq!(
+            invalid(&pubkey[..56]),
+            (
+                OnionAddrParseError::InvalidCharacter('0'),
+                HostLookalike::PublicKey
+            )
+        );
+        assert_eq!(
+            invalid(pubkey),
+            (
+                OnionAddrParseError::InvalidLength(66),
+                HostLookalike::PublicKey
+            )
+        );
+        let err = InetSocketAddr::from_str(&format!("{}:9735", &pubkey[..56]))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AddrParseError::InvalidHost(ref host_err)
+                if matches!(**host_err, AddrParseError::InvalidOnion {
+                    lookalike: HostLookalike::PublicKey,
+                    ..
+                })
+        ));
+        assert!(err
+            .to_string()
+            .contains("resembles a hex-encoded public key"));
+    }
+
+    #[test]
+    #[cfg(feature = "tor")]
+    fn test_onion_case() {
+        const ONION: &str =
+            "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+
+        let lower = InetAddr::from_str(ONION).unwrap();
+        let upper = InetAddr::from_str(&ONION.to_uppercase()).unwrap();
+        let mixed = InetAddr::from_str(
+            "DuckDuckGoGG42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.Onion",
+        )
+        .unwrap();
+        assert_eq!(lower, upper);
+        assert_eq!(lower, mixed);
+        let set = [lower, upper, mixed]
+            .into_iter()
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(set.len(), 1);
+        for addr in [lower, upper, mixed] {
+            assert_eq!(addr.to_string(), ONION);
+        }
+
+        let socket = InetSocketAddr::from_str(&ONION.to_uppercase()).unwrap();
+        assert_eq!(socket, InetSocketAddr::from_str(ONION).unwrap());
+        assert_eq!(socket.to_string(), ONION);
+        assert_eq!(socket.address(), lower);
+
+        let partial =
+            PartialSocketAddr::from_str(&ONION.to_uppercase()).unwrap();
+        assert_eq!(partial.to_string(), ONION);
+        assert_eq!(partial.address(), lower);
+    }
+
     #[test]
     fn test_transport() {
         assert_eq!(format!("{}", Transport::Tcp), "tcp");
@@ -1125,6 +1609,129 @@ mod test {
         assert!(!ip6.is_tor());
     }
 
+    #[test]
+    fn test_partial_socket_addr() {
+        for s in [
+            "127.0.0.1",
+            "127.0.0.1:9735",
+            "::1",
+            "[::1]:9735",
+            "[::1]:0",
+        ] {
+            let addr = PartialSocketAddr::from_str(s).unwrap();
+            assert_eq!(addr.to_string(), s);
+            assert_eq!(
+                PartialSocketAddr::from_str(&addr.to_string()),
+                Ok(addr)
+            );
+        }
+        assert_eq!(
+            PartialSocketAddr::from_str("127.0.0.1").unwrap().port(),
+            None
+        );
+        assert_eq!(
+            PartialSocketAddr::from_str("[::1]:9735").unwrap().port(),
+            Some(9735)
+        );
+
+        let full = InetSocketAddr::from_str("127.0.0.1:9735").unwrap();
+        let partial = PartialSocketAddr::from(full);
+        assert_eq!(partial, PartialSocketAddr::with_optional_port(full));
+        assert_eq!(partial.inet_socket(1), full);
+
+        let unspecified = InetSocketAddr::from_str("127.0.0.1:0").unwrap();
+        let partial = PartialSocketAddr::with_optional_port(unspecified);
+        assert_eq!(partial.to_string(), "127.0.0.1");
+        assert_eq!(partial.port(), None);
+        assert_eq!(partial.inet_socket(0), unspecified);
+        assert_eq!(partial.inet_socket(9735), full);
+        assert_eq!(
+            PartialSocketAddr::from(unspecified).to_string(),
+            "127.0.0.1:0"
+        );
+
+        let unspecified = InetSocketAddr::from_str("[::1]:0").unwrap();
+        let partial = PartialSocketAddr::with_optional_port(unspecified);
+        assert_eq!(partial.to_string(), "::1");
+        assert_eq!(partial.inet_socket(0), unspecified);
+    }
+
+    #[test]
+    fn test_remote_port() {
+        let local = InetSocketAddr::from_str("127.0.0.1:0").unwrap();
+        assert_eq!(local.port(), Some(0));
+        assert!(matches!(
+            local.check_remote(),
+            Err(AddrParseError::InvalidPort(0))
+        ));
+        assert!(matches!(
+            InetSocketAddr::from_str("[::1]:0").unwrap().check_remote(),
+            Err(AddrParseError::InvalidPort(0))
+        ));
+
+        let remote = InetSocketAddr::from_str("127.0.0.1:9735").unwrap();
+        assert_eq!(remote.check_remote().unwrap(), remote);
+
+        assert!(matches!(
+            PartialSocketAddr::from_str("127.0.0.1:0")
+                .unwrap()
+                .check_remote(),
+            Err(AddrParseError::InvalidPort(0))
+        ));
+        let portless = PartialSocketAddr::from_str("127.0.0.1").unwrap();
+        assert_eq!(portless.check_remote().unwrap(), portless);
+    }
+
+    #[test]
+    fn test_socket_addr_port_errors() {
+        for (s, err) in [
+            ("1.2.3.4:65535", None),
+            ("1.2.3.4:65536", Some("PortOutOfRange(65536)")),
+            ("1.2.3.4:99999", Some("PortOutOfRange(99999)")),
+            ("1.2.3.4:99999999999", Some("PortOutOfRange(4294967295)")),
+            ("[::1]:65536", Some("PortOutOfRange(65536)")),
+            ("1.2.3.4:abc", Some(r#"PortNotNumeric("abc")"#)),
+            ("1.2.3.4:-1", Some(r#"PortNotNumeric("-1")"#)),
+            ("1.2.3.4:", Some(r#"PortNotNumeric("")"#)),
+            ("[::1]x", Some(r#"PortNotNumeric("x")"#)),
+            ("1.2.3.4", Some("MissingPort")),
+            ("[::1]", Some("MissingPort")),
+            ("::1", Some("MissingPort")),
+            (
+                "300.2.3.4:80",
+                Some(r#"InvalidHost(InvalidIpv4Octet("300.2.3.4"))"#),
+            ),
+            ("[::g]:80", Some(r#"InvalidHost(InvalidIpv6("::g"))"#)),
+            (
+                "localhost:80",
+                Some(r#"InvalidHost(UnrecognizedFormat("localhost"))"#),
+            ),
+        ] {
+            let res = InetSocketAddr::from_str(s);
+            match err {
+                None => assert!(res.is_ok(), "{}", s),
+                Some(err) => {
+                    assert_eq!(format!("{:?}", res.unwrap_err()), err, "{}", s)
+                }
+            }
+        }
+
+        // Port is optional for partial socket addresses
+        assert!(PartialSocketAddr::from_str("1.2.3.4").is_ok());
+        assert!(matches!(
+            PartialSocketAddr::from_str("1.2.3.4:65536"),
+            Err(AddrParseError::PortOutOfRange(65536))
+        ));
+        assert!(matches!(
+            PartialSocketAddr::from_str("1.2.3.4:abc"),
+            Err(AddrParseError::PortNotNumeric(_))
+        ));
+        assert_eq!(
+            AddrParseError::PortOutOfRange(65536).to_string(),
+            "Port 65536 is out of range; it must not exceed 65535"
+        );
+    }
+
     #[test]
     fn test_inet_socket_addr_ext() {
         let ip4a = "127.0.0.1".parse().unwrap();
@@ -1151,4 +1758,75 @@ mod test {
         assert_eq!(format!("{}", ip4), "tcp://127.0.0.1:6865");
         assert_eq!(format!("{}", ip6), "udp://[::1]:6865");
     }
+
+    #[test]
+    fn test_inet_socket_addr_ext_parts() {
+        let socket = SocketAddr::from(([127, 0, 0, 1], 6865));
+        let addr = InetSocketAddrExt::from(socket);
+        assert_eq!(addr, InetSocketAddrExt::tcp(socket));
+        assert_eq!(addr.transport(), Transport::Tcp);
+        assert_eq!(addr.socket_addr(), InetSocketAddr::from(socket));
+        assert_eq!(addr.into_parts(), (Transport::Tcp, socket.into()));
+
+        for transport in [
+            Transport::Tcp,
+            Transport::Udp,
+            Transport::Mtcp,
+            Transport::Quic,
+        ] {
+            let addr = InetSocketAddrExt::with(transport, socket.into())
+                .unwrap()
+                .into_parts();
+            assert_eq!(addr, (transport, socket.into()));
+        }
+    }
+
+    #[cfg(feature = "tor")]
+    #[test]
+    fn test_inet_socket_addr_ext_tor() {
+        let onion =
+            "tcp://duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad";
+        let addr = InetSocketAddrExt::from_str(onion).unwrap();
+        assert_eq!(addr.transport(), Transport::Tcp);
+        assert!(addr.socket_addr().is_tor());
+
+        for transport in [Transport::Udp, Transport::Mtcp, Transport::Quic] {
+            assert!(matches!(
+                InetSocketAddrExt::with(transport, addr.socket_addr()),
+                Err(AddrParseError::UnsupportedTransport(t)) if t == transport
+            ));
+        }
+        assert!(matches!(
+            InetSocketAddrExt::from_str(&onion.replace("tcp", "udp")),
+            Err(AddrParseError::UnsupportedTransport(Transport::Udp))
+        ));
+        assert!(matches!(
+            InetSocketAddrExt::try_from((Transport::Udp, addr.socket_addr())),
+            Err(AddrParseError::UnsupportedTransport(Transport::Udp))
+        ));
+        assert_eq!(
+            InetSocketAddrExt::try_from((Transport::Tcp, addr.socket_addr())),
+            Ok(addr)
+        );
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let ordered = [
+            "tcp://127.0.0.1:9735",
+            "tcp://127.0.0.1:9736",
+            "tcp://192.168.0.1:80",
+            "tcp://[::1]:80",
+            "udp://10.0.0.1:9735",
+            "udp://[::]:9735",
+        ]
+        .map(|s| InetSocketAddrExt::from_str(s).unwrap());
+        for (no, addr) in ordered.iter().enumerate() {
+            assert_eq!(addr.canonical_cmp(addr), Ordering::Equal);
+            for other in &ordered[no + 1..] {
+                assert_eq!(addr.canonical_cmp(other), Ordering::Less);
+                assert_eq!(other.canonical_cmp(addr), Ordering::Greater);
+            }
+        }
+    }
 }
@@ -33,20 +33,35 @@ extern crate lightning_encoding;
 #[macro_use]
 extern crate serde_crate as serde;
 
+pub mod bootstrap;
 #[cfg(feature = "strict_encoding")]
 mod encoding;
 mod inet;
 mod node;
+#[cfg(feature = "tor")]
+mod onion;
+#[cfg(feature = "serde")]
+pub mod serde_adapters;
 mod server;
+#[cfg(feature = "tor")]
+mod tor;
 
+#[cfg(feature = "tor")]
+pub use inet::HostLookalike;
 pub use inet::{
     AddrParseError, InetAddr, InetSocketAddr, InetSocketAddrExt,
     NoOnionSupportError, PartialSocketAddr, Transport,
 };
 pub use node::{
-    LocalNode, NodeAddr, NodeAddrParseError, NodeId, NodeIdInvalidPubkey,
-    PartialNodeAddr,
+    ListItemError, ListParseError, LocalNode, MergeConflict, NodeAddr,
+    NodeAddrParseError, NodeId, NodeIdInvalidPubkey, PartialNodeAddr,
+    UnsupportedTransportError,
 };
+#[cfg(feature = "tor")]
+pub use onion::{OnionAddrParseError, OnionPublicKey, ONION_PUBKEY_LEN};
 pub use server::{
     ServerAddr, ServerAddrParseError, ServiceAddr, ServiceAddrParseError,
+    UnknownScheme, IPC_PATH_MAX_LEN,
 };
+#[cfg(feature = "tor")]
+pub use tor::{ClientAuthKey, TorAddr, TorAddrParseError, CLIENT_AUTH_KEY_LEN};
@@ -11,34 +11,130 @@
 // You should have received a copy of the MIT License along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
 use std::str::FromStr;
 
 use secp256k1::{ecdsa, Secp256k1, Signing};
 
 use crate::inet::PartialSocketAddr;
-use crate::{AddrParseError, InetSocketAddr};
+#[cfg(feature = "tor")]
+use crate::NoOnionSupportError;
+use crate::{AddrParseError, InetAddr, InetSocketAddr, Transport};
 
-/// Node id contains invalid public key
-#[derive(
-    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error,
-    From
-)]
+/// Node id must be a valid compressed public key: 66 hex characters starting
+/// with `02` or `03`
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
 #[display(doc_comments)]
-#[from(secp256k1::Error)]
-pub struct NodeIdInvalidPubkey;
+pub struct NodeIdInvalidPubkey(
+    /// Public key parse error, if the string had a valid length and prefix
+    pub Option<secp256k1::Error>,
+);
+
+impl From<secp256k1::Error> for NodeIdInvalidPubkey {
+    fn from(err: secp256k1::Error) -> Self { NodeIdInvalidPubkey(Some(err)) }
+}
+
+impl std::error::Error for NodeIdInvalidPubkey {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.as_ref().map(|err| err as &dyn std::error::Error)
+    }
+}
 
 /// Errors parsing [`NodeAddr`] string representation
-#[derive(Debug, Display, Error, From)]
+#[derive(Debug, Display, From)]
 #[display(doc_comments)]
+#[non_exhaustive]
 pub enum NodeAddrParseError {
-    /// invalid public key value representing node id
-    #[from(NodeIdInvalidPubkey)]
-    InvalidId,
+    /// invalid node id; it must be a compressed public key of 66 hex
+    /// characters starting with `02` or `03`
+    #[from]
+    InvalidId(NodeIdInvalidPubkey),
 
     /// Node address parse error
     #[from]
     #[display(inner)]
     InvalidAddr(AddrParseError),
+
+    /// Unsupported transport protocol
+    #[from]
+    #[display(inner)]
+    UnsupportedTransport(UnsupportedTransportError),
+}
+
+impl std::error::Error for NodeAddrParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NodeAddrParseError::InvalidId(err) => Some(err),
+            NodeAddrParseError::InvalidAddr(err) => Some(err),
+            NodeAddrParseError::UnsupportedTransport(err) => Some(err),
+        }
+    }
+}
+
+/// Error parsing a single item of a node address list
+#[derive(Debug, Display)]
+#[display("item #{index} \"{item}\": {error}")]
+pub struct ListItemError {
+    /// Zero-based index of the item among non-empty list items
+    pub index: usize,
+    /// Item string which has failed to parse
+    pub item: String,
+    /// Parse error of the item
+    pub error: NodeAddrParseError,
+}
+
+impl std::error::Error for ListItemError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Errors parsing list of node addresses, reporting all invalid items at once
+#[derive(Debug, Error)]
+pub struct ListParseError {
+    /// Errors of all invalid items, in order of their appearance
+    pub errors: Vec<ListItemError>,
+}
+
+impl Display for ListParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid node address list: ")?;
+        for (no, err) in self.errors.iter().enumerate() {
+            if no > 0 {
+                f.write_str("; ")?;
+            }
+            Display::fmt(err, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Transport protocol {_0} is not supported for P2P node connections
+#[derive(
+    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub struct UnsupportedTransportError(
+    /// Transport protocol which was requested
+    pub Transport,
+);
+
+/// Conflicting information about a node which can't be merged
+#[derive(
+    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub enum MergeConflict {
+    /// addresses belong to different nodes {0} and {1}
+    NodeId(NodeId, NodeId),
+
+    /// node is known under different hosts {0} and {1}
+    Host(InetAddr, InetAddr),
+
+    /// node host is known with different ports {0} and {1}
+    Port(u16, u16),
 }
 
 /// Internet P2P node id, represented by a public key of the node.
@@ -64,10 +160,21 @@ impl NodeId {
     pub fn public_key(self) -> secp256k1::PublicKey { self.0 }
 }
 
+/// Parses node id from a compressed public key. Uncompressed (`04`-prefixed)
+/// and hybrid (`06`/`07`-prefixed) keys, which are accepted by
+/// [`secp256k1::PublicKey`] parser, are rejected since they are not used by
+/// the node software.
 impl FromStr for NodeId {
     type Err = NodeIdInvalidPubkey;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> { Ok(NodeId(s.parse()?)) }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != secp256k1::constants::PUBLIC_KEY_SIZE * 2
+            || !(s.starts_with("02") || s.starts_with("03"))
+        {
+            return Err(NodeIdInvalidPubkey(None));
+        }
+        Ok(NodeId(s.parse()?))
+    }
 }
 
 /// Internet P2P node address.
@@ -101,6 +208,73 @@ impl NodeAddr {
     pub fn public_key(self) -> secp256k1::PublicKey { self.id.public_key() }
 }
 
+/// Fails with [`AddrParseError::InvalidPort`] for zero port, which can't be
+/// used to connect to a remote node.
+impl TryFrom<(secp256k1::PublicKey, SocketAddr)> for NodeAddr {
+    type Error = AddrParseError;
+
+    #[inline]
+    fn try_from(
+        (public_key, addr): (secp256k1::PublicKey, SocketAddr),
+    ) -> Result<Self, Self::Error> {
+        NodeAddr::try_from((public_key, InetSocketAddr::from(addr)))
+    }
+}
+
+/// Fails with [`AddrParseError::InvalidPort`] for zero port, which can't be
+/// used to connect to a remote node.
+impl TryFrom<(secp256k1::PublicKey, InetSocketAddr)> for NodeAddr {
+    type Error = AddrParseError;
+
+    #[inline]
+    fn try_from(
+        (public_key, addr): (secp256k1::PublicKey, InetSocketAddr),
+    ) -> Result<Self, Self::Error> {
+        Ok(NodeAddr::new(public_key.into(), addr.check_remote()?))
+    }
+}
+
+/// Node addresses are always connected to over TCP, so the conversion fails
+/// for all other transport protocols, as well as for zero port.
+impl TryFrom<(secp256k1::PublicKey, InetSocketAddr, Transport)> for NodeAddr {
+    type Error = NodeAddrParseError;
+
+    #[inline]
+    fn try_from(
+        (public_key, addr, transport): (
+            secp256k1::PublicKey,
+            InetSocketAddr,
+            Transport,
+        ),
+    ) -> Result<Self, Self::Error> {
+        match transport {
+            Transport::Tcp => Ok(NodeAddr::try_from((public_key, addr))?),
+            other => Err(UnsupportedTransportError(other).into()),
+        }
+    }
+}
+
+#[cfg(feature = "tor")]
+impl TryFrom<NodeAddr> for (secp256k1::PublicKey, SocketAddr) {
+    type Error = NoOnionSupportError;
+
+    #[inline]
+    fn try_from(node_addr: NodeAddr) -> Result<Self, Self::Error> {
+        Ok((
+            node_addr.public_key(),
+            SocketAddr::try_from(node_addr.addr)?,
+        ))
+    }
+}
+
+#[cfg(not(feature = "tor"))]
+impl From<NodeAddr> for (secp256k1::PublicKey, SocketAddr) {
+    #[inline]
+    fn from(node_addr: NodeAddr) -> Self {
+        (node_addr.public_key(), SocketAddr::from(node_addr.addr))
+    }
+}
+
 impl FromStr for NodeAddr {
     type Err = NodeAddrParseError;
 
@@ -109,7 +283,7 @@ impl FromStr for NodeAddr {
         match (split.next(), split.next(), split.next()) {
             (Some(id), Some(addr), None) => Ok(NodeAddr {
                 id: id.parse()?,
-                addr: addr.parse()?,
+                addr: addr.parse::<InetSocketAddr>()?.check_remote()?,
             }),
             _ => Err(AddrParseError::WrongAddrFormat(s.to_owned()).into()),
         }
@@ -147,6 +321,95 @@ impl PartialNodeAddr {
             addr: self.addr.inet_socket(default_port),
         }
     }
+
+    /// Combines information about the same node and host coming from
+    /// different sources. Address with a port takes precedence over the one
+    /// without it.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the addresses have different node ids or hosts, or if both of
+    /// them have a port and the ports differ.
+    pub fn merge(&self, other: &Self) -> Result<Self, MergeConflict> {
+        if self.id != other.id {
+            return Err(MergeConflict::NodeId(self.id, other.id));
+        }
+        let (host, other_host) = (self.addr.address(), other.addr.address());
+        if host != other_host {
+            return Err(MergeConflict::Host(host, other_host));
+        }
+        match (self.addr.port(), other.addr.port()) {
+            (Some(port), Some(other_port)) if port != other_port => {
+                Err(MergeConflict::Port(port, other_port))
+            }
+            (None, Some(_)) => Ok(*other),
+            _ => Ok(*self),
+        }
+    }
+
+    /// Merges a collection of node addresses with [`PartialNodeAddr::merge`].
+    /// Addresses of the same node and host are combined into a single entry;
+    /// addresses with distinct hosts (including different address families)
+    /// are kept as separate entries, in order of their first appearance.
+    ///
+    /// # Errors
+    ///
+    /// Fails on the first pair of addresses of the same node and host with
+    /// different ports.
+    pub fn merge_all(
+        addrs: impl IntoIterator<Item = PartialNodeAddr>,
+    ) -> Result<Vec<PartialNodeAddr>, MergeConflict> {
+        let mut merged = Vec::<PartialNodeAddr>::new();
+        for addr in addrs {
+            match merged.iter_mut().find(|known| {
+                known.id == addr.id
+                    && known.addr.address() == addr.addr.address()
+            }) {
+                Some(known) => *known = known.merge(&addr)?,
+                None => merged.push(addr),
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Parses list of node addresses separated by commas and/or whitespace,
+    /// skipping empty items. Each item may be in any form accepted by
+    /// [`PartialNodeAddr::from_str`], with or without port.
+    ///
+    /// # Errors
+    ///
+    /// Reports errors for all invalid items, not only the first one.
+    pub fn parse_list(s: &str) -> Result<Vec<PartialNodeAddr>, ListParseError> {
+        let mut list = vec![];
+        let mut errors = vec![];
+        for (index, item) in s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|item| !item.is_empty())
+            .enumerate()
+        {
+            match PartialNodeAddr::from_str(item) {
+                Ok(addr) => list.push(addr),
+                Err(error) => errors.push(ListItemError {
+                    index,
+                    item: item.to_owned(),
+                    error,
+                }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ListParseError { errors });
+        }
+        Ok(list)
+    }
+
+    /// Formats list of node addresses as a comma-separated string, which can
+    /// be parsed back with [`PartialNodeAddr::parse_list`].
+    pub fn to_list_string(list: &[PartialNodeAddr]) -> String {
+        list.iter()
+            .map(PartialNodeAddr::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
 }
 
 impl FromStr for PartialNodeAddr {
@@ -155,10 +418,11 @@ impl FromStr for PartialNodeAddr {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut split = s.split('@');
         match (split.next(), split.next(), split.next()) {
-            (Some(id), Some(addr), None) => Ok(PartialNodeAddr {
-                id: id.parse()?,
-                addr: addr.parse()?,
-            }),
+            (Some(id), Some(addr), None) => {
+                let id = id.parse()?;
+                let addr = addr.parse::<PartialSocketAddr>()?.check_remote()?;
+                Ok(PartialNodeAddr { id, addr })
+            }
             _ => Err(AddrParseError::WrongAddrFormat(s.to_owned()).into()),
         }
     }
@@ -220,3 +484,338 @@ impl LocalNode {
         secp.sign_ecdsa(message, &self.private_key)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NODE_ID: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const OTHER_ID: &str =
+        "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    #[test]
+    fn node_id_compressed_only() {
+        let id = NodeId::from_str(NODE_ID).unwrap();
+        assert_eq!(id.to_string(), NODE_ID);
+        assert!(NodeId::from_str(OTHER_ID).is_ok());
+
+        let y =
+            "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let uncompressed = format!("04{}{}", &NODE_ID[2..], y);
+        let hybrid = format!("06{}{}", &NODE_ID[2..], y);
+        // Both forms are valid for secp256k1 library
+        assert!(secp256k1::PublicKey::from_str(&uncompressed).is_ok());
+        assert!(secp256k1::PublicKey::from_str(&hybrid).is_ok());
+        for id in [
+            uncompressed,
+            hybrid,
+            format!("04{}", &NODE_ID[2..]),
+            NODE_ID[..64].to_owned(),
+        ] {
+            assert_eq!(NodeId::from_str(&id), Err(NodeIdInvalidPubkey(None)));
+            assert!(matches!(
+                NodeAddr::from_str(&format!("{}@127.0.0.1:9735", id)),
+                Err(NodeAddrParseError::InvalidId(NodeIdInvalidPubkey(None)))
+            ));
+        }
+        assert_eq!(
+            NodeAddrParseError::InvalidId(NodeIdInvalidPubkey(None))
+                .to_string(),
+            "invalid node id; it must be a compressed public key of 66 hex \
+             characters starting with `02` or `03`"
+        );
+    }
+
+    #[test]
+    fn error_source() {
+        use std::error::Error;
+
+        // Correct length and prefix, but x coordinate exceeds field size
+        let id = format!("02{}", "ff".repeat(32));
+        let err = NodeId::from_str(&id).unwrap_err();
+        assert_eq!(
+            err,
+            NodeIdInvalidPubkey(Some(secp256k1::Error::InvalidPublicKey))
+        );
+        assert!(err.source().is_some());
+
+        let err =
+            NodeAddr::from_str(&format!("{}@127.0.0.1:9735", id)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid node id; it must be a compressed public key of 66 hex \
+             characters starting with `02` or `03`"
+        );
+        let source = err.source().expect("node id error");
+        assert!(source.is::<NodeIdInvalidPubkey>());
+        let source = source.source().expect("secp256k1 error");
+        assert_eq!(
+            source.downcast_ref::<secp256k1::Error>(),
+            Some(&secp256k1::Error::InvalidPublicKey)
+        );
+
+        let err = NodeAddr::from_str(&format!("{}@300.0.0.1:9735", NODE_ID))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Invalid host in socket address"));
+        let source = err.source().expect("address error");
+        assert!(matches!(
+            source.downcast_ref::<AddrParseError>(),
+            Some(AddrParseError::InvalidHost(_))
+        ));
+        assert!(source.source().is_some());
+
+        assert!(NodeId::from_str("02abcd").unwrap_err().source().is_none());
+    }
+
+    #[cfg(feature = "strict_encoding")]
+    #[test]
+    fn node_id_strict_encoding() {
+        use strict_encoding::{strict_deserialize, strict_serialize};
+
+        let id = NodeId::from_str(NODE_ID).unwrap();
+        let data = strict_serialize(&id).unwrap();
+        assert_eq!(data.len(), secp256k1::constants::PUBLIC_KEY_SIZE);
+        assert_eq!(data, id.public_key().serialize());
+        assert_eq!(strict_deserialize::<NodeId>(&data).unwrap(), id);
+    }
+
+    #[test]
+    fn node_addr_rejects_zero_port() {
+        let addr =
+            NodeAddr::from_str(&format!("{}@127.0.0.1:9735", NODE_ID)).unwrap();
+        assert_eq!(addr.addr.port(), Some(9735));
+
+        assert!(matches!(
+            NodeAddr::from_str(&format!("{}@127.0.0.1:0", NODE_ID)),
+            Err(NodeAddrParseError::InvalidAddr(
+                AddrParseError::InvalidPort(0)
+            ))
+        ));
+        assert!(matches!(
+            PartialNodeAddr::from_str(&format!("{}@[::1]:0", NODE_ID)),
+            Err(NodeAddrParseError::InvalidAddr(
+                AddrParseError::InvalidPort(0)
+            ))
+        ));
+        assert!(
+            PartialNodeAddr::from_str(&format!("{}@[::1]", NODE_ID)).is_ok()
+        );
+    }
+
+    #[test]
+    fn partial_node_addr_list() {
+        let input = format!(
+            " {id}@127.0.0.1:9735,, {other}@[::1]\n{id}@10.0.0.1 ",
+            id = NODE_ID,
+            other = OTHER_ID
+        );
+        let list = PartialNodeAddr::parse_list(&input).unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[1].addr.port(), None);
+        let s = PartialNodeAddr::to_list_string(&list);
+        assert_eq!(
+            s,
+            format!(
+                "{id}@127.0.0.1:9735,{other}@::1,{id}@10.0.0.1",
+                id = NODE_ID,
+                other = OTHER_ID
+            )
+        );
+        assert_eq!(PartialNodeAddr::parse_list(&s).unwrap(), list);
+        assert_eq!(PartialNodeAddr::parse_list(" , ").unwrap(), vec![]);
+
+        let input = format!(
+            "02abcd@127.0.0.1 {}@127.0.0.1:9735, {}@",
+            NODE_ID, OTHER_ID
+        );
+        let err = PartialNodeAddr::parse_list(&input).unwrap_err();
+        assert_eq!(err.errors.len(), 2);
+        assert_eq!(err.errors[0].index, 0);
+        assert_eq!(err.errors[0].item, "02abcd@127.0.0.1");
+        assert!(matches!(
+            err.errors[0].error,
+            NodeAddrParseError::InvalidId(_)
+        ));
+        assert_eq!(err.errors[1].index, 2);
+        assert_eq!(err.errors[1].item, format!("{}@", OTHER_ID));
+        assert!(matches!(
+            err.errors[1].error,
+            NodeAddrParseError::InvalidAddr(_)
+        ));
+        assert!(err.to_string().starts_with(
+            "invalid node address list: item #0 \"02abcd@127.0.0.1\": "
+        ));
+    }
+
+    #[test]
+    fn node_addr_port_errors() {
+        assert!(matches!(
+            NodeAddr::from_str(&format!("{}@127.0.0.1:65536", NODE_ID)),
+            Err(NodeAddrParseError::InvalidAddr(
+                AddrParseError::PortOutOfRange(65536)
+            ))
+        ));
+        assert!(matches!(
+            NodeAddr::from_str(&format!("{}@127.0.0.1", NODE_ID)),
+            Err(NodeAddrParseError::InvalidAddr(AddrParseError::MissingPort))
+        ));
+        assert!(matches!(
+            PartialNodeAddr::from_str(&format!("{}@[::1]:lnd", NODE_ID)),
+            Err(NodeAddrParseError::InvalidAddr(
+                AddrParseError::PortNotNumeric(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn partial_node_addr_merge() {
+        let addr = |s: &str| PartialNodeAddr::from_str(s).unwrap();
+        let bare = addr(&format!("{}@127.0.0.1", NODE_ID));
+        let full = addr(&format!("{}@127.0.0.1:9735", NODE_ID));
+        let other_port = addr(&format!("{}@127.0.0.1:9736", NODE_ID));
+        let other_host = addr(&format!("{}@127.0.0.2:9735", NODE_ID));
+        let ipv6 = addr(&format!("{}@[::1]", NODE_ID));
+        let other_node = addr(&format!("{}@127.0.0.1:9735", OTHER_ID));
+
+        assert_eq!(bare.merge(&bare), Ok(bare));
+        assert_eq!(full.merge(&full), Ok(full));
+        assert_eq!(bare.merge(&full), Ok(full));
+        assert_eq!(full.merge(&bare), Ok(full));
+        assert_eq!(
+            full.merge(&other_port),
+            Err(MergeConflict::Port(9735, 9736))
+        );
+        assert_eq!(
+            full.merge(&other_host),
+            Err(MergeConflict::Host(
+                full.addr.address(),
+                other_host.addr.address()
+            ))
+        );
+        assert_eq!(
+            bare.merge(&ipv6),
+            Err(MergeConflict::Host(
+                bare.addr.address(),
+                ipv6.addr.address()
+            ))
+        );
+        assert_eq!(
+            full.merge(&other_node),
+            Err(MergeConflict::NodeId(full.id, other_node.id))
+        );
+
+        assert_eq!(
+            PartialNodeAddr::merge_all([bare, ipv6, full, other_node, bare]),
+            Ok(vec![full, ipv6, other_node])
+        );
+        assert_eq!(
+            PartialNodeAddr::merge_all([bare, full, other_port]),
+            Err(MergeConflict::Port(9735, 9736))
+        );
+        assert_eq!(PartialNodeAddr::merge_all(None), Ok(vec![]));
+    }
+
+    #[test]
+    fn node_addr_tuples() {
+        let public_key = secp256k1::PublicKey::from_str(NODE_ID).unwrap();
+        let socket = SocketAddr::from_str("127.0.0.1:9735").unwrap();
+        let node_addr =
+            NodeAddr::from_str(&format!("{}@127.0.0.1:9735", NODE_ID)).unwrap();
+
+        assert_eq!(
+            NodeAddr::try_from((public_key, socket)).unwrap(),
+            node_addr
+        );
+        assert_eq!(
+            NodeAddr::try_from((public_key, InetSocketAddr::from(socket)))
+                .unwrap(),
+            node_addr
+        );
+        let zero_port = SocketAddr::from_str("127.0.0.1:0").unwrap();
+        assert!(matches!(
+            NodeAddr::try_from((public_key, zero_port)),
+            Err(AddrParseError::InvalidPort(0))
+        ));
+        assert!(matches!(
+            NodeAddr::try_from((
+                public_key,
+                InetSocketAddr::from(zero_port),
+                Transport::Tcp
+            )),
+            Err(NodeAddrParseError::InvalidAddr(
+                AddrParseError::InvalidPort(0)
+            ))
+        ));
+        assert_eq!(
+            NodeAddr::try_from((
+                public_key,
+                InetSocketAddr::from(socket),
+                Transport::Tcp
+            ))
+            .unwrap(),
+            node_addr
+        );
+        assert!(matches!(
+            NodeAddr::try_from((
+                public_key,
+                InetSocketAddr::from(socket),
+                Transport::Udp
+            )),
+            Err(NodeAddrParseError::UnsupportedTransport(
+                UnsupportedTransportError(Transport::Udp)
+            ))
+        ));
+
+        #[cfg(feature = "tor")]
+        assert_eq!(
+            <(secp256k1::PublicKey, SocketAddr)>::try_from(node_addr),
+            Ok((public_key, socket))
+        );
+        #[cfg(not(feature = "tor"))]
+        assert_eq!(
+            <(secp256k1::PublicKey, SocketAddr)>::from(node_addr),
+            (public_key, socket)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tor")]
+    fn onion_node_addr_to_socket() {
+        use crate::onion::{OnionPublicKey, ONION_PUBKEY_LEN};
+
+        // Ed25519 base point, which is a valid Tor v3 public key
+        let mut key = [0x66u8; ONION_PUBKEY_LEN];
+        key[0] = 0x58;
+        let onion = OnionPublicKey::from_bytes(key);
+        let node_addr = NodeAddr::new(
+            NodeId::from_str(NODE_ID).unwrap(),
+            InetSocketAddr::Tor(onion),
+        );
+        assert_eq!(
+            <(secp256k1::PublicKey, SocketAddr)>::try_from(node_addr),
+            Err(NoOnionSupportError)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tor")]
+    fn onion_node_addr_case() {
+        const ONION: &str =
+            "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+
+        let node_addr = NodeAddr::from_str(&format!(
+            "{}@{}",
+            NODE_ID,
+            ONION.to_uppercase()
+        ))
+        .unwrap();
+        assert_eq!(node_addr.to_string(), format!("{}@{}", NODE_ID, ONION));
+        assert_eq!(
+            node_addr.to_string().parse::<NodeAddr>().unwrap(),
+            node_addr
+        );
+    }
+}
@@ -0,0 +1,535 @@
+// Internet2 addresses with support for Tor v3
+//
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//     Martin Habovstiak <martin.habovstiak@gmail.com>
+//
+// To the extent possible under law, the author(s) have dedicated all copyright
+// and related and neighboring rights to this software to the public domain
+// worldwide. This software is distributed without any warranty.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Native implementation of Tor v3 onion addresses.
+//!
+//! Onion v3 address is a base32 encoding of 35 bytes: 32-byte ed25519 public
+//! key of the service, followed by 2-byte checksum and version byte `0x03`.
+//! The checksum is the first two bytes of
+//! `SHA3-256(".onion checksum" || pubkey || version)`.
+//!
+//! NB: the public key is not checked to be a valid ed25519 curve point; only
+//! the checksum and version are verified.
+
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+
+/// Length of the Tor v3 public key, in bytes
+pub const ONION_PUBKEY_LEN: usize = 32;
+
+/// Length of the onion v3 address without `.onion` suffix, in characters
+pub const ONION_ADDR_LEN: usize = 56;
+
+/// Version byte of onion v3 addresses
+pub const ONION_VERSION: u8 = 3;
+
+const CHECKSUM_PREFIX: &[u8] = b".onion checksum";
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Errors parsing onion v3 address
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub enum OnionAddrParseError {
+    /// onion address must be 56 characters long without `.onion` suffix,
+    /// while the provided one has {0} characters
+    InvalidLength(usize),
+
+    /// onion address contains non-base32 character '{0}'
+    InvalidCharacter(char),
+
+    /// onion address has version {0} while only version 3 is supported
+    UnsupportedVersion(u8),
+
+    /// onion address checksum does not match the public key
+    InvalidChecksum,
+
+    /// onion public key is not a valid ed25519 curve point
+    InvalidKey,
+}
+
+/// Public key of Tor v3 onion service, which fully defines the onion address.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct OnionPublicKey([u8; ONION_PUBKEY_LEN]);
+
+impl OnionPublicKey {
+    /// Constructs onion public key from raw ed25519 public key bytes.
+    #[inline]
+    pub fn from_bytes(key: [u8; ONION_PUBKEY_LEN]) -> Self {
+        OnionPublicKey(key)
+    }
+
+    /// Returns raw ed25519 public key bytes.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; ONION_PUBKEY_LEN] { &self.0 }
+
+    /// Returns copy of raw ed25519 public key bytes.
+    #[inline]
+    pub fn to_bytes(self) -> [u8; ONION_PUBKEY_LEN] { self.0 }
+
+    /// Computes 2-byte checksum of the onion v3 address for this key.
+    pub fn checksum(&self) -> [u8; 2] {
+        let mut data =
+            Vec::with_capacity(CHECKSUM_PREFIX.len() + ONION_PUBKEY_LEN + 1);
+        data.extend_from_slice(CHECKSUM_PREFIX);
+        data.extend_from_slice(&self.0);
+        data.push(ONION_VERSION);
+        let hash = sha3_256(&data);
+        [hash[0], hash[1]]
+    }
+
+    /// Returns lowercase onion v3 address without `.onion` suffix.
+    pub fn to_onion_string(&self) -> String {
+        let mut data = [0u8; ONION_PUBKEY_LEN + 3];
+        data[..ONION_PUBKEY_LEN].copy_from_slice(&self.0);
+        data[ONION_PUBKEY_LEN..ONION_PUBKEY_LEN + 2]
+            .copy_from_slice(&self.checksum());
+        data[ONION_PUBKEY_LEN + 2] = ONION_VERSION;
+        base32_encode(&data)
+    }
+
+    /// Parses onion v3 address in any letter case without `.onion` suffix.
+    pub fn from_onion_str(s: &str) -> Result<Self, OnionAddrParseError> {
+        let len = s.chars().count();
+        if len != ONION_ADDR_LEN {
+            return Err(OnionAddrParseError::InvalidLength(len));
+        }
+        let data =
+            base32_decode::<{ ONION_PUBKEY_LEN + 3 }>(s).map_err(|err| {
+                match err {
+                    Base32Error::InvalidCharacter(c) => {
+                        OnionAddrParseError::InvalidCharacter(c)
+                    }
+                    Base32Error::InvalidLength
+                    | Base32Error::NonZeroPadding => {
+                        OnionAddrParseError::InvalidLength(len)
+                    }
+                }
+            })?;
+        let version = data[ONION_PUBKEY_LEN + 2];
+        if version != ONION_VERSION {
+            return Err(OnionAddrParseError::UnsupportedVersion(version));
+        }
+        let mut key = [0u8; ONION_PUBKEY_LEN];
+        key.copy_from_slice(&data[..ONION_PUBKEY_LEN]);
+        let key = OnionPublicKey(key);
+        if data[ONION_PUBKEY_LEN..ONION_PUBKEY_LEN + 2] != key.checksum() {
+            return Err(OnionAddrParseError::InvalidChecksum);
+        }
+        Ok(key)
+    }
+}
+
+impl Debug for OnionPublicKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OnionPublicKey")
+            .field(&self.to_onion_string())
+            .finish()
+    }
+}
+
+/// Formats key as lowercase onion v3 address with `.onion` suffix.
+impl Display for OnionPublicKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.onion", self.to_onion_string())
+    }
+}
+
+/// Parses onion v3 address in any letter case, with or without `.onion`
+/// suffix.
+impl FromStr for OnionPublicKey {
+    type Err = OnionAddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let addr = match s.len().checked_sub(6) {
+            Some(pos)
+                if s.get(pos..).map_or(false, |suffix| {
+                    suffix.eq_ignore_ascii_case(".onion")
+                }) =>
+            {
+                &s[..pos]
+            }
+            _ => s,
+        };
+        OnionPublicKey::from_onion_str(addr)
+    }
+}
+
+impl From<[u8; ONION_PUBKEY_LEN]> for OnionPublicKey {
+    #[inline]
+    fn from(key: [u8; ONION_PUBKEY_LEN]) -> Self { OnionPublicKey(key) }
+}
+
+impl From<OnionPublicKey> for [u8; ONION_PUBKEY_LEN] {
+    #[inline]
+    fn from(key: OnionPublicKey) -> Self { key.0 }
+}
+
+#[cfg(feature = "torut_compat")]
+mod torut_compat {
+    use torut::onion::{OnionAddressV3, TorPublicKeyV3};
+
+    use super::*;
+    use crate::{InetAddr, InetSocketAddr, PartialSocketAddr};
+
+    impl From<TorPublicKeyV3> for OnionPublicKey {
+        #[inline]
+        fn from(key: TorPublicKeyV3) -> Self { OnionPublicKey(key.to_bytes()) }
+    }
+
+    impl From<OnionAddressV3> for OnionPublicKey {
+        #[inline]
+        fn from(addr: OnionAddressV3) -> Self { addr.get_public_key().into() }
+    }
+
+    /// Fails if the key is not a valid ed25519 curve point, which is checked
+    /// by `torut` but not by the native implementation.
+    impl TryFrom<OnionPublicKey> for TorPublicKeyV3 {
+        type Error = OnionAddrParseError;
+
+        fn try_from(key: OnionPublicKey) -> Result<Self, Self::Error> {
+            TorPublicKeyV3::from_bytes(&key.0)
+                .map_err(|_| OnionAddrParseError::InvalidKey)
+        }
+    }
+
+    impl TryFrom<OnionPublicKey> for OnionAddressV3 {
+        type Error = OnionAddrParseError;
+
+        fn try_from(key: OnionPublicKey) -> Result<Self, Self::Error> {
+            TorPublicKeyV3::try_from(key).map(|key| OnionAddressV3::from(&key))
+        }
+    }
+
+    impl From<TorPublicKeyV3> for InetAddr {
+        #[inline]
+        fn from(key: TorPublicKeyV3) -> Self { InetAddr::Tor(key.into()) }
+    }
+
+    impl From<OnionAddressV3> for InetAddr {
+        #[inline]
+        fn from(addr: OnionAddressV3) -> Self { InetAddr::Tor(addr.into()) }
+    }
+
+    impl From<TorPublicKeyV3> for PartialSocketAddr {
+        #[inline]
+        fn from(key: TorPublicKeyV3) -> Self {
+            PartialSocketAddr::Tor(key.into())
+        }
+    }
+
+    impl From<OnionAddressV3> for PartialSocketAddr {
+        #[inline]
+        fn from(addr: OnionAddressV3) -> Self {
+            PartialSocketAddr::Tor(addr.into())
+        }
+    }
+
+    impl From<TorPublicKeyV3> for InetSocketAddr {
+        #[inline]
+        fn from(key: TorPublicKeyV3) -> Self { InetSocketAddr::Tor(key.into()) }
+    }
+}
+
+/// Errors decoding base32 data
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Base32Error {
+    /// Character outside of base32 alphabet
+    InvalidCharacter(char),
+
+    /// Decoded data length does not match the expected one
+    InvalidLength,
+
+    /// Unused trailing bits are not zero, i.e. encoding is not canonical
+    NonZeroPadding,
+}
+
+/// Encodes data in unpadded lowercase base32 (RFC 4648 alphabet), as used by
+/// Tor for onion addresses and client authorization keys.
+pub(crate) fn base32_encode(data: &[u8]) -> String {
+    let mut s = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut acc = 0u16;
+    let mut bits = 0u8;
+    for byte in data {
+        acc = (acc << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(BASE32_ALPHABET[(acc >> bits) as usize & 0x1F] as char);
+        }
+    }
+    if bits > 0 {
+        s.push(BASE32_ALPHABET[(acc << (5 - bits)) as usize & 0x1F] as char);
+    }
+    s
+}
+
+/// Decodes unpadded base32 string in any letter case into exactly `LEN`
+/// bytes.
+pub(crate) fn base32_decode<const LEN: usize>(
+    s: &str,
+) -> Result<[u8; LEN], Base32Error> {
+    let mut data = [0u8; LEN];
+    let mut len = 0usize;
+    let mut acc = 0u16;
+    let mut bits = 0u8;
+    for c in s.chars() {
+        let val = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_lowercase())
+            .ok_or(Base32Error::InvalidCharacter(c))?;
+        acc = (acc << 5) | val as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            *data.get_mut(len).ok_or(Base32Error::InvalidLength)? =
+                (acc >> bits) as u8;
+            len += 1;
+        }
+    }
+    if len != LEN {
+        return Err(Base32Error::InvalidLength);
+    }
+    if acc & ((1 << bits) - 1) != 0 {
+        return Err(Base32Error::NonZeroPadding);
+    }
+    Ok(data)
+}
+
+const KECCAK_ROUND_CONSTANTS: [u64; 24] = [
+    0x0000_0000_0000_0001,
+    0x0000_0000_0000_8082,
+    0x8000_0000_0000_808a,
+    0x8000_0000_8000_8000,
+    0x0000_0000_0000_808b,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8009,
+    0x0000_0000_0000_008a,
+    0x0000_0000_0000_0088,
+    0x0000_0000_8000_8009,
+    0x0000_0000_8000_000a,
+    0x0000_0000_8000_808b,
+    0x8000_0000_0000_008b,
+    0x8000_0000_0000_8089,
+    0x8000_0000_0000_8003,
+    0x8000_0000_0000_8002,
+    0x8000_0000_0000_0080,
+    0x0000_0000_0000_800a,
+    0x8000_0000_8000_000a,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8080,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8008,
+];
+
+const KECCAK_RHO: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18,
+    39, 61, 20, 44,
+];
+
+const KECCAK_PI: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14,
+    22, 9, 6, 1,
+];
+
+/// Keccak-f\[1600\] permutation
+fn keccak_f(state: &mut [u64; 25]) {
+    for rc in KECCAK_ROUND_CONSTANTS {
+        // θ step
+        let mut c = [0u64; 5];
+        for (x, c) in c.iter_mut().enumerate() {
+            *c = state[x]
+                ^ state[x + 5]
+                ^ state[x + 10]
+                ^ state[x + 15]
+                ^ state[x + 20];
+        }
+        for x in 0..5 {
+            let d = c[(x + 4) % 5] ^ c[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                state[5 * y + x] ^= d;
+            }
+        }
+
+        // ρ and π steps
+        let mut last = state[1];
+        for (pi, rho) in KECCAK_PI.iter().zip(KECCAK_RHO) {
+            let tmp = state[*pi];
+            state[*pi] = last.rotate_left(rho);
+            last = tmp;
+        }
+
+        // χ step
+        for y in 0..5 {
+            let mut row = [0u64; 5];
+            row.copy_from_slice(&state[5 * y..5 * y + 5]);
+            for x in 0..5 {
+                state[5 * y + x] =
+                    row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+
+        // ι step
+        state[0] ^= rc;
+    }
+}
+
+/// SHA3-256 hash function (FIPS 202)
+fn sha3_256(data: &[u8]) -> [u8; 32] {
+    const RATE: usize = 136;
+
+    let mut padded = data.to_vec();
+    padded.push(0x06);
+    padded.resize((padded.len() + RATE - 1) / RATE * RATE, 0);
+    *padded.last_mut().expect("non-empty") |= 0x80;
+
+    let mut state = [0u64; 25];
+    for block in padded.chunks(RATE) {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks(8)) {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(bytes);
+            *lane ^= u64::from_le_bytes(buf);
+        }
+        keccak_f(&mut state);
+    }
+
+    let mut hash = [0u8; 32];
+    for (bytes, lane) in hash.chunks_mut(8).zip(state) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ONION: &str =
+        "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad";
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|pos| u8::from_str_radix(&s[pos..pos + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sha3_vectors() {
+        assert_eq!(
+            sha3_256(b"").to_vec(),
+            unhex(
+                "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"
+            )
+        );
+        assert_eq!(
+            sha3_256(b"abc").to_vec(),
+            unhex(
+                "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+            )
+        );
+        // Multi-block message: 200 bytes of 0xa3
+        assert_eq!(
+            sha3_256(&[0xa3; 200]).to_vec(),
+            unhex(
+                "79f38adec5c20307a98ef76e8324afbfd46cfd81b22e3973c65fa1bd9de31787"
+            )
+        );
+    }
+
+    #[test]
+    fn onion_fixtures() {
+        let key = OnionPublicKey::from_str(ONION).unwrap();
+        assert_eq!(key.to_onion_string(), ONION);
+        assert_eq!(key.to_string(), format!("{}.onion", ONION));
+        assert_eq!(
+            OnionPublicKey::from_str(&format!(
+                "{}.ONION",
+                ONION.to_uppercase()
+            ))
+            .unwrap(),
+            key
+        );
+
+        // Ed25519 base point
+        let mut bytes = [0x66u8; ONION_PUBKEY_LEN];
+        bytes[0] = 0x58;
+        let key = OnionPublicKey::from_bytes(bytes);
+        let onion = "lbtgmztgmztgmztgmztgmztgmztgmztgmztgmztgmztgmztgmztmn5ad";
+        assert_eq!(key.to_onion_string(), onion);
+        assert_eq!(OnionPublicKey::from_str(onion).unwrap(), key);
+    }
+
+    #[test]
+    fn onion_errors() {
+        assert_eq!(
+            OnionPublicKey::from_str(&ONION[1..]),
+            Err(OnionAddrParseError::InvalidLength(55))
+        );
+        assert_eq!(
+            OnionPublicKey::from_str("€a€"),
+            Err(OnionAddrParseError::InvalidLength(3))
+        );
+        assert_eq!(
+            OnionPublicKey::from_str(&ONION.replace('d', "1")),
+            Err(OnionAddrParseError::InvalidCharacter('1'))
+        );
+        // Changed key byte
+        assert_eq!(
+            OnionPublicKey::from_str(&ONION.replacen('u', "v", 1)),
+            Err(OnionAddrParseError::InvalidChecksum)
+        );
+        // Version 2 instead of 3 in the last byte
+        let mut v2 = ONION.to_owned();
+        v2.replace_range(55.., "c");
+        assert_eq!(
+            OnionPublicKey::from_str(&v2),
+            Err(OnionAddrParseError::UnsupportedVersion(2))
+        );
+    }
+
+    #[cfg(feature = "torut_compat")]
+    #[test]
+    fn torut_equivalence() {
+        use std::convert::TryFrom;
+
+        use torut::onion::{OnionAddressV3, TorPublicKeyV3};
+
+        for onion in [
+            ONION,
+            "lbtgmztgmztgmztgmztgmztgmztgmztgmztgmztgmztgmztgmztmn5ad",
+            "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid",
+            "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd",
+        ] {
+            let native = OnionPublicKey::from_str(onion).unwrap();
+            let torut = OnionAddressV3::from_str(onion).unwrap();
+            assert_eq!(OnionPublicKey::from(torut), native);
+            assert_eq!(
+                torut.get_address_without_dot_onion(),
+                native.to_onion_string()
+            );
+            assert_eq!(
+                TorPublicKeyV3::try_from(native).unwrap(),
+                torut.get_public_key()
+            );
+        }
+    }
+}
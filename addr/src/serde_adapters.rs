@@ -0,0 +1,292 @@
+// Internet2 addresses with support for Tor v3
+//
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//     Martin Habovstiak <martin.habovstiak@gmail.com>
+//
+// To the extent possible under law, the author(s) have dedicated all copyright
+// and related and neighboring rights to this software to the public domain
+// worldwide. This software is distributed without any warranty.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Alternative serde representations of [`InetAddr`] for use with
+//! `#[serde(with = "...")]` field attribute:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Peer {
+//!     #[serde(with = "inet2_addr::serde_adapters::as_onion_pubkey")]
+//!     onion: InetAddr,
+//!     #[serde(with = "inet2_addr::serde_adapters::as_uniform_bytes")]
+//!     addr: InetAddr,
+//! }
+//! ```
+//!
+//! [`InetAddr`]: crate::InetAddr
+
+#[cfg(any(feature = "tor", feature = "strict_encoding"))]
+use std::fmt;
+
+#[cfg(any(feature = "tor", feature = "strict_encoding"))]
+use serde::de::{self, SeqAccess, Visitor};
+
+/// Visitor collecting fixed-length byte array either from serde bytes or from
+/// a sequence of `u8` (for formats without native byte strings support)
+#[cfg(any(feature = "tor", feature = "strict_encoding"))]
+struct FixedBytes<const LEN: usize>;
+
+#[cfg(any(feature = "tor", feature = "strict_encoding"))]
+impl<'de, const LEN: usize> Visitor<'de> for FixedBytes<LEN> {
+    type Value = [u8; LEN];
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes", LEN)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        if v.len() != LEN {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+        let mut buf = [0u8; LEN];
+        buf.copy_from_slice(v);
+        Ok(buf)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> Result<Self::Value, A::Error> {
+        let mut buf = [0u8; LEN];
+        for (len, byte) in buf.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(len, &self))?;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(de::Error::invalid_length(LEN + 1, &self));
+        }
+        Ok(buf)
+    }
+}
+
+/// Serializes Tor [`InetAddr`] as raw 32-byte ed25519 public key, without
+/// checksum and version which are present in the onion address. Fails for
+/// IPv4 and IPv6 addresses.
+///
+/// [`InetAddr`]: crate::InetAddr
+#[cfg(feature = "tor")]
+pub mod as_onion_pubkey {
+    use serde::{ser, Deserializer, Serializer};
+
+    use super::FixedBytes;
+    use crate::onion::{OnionPublicKey, ONION_PUBKEY_LEN};
+    use crate::InetAddr;
+
+    /// Serializes Tor address as raw public key bytes.
+    pub fn serialize<S: Serializer>(
+        addr: &InetAddr,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match addr {
+            InetAddr::Tor(key) => serializer.serialize_bytes(&key.to_bytes()),
+            addr => Err(ser::Error::custom(format!(
+                "address {} is not a Tor address and can't be serialized as \
+                 an onion public key",
+                addr
+            ))),
+        }
+    }
+
+    /// Deserializes Tor address from raw public key bytes.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<InetAddr, D::Error> {
+        let key =
+            deserializer.deserialize_bytes(FixedBytes::<ONION_PUBKEY_LEN>)?;
+        Ok(InetAddr::Tor(OnionPublicKey::from_bytes(key)))
+    }
+}
+
+/// Serializes [`InetAddr`] as fixed-length 33-byte uniform encoding: address
+/// format byte followed by 32 bytes of right-aligned address data, as in the
+/// strict encoding of the uniform address.
+///
+/// [`InetAddr`]: crate::InetAddr
+#[cfg(feature = "strict_encoding")]
+pub mod as_uniform_bytes {
+    use serde::{de, ser, Deserializer, Serializer};
+    use strict_encoding::net::{AddrFormat, Uniform, UniformAddr};
+    use strict_encoding::{strict_deserialize, strict_serialize};
+
+    use super::FixedBytes;
+    use crate::InetAddr;
+
+    /// Length of the uniform address representation
+    pub const LEN: usize = 33;
+
+    /// Serializes address as uniform encoding bytes.
+    pub fn serialize<S: Serializer>(
+        addr: &InetAddr,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let format = strict_serialize(&addr.addr_format())
+            .map_err(ser::Error::custom)?;
+        let raw = addr.addr();
+        let mut buf = [0u8; LEN];
+        buf[0] = format[0];
+        buf[1..].copy_from_slice(&raw[1..]);
+        serializer.serialize_bytes(&buf)
+    }
+
+    /// Deserializes address from uniform encoding bytes.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<InetAddr, D::Error> {
+        let buf = deserializer.deserialize_bytes(FixedBytes::<LEN>)?;
+        let addr_format: AddrFormat =
+            strict_deserialize(&buf[..1]).map_err(|_| {
+                de::Error::custom(format!("unknown address format {}", buf[0]))
+            })?;
+        let mut addr = [0u8; LEN];
+        addr[1..].copy_from_slice(&buf[1..]);
+        InetAddr::from_uniform_addr(UniformAddr {
+            addr_format,
+            addr,
+            port: None,
+            transport: None,
+        })
+        .map_err(|err| {
+            de::Error::custom(format!("invalid uniform address: {}", err))
+        })
+    }
+}
+
+/// Serializes [`InetAddr`] as a string (IP address or onion address with
+/// `.onion` suffix) regardless of whether `serde_str_helpers` feature is used.
+///
+/// [`InetAddr`]: crate::InetAddr
+pub mod as_string {
+    use std::str::FromStr;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::InetAddr;
+
+    /// Serializes address as a string.
+    pub fn serialize<S: Serializer>(
+        addr: &InetAddr,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(addr)
+    }
+
+    /// Deserializes address from a string.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<InetAddr, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        InetAddr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::InetAddr;
+
+    #[derive(PartialEq, Debug, Serialize, Deserialize)]
+    #[serde(crate = "serde_crate")]
+    struct Addrs {
+        #[serde(with = "as_string")]
+        string: InetAddr,
+        #[cfg(feature = "strict_encoding")]
+        #[serde(with = "as_uniform_bytes")]
+        uniform: InetAddr,
+    }
+
+    fn addrs() -> Vec<InetAddr> {
+        #[allow(unused_mut)]
+        let mut addrs = vec![
+            InetAddr::IPv4(Ipv4Addr::new(192, 168, 0, 1)),
+            InetAddr::IPv6(Ipv6Addr::LOCALHOST),
+            InetAddr::IPv6(Ipv4Addr::LOCALHOST.to_ipv6_mapped()),
+        ];
+        #[cfg(feature = "tor")]
+        addrs.push(onion());
+        addrs
+    }
+
+    #[cfg(feature = "tor")]
+    fn onion() -> InetAddr {
+        // Ed25519 base point, which is a valid Tor v3 public key
+        let mut key = [0x66u8; 32];
+        key[0] = 0x58;
+        InetAddr::Tor(crate::OnionPublicKey::from_bytes(key))
+    }
+
+    #[test]
+    fn string_and_uniform_roundtrip() {
+        for addr in addrs() {
+            let val = Addrs {
+                string: addr,
+                #[cfg(feature = "strict_encoding")]
+                uniform: addr,
+            };
+            let json = serde_json::to_string(&val).unwrap();
+            assert_eq!(serde_json::from_str::<Addrs>(&json).unwrap(), val);
+        }
+
+        assert!(serde_json::from_str::<Addrs>(
+            r#"{"string":"300.0.0.1","uniform":[]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "strict_encoding")]
+    fn uniform_bytes_format() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        #[serde(crate = "serde_crate")]
+        struct Uniform(#[serde(with = "as_uniform_bytes")] InetAddr);
+
+        let json =
+            serde_json::to_value(Uniform(Ipv4Addr::LOCALHOST.into())).unwrap();
+        let bytes: Vec<u8> = serde_json::from_value(json).unwrap();
+        assert_eq!(bytes.len(), as_uniform_bytes::LEN);
+        assert_eq!(&bytes[as_uniform_bytes::LEN - 4..], &[127, 0, 0, 1]);
+
+        // Wrong length and unknown address format
+        assert!(serde_json::from_str::<Uniform>("[0, 1, 2]").is_err());
+        let mut bytes = bytes;
+        bytes[0] = 0xFF;
+        assert!(serde_json::from_value::<Uniform>(
+            serde_json::to_value(bytes).unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "tor")]
+    fn onion_pubkey_roundtrip() {
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        #[serde(crate = "serde_crate")]
+        struct Onion(#[serde(with = "as_onion_pubkey")] InetAddr);
+
+        let json = serde_json::to_string(&Onion(onion())).unwrap();
+        let bytes: Vec<u8> = serde_json::from_str(&json).unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(
+            serde_json::from_str::<Onion>(&json).unwrap(),
+            Onion(onion())
+        );
+
+        let err = serde_json::to_string(&Onion(Ipv4Addr::LOCALHOST.into()))
+            .unwrap_err();
+        assert!(err.to_string().contains("not a Tor address"));
+        assert!(serde_json::from_str::<Onion>("[1, 2, 3]").is_err());
+    }
+}
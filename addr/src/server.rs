@@ -13,12 +13,69 @@
 
 #![allow(clippy::init_numbered_fields)]
 
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
 use std::net::{self, SocketAddr};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::node::NodeAddrParseError;
 use crate::{AddrParseError, InetSocketAddr, NodeAddr};
 
+/// Unknown address scheme, with a suggestion of the closest known one
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Error)]
+pub struct UnknownScheme {
+    /// Scheme which was not recognized
+    pub scheme: String,
+
+    /// Known scheme which is the closest to the unrecognized one, if any
+    pub suggestion: Option<&'static str>,
+}
+
+impl Display for UnknownScheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown address scheme '{}'", self.scheme)?;
+        if let Some(suggestion) = self.suggestion {
+            write!(f, "; did you mean '{}://'?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl UnknownScheme {
+    /// Maximal edit distance between the unknown scheme and a known one for
+    /// the known scheme to be suggested
+    const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+    fn with(scheme: &str, known: &[&'static str]) -> Self {
+        let scheme = scheme.to_owned();
+        let suggestion = known
+            .iter()
+            .map(|known| (edit_distance(&scheme, known), *known))
+            .filter(|(distance, _)| *distance <= Self::MAX_SUGGESTION_DISTANCE)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, known)| known);
+        UnknownScheme { scheme, suggestion }
+    }
+}
+
+/// Levenshtein distance between two strings, computed over characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            row[j + 1] = substitution.min(prev[j + 1] + 1).min(row[j] + 1);
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
 /// Errors parsing [`ServerAddr`] string representation
 #[derive(Debug, Display, Error, From)]
 #[display(doc_comments)]
@@ -33,6 +90,11 @@ pub enum ServerAddrParseError {
     #[display(inner)]
     InvalidAddr(AddrParseError),
 
+    /// Unknown address scheme
+    #[from]
+    #[display(inner)]
+    UnknownScheme(UnknownScheme),
+
     /// invalid server address string '{0}'
     Unrecognized(String),
 }
@@ -63,6 +125,11 @@ pub enum ServerAddr {
     Ipc(String),
 }
 
+impl ServerAddr {
+    /// URL schemes recognized when parsing server address strings
+    pub const SCHEMES: &'static [&'static str] = &["bronze", "tcp", "ipc"];
+}
+
 impl FromStr for ServerAddr {
     type Err = ServerAddrParseError;
 
@@ -72,6 +139,11 @@ impl FromStr for ServerAddr {
             (Some("bronze"), Some(s), None) => NodeAddr::from_str(s)?.into(),
             (Some("tcp"), Some(s), None) => InetSocketAddr::from_str(s)?.into(),
             (Some("ipc"), Some(s), None) => ServerAddr::Ipc(s.to_owned()),
+            (Some(scheme), Some(_), None) => {
+                return Err(
+                    UnknownScheme::with(scheme, ServerAddr::SCHEMES).into()
+                )
+            }
             (Some(s), None, _) => NodeAddr::from_str(s)
                 .map(ServerAddr::from)
                 .map_err(ServerAddrParseError::from)
@@ -95,18 +167,41 @@ pub enum ServiceAddrParseError {
     #[display(inner)]
     InvalidAddr(net::AddrParseError),
 
+    /// Unknown address scheme
+    #[from]
+    #[display(inner)]
+    UnknownScheme(UnknownScheme),
+
     /// invalid server address string '{0}'
     Unrecognized(String),
+
+    /// empty {0} endpoint
+    EmptyEndpoint(&'static str),
+
+    /// IPC socket path is {0} bytes long, while it must not exceed 107 bytes
+    IpcPathTooLong(usize),
+
+    /// in-process endpoint name '{0}' contains invalid characters; only
+    /// printable ASCII characters except space are allowed
+    InvalidInprocName(String),
 }
 
+/// Maximal length of IPC socket path, limited by the size of `sun_path` field
+/// of unix socket address (including terminating zero byte)
+pub const IPC_PATH_MAX_LEN: usize = 107;
+
 /// Address of microservice which may be local or remote; standalone process or
 /// a thread, connectable via ZMQ.
+///
+/// Serde represents the address as a ZMQ endpoint string (see
+/// [`ServiceAddr::zmq_connect_string`]); both deserialization and strict
+/// decoding check the address with [`ServiceAddr::validate`].
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, From)]
-#[cfg_attr(feature = "strict_encoding", derive(StrictEncode, StrictDecode))]
+#[cfg_attr(feature = "strict_encoding", derive(StrictEncode))]
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
-    serde(crate = "serde_crate")
+    serde(crate = "serde_crate", try_from = "String", into = "String")
 )]
 pub enum ServiceAddr {
     /// Connection via TCP
@@ -126,14 +221,24 @@ pub enum ServiceAddr {
 impl FromStr for ServiceAddr {
     type Err = ServiceAddrParseError;
 
+    /// Parses ZMQ endpoint string (`tcp://`, `ipc://` or `inproc://`); the
+    /// scheme may be omitted, in which case strings containing `/` are
+    /// treated as IPC paths, valid socket addresses as TCP and all other
+    /// strings as in-process endpoint names. The parsed address is checked
+    /// with [`ServiceAddr::validate`].
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut split = s.split("://");
-        Ok(match (split.next(), split.next(), split.next()) {
+        let addr = match (split.next(), split.next(), split.next()) {
             (Some("tcp"), Some(s), None) => SocketAddr::from_str(s)?.into(),
             (Some("ipc"), Some(s), None) => ServiceAddr::Ipc(s.to_owned()),
             (Some("inproc"), Some(s), None) => {
                 ServiceAddr::Inproc(s.to_owned())
             }
+            (Some(scheme), Some(_), None) => {
+                return Err(
+                    UnknownScheme::with(scheme, ServiceAddr::SCHEMES).into()
+                )
+            }
             (Some(s), None, _) if s.contains('/') => {
                 ServiceAddr::Ipc(s.to_owned())
             }
@@ -141,11 +246,220 @@ impl FromStr for ServiceAddr {
                 .map(ServiceAddr::from)
                 .unwrap_or_else(|_| ServiceAddr::Inproc(s.to_owned())),
             _ => return Err(ServiceAddrParseError::Unrecognized(s.to_owned())),
-        })
+        };
+        addr.validate()?;
+        Ok(addr)
+    }
+}
+
+impl TryFrom<String> for ServiceAddr {
+    type Error = ServiceAddrParseError;
+
+    #[inline]
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        ServiceAddr::from_str(&s)
     }
 }
 
+impl From<ServiceAddr> for String {
+    #[inline]
+    fn from(addr: ServiceAddr) -> Self { addr.zmq_connect_string() }
+}
+
 impl ServiceAddr {
+    /// URL schemes recognized when parsing service address strings
+    pub const SCHEMES: &'static [&'static str] = &["tcp", "ipc", "inproc"];
+
     /// Returns ZeroMQ connection string
     pub fn zmq_connect_string(&self) -> String { format!("{self:#}") }
+
+    /// Checks that the address can be used as ZMQ endpoint: IPC path must be
+    /// non-empty and fit into [`IPC_PATH_MAX_LEN`] bytes, and in-process
+    /// endpoint name must be non-empty and consist of printable ASCII
+    /// characters other than space.
+    pub fn validate(&self) -> Result<(), ServiceAddrParseError> {
+        match self {
+            ServiceAddr::Tcp(_) => Ok(()),
+            ServiceAddr::Ipc(path) if path.is_empty() => {
+                Err(ServiceAddrParseError::EmptyEndpoint("IPC"))
+            }
+            ServiceAddr::Ipc(path) if path.len() > IPC_PATH_MAX_LEN => {
+                Err(ServiceAddrParseError::IpcPathTooLong(path.len()))
+            }
+            ServiceAddr::Ipc(_) => Ok(()),
+            ServiceAddr::Inproc(name) if name.is_empty() => {
+                Err(ServiceAddrParseError::EmptyEndpoint("in-process"))
+            }
+            ServiceAddr::Inproc(name)
+                if !name.bytes().all(|b| b.is_ascii_graphic()) =>
+            {
+                Err(ServiceAddrParseError::InvalidInprocName(name.clone()))
+            }
+            ServiceAddr::Inproc(_) => Ok(()),
+        }
+    }
+
+    /// Generates in-memory service address which does not collide with any
+    /// other address generated by this function within the process. The name
+    /// starts with `prefix` and includes process-wide counter and a random
+    /// suffix, making collisions with hard-coded names unlikely as well.
+    pub fn inproc_unique(prefix: &str) -> ServiceAddr {
+        static INPROC_NO: AtomicU64 = AtomicU64::new(0);
+        let no = INPROC_NO.fetch_add(1, Ordering::Relaxed);
+        let random = RandomState::new().build_hasher().finish();
+        ServiceAddr::Inproc(format!("{}-{}-{:016x}", prefix, no, random))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scheme_edit_distance() {
+        assert_eq!(edit_distance("tcp", "tcp"), 0);
+        assert_eq!(edit_distance("tpc", "tcp"), 2);
+        assert_eq!(edit_distance("tcpp", "tcp"), 1);
+        assert_eq!(edit_distance("", "ipc"), 3);
+        assert_eq!(edit_distance("inpro", "inproc"), 1);
+    }
+
+    #[test]
+    fn unknown_service_scheme() {
+        let err = ServiceAddr::from_str("tcpp://127.0.0.1:9735").unwrap_err();
+        assert_eq!(
+            err,
+            ServiceAddrParseError::UnknownScheme(UnknownScheme {
+                scheme: s!("tcpp"),
+                suggestion: Some("tcp")
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "unknown address scheme 'tcpp'; did you mean 'tcp://'?"
+        );
+
+        let err = ServiceAddr::from_str("inprc://test").unwrap_err();
+        assert!(err.to_string().contains("did you mean 'inproc://'?"));
+
+        let err = ServiceAddr::from_str("lightning://test").unwrap_err();
+        assert_eq!(err.to_string(), "unknown address scheme 'lightning'");
+
+        assert_eq!(
+            ServiceAddr::from_str("inproc://test"),
+            Ok(ServiceAddr::Inproc(s!("test")))
+        );
+    }
+
+    #[test]
+    fn inproc_unique() {
+        let addrs = (0..1000)
+            .map(|_| ServiceAddr::inproc_unique("test"))
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(addrs.len(), 1000);
+        let addr = addrs.into_iter().next().unwrap();
+        assert!(addr.zmq_connect_string().starts_with("inproc://test-"));
+        assert_eq!(ServiceAddr::from_str(&addr.zmq_connect_string()), Ok(addr));
+    }
+
+    #[test]
+    fn service_addr_roundtrip() {
+        for s in [
+            "tcp://127.0.0.1:9735",
+            "tcp://[::1]:61399",
+            "ipc:///tmp/lnp/ctl.rpc",
+            "ipc://relative/msg.rpc",
+            "inproc://esb-ctl",
+        ] {
+            let addr = ServiceAddr::from_str(s).unwrap();
+            assert_eq!(addr.zmq_connect_string(), s);
+            assert_eq!(ServiceAddr::from_str(&addr.to_string()), Ok(addr));
+        }
+        assert_eq!(
+            ServiceAddr::from_str("127.0.0.1:9735"),
+            Ok(ServiceAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 9735))))
+        );
+        assert_eq!(
+            ServiceAddr::from_str("/tmp/ctl.rpc"),
+            Ok(ServiceAddr::Ipc(s!("/tmp/ctl.rpc")))
+        );
+        assert_eq!(
+            ServiceAddr::from_str("ctl"),
+            Ok(ServiceAddr::Inproc(s!("ctl")))
+        );
+    }
+
+    #[test]
+    fn service_addr_validation() {
+        let path = format!("/{}", "a".repeat(IPC_PATH_MAX_LEN - 1));
+        assert_eq!(
+            ServiceAddr::from_str(&format!("ipc://{}", path)),
+            Ok(ServiceAddr::Ipc(path.clone()))
+        );
+        assert_eq!(
+            ServiceAddr::from_str(&format!("ipc://{}a", path)),
+            Err(ServiceAddrParseError::IpcPathTooLong(IPC_PATH_MAX_LEN + 1))
+        );
+        assert_eq!(
+            ServiceAddr::from_str("ipc://"),
+            Err(ServiceAddrParseError::EmptyEndpoint("IPC"))
+        );
+        assert_eq!(
+            ServiceAddr::from_str("inproc://"),
+            Err(ServiceAddrParseError::EmptyEndpoint("in-process"))
+        );
+        assert_eq!(
+            ServiceAddr::from_str("inproc://esb ctl"),
+            Err(ServiceAddrParseError::InvalidInprocName(s!("esb ctl")))
+        );
+        assert_eq!(
+            ServiceAddr::from_str("ctl\n"),
+            Err(ServiceAddrParseError::InvalidInprocName(s!("ctl\n")))
+        );
+        assert_eq!(
+            ServiceAddr::Inproc(s!("bus\u{e9}")).validate(),
+            Err(ServiceAddrParseError::InvalidInprocName(s!("bus\u{e9}")))
+        );
+        assert!(ServiceAddr::inproc_unique("esb").validate().is_ok());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn service_addr_serde() {
+        for addr in [
+            ServiceAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 9735))),
+            ServiceAddr::Ipc(s!("relative")),
+            ServiceAddr::Inproc(s!("esb-ctl")),
+        ] {
+            let json = serde_json::to_string(&addr).unwrap();
+            assert_eq!(json, format!("\"{}\"", addr.zmq_connect_string()));
+            assert_eq!(
+                serde_json::from_str::<ServiceAddr>(&json).unwrap(),
+                addr
+            );
+        }
+
+        for json in [r#""inproc://esb ctl""#, r#""ipc://""#, r#""tcpp://ctl""#]
+        {
+            assert!(serde_json::from_str::<ServiceAddr>(json).is_err());
+        }
+        let path = format!("\"ipc:///{}\"", "a".repeat(IPC_PATH_MAX_LEN));
+        assert!(serde_json::from_str::<ServiceAddr>(&path)
+            .unwrap_err()
+            .to_string()
+            .contains("must not exceed 107 bytes"));
+    }
+
+    #[test]
+    fn unknown_server_scheme() {
+        let err = ServerAddr::from_str("brone://test").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown address scheme 'brone'; did you mean 'bronze://'?"
+        );
+        assert!(matches!(
+            ServerAddr::from_str("ipc://test"),
+            Ok(ServerAddr::Ipc(_))
+        ));
+    }
 }
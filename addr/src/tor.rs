@@ -0,0 +1,342 @@
+// Internet2 addresses with support for Tor v3
+//
+// Written in 2019-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//     Martin Habovstiak <martin.habovstiak@gmail.com>
+//
+// To the extent possible under law, the author(s) have dedicated all copyright
+// and related and neighboring rights to this software to the public domain
+// worldwide. This software is distributed without any warranty.
+//
+// You should have received a copy of the MIT License along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Onion service addresses carrying client authorization keys.
+//!
+//! Client-authorized onion services can be reached only by presenting an
+//! x25519 private key of an authorized client. [`TorAddr`] keeps such a key
+//! together with the onion address and uses the same string form as the
+//! `.auth_private` files read by Tor from its `ClientOnionAuthDir`:
+//!
+//! ```text
+//! <onion-address>:descriptor:x25519:<base32-encoded-private-key>
+//! ```
+//!
+//! where the onion address may be given with or without `.onion` suffix.
+//! Addresses without client authorization are represented by the plain onion
+//! address.
+
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::inet::parse_onion;
+use crate::onion::{base32_decode, base32_encode, OnionPublicKey};
+use crate::{AddrParseError, InetAddr};
+
+/// Length of x25519 client authorization key
+pub const CLIENT_AUTH_KEY_LEN: usize = 32;
+
+const AUTH_DESCRIPTOR: &str = ":descriptor:x25519:";
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Errors parsing onion address with client authorization key
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum TorAddrParseError {
+    /// Invalid onion address
+    #[display(inner)]
+    #[from]
+    Address(AddrParseError),
+
+    /// unsupported client authorization "{0}"; only
+    /// `descriptor:x25519:<base32-key>` is supported
+    UnsupportedAuth(String),
+
+    /// invalid client authorization key; it must be 32 bytes encoded as 52
+    /// base32 characters
+    InvalidKey,
+}
+
+/// x25519 private key of an onion service client, used for client
+/// authorization.
+///
+/// The key is not shown by `Debug` formatting.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ClientAuthKey([u8; CLIENT_AUTH_KEY_LEN]);
+
+impl ClientAuthKey {
+    /// Constructs key from raw x25519 private key bytes.
+    #[inline]
+    pub fn from_bytes(key: [u8; CLIENT_AUTH_KEY_LEN]) -> Self {
+        ClientAuthKey(key)
+    }
+
+    /// Returns raw x25519 private key bytes.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; CLIENT_AUTH_KEY_LEN] { &self.0 }
+
+    /// Returns key in unpadded base32 encoding used by Tor in `.auth_private`
+    /// files.
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0).to_ascii_uppercase()
+    }
+
+    /// Parses key from base32 encoding in any letter case.
+    pub fn from_base32(s: &str) -> Result<Self, TorAddrParseError> {
+        base32_decode(s)
+            .map(ClientAuthKey)
+            .map_err(|_| TorAddrParseError::InvalidKey)
+    }
+
+    /// Returns key in padded base64 encoding used by Tor control protocol.
+    pub fn to_base64(&self) -> String {
+        let mut s = String::with_capacity(44);
+        for chunk in self.0.chunks(3) {
+            let mut buf = [0u8; 3];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            let n = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    let idx = (n >> (18 - 6 * i)) as usize & 0x3F;
+                    s.push(BASE64_ALPHABET[idx] as char);
+                } else {
+                    s.push('=');
+                }
+            }
+        }
+        s
+    }
+}
+
+impl Debug for ClientAuthKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("ClientAuthKey(..)")
+    }
+}
+
+/// Tor v3 onion address with optional client authorization key.
+///
+/// Addresses are compared and hashed by the onion address only, so the same
+/// service is matched regardless of the authorization data; the key is still
+/// preserved by string conversions.
+#[derive(Clone, Debug)]
+pub struct TorAddr {
+    /// Public key of the onion service
+    pub key: OnionPublicKey,
+
+    /// Client authorization key, if the service requires one
+    pub client_auth: Option<ClientAuthKey>,
+}
+
+impl TorAddr {
+    /// Constructs onion address without client authorization.
+    #[inline]
+    pub fn new(key: OnionPublicKey) -> Self {
+        TorAddr {
+            key,
+            client_auth: None,
+        }
+    }
+
+    /// Constructs onion address with client authorization key.
+    #[inline]
+    pub fn with_client_auth(
+        key: OnionPublicKey,
+        client_auth: ClientAuthKey,
+    ) -> Self {
+        TorAddr {
+            key,
+            client_auth: Some(client_auth),
+        }
+    }
+
+    /// Returns onion address without authorization data.
+    #[inline]
+    pub fn inet_addr(&self) -> InetAddr { InetAddr::Tor(self.key) }
+
+    /// Returns `ONION_CLIENT_AUTH_ADD` Tor control port command registering
+    /// the client authorization key for the service, or `None` if the address
+    /// has no key.
+    pub fn client_auth_add_command(&self) -> Option<String> {
+        self.client_auth.as_ref().map(|auth| {
+            format!(
+                "ONION_CLIENT_AUTH_ADD {} x25519:{}",
+                self.onion_without_suffix(),
+                auth.to_base64()
+            )
+        })
+    }
+
+    fn onion_without_suffix(&self) -> String { self.key.to_onion_string() }
+}
+
+impl PartialEq for TorAddr {
+    fn eq(&self, other: &Self) -> bool { self.key == other.key }
+}
+
+impl Eq for TorAddr {}
+
+impl Hash for TorAddr {
+    fn hash<H: Hasher>(&self, state: &mut H) { self.key.as_bytes().hash(state) }
+}
+
+impl From<OnionPublicKey> for TorAddr {
+    #[inline]
+    fn from(key: OnionPublicKey) -> Self { TorAddr::new(key) }
+}
+
+impl From<TorAddr> for InetAddr {
+    #[inline]
+    fn from(addr: TorAddr) -> Self { addr.inet_addr() }
+}
+
+impl Display for TorAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.client_auth {
+            None => Display::fmt(&self.inet_addr(), f),
+            Some(ref auth) => write!(
+                f,
+                "{}{}{}",
+                self.onion_without_suffix(),
+                AUTH_DESCRIPTOR,
+                auth.to_base32()
+            ),
+        }
+    }
+}
+
+impl FromStr for TorAddr {
+    type Err = TorAddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, auth) = match s.split_once(':') {
+            None => (s, None),
+            Some((addr, auth)) => (addr, Some(auth)),
+        };
+        let key = parse_onion::<OnionPublicKey>(addr)?;
+        let auth = match auth {
+            None => return Ok(TorAddr::new(key)),
+            Some(auth) => auth,
+        };
+        let encoded =
+            auth.strip_prefix(&AUTH_DESCRIPTOR[1..]).ok_or_else(|| {
+                TorAddrParseError::UnsupportedAuth(auth.to_owned())
+            })?;
+        Ok(TorAddr::with_client_auth(
+            key,
+            ClientAuthKey::from_base32(encoded)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ONION: &str =
+        "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad";
+
+    fn key() -> ClientAuthKey {
+        let mut key = [0u8; CLIENT_AUTH_KEY_LEN];
+        for (no, byte) in key.iter_mut().enumerate() {
+            *byte = no as u8 * 7 + 1;
+        }
+        ClientAuthKey::from_bytes(key)
+    }
+
+    #[test]
+    fn key_encodings() {
+        let zero = ClientAuthKey::from_bytes([0u8; CLIENT_AUTH_KEY_LEN]);
+        assert_eq!(zero.to_base32(), "A".repeat(52));
+        assert_eq!(zero.to_base64(), format!("{}=", "A".repeat(43)));
+
+        let ones = ClientAuthKey::from_bytes([0xFF; CLIENT_AUTH_KEY_LEN]);
+        assert_eq!(ones.to_base32(), format!("{}Q", "7".repeat(51)));
+        assert_eq!(ones.to_base64(), format!("{}8=", "/".repeat(42)));
+
+        let key = key();
+        assert_eq!(ClientAuthKey::from_base32(&key.to_base32()).unwrap(), key);
+        assert_eq!(
+            ClientAuthKey::from_base32(&key.to_base32().to_lowercase())
+                .unwrap(),
+            key
+        );
+        assert_eq!(format!("{:?}", key), "ClientAuthKey(..)");
+
+        // Wrong length, non-zero padding bits and invalid characters
+        assert!(ClientAuthKey::from_base32(&"A".repeat(51)).is_err());
+        assert!(ClientAuthKey::from_base32(&"A".repeat(53)).is_err());
+        assert!(ClientAuthKey::from_base32(&"7".repeat(52)).is_err());
+        assert!(ClientAuthKey::from_base32(&"1".repeat(52)).is_err());
+    }
+
+    #[test]
+    fn parse_display() {
+        let plain = TorAddr::from_str(ONION).unwrap();
+        assert_eq!(plain.client_auth, None);
+        assert_eq!(plain.to_string(), format!("{}.onion", ONION));
+        assert_eq!(TorAddr::from_str(&plain.to_string()).unwrap(), plain);
+        assert_eq!(plain.client_auth_add_command(), None);
+
+        let s = format!("{}:descriptor:x25519:{}", ONION, key().to_base32());
+        let authorized = TorAddr::from_str(&s).unwrap();
+        assert_eq!(authorized.client_auth, Some(key()));
+        assert_eq!(authorized.to_string(), s);
+        assert_eq!(
+            TorAddr::from_str(&format!(
+                "{}.onion:descriptor:x25519:{}",
+                ONION.to_uppercase(),
+                key().to_base32().to_lowercase()
+            ))
+            .unwrap()
+            .to_string(),
+            s
+        );
+
+        // Authorization data does not affect the address identity
+        assert_eq!(authorized, plain);
+        assert_eq!(authorized.inet_addr(), plain.inet_addr());
+        assert_eq!(
+            InetAddr::from(authorized),
+            InetAddr::from_str(ONION).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            TorAddr::from_str("127.0.0.1"),
+            Err(TorAddrParseError::Address(_))
+        ));
+        assert!(matches!(
+            TorAddr::from_str(&format!("{}:descriptor:ed25519:AAAA", ONION)),
+            Err(TorAddrParseError::UnsupportedAuth(auth))
+                if auth == "descriptor:ed25519:AAAA"
+        ));
+        assert!(matches!(
+            TorAddr::from_str(&format!("{}:descriptor:x25519:AAAA", ONION)),
+            Err(TorAddrParseError::InvalidKey)
+        ));
+    }
+
+    #[test]
+    fn control_port_command() {
+        let addr = TorAddr::from_str(&format!(
+            "{}:descriptor:x25519:{}",
+            ONION,
+            key().to_base32()
+        ))
+        .unwrap();
+        assert_eq!(
+            addr.client_auth_add_command().unwrap(),
+            format!(
+                "ONION_CLIENT_AUTH_ADD {} x25519:{}",
+                ONION,
+                key().to_base64()
+            )
+        );
+    }
+}
@@ -69,6 +69,7 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
     let example = "#[api(type=1000)]";
     let mut msg_const = vec![];
     let mut unmarshaller = vec![];
+    let mut type_names = vec![];
     let mut unmarshall_fn = vec![];
     let mut from_type = vec![];
     let mut get_type = vec![];
@@ -88,12 +89,15 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
         let type_lit: Lit = nested_one_named_value(&meta, "type", EXAMPLE)?
             .ok_or_else(|| attr_err!(v, "type must be specified"))?
             .lit;
-        let type_id: u16 = match type_lit {
+        let type_id: u64 = match &type_lit {
             Lit::Int(i) => i
                 .base10_parse()
                 .map_err(|_| attr_err!(i, "`type` must be an integer"))?,
             _ => err!(type_lit, "`type` must be an integer"),
         };
+        let type_id = u16::try_from(type_id).map_err(|_| {
+            attr_err!(type_lit, "`type` exceeds maximum message type id 65535")
+        })?;
         let type_name = &v.ident;
         let type_snake = Ident::new(
             &format!("parse_{}", type_name.to_string().to_lowercase()),
@@ -112,6 +116,11 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
             map.insert(Self::#type_const, Self::#type_snake as ::internet2::UnmarshallFn<_>);
         });
 
+        let type_str = type_name.to_string();
+        type_names.push(quote_spanned! { v.span() =>
+            (Self::#type_const, #type_str),
+        });
+
         let unmarshall_empty = quote_spanned! { v.span() =>
             fn #type_snake(_: &mut dyn ::std::io::Read) -> Result<::std::sync::Arc<dyn ::std::any::Any>, ::internet2::presentation::Error> {
                 struct NoData;
@@ -205,6 +214,8 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
     }
     let msg_const = quote! { #( #msg_const )* };
     let unmarshaller = quote! { #( #unmarshaller )* };
+    let type_names = quote! { #( #type_names )* };
+    let set_name = ident_name.to_string();
     let unmarshall_fn = quote! { #( #unmarshall_fn )* };
     let from_type = quote! { #( #from_type )* };
     let get_type = quote! { #( #get_type )* };
@@ -222,6 +233,7 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
                 let mut map = ::std::collections::BTreeMap::new();
                 #unmarshaller
                 ::internet2::Unmarshaller::new(map, ::internet2::presentation::EncodingType::#encoding_type)
+                    .with_names(#set_name, vec![#type_names])
             }
         }
 
@@ -233,10 +245,8 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
 
         impl ::internet2::TypedEnum for #ident_name {
             fn try_from_type(type_id: ::internet2::TypeId, data: &dyn ::std::any::Any) -> Result<Self, ::internet2::UnknownTypeError> {
-                use ::amplify::Wrapper;
-
                 const ERR: &'static str = "Internal API parsing inconsistency";
-                Ok(match type_id.into_inner() {
+                Ok(match type_id.to_u16() {
                     #from_type
                     // Here we receive odd-numbered messages. However, in terms of RPC,
                     // there is no "upstream processor", so we return error (but do not
@@ -246,8 +256,7 @@ fn inner_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream2> {
             }
 
             fn get_type(&self) -> ::internet2::TypeId {
-                use ::amplify::Wrapper;
-                ::internet2::TypeId::from_inner(match self {
+                ::internet2::TypeId::new(match self {
                     #get_type
                 })
             }
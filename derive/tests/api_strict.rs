@@ -61,3 +61,18 @@ fn roundtrip() {
     let roundtrip = &*unmarshaller.unmarshall(Cursor::new(payload)).unwrap();
     assert_eq!(&message, roundtrip);
 }
+
+#[test]
+fn type_names() {
+    let unmarshaller = Request::create_unmarshaller();
+    let names: Vec<_> = unmarshaller
+        .registered_types()
+        .map(|info| (info.type_id.to_string(), info.name, info.message_set))
+        .collect();
+    assert_eq!(names, vec![
+        ("1".to_owned(), Some("Hello"), Some("Request")),
+        ("3".to_owned(), Some("Empty"), Some("Request")),
+        ("5".to_owned(), Some("NoArgs"), Some("Request")),
+        ("259".to_owned(), Some("AddKeys"), Some("Request")),
+    ]);
+}
@@ -0,0 +1,73 @@
+//! Client measuring round-trip latency of an echo server.
+//!
+//! Usage: `echo_client [<server_addr>] [--node-id <id>] [--count <n>]
+//! [--size <bytes>]`
+//!
+//! If node id of the server is given, the client connects over an encrypted
+//! Brontide session; otherwise a plaintext session is used.
+
+use std::time::{Duration, Instant};
+
+use inet2_addr::{InetSocketAddr, LocalNode, NodeAddr, NodeId};
+use internet2::session::{BrontideSession, PlainTranscoder, Session};
+use internet2::transport::unencrypted;
+use internet2::SendRecvMessage;
+
+fn main() {
+    let mut addr = InetSocketAddr::socket([127, 0, 0, 1].into(), 9735);
+    let mut node_id: Option<NodeId> = None;
+    let mut count = 100usize;
+    let mut size = 64usize;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().expect("missing argument value");
+        match arg.as_str() {
+            "--node-id" => {
+                node_id = Some(value().parse().expect("invalid node id"))
+            }
+            "--count" => count = value().parse().expect("invalid count"),
+            "--size" => size = value().parse().expect("invalid size"),
+            other => addr = other.parse().expect("invalid server address"),
+        }
+    }
+
+    let mut session: Box<dyn SendRecvMessage> = match node_id {
+        Some(id) => {
+            let node = LocalNode::new(&secp256k1::Secp256k1::new());
+            let remote = NodeAddr::new(id, addr);
+            Box::new(
+                BrontideSession::connect(node.private_key(), remote)
+                    .expect("unable to connect"),
+            )
+        }
+        None => Box::new(Session::with_transport(
+            unencrypted::Connection::connect(addr).expect("unable to connect"),
+            PlainTranscoder,
+        )),
+    };
+
+    let msg = vec![0xA5u8; size];
+    let mut rtts = Vec::with_capacity(count);
+    for _ in 0..count {
+        let started = Instant::now();
+        session.send_raw_message(&msg).expect("unable to send");
+        let echo = session.recv_raw_message().expect("unable to receive");
+        rtts.push(started.elapsed());
+        assert_eq!(echo, msg, "echo does not match the sent message");
+    }
+
+    rtts.sort();
+    let total: Duration = rtts.iter().sum();
+    if let (Some(min), Some(max)) = (rtts.first(), rtts.last()) {
+        println!(
+            "{} round trips of {} bytes: min {:?}, median {:?}, avg {:?}, max \
+             {:?}",
+            count,
+            size,
+            min,
+            rtts[rtts.len() / 2],
+            total / count as u32,
+            max
+        );
+    }
+}
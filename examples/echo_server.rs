@@ -0,0 +1,53 @@
+//! Echo server sending back all received messages.
+//!
+//! Usage: `echo_server [<bind_addr>] [--brontide] [--delay <ms>]
+//! [--drop-rate <rate>]`
+//!
+//! With `--brontide` the server accepts encrypted sessions and prints its
+//! node id, which must be passed to the client. The server stops when its
+//! standard input is closed (for instance, with Ctrl+D).
+
+use std::io::Read;
+use std::time::Duration;
+
+use inet2_addr::{InetSocketAddr, LocalNode};
+use internet2::testing::{EchoConfig, EchoService};
+
+fn main() {
+    let mut addr = InetSocketAddr::socket([127, 0, 0, 1].into(), 9735);
+    let mut config = EchoConfig::plain();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().expect("missing argument value");
+        match arg.as_str() {
+            "--brontide" => {
+                let node = LocalNode::new(&secp256k1::Secp256k1::new());
+                eprintln!("node id: {}", node.node_id());
+                config.mode = EchoConfig::brontide(node.private_key()).mode;
+            }
+            "--delay" => {
+                let ms = value().parse().expect("invalid delay");
+                config.delay = Some(Duration::from_millis(ms));
+            }
+            "--drop-rate" => {
+                config.drop_rate = value().parse().expect("invalid drop rate")
+            }
+            other => addr = other.parse().expect("invalid bind address"),
+        }
+    }
+
+    let service = EchoService::bind(addr, config).expect("unable to bind");
+    eprintln!("listening on {}", service.local_addr());
+
+    let handle = service.shutdown_handle();
+    std::thread::spawn(move || {
+        let _ = std::io::stdin().read_to_end(&mut vec![]);
+        handle.shutdown();
+    });
+
+    let stats = service.wait();
+    eprintln!(
+        "served {} sessions, {} messages ({} bytes), {} dropped",
+        stats.sessions, stats.messages, stats.bytes, stats.dropped
+    );
+}
@@ -0,0 +1,156 @@
+//! Measures Noise_XK (Brontide) handshakes per second, single- and
+//! multi-threaded, comparing the default handshake backend using the global
+//! `secp256k1` context with a backend creating a new context per operation.
+//!
+//! Usage: `handshake_bench [<handshakes>] [<threads>]`
+//!
+//! Handshakes are performed in memory, so the numbers do not include network
+//! latency. Build with `--release` for meaningful results.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin_hashes::sha256::Hash as Sha256;
+use bitcoin_hashes::Hash;
+use internet2::session::noise::{
+    EncryptionError, HandshakeCrypto, HandshakeError, KeyRole,
+    ResumableHandshake, SoftwareCrypto,
+};
+use secp256k1::{PublicKey, Scalar, SecretKey};
+
+/// Backend creating a new `secp256k1` context for each elliptic curve
+/// operation
+#[derive(Debug)]
+struct PerCallContext;
+
+impl HandshakeCrypto for PerCallContext {
+    fn public_key(&self, _: KeyRole, key: &SecretKey) -> PublicKey {
+        PublicKey::from_secret_key(&secp256k1::Secp256k1::new(), key)
+    }
+
+    fn ecdh(
+        &self,
+        _: KeyRole,
+        private_key: &SecretKey,
+        public_key: &PublicKey,
+    ) -> Result<[u8; 32], HandshakeError> {
+        let curve = secp256k1::Secp256k1::new();
+        let scalar = Scalar::from_be_bytes(private_key.secret_bytes())?;
+        let preimage = public_key
+            .mul_tweak(&curve, &scalar)
+            .expect("invalid multiplication")
+            .serialize();
+        Ok(Sha256::hash(&preimage).into_inner())
+    }
+
+    fn hkdf(&self, salt: &[u8], ikm: &[u8]) -> ([u8; 32], [u8; 32]) {
+        SoftwareCrypto.hkdf(salt, ikm)
+    }
+
+    fn seal(
+        &self,
+        key: &[u8; 32],
+        nonce: u64,
+        ad: &[u8],
+        plaintext: &[u8],
+        ciphertext: &mut [u8],
+    ) -> Result<(), EncryptionError> {
+        SoftwareCrypto.seal(key, nonce, ad, plaintext, ciphertext)
+    }
+
+    fn open(
+        &self,
+        key: &[u8; 32],
+        nonce: u64,
+        ad: &[u8],
+        ciphertext: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), EncryptionError> {
+        SoftwareCrypto.open(key, nonce, ad, ciphertext, plaintext)
+    }
+}
+
+fn key(byte: u8) -> SecretKey {
+    SecretKey::from_slice(&[byte; 32]).expect("valid key")
+}
+
+fn handshake(crypto: fn() -> Box<dyn HandshakeCrypto>) {
+    let responder_pubkey = SoftwareCrypto.public_key(KeyRole::Static, &key(3));
+    let mut initiator = ResumableHandshake::<2>::initiator_with_crypto(
+        &key(1),
+        &responder_pubkey,
+        &key(2),
+        crypto(),
+    )
+    .expect("act one");
+    let mut responder = ResumableHandshake::<2>::responder_with_crypto(
+        &key(3),
+        &key(4),
+        crypto(),
+    );
+    for from_initiator in [true, false, true] {
+        let (from, to) = if from_initiator {
+            (&mut initiator, &mut responder)
+        } else {
+            (&mut responder, &mut initiator)
+        };
+        let act = from.next_act().expect("pending act");
+        to.push_bytes(&act).expect("valid act");
+    }
+    assert!(initiator.into_transcoder().is_some());
+    assert!(responder.into_transcoder().is_some());
+}
+
+fn measure(
+    name: &str,
+    crypto: fn() -> Box<dyn HandshakeCrypto>,
+    count: usize,
+    threads: usize,
+) {
+    let started = Instant::now();
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            thread::spawn(move || {
+                for _ in 0..count / threads {
+                    handshake(crypto)
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("benchmark thread panicked");
+    }
+    let elapsed = started.elapsed().max(Duration::from_micros(1));
+    let total = count / threads * threads;
+    println!(
+        "{:<16} {:>2} thread(s): {:>10.1} handshakes/s",
+        name,
+        threads,
+        total as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let count = args
+        .next()
+        .map(|s| s.parse().expect("invalid number of handshakes"))
+        .unwrap_or(1000);
+    let threads = args
+        .next()
+        .map(|s| s.parse().expect("invalid number of threads"))
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(usize::from)
+                .unwrap_or(4)
+        })
+        .max(1);
+
+    let shared: fn() -> Box<dyn HandshakeCrypto> = || Box::new(SoftwareCrypto);
+    let per_call: fn() -> Box<dyn HandshakeCrypto> =
+        || Box::new(PerCallContext);
+    for threads in [1, threads] {
+        measure("global context", shared, count, threads);
+        measure("per-call context", per_call, count, threads);
+    }
+}
@@ -0,0 +1,38 @@
+//! Connects to a Lightning node, prints features it advertises in `init`
+//! message and exits.
+//!
+//! Usage: `ln_probe <node_id>@<host>:<port>`
+
+use internet2::session::MinimalPeer;
+
+/// Features advertised by the probe: optional `var_onion_optin` and
+/// `option_static_remotekey`
+const FEATURES: [u16; 2] = [9, 13];
+
+fn main() {
+    let remote = std::env::args()
+        .nth(1)
+        .expect("usage: ln_probe <node_id>@<host>:<port>");
+
+    let peer =
+        MinimalPeer::connect(&remote, None, &FEATURES).unwrap_or_else(|err| {
+            panic!("unable to connect to {}: {}", remote, err)
+        });
+    println!("Connected to {} as {}", peer.remote_id(), peer.local_node());
+
+    let features = peer.remote_features();
+    print!("Features: ");
+    for byte in features {
+        print!("{:02x}", byte);
+    }
+    println!();
+    for bit in peer.remote_feature_bits() {
+        println!(
+            "- bit {} ({})",
+            bit,
+            if bit % 2 == 0 { "required" } else { "optional" }
+        );
+    }
+
+    peer.shutdown().expect("unable to close the connection");
+}
@@ -0,0 +1,298 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Helpers for reading configuration options from human-written files.
+//!
+//! Timeouts and intervals are written as an integer number followed by one
+//! of `ms`, `s`, `m` or `h` unit suffixes (like `"500ms"` or `"30s"`); an
+//! integer without a suffix means seconds. With `serde` feature enabled,
+//! [`HumanDuration`] and `Duration` fields marked with
+//! `#[serde(with = "internet2::config::duration")]` accept both forms.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Errors parsing [`HumanDuration`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DurationParseError {
+    /// duration value is empty
+    Empty,
+
+    /// duration `{0}` is negative
+    Negative(String),
+
+    /// duration `{0}` must start with an integer number
+    InvalidNumber(String),
+
+    /// duration `{0}` has unknown unit; use `ms`, `s`, `m` or `h`
+    UnknownUnit(String),
+
+    /// duration `{0}` is too large
+    Overflow(String),
+}
+
+/// Duration which is parsed from and displayed as a human-readable string,
+/// like `"30s"`.
+///
+/// The string representation uses the largest of `ms`, `s`, `m` and `h`
+/// units which represents the duration exactly; sub-millisecond precision is
+/// truncated.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct HumanDuration(pub Duration);
+
+impl From<Duration> for HumanDuration {
+    #[inline]
+    fn from(duration: Duration) -> Self { HumanDuration(duration) }
+}
+
+impl From<HumanDuration> for Duration {
+    #[inline]
+    fn from(duration: HumanDuration) -> Self { duration.0 }
+}
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let millis = self.0.as_millis();
+        if millis % 1000 != 0 {
+            return write!(f, "{}ms", millis);
+        }
+        match self.0.as_secs() {
+            0 => f.write_str("0s"),
+            secs if secs % 3600 == 0 => write!(f, "{}h", secs / 3600),
+            secs if secs % 60 == 0 => write!(f, "{}m", secs / 60),
+            secs => write!(f, "{}s", secs),
+        }
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = DurationParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(DurationParseError::Empty);
+        }
+        if s.starts_with('-') {
+            return Err(DurationParseError::Negative(s.to_owned()));
+        }
+        let pos = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(pos);
+        if number.is_empty() || unit.starts_with(&['.', ','][..]) {
+            return Err(DurationParseError::InvalidNumber(s.to_owned()));
+        }
+        // The string contains only digits, so parsing may fail only on
+        // overflow
+        let number = u64::from_str(number)
+            .map_err(|_| DurationParseError::Overflow(s.to_owned()))?;
+        let multiplier = match unit.trim_start() {
+            "ms" => return Ok(HumanDuration(Duration::from_millis(number))),
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            _ => return Err(DurationParseError::UnknownUnit(s.to_owned())),
+        };
+        number
+            .checked_mul(multiplier)
+            .map(|secs| HumanDuration(Duration::from_secs(secs)))
+            .ok_or_else(|| DurationParseError::Overflow(s.to_owned()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for HumanDuration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for HumanDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = HumanDuration;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str(
+                    "duration string like `30s` or an integer number of \
+                     seconds",
+                )
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                HumanDuration::from_str(v).map_err(E::custom)
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(HumanDuration(Duration::from_secs(v)))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                if v < 0 {
+                    return Err(E::custom(DurationParseError::Negative(
+                        v.to_string(),
+                    )));
+                }
+                self.visit_u64(v as u64)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Serde adapter for `Duration` fields, (de)serializing them as
+/// [`HumanDuration`]
+#[cfg(feature = "serde")]
+pub mod duration {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::HumanDuration;
+
+    /// Serializes duration as a human-readable string
+    pub fn serialize<S>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        HumanDuration(*duration).serialize(serializer)
+    }
+
+    /// Deserializes duration from a human-readable string or an integer
+    /// number of seconds
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        HumanDuration::deserialize(deserializer).map(Duration::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Duration, DurationParseError> {
+        HumanDuration::from_str(s).map(Duration::from)
+    }
+
+    #[test]
+    fn units() {
+        for (s, duration) in [
+            ("1500ms", Duration::from_millis(1500)),
+            ("45s", Duration::from_secs(45)),
+            ("5m", Duration::from_secs(300)),
+            ("2h", Duration::from_secs(7200)),
+        ] {
+            assert_eq!(parse(s), Ok(duration));
+            assert_eq!(HumanDuration(duration).to_string(), s);
+        }
+        assert_eq!(parse("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse(" 10 s "), Ok(Duration::from_secs(10)));
+        assert_eq!(parse("0ms"), Ok(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn canonical_display() {
+        assert_eq!(HumanDuration::default().to_string(), "0s");
+        assert_eq!(HumanDuration(Duration::from_secs(90)).to_string(), "90s");
+        assert_eq!(HumanDuration(Duration::from_secs(120)).to_string(), "2m");
+        assert_eq!(HumanDuration(Duration::from_secs(3600)).to_string(), "1h");
+        assert_eq!(
+            HumanDuration(Duration::from_millis(2000)).to_string(),
+            "2s"
+        );
+        assert_eq!(
+            HumanDuration(Duration::from_micros(1500)).to_string(),
+            "1ms"
+        );
+        let max = HumanDuration(Duration::from_secs(u64::MAX));
+        assert_eq!(max.to_string().parse(), Ok(max));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse(""), Err(DurationParseError::Empty));
+        assert_eq!(
+            parse("-5s"),
+            Err(DurationParseError::Negative("-5s".to_owned()))
+        );
+        assert_eq!(
+            parse("s"),
+            Err(DurationParseError::InvalidNumber("s".to_owned()))
+        );
+        assert_eq!(
+            parse("1.5s"),
+            Err(DurationParseError::InvalidNumber("1.5s".to_owned()))
+        );
+        assert_eq!(
+            parse("5d"),
+            Err(DurationParseError::UnknownUnit("5d".to_owned()))
+        );
+        assert_eq!(
+            parse("18446744073709551616"),
+            Err(DurationParseError::Overflow(
+                "18446744073709551616".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse("5124095576030432h"),
+            Err(DurationParseError::Overflow("5124095576030432h".to_owned()))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_adapter() {
+        use serde::de::value::{
+            Error, I64Deserializer, StrDeserializer, U64Deserializer,
+        };
+        use serde::de::IntoDeserializer;
+        use serde::Deserialize;
+
+        let de: StrDeserializer<Error> = "30s".into_deserializer();
+        assert_eq!(
+            HumanDuration::deserialize(de).unwrap(),
+            HumanDuration(Duration::from_secs(30))
+        );
+        let de: U64Deserializer<Error> = 30u64.into_deserializer();
+        assert_eq!(duration::deserialize(de).unwrap(), Duration::from_secs(30));
+        let de: I64Deserializer<Error> = (-30i64).into_deserializer();
+        assert!(HumanDuration::deserialize(de).is_err());
+        let de: StrDeserializer<Error> = "30d".into_deserializer();
+        assert!(HumanDuration::deserialize(de).is_err());
+    }
+}
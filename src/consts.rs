@@ -0,0 +1,118 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Protocol constants shared by the transport, session and presentation
+//! layers. Most of them are also re-exported from the modules where they were
+//! originally defined.
+
+/// Size of the ChaCha20-Poly1305 MAC tag
+pub const MAC_SIZE: usize = 16;
+
+/// Size of the serialized compressed public key used in the handshake
+pub const PUBKEY_SIZE: usize = secp256k1::constants::PUBLIC_KEY_SIZE;
+
+/// Size of the handshake version byte which starts each handshake act
+pub const ACT_VERSION_SIZE: usize = 1;
+
+/// Length of the first act of Noise_XK handshake: version, ephemeral public
+/// key and MAC
+pub const ACT_ONE_LENGTH: usize = ACT_VERSION_SIZE + PUBKEY_SIZE + MAC_SIZE;
+
+/// Length of the second act of Noise_XK handshake, which has the same
+/// structure as the first act
+pub const ACT_TWO_LENGTH: usize = ACT_ONE_LENGTH;
+
+/// Length of the third act of Noise_XK handshake: version, encrypted static
+/// public key with its MAC and the final MAC
+pub const ACT_THREE_LENGTH: usize =
+    ACT_VERSION_SIZE + PUBKEY_SIZE + MAC_SIZE + MAC_SIZE;
+
+/// Number of messages encrypted with a single key before the key is rotated
+pub const KEY_ROTATION_PERIOD: u32 = 1000;
+
+/// Size of the message length prefix in Brontide frames
+pub const BRONTIDE_LEN_SIZE: usize = 2;
+
+/// Size of the message length prefix in Brontozaur frames
+pub const BRONTOZAUR_LEN_SIZE: usize = 3;
+
+/// Maximum message (packet payload) length for Brontide protocol
+pub const BRONTIDE_MSG_MAX_LEN: usize = u16::MAX as usize;
+
+/// Maximum message (packet payload) length for Brontozaur protocol
+pub const BRONTOZAUR_MSG_MAX_LEN: usize = 0xFFFFFF;
+
+/// Size of the frame prefix which is not included into payload size, consisting
+/// of the 2-bytes message size data and 16-byte MAC of the payload length
+pub const FRAME_PREFIX_SIZE: usize = BRONTIDE_LEN_SIZE + MAC_SIZE;
+
+/// Size of the frame suffix represented by a 16-byte MAC of the frame payload
+pub const FRAME_SUFFIX_SIZE: usize = MAC_SIZE;
+
+/// Maximum size of the frame payload which may be expressed by two bytes
+pub const MAX_FRAME_PAYLOAD_SIZE: usize = BRONTIDE_MSG_MAX_LEN;
+
+/// Maximum size of the transport frame; chosen in compliance with LN specs
+pub const MAX_FRAME_SIZE: usize =
+    FRAME_PREFIX_SIZE + MAX_FRAME_PAYLOAD_SIZE + FRAME_SUFFIX_SIZE;
+
+#[cfg(test)]
+mod test {
+    use amplify::num::u24;
+
+    use super::*;
+    use crate::session::noise::{chacha, FramingProtocol};
+    use crate::session::{Encrypt, PlainTranscoder};
+
+    #[test]
+    fn frame_invariants() {
+        assert_eq!(
+            MAX_FRAME_SIZE,
+            BRONTIDE_LEN_SIZE + MAC_SIZE + MAX_FRAME_PAYLOAD_SIZE + MAC_SIZE
+        );
+        assert_eq!(MAX_FRAME_PAYLOAD_SIZE, u16::MAX as usize);
+        assert_eq!(BRONTOZAUR_MSG_MAX_LEN, u24::MAX.into_usize());
+        assert_eq!(chacha::TAG_SIZE, MAC_SIZE);
+
+        assert_eq!(
+            PlainTranscoder.frame_overhead(),
+            FRAME_PREFIX_SIZE + FRAME_SUFFIX_SIZE
+        );
+        assert_eq!(PlainTranscoder.max_payload_len(), MAX_FRAME_PAYLOAD_SIZE);
+        assert_eq!(
+            FramingProtocol::Brontide.frame_overhead(),
+            FRAME_PREFIX_SIZE + FRAME_SUFFIX_SIZE
+        );
+        assert_eq!(
+            FramingProtocol::Brontide.message_len_size(),
+            BRONTIDE_LEN_SIZE
+        );
+        assert_eq!(
+            FramingProtocol::Brontozaur.message_len_size(),
+            BRONTOZAUR_LEN_SIZE
+        );
+        assert_eq!(
+            FramingProtocol::Brontide.max_message_len(),
+            MAX_FRAME_PAYLOAD_SIZE
+        );
+    }
+
+    #[test]
+    fn handshake_invariants() {
+        // Values from BOLT-8
+        assert_eq!(ACT_ONE_LENGTH, 50);
+        assert_eq!(ACT_TWO_LENGTH, 50);
+        assert_eq!(ACT_THREE_LENGTH, 66);
+        assert_eq!(ACT_THREE_LENGTH, ACT_ONE_LENGTH + MAC_SIZE);
+    }
+}
@@ -0,0 +1,499 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! End-to-end diagnostics of a remote node address.
+//!
+//! [`doctor`] runs a sequence of checks against a node address given as a
+//! string (`<node_id>@<host>:<port>`) and reports the outcome of each of
+//! them, so it is possible to see at which stage connecting to the node
+//! fails. Once a check fails, all the following checks are skipped. The
+//! routine never panics and all its network operations are bounded by
+//! [`DoctorOpts::deadline`]. The first check which is due after the deadline
+//! has expired is reported as [`Outcome::TimedOut`] and makes the report
+//! unsuccessful.
+
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use inet2_addr::NodeAddr;
+use secp256k1::rand::thread_rng;
+use secp256k1::SecretKey;
+
+use crate::consts::{ACT_TWO_LENGTH, BRONTIDE_LEN_SIZE};
+use crate::session::noise::ResumableHandshake;
+
+/// Diagnostic check performed by [`doctor`]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum Step {
+    /// Parsing of the node address
+    #[display("address")]
+    Address,
+
+    /// Availability of the address family (IPv4, IPv6) on this host
+    #[display("address-family")]
+    AddressFamily,
+
+    /// Establishing TCP connection
+    #[display("tcp-connect")]
+    TcpConnect,
+
+    /// Noise_XK handshake
+    #[display("handshake")]
+    Handshake,
+
+    /// Exchange of BOLT-1 `init` messages
+    #[display("init")]
+    Init,
+
+    /// Round-trip latency of BOLT-1 `ping` message
+    #[display("ping")]
+    Ping,
+}
+
+/// Outcome of a single diagnostic check
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum Outcome {
+    /// Check has passed
+    #[display("ok")]
+    Ok,
+
+    /// Check was not performed for the given reason
+    #[display("skipped: {0}")]
+    Skipped(String),
+
+    /// Check was not performed since the deadline has expired
+    #[display("timed out")]
+    TimedOut,
+
+    /// Check has failed with the given error
+    #[display("failed: {0}")]
+    Failed(String),
+}
+
+/// Result of a single diagnostic check
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Check {
+    /// Performed check
+    pub step: Step,
+
+    /// Outcome of the check
+    pub outcome: Outcome,
+
+    /// Time spent on the check
+    pub elapsed: Duration,
+}
+
+/// Results of all checks performed by [`doctor`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct DiagnosticReport {
+    /// Node address as it was provided
+    pub target: String,
+
+    /// Problems which do not prevent connecting to the node, like the
+    /// address being given in a non-canonical form
+    pub warnings: Vec<String>,
+
+    /// Results of the checks, in the order they were performed
+    pub checks: Vec<Check>,
+}
+
+impl DiagnosticReport {
+    /// Returns outcome of the given check.
+    pub fn outcome(&self, step: Step) -> Option<&Outcome> {
+        self.checks
+            .iter()
+            .find(|check| check.step == step)
+            .map(|check| &check.outcome)
+    }
+
+    /// Returns the failed check or the check which has hit the deadline, if
+    /// any.
+    pub fn failure(&self) -> Option<&Check> {
+        self.checks.iter().find(|check| {
+            matches!(check.outcome, Outcome::Failed(_) | Outcome::TimedOut)
+        })
+    }
+
+    /// Detects whether none of the checks has failed or timed out.
+    #[inline]
+    pub fn is_ok(&self) -> bool { self.failure().is_none() }
+}
+
+impl Display for DiagnosticReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "diagnostics of {}", self.target)?;
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        for check in &self.checks {
+            writeln!(
+                f,
+                "{}: {} ({:?})",
+                check.step, check.outcome, check.elapsed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Options of [`doctor`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", default)
+)]
+pub struct DoctorOpts {
+    /// Local node key used for the handshake. A random key is used if not
+    /// provided.
+    pub local_key: Option<SecretKey>,
+
+    /// Timeout for establishing TCP connection
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::duration"))]
+    pub connect_timeout: Duration,
+
+    /// Deadline for all the checks
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::duration"))]
+    pub deadline: Duration,
+}
+
+impl Default for DoctorOpts {
+    fn default() -> Self {
+        DoctorOpts {
+            local_key: None,
+            connect_timeout: Duration::from_secs(5),
+            deadline: Duration::from_secs(15),
+        }
+    }
+}
+
+struct Runner {
+    report: DiagnosticReport,
+    started: Instant,
+    deadline: Duration,
+    // Reason for skipping all the following checks
+    skip: Option<String>,
+}
+
+impl Runner {
+    fn run<T>(
+        &mut self,
+        step: Step,
+        check: impl FnOnce(Duration) -> Result<T, String>,
+    ) -> Option<T> {
+        let remaining = self.deadline.saturating_sub(self.started.elapsed());
+        if self.skip.is_none() && remaining == Duration::from_secs(0) {
+            self.push(step, Outcome::TimedOut, Instant::now());
+            self.skip =
+                Some(format!("deadline of {:?} expired", self.deadline));
+            return None;
+        }
+        if let Some(ref reason) = self.skip {
+            self.push(step, Outcome::Skipped(reason.clone()), Instant::now());
+            return None;
+        }
+        let started = Instant::now();
+        match check(remaining) {
+            Ok(val) => {
+                self.push(step, Outcome::Ok, started);
+                Some(val)
+            }
+            Err(err) => {
+                self.push(step, Outcome::Failed(err), started);
+                self.skip = Some(format!("{} check has failed", step));
+                None
+            }
+        }
+    }
+
+    fn skip(&mut self, step: Step, reason: &str) {
+        let reason = self.skip.clone().unwrap_or_else(|| reason.to_owned());
+        self.push(step, Outcome::Skipped(reason), Instant::now());
+    }
+
+    fn push(&mut self, step: Step, outcome: Outcome, started: Instant) {
+        self.report.checks.push(Check {
+            step,
+            outcome,
+            elapsed: started.elapsed(),
+        });
+    }
+}
+
+/// Checks whether it is possible to connect to the remote node and returns
+/// report with the results of each check; see the [module](self)
+/// documentation.
+pub fn doctor(target: &str, opts: &DoctorOpts) -> DiagnosticReport {
+    let mut runner = Runner {
+        report: DiagnosticReport {
+            target: target.to_owned(),
+            warnings: vec![],
+            checks: vec![],
+        },
+        started: Instant::now(),
+        deadline: opts.deadline,
+        skip: None,
+    };
+
+    let node = runner.run(Step::Address, |_| {
+        NodeAddr::from_str(target.trim()).map_err(|err| err.to_string())
+    });
+    if let Some(node) = node {
+        let canonical = node.to_string();
+        if canonical != target {
+            runner.report.warnings.push(format!(
+                "address is not in canonical form, which is {}",
+                canonical
+            ));
+        }
+    }
+
+    let socket_addr = runner.run(Step::AddressFamily, |_| {
+        let node = node.expect("address check has passed");
+        let socket_addr = SocketAddr::try_from(node.addr).map_err(|_| {
+            String::from("connections over Tor are not supported yet")
+        })?;
+        let unspecified: SocketAddr = match socket_addr {
+            SocketAddr::V4(_) => ([0u8; 4], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        UdpSocket::bind(unspecified).map_err(|err| {
+            format!("address family is not available on this host: {}", err)
+        })?;
+        Ok(socket_addr)
+    });
+
+    let stream = runner.run(Step::TcpConnect, |remaining| {
+        let socket_addr = socket_addr.expect("address family check has passed");
+        let timeout = opts.connect_timeout.min(remaining);
+        TcpStream::connect_timeout(&socket_addr, timeout)
+            .map_err(|err| err.to_string())
+    });
+
+    runner.run(Step::Handshake, |remaining| {
+        let node = node.expect("address check has passed");
+        let stream = stream.expect("TCP connection is established");
+        let local_key = opts
+            .local_key
+            .unwrap_or_else(|| SecretKey::new(&mut thread_rng()));
+        handshake(stream, local_key, node, remaining)
+    });
+
+    let reason = "not implemented by this library";
+    runner.skip(Step::Init, reason);
+    runner.skip(Step::Ping, reason);
+
+    runner.report
+}
+
+// Runs handshake manually, so it is known which act has failed
+fn handshake(
+    mut stream: TcpStream,
+    local_key: SecretKey,
+    node: NodeAddr,
+    remaining: Duration,
+) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(remaining))
+        .and_then(|_| stream.set_write_timeout(Some(remaining)))
+        .map_err(|err| err.to_string())?;
+
+    let ephemeral_key = SecretKey::new(&mut thread_rng());
+    let mut handshake = ResumableHandshake::<BRONTIDE_LEN_SIZE>::initiator(
+        &local_key,
+        &node.public_key(),
+        &ephemeral_key,
+    )
+    .map_err(|err| format!("unable to compose act one: {}", err))?;
+
+    if let Some(act) = handshake.next_act() {
+        stream
+            .write_all(&act)
+            .map_err(|err| format!("unable to send act one: {}", err))?;
+    }
+
+    let mut act_two = [0u8; ACT_TWO_LENGTH];
+    stream.read_exact(&mut act_two).map_err(|err| {
+        format!(
+            "act two was not received ({}); the remote node may have rejected \
+             act one, which happens if the node id is wrong",
+            err
+        )
+    })?;
+    handshake
+        .push_bytes(&act_two)
+        .map_err(|err| format!("invalid act two: {}", err))?;
+
+    if let Some(act) = handshake.next_act() {
+        stream
+            .write_all(&act)
+            .map_err(|err| format!("unable to send act three: {}", err))?;
+    }
+    if !handshake.is_complete() {
+        return Err(String::from("handshake has not completed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use inet2_addr::LocalNode;
+
+    use super::*;
+    use crate::session::BrontideSession;
+
+    fn outcomes(report: &DiagnosticReport) -> Vec<(Step, bool, bool)> {
+        report
+            .checks
+            .iter()
+            .map(|check| {
+                (
+                    check.step,
+                    check.outcome == Outcome::Ok,
+                    matches!(check.outcome, Outcome::Skipped(_)),
+                )
+            })
+            .collect()
+    }
+
+    fn listen(node: &LocalNode) -> (u16, std::thread::JoinHandle<bool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let key = node.private_key();
+        let responder = std::thread::spawn(move || {
+            BrontideSession::accept(key, &listener).is_ok()
+        });
+        (port, responder)
+    }
+
+    #[test]
+    fn all_green() {
+        let secp = secp256k1::Secp256k1::new();
+        let remote = LocalNode::new(&secp);
+        let (port, responder) = listen(&remote);
+
+        let target = format!("{}@127.0.0.1:{}", remote.node_id(), port);
+        let report = doctor(&target, &DoctorOpts::default());
+        assert!(report.is_ok(), "{}", report);
+        assert!(report.warnings.is_empty());
+        assert_eq!(outcomes(&report), vec![
+            (Step::Address, true, false),
+            (Step::AddressFamily, true, false),
+            (Step::TcpConnect, true, false),
+            (Step::Handshake, true, false),
+            (Step::Init, false, true),
+            (Step::Ping, false, true),
+        ]);
+        assert!(responder.join().unwrap());
+
+        // Non-canonical form gives a warning
+        let (port, responder) = listen(&remote);
+        let target = format!(" {}@127.0.0.1:{}", remote.node_id(), port);
+        let report = doctor(&target, &DoctorOpts::default());
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(responder.join().unwrap());
+    }
+
+    #[test]
+    fn invalid_address() {
+        let report = doctor("not-an-address", &DoctorOpts::default());
+        assert_eq!(report.failure().unwrap().step, Step::Address);
+        assert_eq!(
+            report.outcome(Step::Handshake),
+            Some(&Outcome::Skipped(s!("address check has failed")))
+        );
+        assert_eq!(report.checks.len(), 6);
+    }
+
+    #[test]
+    fn closed_port() {
+        let secp = secp256k1::Secp256k1::new();
+        let remote = LocalNode::new(&secp);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let target = format!("{}@127.0.0.1:{}", remote.node_id(), port);
+        let report = doctor(&target, &DoctorOpts::default());
+        assert_eq!(report.failure().unwrap().step, Step::TcpConnect);
+        assert_eq!(outcomes(&report)[..3], [
+            (Step::Address, true, false),
+            (Step::AddressFamily, true, false),
+            (Step::TcpConnect, false, false),
+        ]);
+        assert!(matches!(
+            report.outcome(Step::Handshake),
+            Some(Outcome::Skipped(_))
+        ));
+    }
+
+    #[test]
+    fn wrong_node_id() {
+        let secp = secp256k1::Secp256k1::new();
+        let remote = LocalNode::new(&secp);
+        let other = LocalNode::new(&secp);
+        let (port, responder) = listen(&remote);
+
+        let target = format!("{}@127.0.0.1:{}", other.node_id(), port);
+        let report = doctor(&target, &DoctorOpts::default());
+        let failure = report.failure().unwrap();
+        assert_eq!(failure.step, Step::Handshake);
+        let err = failure.outcome.to_string();
+        assert!(err.contains("act two"), "{}", report);
+        assert!(!responder.join().unwrap());
+    }
+
+    #[test]
+    fn expired_deadline() {
+        let secp = secp256k1::Secp256k1::new();
+        let remote = LocalNode::new(&secp);
+        let target = format!("{}@127.0.0.1:9735", remote.node_id());
+        let report = doctor(&target, &DoctorOpts {
+            deadline: Duration::from_secs(0),
+            ..DoctorOpts::default()
+        });
+        assert!(!report.is_ok());
+        assert_eq!(report.checks[0].outcome, Outcome::TimedOut);
+        assert_eq!(report.failure(), Some(&report.checks[0]));
+        assert!(report.checks[1..]
+            .iter()
+            .all(|check| matches!(check.outcome, Outcome::Skipped(_))));
+    }
+}
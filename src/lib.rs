@@ -42,10 +42,24 @@ pub extern crate inet2_addr as addr;
 #[cfg(feature = "derive")]
 pub use inet2_derive::Api;
 
+pub mod config;
+pub mod consts;
+#[cfg(feature = "keygen")]
+pub mod doctor;
+pub mod prelude;
 pub mod presentation;
+pub mod privacy;
+mod rng;
+#[cfg(feature = "zmq")]
+pub mod rpc;
 pub mod session;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod transport;
 
+pub use consts::{BRONTIDE_MSG_MAX_LEN, BRONTOZAUR_MSG_MAX_LEN};
+#[cfg(feature = "keygen")]
+pub use doctor::doctor;
 pub use presentation::{
     sphinx, tlv, CreateUnmarshaller, Payload, TypeId, TypedEnum,
     UnknownTypeError, Unmarshall, UnmarshallFn, Unmarshaller,
@@ -60,11 +74,6 @@ pub use transport::{DuplexConnection, RoutedFrame};
 #[cfg(feature = "zmq")]
 pub use transport::{ZmqConnectionType, ZmqSocketType};
 
-/// Maximum message (packet payload) length for Brontide protocol
-pub const BRONTIDE_MSG_MAX_LEN: usize = u16::MAX as usize;
-/// Maximum message (packet payload) length for Brontozaur protocol
-pub const BRONTOZAUR_MSG_MAX_LEN: usize = 0xFFFFFF;
-
 /// Trait used by different address types (transport-, session- and
 /// presentation-based) for getting scheme part of the URL
 pub trait UrlString {
@@ -0,0 +1,46 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Commonly used types of the library, intended for glob import:
+//!
+//! ```
+//! use internet2::prelude::*;
+//! ```
+//!
+//! Items are re-exported here under stable names regardless of the module
+//! in which they are defined, so downstream code importing them from the
+//! prelude is not affected by refactoring of the module tree. Error types
+//! from different layers are re-exported with the layer prefix.
+
+pub use inet2_addr::{
+    AddrParseError, InetAddr, InetSocketAddr, LocalNode, NodeAddr,
+    NodeAddrParseError, NodeId, PartialNodeAddr, PartialSocketAddr, ServerAddr,
+    ServiceAddr,
+};
+
+pub use crate::presentation::{
+    CreateUnmarshaller, Error as PresentationError, Payload, TypeId, TypedEnum,
+    UnknownTypeError, Unmarshall, Unmarshaller,
+};
+pub use crate::session::{
+    BrontideSession, BrontozaurSession, HandshakeError, RecvMessage,
+    SendMessage, SendRecvMessage, Session, SharedSession, Split, VersionError,
+    VersionRange, VersionedSession,
+};
+#[cfg(feature = "zmq")]
+pub use crate::session::{LocalSession, RpcSession};
+pub use crate::transport::{
+    DuplexConnection, Error as TransportError, RoutedFrame,
+};
+#[cfg(feature = "zmq")]
+pub use crate::transport::{ZmqConnectionType, ZmqSocketType};
@@ -0,0 +1,85 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Content hashes of presentation-level messages.
+//!
+//! Digests are computed over the canonical encoded message (type id followed
+//! by the payload), excluding any transport framing and encryption, so the
+//! same message always has the same digest regardless of the session it was
+//! sent over.
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+
+/// Tag used for domain separation in [`tagged_frame_digest`]
+pub const MESSAGE_DIGEST_TAG: &str = "internet2:message";
+
+/// Computes SHA256 digest of the encoded message.
+pub fn frame_digest(msg: &[u8]) -> [u8; 32] {
+    sha256::Hash::hash(msg).into_inner()
+}
+
+/// Computes BIP-340 style tagged SHA256 digest of the encoded message, i.e.
+/// `SHA256(SHA256(tag) || SHA256(tag) || msg)` with [`MESSAGE_DIGEST_TAG`].
+/// Should be used when message digests may be mixed with other hashes of
+/// the same data.
+pub fn tagged_frame_digest(msg: &[u8]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(MESSAGE_DIGEST_TAG.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(msg);
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::hex::ToHex;
+
+    use super::*;
+    use crate::presentation::{EncodingType, Payload, TypeId};
+
+    // Test vectors pin the digest definition: changing any of them breaks
+    // compatibility with previously stored digests.
+    #[test]
+    fn test_vectors() {
+        assert_eq!(
+            frame_digest(&[]).to_hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            tagged_frame_digest(&[]).to_hex(),
+            "c96444d1f79d20a5330922959b3dec0cd9151707ea212206495ab1e50826e26b"
+        );
+
+        // Lightning `ping` message with `num_pong_bytes = 4` and no padding
+        let ping = [0x00, 0x12, 0x00, 0x04, 0x00, 0x00];
+        assert_eq!(
+            frame_digest(&ping).to_hex(),
+            "46e67448e6b3c82b086b1d769d540f2fe15c9c67ca8de3b302a8db0947d8b345"
+        );
+        assert_eq!(
+            tagged_frame_digest(&ping).to_hex(),
+            "ae9e8a7e2247d35e7a0af2b06a9707e4ca9802f39c20bbe2ff0021afb1570d9c"
+        );
+
+        let payload = Payload {
+            type_id: TypeId::from(18),
+            payload: vec![0x00, 0x04, 0x00, 0x00],
+        };
+        assert_eq!(
+            payload.digest(EncodingType::Lightning),
+            frame_digest(&ping)
+        );
+        assert_ne!(payload.digest(EncodingType::Strict), frame_digest(&ping));
+    }
+}
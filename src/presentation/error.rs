@@ -70,6 +70,13 @@ pub enum Error {
     /// invalid length of TLV record inside LNP message
     TlvRecordInvalidLen,
 
+    /// LNP message nests composite data deeper than the allowed maximum of
+    /// {0} levels
+    MaxDepthExceeded(usize),
+
+    /// decoding LNP message requires more than {0} bytes of work budget
+    WorkBudgetExceeded(usize),
+
     /// Transport-level LNP error
     #[display(inner)]
     #[from]
@@ -94,6 +101,8 @@ impl From<Error> for u8 {
             Error::TlvStreamDuplicateItem => 0x33,
             Error::TlvRecordEvenType => 0x34,
             Error::TlvRecordInvalidLen => 0x35,
+            Error::MaxDepthExceeded(_) => 0x36,
+            Error::WorkBudgetExceeded(_) => 0x37,
             Error::Transport(_) => 0xF0,
         }
     }
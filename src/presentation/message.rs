@@ -12,7 +12,8 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::any::Any;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::sync::Arc;
 
@@ -20,17 +21,86 @@ use amplify::AsAny;
 use lightning_encoding::{self, LightningDecode, LightningEncode};
 use strict_encoding::{self, StrictEncode};
 
-use super::{tlv, EvenOdd, UnknownTypeError};
+use super::{frame_digest, tlv, EncodingType, EvenOdd, UnknownTypeError};
 
 /// Message type field value
 #[derive(
-    Wrapper, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
-    Display, Debug, From
+    Wrapper, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug,
+    From
 )]
-#[display(inner)]
 #[wrapper(LowerHex, UpperHex, Octal, FromStr)]
 pub struct TypeId(u16);
 
+impl TypeId {
+    /// Constructs message type id from its numeric value
+    #[inline]
+    pub const fn new(id: u16) -> Self { TypeId(id) }
+
+    /// Returns numeric value of the message type id
+    #[inline]
+    pub const fn to_u16(self) -> u16 { self.0 }
+
+    /// Detects whether the message type is even, i.e. the message must be
+    /// understood by the receiving peer, which otherwise has to fail the
+    /// connection (BOLT-1 "it's ok to be odd" rule).
+    #[inline]
+    pub const fn is_even(self) -> bool { self.0 % 2 == 0 }
+
+    /// Detects whether the message type is odd, i.e. the message may be
+    /// ignored by the peers not knowing it.
+    #[inline]
+    pub const fn is_odd(self) -> bool { self.0 % 2 == 1 }
+
+    /// Returns type id `n` positions after this one, or [`None`] if it does
+    /// not fit into the message type id range.
+    #[inline]
+    pub const fn checked_add(self, n: u16) -> Option<TypeId> {
+        match self.0.checked_add(n) {
+            Some(id) => Some(TypeId(id)),
+            None => None,
+        }
+    }
+}
+
+/// Formats message type id as a decimal number; the alternate form (`{:#}`)
+/// adds hexadecimal representation, i.e. `18 (0x0012)`.
+impl Display for TypeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{} ({:#06x})", self.0, self.0)
+        } else {
+            Display::fmt(&self.0, f)
+        }
+    }
+}
+
+/// Error converting integer value which exceeds the message type id range
+/// into [`TypeId`]
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error
+)]
+#[display("value {0} exceeds maximum message type id 65535")]
+pub struct TypeIdOverflow(pub u64);
+
+impl TryFrom<u64> for TypeId {
+    type Error = TypeIdOverflow;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        u16::try_from(value)
+            .map(TypeId)
+            .map_err(|_| TypeIdOverflow(value))
+    }
+}
+
+impl TryFrom<usize> for TypeId {
+    type Error = TypeIdOverflow;
+
+    #[inline]
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        TypeId::try_from(value as u64)
+    }
+}
+
 impl strict_encoding::Strategy for TypeId {
     type Strategy = strict_encoding::strategies::Wrapped;
 }
@@ -91,6 +161,21 @@ pub struct Payload {
     pub payload: Vec<u8>,
 }
 
+impl Payload {
+    /// Computes [`frame_digest`] of the message encoded with the given
+    /// encoding.
+    pub fn digest(&self, encoding: EncodingType) -> [u8; 32] {
+        let type_id = match encoding {
+            EncodingType::Lightning => self.type_id.0.to_be_bytes(),
+            EncodingType::Strict => self.type_id.0.to_le_bytes(),
+        };
+        let mut msg = Vec::with_capacity(2 + self.payload.len());
+        msg.extend(type_id);
+        msg.extend(&self.payload);
+        frame_digest(&msg)
+    }
+}
+
 impl Extract for Payload {
     fn get_type(&self) -> TypeId { self.type_id }
 
@@ -130,6 +215,10 @@ where
     fn get_type(&self) -> TypeId;
     fn get_payload(&self) -> Vec<u8>;
     fn serialize(&self) -> Vec<u8>;
+
+    /// Computes [`frame_digest`] of the serialized message.
+    #[inline]
+    fn digest(&self) -> [u8; 32] { frame_digest(&self.serialize()) }
 }
 
 impl<T> From<T> for Payload
@@ -143,3 +232,69 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use strict_encoding::{strict_deserialize, strict_serialize};
+
+    use super::*;
+
+    #[test]
+    fn parity() {
+        assert!(TypeId::new(16).is_even());
+        assert!(!TypeId::new(16).is_odd());
+        assert!(TypeId::new(17).is_odd());
+        assert!(TypeId::new(u16::MAX).is_odd());
+        assert!(TypeId::default().is_even());
+        // Helpers agree with the generic even/odd rule
+        for id in [0u16, 1, 18, 19, 0x8000, u16::MAX] {
+            let type_id = TypeId::new(id);
+            assert_eq!(type_id.is_even(), EvenOdd::is_even(&type_id));
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        const INIT: TypeId = TypeId::new(16);
+        assert_eq!(INIT.to_u16(), 16);
+        assert_eq!(INIT.checked_add(3), Some(TypeId::new(19)));
+        assert_eq!(
+            TypeId::new(u16::MAX).checked_add(0),
+            Some(TypeId::new(u16::MAX))
+        );
+        assert_eq!(TypeId::new(u16::MAX).checked_add(1), None);
+        assert!(TypeId::new(16) < TypeId::new(17));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(TypeId::try_from(18u64), Ok(TypeId::new(18)));
+        assert_eq!(TypeId::try_from(0xFFFFusize), Ok(TypeId::new(u16::MAX)));
+        assert_eq!(TypeId::try_from(0x10000u64), Err(TypeIdOverflow(0x10000)));
+        assert_eq!(
+            TypeIdOverflow(0x10000).to_string(),
+            "value 65536 exceeds maximum message type id 65535"
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(TypeId::new(18).to_string(), "18");
+        assert_eq!(format!("{:#}", TypeId::new(18)), "18 (0x0012)");
+        assert_eq!(format!("{:#06x}", TypeId::new(0xfd)), "0x00fd");
+    }
+
+    #[test]
+    fn encoding() {
+        let type_id = TypeId::new(0x0102);
+        assert_eq!(strict_serialize(&type_id).unwrap(), vec![0x02, 0x01]);
+        assert_eq!(
+            strict_deserialize::<TypeId>(&[0x02, 0x01]).unwrap(),
+            type_id
+        );
+        let mut data = vec![];
+        type_id.lightning_encode(&mut data).unwrap();
+        assert_eq!(data, vec![0x01, 0x02]);
+        assert_eq!(TypeId::lightning_decode(&data[..]).unwrap(), type_id);
+    }
+}
@@ -11,6 +11,7 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+mod digest;
 mod error;
 pub mod message;
 pub mod sphinx;
@@ -20,10 +21,13 @@ mod unmarshall;
 use std::ops::Rem;
 
 use amplify::Wrapper;
+pub use digest::{frame_digest, tagged_frame_digest, MESSAGE_DIGEST_TAG};
 pub use error::{Error, UnknownTypeError};
-pub use message::{Payload, TypeId, TypedEnum};
+pub use message::{Payload, TypeId, TypeIdOverflow, TypedEnum};
 pub use unmarshall::{
-    CreateUnmarshaller, Unmarshall, UnmarshallFn, Unmarshaller,
+    reserve_work, CreateUnmarshaller, DecodeLimits, DepthGuard,
+    DuplicateTypeError, LimitExceeded, TypeInfo, Unmarshall, UnmarshallFn,
+    Unmarshaller, DEFAULT_MAX_DEPTH, DEFAULT_WORK_BUDGET,
 };
 
 pub trait EvenOdd
@@ -12,16 +12,16 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 use std::any::Any;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::io;
 use std::marker::PhantomData;
 use std::sync::Arc;
 
-use amplify::Wrapper;
 use lightning_encoding::LightningDecode;
 use strict_encoding::{self, StrictDecode};
 
-use super::{EncodingType, Error, EvenOdd, Payload, TypeId, TypedEnum};
+use super::{EncodingType, Error, Payload, TypeId, TypedEnum};
 
 pub trait Unmarshall {
     type Data;
@@ -35,16 +35,217 @@ pub trait Unmarshall {
 pub type UnmarshallFn<E> =
     fn(reader: &mut dyn io::Read) -> Result<Arc<dyn Any>, E>;
 
+/// Default maximum depth of nested composite data, see [`DecodeLimits`]
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// Default work budget in bytes, see [`DecodeLimits`]
+pub const DEFAULT_WORK_BUDGET: usize = 0x100_0000;
+
+/// Limits applied by [`Unmarshaller`] to decoding of a single message.
+///
+/// The limits are enforced only for decoders reporting their progress to the
+/// decoding context with [`DepthGuard::enter`] (when decoding nested
+/// composite data) and [`reserve_work`] (before allocating memory for data
+/// with a length prefix). Decoders of recursive and deeply nested
+/// structures must use them to be protected from stack overflows and
+/// excessive allocations.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct DecodeLimits {
+    /// Maximum number of simultaneously entered [`DepthGuard`]s
+    pub max_depth: usize,
+
+    /// Maximum number of bytes which can be reserved with [`reserve_work`]
+    /// during decoding of a single message
+    pub work_budget: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_depth: DEFAULT_MAX_DEPTH,
+            work_budget: DEFAULT_WORK_BUDGET,
+        }
+    }
+}
+
+/// Errors reported by the decoding context when [`DecodeLimits`] are
+/// exceeded
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum LimitExceeded {
+    /// maximum decoding depth of {0} levels is exceeded
+    Depth(usize),
+
+    /// decoding work budget of {0} bytes is exceeded
+    WorkBudget(usize),
+}
+
+impl From<LimitExceeded> for Error {
+    fn from(err: LimitExceeded) -> Self {
+        match err {
+            LimitExceeded::Depth(max) => Error::MaxDepthExceeded(max),
+            LimitExceeded::WorkBudget(max) => Error::WorkBudgetExceeded(max),
+        }
+    }
+}
+
+impl From<LimitExceeded> for strict_encoding::Error {
+    fn from(err: LimitExceeded) -> Self {
+        strict_encoding::Error::DataIntegrityError(err.to_string())
+    }
+}
+
+struct DecodeContext {
+    limits: DecodeLimits,
+    depth: usize,
+    work: usize,
+    exceeded: Option<LimitExceeded>,
+}
+
+impl DecodeContext {
+    fn fail(&mut self, err: LimitExceeded) -> LimitExceeded {
+        *self.exceeded.get_or_insert(err)
+    }
+}
+
+thread_local! {
+    static DECODE_CONTEXT: RefCell<Option<DecodeContext>> = RefCell::new(None);
+}
+
+fn with_context(
+    f: impl FnOnce(&mut DecodeContext) -> Result<(), LimitExceeded>,
+) -> Result<(), LimitExceeded> {
+    DECODE_CONTEXT.with(|context| match &mut *context.borrow_mut() {
+        Some(context) => f(context),
+        None => Ok(()),
+    })
+}
+
+/// Decoding context installed by [`Unmarshaller`] for the duration of a
+/// message decoding; restores the previous context when dropped.
+struct ContextScope(Option<DecodeContext>);
+
+impl ContextScope {
+    fn install(limits: DecodeLimits) -> Self {
+        let context = DecodeContext {
+            limits,
+            depth: 0,
+            work: 0,
+            exceeded: None,
+        };
+        ContextScope(DECODE_CONTEXT.with(|c| c.replace(Some(context))))
+    }
+
+    fn exceeded(&self) -> Option<LimitExceeded> {
+        DECODE_CONTEXT
+            .with(|c| c.borrow().as_ref().and_then(|context| context.exceeded))
+    }
+}
+
+impl Drop for ContextScope {
+    fn drop(&mut self) {
+        let prev = self.0.take();
+        DECODE_CONTEXT.with(|c| *c.borrow_mut() = prev);
+    }
+}
+
+/// Guard of a single nesting level of composite data decoding.
+///
+/// Decoders of nested data must hold the guard while decoding the nested
+/// items. Outside of [`Unmarshaller`] no limits are applied.
+#[must_use]
+pub struct DepthGuard(bool);
+
+impl DepthGuard {
+    /// Enters next nesting level.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`LimitExceeded::Depth`] if the maximum depth is already
+    /// reached.
+    pub fn enter() -> Result<DepthGuard, LimitExceeded> {
+        let mut entered = false;
+        with_context(|context| {
+            if context.depth >= context.limits.max_depth {
+                let max = context.limits.max_depth;
+                return Err(context.fail(LimitExceeded::Depth(max)));
+            }
+            context.depth += 1;
+            entered = true;
+            Ok(())
+        })?;
+        Ok(DepthGuard(entered))
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        if self.0 {
+            let _ = with_context(|context| {
+                context.depth = context.depth.saturating_sub(1);
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Charges `len` bytes against the work budget of the message being decoded.
+/// Must be called by decoders before allocating memory for data with a
+/// length prefix. Outside of [`Unmarshaller`] no limits are applied.
+///
+/// # Errors
+///
+/// Returns [`LimitExceeded::WorkBudget`] if the total amount of reserved
+/// bytes exceeds the work budget.
+pub fn reserve_work(len: usize) -> Result<(), LimitExceeded> {
+    with_context(|context| {
+        context.work = context.work.saturating_add(len);
+        if context.work > context.limits.work_budget {
+            let max = context.limits.work_budget;
+            return Err(context.fail(LimitExceeded::WorkBudget(max)));
+        }
+        Ok(())
+    })
+}
+
 pub trait CreateUnmarshaller: Sized + TypedEnum {
     fn create_unmarshaller() -> Unmarshaller<Self>;
 }
 
+/// Information about a message type registered in [`Unmarshaller`]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TypeInfo {
+    /// Message type id
+    pub type_id: TypeId,
+
+    /// Name of the message type, if provided at registration
+    pub name: Option<&'static str>,
+
+    /// Name of the message set the type originates from, if provided at
+    /// registration
+    pub message_set: Option<&'static str>,
+}
+
+impl TypeInfo {
+    /// Detects whether the message type is odd, i.e. it may be ignored by
+    /// the peers not knowing it.
+    #[inline]
+    pub fn is_odd(&self) -> bool { self.type_id.is_odd() }
+}
+
+/// Error merging unmarshallers which have message types in common
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display("message type {0} is registered in both unmarshallers")]
+pub struct DuplicateTypeError(pub TypeId);
+
 pub struct Unmarshaller<T>
 where
     T: TypedEnum,
 {
     known_types: BTreeMap<TypeId, UnmarshallFn<Error>>,
+    type_names: BTreeMap<TypeId, (&'static str, &'static str)>,
     encoding: EncodingType,
+    limits: DecodeLimits,
     _phantom: PhantomData<T>,
 }
 
@@ -55,10 +256,28 @@ where
     type Data = Arc<T>;
     type Error = Error;
 
+    /// Decodes message applying [`DecodeLimits`] of the unmarshaller. If a
+    /// limit is exceeded, [`Error::MaxDepthExceeded`] or
+    /// [`Error::WorkBudgetExceeded`] is returned regardless of how the decoder
+    /// has reported the failure.
     fn unmarshall(
         &self,
-        mut reader: impl io::Read,
+        reader: impl io::Read,
     ) -> Result<Self::Data, Self::Error> {
+        let scope = ContextScope::install(self.limits);
+        let data = self.decode(reader);
+        match scope.exceeded() {
+            Some(err) => Err(err.into()),
+            None => data,
+        }
+    }
+}
+
+impl<T> Unmarshaller<T>
+where
+    T: TypedEnum,
+{
+    fn decode(&self, mut reader: impl io::Read) -> Result<Arc<T>, Error> {
         let type_id = match self.encoding {
             EncodingType::Lightning => TypeId::lightning_decode(&mut reader)?,
             EncodingType::Strict => TypeId::strict_decode(&mut reader)?,
@@ -78,12 +297,7 @@ where
             }),
         }
     }
-}
 
-impl<T> Unmarshaller<T>
-where
-    T: TypedEnum,
-{
     pub fn new(
         known_types: BTreeMap<u16, UnmarshallFn<Error>>,
         encoding: EncodingType,
@@ -91,10 +305,302 @@ where
         Self {
             known_types: known_types
                 .into_iter()
-                .map(|(t, f)| (TypeId::from_inner(t), f))
+                .map(|(t, f)| (TypeId::new(t), f))
                 .collect(),
+            type_names: BTreeMap::new(),
             encoding,
+            limits: DecodeLimits::default(),
             _phantom: PhantomData,
         }
     }
+
+    /// Replaces decoding limits of the unmarshaller.
+    #[inline]
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns decoding limits of the unmarshaller.
+    #[inline]
+    pub fn limits(&self) -> DecodeLimits { self.limits }
+
+    /// Assigns names to the registered message types and marks them as
+    /// originating from `message_set`. Names for the types which are not
+    /// registered are ignored.
+    pub fn with_names(
+        mut self,
+        message_set: &'static str,
+        names: impl IntoIterator<Item = (u16, &'static str)>,
+    ) -> Self {
+        for (type_id, name) in names {
+            let type_id = TypeId::new(type_id);
+            if self.known_types.contains_key(&type_id) {
+                self.type_names.insert(type_id, (name, message_set));
+            }
+        }
+        self
+    }
+
+    /// Adds all message types registered in `other` unmarshaller, keeping
+    /// their names. The resulting unmarshaller uses encoding and limits of
+    /// `self`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DuplicateTypeError`] if some message type is registered in
+    /// both unmarshallers.
+    pub fn merge<U>(
+        mut self,
+        other: Unmarshaller<U>,
+    ) -> Result<Self, DuplicateTypeError>
+    where
+        U: TypedEnum,
+    {
+        if let Some(type_id) = other
+            .known_types
+            .keys()
+            .find(|type_id| self.known_types.contains_key(type_id))
+        {
+            return Err(DuplicateTypeError(*type_id));
+        }
+        self.known_types.extend(other.known_types);
+        self.type_names.extend(other.type_names);
+        Ok(self)
+    }
+
+    /// Detects whether the message type is registered in the unmarshaller.
+    #[inline]
+    pub fn knows(&self, type_id: TypeId) -> bool {
+        self.known_types.contains_key(&type_id)
+    }
+
+    /// Iterates over all registered message types, sorted by their type id.
+    pub fn registered_types(&self) -> impl Iterator<Item = TypeInfo> + '_ {
+        self.known_types.keys().map(move |type_id| {
+            let names = self.type_names.get(type_id);
+            TypeInfo {
+                type_id: *type_id,
+                name: names.map(|(name, _)| *name),
+                message_set: names.map(|(_, set)| *set),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::Wrapper;
+    use strict_encoding::{strict_serialize, StrictEncode};
+
+    use super::*;
+    use crate::UnknownTypeError;
+
+    /// Recursive structure with a decoder reporting to the decoding context
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Nested(Vec<Nested>);
+
+    impl StrictEncode for Nested {
+        fn strict_encode<E: io::Write>(
+            &self,
+            mut e: E,
+        ) -> Result<usize, strict_encoding::Error> {
+            let mut len = (self.0.len() as u16).strict_encode(&mut e)?;
+            for item in &self.0 {
+                len += item.strict_encode(&mut e)?;
+            }
+            Ok(len)
+        }
+    }
+
+    impl StrictDecode for Nested {
+        fn strict_decode<D: io::Read>(
+            mut d: D,
+        ) -> Result<Self, strict_encoding::Error> {
+            let _guard = DepthGuard::enter()?;
+            let count = u16::strict_decode(&mut d)? as usize;
+            reserve_work(count * std::mem::size_of::<Nested>())?;
+            let mut items = Vec::with_capacity(count);
+            for _ in 0..count {
+                items.push(Nested::strict_decode(&mut d)?);
+            }
+            Ok(Nested(items))
+        }
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    enum Msg {
+        Nested(Nested),
+    }
+
+    impl TypedEnum for Msg {
+        fn try_from_type(
+            type_id: TypeId,
+            data: &dyn Any,
+        ) -> Result<Self, UnknownTypeError> {
+            match type_id.into_inner() {
+                1 => data
+                    .downcast_ref::<Nested>()
+                    .cloned()
+                    .map(Msg::Nested)
+                    .ok_or(UnknownTypeError),
+                _ => Err(UnknownTypeError),
+            }
+        }
+
+        fn get_type(&self) -> TypeId { TypeId::from_inner(1) }
+
+        fn get_payload(&self) -> Vec<u8> {
+            let Msg::Nested(nested) = self;
+            strict_serialize(nested).expect("memory encoders do not fail")
+        }
+
+        fn serialize(&self) -> Vec<u8> {
+            let mut data = strict_serialize(&self.get_type())
+                .expect("memory encoders do not fail");
+            data.extend(self.get_payload());
+            data
+        }
+    }
+
+    fn parse_nested(reader: &mut dyn io::Read) -> Result<Arc<dyn Any>, Error> {
+        Ok(Arc::new(Nested::strict_decode(reader)?))
+    }
+
+    fn unmarshaller() -> Unmarshaller<Msg> {
+        let mut known_types = BTreeMap::new();
+        known_types.insert(1u16, parse_nested as UnmarshallFn<_>);
+        Unmarshaller::new(known_types, EncodingType::Strict)
+    }
+
+    /// Message with a chain of `depth` items having a single child each,
+    /// constructed without recursion
+    fn chain(depth: usize) -> Vec<u8> {
+        // Message type id
+        let mut data = vec![0x01, 0x00];
+        for _ in 0..depth {
+            data.extend([0x01, 0x00]);
+        }
+        data.extend([0x00, 0x00]);
+        data
+    }
+
+    fn parse_empty(_: &mut dyn io::Read) -> Result<Arc<dyn Any>, Error> {
+        Ok(Arc::new(()))
+    }
+
+    fn message_set(types: &[u16]) -> Unmarshaller<Msg> {
+        let known_types = types
+            .iter()
+            .map(|type_id| (*type_id, parse_empty as UnmarshallFn<_>))
+            .collect();
+        Unmarshaller::new(known_types, EncodingType::Strict)
+    }
+
+    #[test]
+    fn registered_types() {
+        let core = message_set(&[0x10, 0x03]).with_names("Core", [
+            (0x03, "Ping"),
+            (0x10, "Init"),
+            // Not registered, ignored
+            (0x20, "Unknown"),
+        ]);
+        let ext = message_set(&[0x0a, 0x21, 0x01])
+            .with_names("Extension", [(0x0a, "Status"), (0x21, "Notify")]);
+        let unmarshaller = core.merge(ext).unwrap();
+
+        let info = |type_id: u16, name, set| TypeInfo {
+            type_id: TypeId::from_inner(type_id),
+            name,
+            message_set: set,
+        };
+        assert_eq!(unmarshaller.registered_types().collect::<Vec<_>>(), vec![
+            info(0x01, None, None),
+            info(0x03, Some("Ping"), Some("Core")),
+            info(0x0a, Some("Status"), Some("Extension")),
+            info(0x10, Some("Init"), Some("Core")),
+            info(0x21, Some("Notify"), Some("Extension")),
+        ]);
+        assert_eq!(
+            unmarshaller
+                .registered_types()
+                .map(|info| info.is_odd())
+                .collect::<Vec<_>>(),
+            vec![true, true, false, false, true]
+        );
+
+        assert!(unmarshaller.knows(TypeId::from_inner(0x0a)));
+        assert!(unmarshaller.knows(TypeId::from_inner(0x10)));
+        assert!(!unmarshaller.knows(TypeId::from_inner(0x20)));
+
+        assert_eq!(
+            unmarshaller.merge(message_set(&[0x05, 0x10])).err(),
+            Some(DuplicateTypeError(TypeId::from_inner(0x10)))
+        );
+    }
+
+    #[test]
+    fn nested_roundtrip() {
+        let msg = Msg::Nested(Nested(vec![
+            Nested(vec![]),
+            Nested(vec![Nested(vec![])]),
+        ]));
+        let data = msg.serialize();
+        assert_eq!(*unmarshaller().unmarshall(&data[..]).unwrap(), msg);
+    }
+
+    #[test]
+    fn max_depth() {
+        let unmarshaller = unmarshaller();
+        assert_eq!(unmarshaller.limits(), DecodeLimits::default());
+
+        // Top-level item and its children occupy the whole depth
+        assert!(unmarshaller
+            .unmarshall(&chain(DEFAULT_MAX_DEPTH - 1)[..])
+            .is_ok());
+        assert_eq!(
+            unmarshaller.unmarshall(&chain(DEFAULT_MAX_DEPTH)[..]),
+            Err(Error::MaxDepthExceeded(DEFAULT_MAX_DEPTH))
+        );
+        // Would overflow the stack without the depth limit
+        assert_eq!(
+            unmarshaller.unmarshall(&chain(1_000_000)[..]),
+            Err(Error::MaxDepthExceeded(DEFAULT_MAX_DEPTH))
+        );
+
+        let unmarshaller = unmarshaller.with_limits(DecodeLimits {
+            max_depth: 64,
+            ..DecodeLimits::default()
+        });
+        assert!(unmarshaller
+            .unmarshall(&chain(DEFAULT_MAX_DEPTH)[..])
+            .is_ok());
+
+        // Limits are not applied outside of the unmarshaller
+        assert!(DepthGuard::enter().is_ok());
+    }
+
+    #[test]
+    fn work_budget() {
+        let item_len = std::mem::size_of::<Nested>();
+        let unmarshaller = unmarshaller().with_limits(DecodeLimits {
+            work_budget: item_len * 10,
+            ..DecodeLimits::default()
+        });
+
+        // Single item declaring too many children
+        let mut data = vec![0x01, 0x00, 0xFF, 0xFF];
+        data.extend([0u8; 0x20]);
+        assert_eq!(
+            unmarshaller.unmarshall(&data[..]),
+            Err(Error::WorkBudgetExceeded(item_len * 10))
+        );
+
+        // Many small allocations adding up over the budget
+        assert!(unmarshaller.unmarshall(&chain(10)[..]).is_ok());
+        assert_eq!(
+            unmarshaller.unmarshall(&chain(11)[..]),
+            Err(Error::WorkBudgetExceeded(item_len * 10))
+        );
+    }
 }
@@ -0,0 +1,123 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Redaction of node ids in log output for privacy-sensitive deployments.
+//!
+//! Node ids should be logged through the [`Redacted`] wrapper, which formats
+//! them according to the mode set with [`set_log_privacy`].
+
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use bitcoin_hashes::{sha256, Hash};
+use inet2_addr::NodeId;
+
+/// Number of hex characters of the node id or its hash shown in redacted
+/// form
+pub const REDACTED_HEX_LEN: usize = 8;
+
+static LOG_PRIVACY: AtomicU8 = AtomicU8::new(LogPrivacy::Full as u8);
+
+/// Form in which node ids are shown in logs
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[repr(u8)]
+pub enum LogPrivacy {
+    /// Full node id
+    #[display("full")]
+    Full = 0,
+
+    /// First [`REDACTED_HEX_LEN`] hex characters of the node id
+    #[display("truncated")]
+    Truncated = 1,
+
+    /// First [`REDACTED_HEX_LEN`] hex characters of SHA256 hash of the node
+    /// id, which allows correlating log entries without revealing the key
+    #[display("hashed")]
+    Hashed = 2,
+}
+
+/// Sets the form in which node ids are shown in logs for the whole process.
+/// Defaults to [`LogPrivacy::Full`].
+#[inline]
+pub fn set_log_privacy(privacy: LogPrivacy) {
+    LOG_PRIVACY.store(privacy as u8, Ordering::Relaxed);
+}
+
+/// Returns the form in which node ids are shown in logs.
+pub fn log_privacy() -> LogPrivacy {
+    match LOG_PRIVACY.load(Ordering::Relaxed) {
+        0 => LogPrivacy::Full,
+        1 => LogPrivacy::Truncated,
+        _ => LogPrivacy::Hashed,
+    }
+}
+
+/// Wrapper displaying node id according to the current [`log_privacy`] mode.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Redacted(pub NodeId);
+
+impl From<NodeId> for Redacted {
+    #[inline]
+    fn from(node_id: NodeId) -> Self { Redacted(node_id) }
+}
+
+impl Display for Redacted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match log_privacy() {
+            LogPrivacy::Full => Display::fmt(&self.0, f),
+            LogPrivacy::Truncated => {
+                let hex = self.0.to_string();
+                write!(f, "{}…", &hex[..REDACTED_HEX_LEN])
+            }
+            LogPrivacy::Hashed => {
+                let hash = sha256::Hash::hash(&self.0.public_key().serialize());
+                write!(f, "sha256:{}", &hash.to_string()[..REDACTED_HEX_LEN])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const NODE_ID: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    fn redaction_modes() {
+        let node_id = NodeId::from_str(NODE_ID).unwrap();
+        let hash = sha256::Hash::hash(&node_id.public_key().serialize());
+        let log = || format!("connected to {}", Redacted(node_id));
+
+        assert_eq!(log_privacy(), LogPrivacy::Full);
+        assert_eq!(log(), format!("connected to {}", NODE_ID));
+
+        set_log_privacy(LogPrivacy::Truncated);
+        assert_eq!(log_privacy(), LogPrivacy::Truncated);
+        assert_eq!(log(), "connected to 0279be66…");
+
+        set_log_privacy(LogPrivacy::Hashed);
+        assert_eq!(log_privacy(), LogPrivacy::Hashed);
+        assert_eq!(
+            log(),
+            format!("connected to sha256:{}", &hash.to_string()[..8])
+        );
+        assert!(!log().contains(&NODE_ID[..REDACTED_HEX_LEN]));
+
+        set_log_privacy(LogPrivacy::Full);
+        assert_eq!(log(), format!("connected to {}", NODE_ID));
+    }
+}
@@ -0,0 +1,483 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Multiplexing of concurrent RPC calls from multiple threads over a single
+//! ZMQ connection to an RPC server.
+//!
+//! The multiplexer connects to the server with a `DEALER` socket and prefixes
+//! each request with a correlation frame followed by an empty delimiter frame.
+//! Servers using `REP` sockets (like [`crate::session::LocalSession`] created
+//! with [`crate::ZmqSocketType::Rep`]) or `ROUTER` sockets echoing all
+//! envelope frames back with the reply do not require any changes for this to
+//! work. Servers which drop or reorder envelope frames are not supported.
+//!
+//! Calls made with [`MultiplexerHandle::call_with_deadline`] carry an
+//! additional envelope frame between the correlation frame and the delimiter,
+//! containing the remaining time budget in milliseconds as a big-endian `u64`.
+//! `ROUTER` servers may use it for their own scheduling; servers ignoring it
+//! just have to echo it back as a part of the envelope.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use inet2_addr::ServiceAddr;
+
+use crate::session::{Decrypt, Encrypt, PlainTranscoder};
+use crate::transport::guard::{allow_service, ConnectGuard};
+use crate::transport::Error;
+
+type Reply = Result<Vec<u8>, Error>;
+type Pending = Arc<Mutex<BTreeMap<u64, mpsc::Sender<Reply>>>>;
+
+/// RPC call multiplexer owning a single connection to the RPC server and a
+/// worker thread dispatching replies to the waiting callers.
+///
+/// Calls are made through cloneable [`MultiplexerHandle`]s. Dropping the
+/// multiplexer stops the worker thread; calls made through the remaining
+/// handles after that time out.
+pub struct Multiplexer {
+    handle: MultiplexerHandle,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// Cloneable handle for making RPC calls through a [`Multiplexer`] from
+/// multiple threads.
+#[derive(Clone)]
+pub struct MultiplexerHandle {
+    requests: Arc<Mutex<zmq::Socket>>,
+    pending: Pending,
+    next_id: Arc<AtomicU64>,
+    late_replies: Arc<AtomicU64>,
+    timeout: Duration,
+}
+
+impl Multiplexer {
+    /// Connects to the RPC server at `remote` address. Calls which are not
+    /// replied within `timeout` fail with [`Error::TimedOut`].
+    pub fn connect(
+        remote: &ServiceAddr,
+        context: &zmq::Context,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let endpoint = ServiceAddr::inproc_unique("internet2-rpc-multiplexer")
+            .zmq_connect_string();
+
+        let dealer = context.socket(zmq::DEALER)?;
+        dealer.set_linger(0)?;
+        dealer.connect(&remote.zmq_connect_string())?;
+        let worker_requests = context.socket(zmq::PULL)?;
+        worker_requests.set_linger(0)?;
+        worker_requests.bind(&endpoint)?;
+        let requests = context.socket(zmq::PUSH)?;
+        requests.set_linger(0)?;
+        requests.connect(&endpoint)?;
+
+        let pending = Pending::default();
+        let late_replies = Arc::new(AtomicU64::new(0));
+        let worker_pending = pending.clone();
+        let worker_late = late_replies.clone();
+        let worker = std::thread::spawn(move || {
+            if let Err(err) =
+                dispatch(dealer, worker_requests, &worker_pending, &worker_late)
+            {
+                // Fail all waiting calls; new calls will time out
+                let mut pending =
+                    worker_pending.lock().expect("poisoned multiplexer lock");
+                for (_, caller) in std::mem::take(&mut *pending) {
+                    let _ = caller.send(Err(Error::from(err)));
+                }
+            }
+        });
+
+        Ok(Multiplexer {
+            handle: MultiplexerHandle {
+                requests: Arc::new(Mutex::new(requests)),
+                pending,
+                next_id: Arc::new(AtomicU64::new(0)),
+                late_replies,
+                timeout,
+            },
+            worker: Some(worker),
+        })
+    }
+
+    /// Connects to the RPC server at `remote` address if the `guard` allows
+    /// it; otherwise fails with [`Error::ConnectionDenied`] before creating
+    /// any socket. See [`Multiplexer::connect`] for the details.
+    pub fn connect_guarded(
+        remote: &ServiceAddr,
+        context: &zmq::Context,
+        timeout: Duration,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        allow_service(guard, remote)?;
+        Multiplexer::connect(remote, context, timeout)
+    }
+
+    /// Returns new handle for making RPC calls.
+    #[inline]
+    pub fn handle(&self) -> MultiplexerHandle { self.handle.clone() }
+}
+
+impl Drop for Multiplexer {
+    fn drop(&mut self) {
+        // Single-part message without correlation frame stops the worker
+        let stopped = self
+            .handle
+            .requests
+            .lock()
+            .map(|requests| {
+                requests.send(zmq::Message::new(), zmq::DONTWAIT).is_ok()
+            })
+            .unwrap_or_default();
+        if let (true, Some(worker)) = (stopped, self.worker.take()) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl MultiplexerHandle {
+    /// Sends raw request message to the RPC server and waits for the reply.
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::FrameTooLargeForTransport`] if the message can't be framed;
+    /// * [`Error::TimedOut`] if no reply was received within the multiplexer
+    ///   timeout;
+    /// * [`Error::Zmq`] if the multiplexer connection has failed or its worker
+    ///   thread is not running.
+    pub fn call(&self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        self.request(msg, None)
+    }
+
+    /// Sends raw request message to the RPC server and waits for the reply
+    /// until the `deadline` (instead of the multiplexer timeout). The
+    /// remaining time budget is attached to the request envelope (see module
+    /// documentation). Replies arriving after the deadline are discarded and
+    /// counted in [`MultiplexerHandle::late_replies`].
+    ///
+    /// # Errors
+    ///
+    /// * [`Error::DeadlineExpired`] with `sent` set to `false` if the deadline
+    ///   has already passed and the request was not sent, or to `true` if the
+    ///   request was sent but the reply was not received in time;
+    /// * the same errors as [`MultiplexerHandle::call`], except
+    ///   [`Error::TimedOut`].
+    pub fn call_with_deadline(
+        &self,
+        msg: &[u8],
+        deadline: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        self.request(msg, Some(deadline))
+    }
+
+    /// Returns timeout for the calls.
+    #[inline]
+    pub fn timeout(&self) -> Duration { self.timeout }
+
+    /// Returns number of replies which were received after the caller has
+    /// stopped waiting for them and were discarded.
+    #[inline]
+    pub fn late_replies(&self) -> u64 {
+        self.late_replies.load(Ordering::Relaxed)
+    }
+
+    fn request(
+        &self,
+        msg: &[u8],
+        deadline: Option<Instant>,
+    ) -> Result<Vec<u8>, Error> {
+        let max = PlainTranscoder.max_payload_len();
+        if msg.len() > max {
+            return Err(Error::FrameTooLargeForTransport {
+                len: msg.len(),
+                max,
+            });
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut frames = vec![id.to_be_bytes().to_vec()];
+        let wait = match deadline {
+            None => self.timeout,
+            Some(deadline) => match deadline
+                .checked_duration_since(Instant::now())
+                .filter(|budget| !budget.is_zero())
+            {
+                None => return Err(Error::DeadlineExpired { sent: false }),
+                Some(budget) => {
+                    let millis =
+                        u64::try_from(budget.as_millis()).unwrap_or(u64::MAX);
+                    frames.push(millis.to_be_bytes().to_vec());
+                    budget
+                }
+            },
+        };
+        frames.push(PlainTranscoder.encrypt(msg));
+
+        let (sender, receiver) = mpsc::channel();
+        self.pending().insert(id, sender);
+        let sent = self
+            .requests
+            .lock()
+            .expect("poisoned multiplexer lock")
+            .send_multipart(frames, zmq::DONTWAIT);
+        if let Err(err) = sent {
+            self.pending().remove(&id);
+            return Err(err.into());
+        }
+
+        receiver.recv_timeout(wait).unwrap_or_else(|_| {
+            // If the worker has already taken the caller out of the pending
+            // list, the reply is either in the channel or will fail to be
+            // delivered; the latter case is counted by the worker
+            if self.pending().remove(&id).is_none()
+                && receiver.try_recv().is_ok()
+            {
+                self.late_replies.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(match deadline {
+                None => Error::TimedOut,
+                Some(_) => Error::DeadlineExpired { sent: true },
+            })
+        })
+    }
+
+    fn pending(
+        &self,
+    ) -> std::sync::MutexGuard<'_, BTreeMap<u64, mpsc::Sender<Reply>>> {
+        self.pending.lock().expect("poisoned multiplexer lock")
+    }
+}
+
+/// Worker loop forwarding requests to the server and replies to the callers
+fn dispatch(
+    dealer: zmq::Socket,
+    requests: zmq::Socket,
+    pending: &Pending,
+    late_replies: &AtomicU64,
+) -> Result<(), zmq::Error> {
+    loop {
+        let mut items = [
+            dealer.as_poll_item(zmq::POLLIN),
+            requests.as_poll_item(zmq::POLLIN),
+        ];
+        zmq::poll(&mut items, -1)?;
+
+        if items[1].is_readable() {
+            let mut request = requests.recv_multipart(0)?;
+            if request.len() < 2 {
+                return Ok(());
+            }
+            // Insert delimiter between the envelope and the payload frame
+            request.insert(request.len() - 1, vec![]);
+            dealer.send_multipart(request, 0)?;
+        }
+
+        if items[0].is_readable() {
+            let reply = dealer.recv_multipart(0)?;
+            let (id, frame) = match reply.as_slice() {
+                [id, delimiter, frame] | [id, _, delimiter, frame]
+                    if delimiter.is_empty() =>
+                {
+                    (id, frame)
+                }
+                // Not a reply to our request
+                _ => continue,
+            };
+            let mut id_buf = [0u8; 8];
+            if id.len() != id_buf.len() {
+                continue;
+            }
+            id_buf.copy_from_slice(id);
+            let caller = pending
+                .lock()
+                .expect("poisoned multiplexer lock")
+                .remove(&u64::from_be_bytes(id_buf));
+            let delivered = caller
+                .map(|caller| {
+                    caller
+                        .send(PlainTranscoder.decrypt(frame.as_slice()))
+                        .is_ok()
+                })
+                .unwrap_or_default();
+            if !delivered {
+                // Caller has already timed out
+                late_replies.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::session::LocalSession;
+    use crate::{SendRecvMessage, ZmqSocketType};
+
+    fn echo_server(ctx: &zmq::Context, addr: &ServiceAddr) -> JoinHandle<()> {
+        let mut session =
+            LocalSession::connect(ZmqSocketType::Rep, addr, None, None, ctx)
+                .unwrap();
+        std::thread::spawn(move || loop {
+            let msg = session.recv_raw_message().unwrap();
+            if msg == b"stop" {
+                session.send_raw_message(&msg).unwrap();
+                return;
+            }
+            if msg == b"slow" {
+                std::thread::sleep(Duration::from_millis(300));
+            }
+            session.send_raw_message(&msg).unwrap();
+        })
+    }
+
+    #[test]
+    fn concurrent_calls() {
+        let ctx = zmq::Context::new();
+        let addr = ServiceAddr::inproc_unique("rpc-multiplexer-concurrent");
+        let server = echo_server(&ctx, &addr);
+        let multiplexer =
+            Multiplexer::connect(&addr, &ctx, Duration::from_secs(10)).unwrap();
+
+        let callers = (0..50u32)
+            .map(|no| {
+                let handle = multiplexer.handle();
+                std::thread::spawn(move || {
+                    for call in 0..20u32 {
+                        let msg = format!("caller {} call {}", no, call);
+                        assert_eq!(
+                            handle.call(msg.as_bytes()).unwrap(),
+                            msg.as_bytes()
+                        );
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for caller in callers {
+            caller.join().unwrap();
+        }
+
+        multiplexer.handle().call(b"stop").unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn call_timeout() {
+        let ctx = zmq::Context::new();
+        let addr = ServiceAddr::inproc_unique("rpc-multiplexer-timeout");
+        let server = echo_server(&ctx, &addr);
+        let multiplexer =
+            Multiplexer::connect(&addr, &ctx, Duration::from_millis(100))
+                .unwrap();
+        let handle = multiplexer.handle();
+
+        let start = Instant::now();
+        assert_eq!(handle.call(b"slow"), Err(Error::TimedOut));
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        // Late reply to the timed out call must not be delivered to the next
+        // caller
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(handle.call(b"fast").unwrap(), b"fast");
+        assert!(handle.pending().is_empty());
+        assert_eq!(handle.late_replies(), 1);
+
+        assert_eq!(
+            handle.call(&[0u8; 0x10000]),
+            Err(Error::FrameTooLargeForTransport {
+                len: 0x10000,
+                max: 0xFFFF
+            })
+        );
+
+        handle.call(b"stop").unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn call_deadline() {
+        let ctx = zmq::Context::new();
+        let addr = ServiceAddr::inproc_unique("rpc-multiplexer-deadline");
+        let server = echo_server(&ctx, &addr);
+        let multiplexer =
+            Multiplexer::connect(&addr, &ctx, Duration::from_secs(10)).unwrap();
+        let handle = multiplexer.handle();
+
+        // Expired deadline: nothing is sent, so the server must not see
+        // the "stop" message
+        assert_eq!(
+            handle.call_with_deadline(b"stop", Instant::now()),
+            Err(Error::DeadlineExpired { sent: false })
+        );
+        assert!(handle.pending().is_empty());
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(
+            handle.call_with_deadline(b"fast", deadline).unwrap(),
+            b"fast"
+        );
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert_eq!(
+            handle.call_with_deadline(b"slow", deadline),
+            Err(Error::DeadlineExpired { sent: true })
+        );
+        assert!(Instant::now() < deadline + Duration::from_millis(200));
+
+        // Late reply is discarded and counted
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(handle.late_replies(), 1);
+        assert_eq!(handle.call(b"fast").unwrap(), b"fast");
+
+        handle.call(b"stop").unwrap();
+        server.join().unwrap();
+        assert_eq!(handle.late_replies(), 1);
+    }
+
+    #[test]
+    fn deadline_frame() {
+        let ctx = zmq::Context::new();
+        let addr = ServiceAddr::inproc_unique("rpc-multiplexer-deadline-frame");
+        let router = ctx.socket(zmq::ROUTER).unwrap();
+        router.bind(&addr.zmq_connect_string()).unwrap();
+        let server = std::thread::spawn(move || {
+            for budget_frame in [false, true] {
+                let request = router.recv_multipart(0).unwrap();
+                assert_eq!(request.len(), 4 + budget_frame as usize);
+                if budget_frame {
+                    let mut budget = [0u8; 8];
+                    budget.copy_from_slice(&request[2]);
+                    let budget = u64::from_be_bytes(budget);
+                    assert!(budget > 0 && budget <= 1000);
+                }
+                router.send_multipart(request, 0).unwrap();
+            }
+        });
+        let multiplexer =
+            Multiplexer::connect(&addr, &ctx, Duration::from_secs(10)).unwrap();
+        let handle = multiplexer.handle();
+
+        assert_eq!(handle.call(b"plain").unwrap(), b"plain");
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(
+            handle.call_with_deadline(b"budget", deadline).unwrap(),
+            b"budget"
+        );
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Minimal Lightning-compatible peer: connects to a node, runs Noise_XK
+//! handshake and BOLT-1 `init` exchange and answers pings, leaving all other
+//! messages to the application.
+//!
+//! The peer is assembled only from the public APIs of the crate, so it also
+//! serves as an example of how to use them.
+
+use std::io::ErrorKind;
+use std::net::{Shutdown, TcpListener};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use inet2_addr::{LocalNode, NodeAddr, NodeAddrParseError, NodeId};
+
+use crate::session::BrontideSession;
+use crate::transport::guard::ConnectGuard;
+use crate::{transport, SendRecvMessage};
+
+/// BOLT-1 `init` message type
+pub const MSG_TYPE_INIT: u16 = 16;
+/// BOLT-1 `error` message type
+pub const MSG_TYPE_ERROR: u16 = 17;
+/// BOLT-1 `ping` message type
+pub const MSG_TYPE_PING: u16 = 18;
+/// BOLT-1 `pong` message type
+pub const MSG_TYPE_PONG: u16 = 19;
+
+/// Default interval between keepalive pings sent by [`MinimalPeer`]
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Pings requesting this number of pong bytes or more must be ignored
+/// (BOLT-1)
+const PING_IGNORE_THRESHOLD: u16 = 65532;
+
+/// Errors of [`MinimalPeer`]
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+#[non_exhaustive]
+pub enum MinimalPeerError {
+    /// invalid remote node address: {0}
+    #[from]
+    InvalidAddress(NodeAddrParseError),
+
+    /// {0}
+    #[from]
+    Transport(transport::Error),
+
+    /// remote peer has sent message of type {0} before `init`
+    NoInit(u16),
+
+    /// remote peer has sent malformed `{0}` message
+    Malformed(&'static str),
+
+    /// remote peer has failed the connection with error: {0}
+    PeerError(String),
+}
+
+/// Minimal Lightning-compatible peer over an encrypted Brontide session.
+///
+/// The peer performs BOLT-1 `init` exchange once connected, answers pings
+/// and drops pongs transparently, and sends keepalive pings (see
+/// [`MinimalPeer::keepalive`]); all other messages are passed to and from
+/// the application as raw bytes starting with the message type.
+pub struct MinimalPeer {
+    session: BrontideSession,
+    local: LocalNode,
+    remote_id: NodeId,
+    remote_features: Vec<u8>,
+    ping_interval: Duration,
+    last_ping: Instant,
+}
+
+impl MinimalPeer {
+    /// Connects to a remote node given as `<node_id>@<host>:<port>` string
+    /// using either the provided local node keys or newly generated ones,
+    /// and exchanges `init` messages advertising the `features` bits.
+    pub fn connect(
+        remote: &str,
+        local: Option<LocalNode>,
+        features: &[u16],
+    ) -> Result<Self, MinimalPeerError> {
+        let remote = NodeAddr::from_str(remote)?;
+        let local =
+            local.unwrap_or_else(|| LocalNode::new(secp256k1::SECP256K1));
+        let session = BrontideSession::connect(local.private_key(), remote)?;
+        MinimalPeer::with_session(session, local, features)
+    }
+
+    /// Connects to a remote node as [`MinimalPeer::connect`] does if the
+    /// `guard` allows connection to the node; otherwise fails with
+    /// [`transport::Error::ConnectionDenied`] before opening any socket.
+    pub fn connect_guarded(
+        remote: &str,
+        local: Option<LocalNode>,
+        features: &[u16],
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, MinimalPeerError> {
+        let remote = NodeAddr::from_str(remote)?;
+        let local =
+            local.unwrap_or_else(|| LocalNode::new(secp256k1::SECP256K1));
+        let session = BrontideSession::connect_guarded(
+            local.private_key(),
+            remote,
+            guard,
+        )?;
+        MinimalPeer::with_session(session, local, features)
+    }
+
+    /// Accepts incoming connection and exchanges `init` messages advertising
+    /// the `features` bits.
+    pub fn accept(
+        listener: &TcpListener,
+        local: LocalNode,
+        features: &[u16],
+    ) -> Result<Self, MinimalPeerError> {
+        let session = BrontideSession::accept(local.private_key(), listener)?;
+        MinimalPeer::with_session(session, local, features)
+    }
+
+    /// Exchanges `init` messages over an already established session.
+    pub fn with_session(
+        mut session: BrontideSession,
+        local: LocalNode,
+        features: &[u16],
+    ) -> Result<Self, MinimalPeerError> {
+        session.send_raw_message(&init_message(features))?;
+        let msg = session.recv_raw_message()?;
+        let remote_features = match message_type(&msg) {
+            Some(MSG_TYPE_INIT) => parse_init(&msg)?,
+            Some(MSG_TYPE_ERROR) => return Err(parse_error(&msg)),
+            Some(ty) => return Err(MinimalPeerError::NoInit(ty)),
+            None => return Err(MinimalPeerError::Malformed("init")),
+        };
+        session
+            .as_tcp_stream()
+            .set_read_timeout(Some(DEFAULT_PING_INTERVAL))
+            .map_err(transport::Error::from)?;
+        Ok(MinimalPeer {
+            remote_id: session.remote_id(),
+            session,
+            local,
+            remote_features,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            last_ping: Instant::now(),
+        })
+    }
+
+    /// Returns local node used by the peer
+    #[inline]
+    pub fn local_node(&self) -> LocalNode { self.local }
+
+    /// Returns id of the remote node
+    #[inline]
+    pub fn remote_id(&self) -> NodeId { self.remote_id }
+
+    /// Returns features advertised by the remote node in `init` message as a
+    /// big-endian bit field, with global features merged in
+    #[inline]
+    pub fn remote_features(&self) -> &[u8] { &self.remote_features }
+
+    /// Returns numbers of the feature bits advertised by the remote node, in
+    /// ascending order
+    pub fn remote_feature_bits(&self) -> Vec<u16> {
+        let len = self.remote_features.len();
+        (0..len * 8)
+            .filter(|bit| {
+                self.remote_features[len - 1 - bit / 8] & (1 << (bit % 8)) != 0
+            })
+            .map(|bit| bit as u16)
+            .collect()
+    }
+
+    /// Sets interval between keepalive pings, which is also used as the read
+    /// timeout of the underlying socket. The interval must not be zero.
+    pub fn set_ping_interval(
+        &mut self,
+        interval: Duration,
+    ) -> Result<(), MinimalPeerError> {
+        self.session
+            .as_tcp_stream()
+            .set_read_timeout(Some(interval))
+            .map_err(transport::Error::from)?;
+        self.ping_interval = interval;
+        Ok(())
+    }
+
+    /// Sends ping if no ping was sent during the ping interval. Called by
+    /// [`MinimalPeer::recv_message`] before and while waiting for each
+    /// incoming message.
+    pub fn keepalive(&mut self) -> Result<(), MinimalPeerError> {
+        if self.last_ping.elapsed() >= self.ping_interval {
+            self.ping()?;
+        }
+        Ok(())
+    }
+
+    /// Sends ping requesting an empty pong
+    pub fn ping(&mut self) -> Result<(), MinimalPeerError> {
+        let mut msg = MSG_TYPE_PING.to_be_bytes().to_vec();
+        msg.extend(0u16.to_be_bytes());
+        msg.extend(0u16.to_be_bytes());
+        self.session.send_raw_message(&msg)?;
+        self.last_ping = Instant::now();
+        Ok(())
+    }
+
+    /// Sends message, which must start with the two-byte message type
+    pub fn send_message(
+        &mut self,
+        msg: &[u8],
+    ) -> Result<usize, MinimalPeerError> {
+        Ok(self.session.send_raw_message(msg)?)
+    }
+
+    /// Receives next application message, answering pings and skipping
+    /// pongs. While no data are coming, keepalive pings are sent each ping
+    /// interval. BOLT-1 `error` messages are returned as
+    /// [`MinimalPeerError::PeerError`].
+    pub fn recv_message(&mut self) -> Result<Vec<u8>, MinimalPeerError> {
+        loop {
+            self.keepalive()?;
+            if !self.wait_incoming()? {
+                continue;
+            }
+            let msg = self.session.recv_raw_message()?;
+            match message_type(&msg) {
+                Some(MSG_TYPE_PING) => self.pong(&msg)?,
+                Some(MSG_TYPE_PONG) => {}
+                Some(MSG_TYPE_ERROR) => return Err(parse_error(&msg)),
+                _ => return Ok(msg),
+            }
+        }
+    }
+
+    /// Closes the connection
+    pub fn shutdown(mut self) -> Result<(), MinimalPeerError> {
+        self.session.flush()?;
+        self.session
+            .as_tcp_stream()
+            .shutdown(Shutdown::Both)
+            .map_err(transport::Error::from)?;
+        Ok(())
+    }
+
+    /// Waits for the incoming data for no longer than the socket read timeout
+    /// (the ping interval) without consuming them. Returns `false` if the
+    /// timeout has expired, meaning that the remote peer stays idle; an
+    /// incomplete frame is never read in this case, so the session can be
+    /// used further.
+    fn wait_incoming(&mut self) -> Result<bool, MinimalPeerError> {
+        match self.session.as_tcp_stream().peek(&mut [0u8]) {
+            // Closed connection is reported by the subsequent read
+            Ok(_) => Ok(true),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(transport::Error::from(err).into()),
+        }
+    }
+
+    fn pong(&mut self, ping: &[u8]) -> Result<(), MinimalPeerError> {
+        let num_pong_bytes =
+            read_u16(ping, 2).ok_or(MinimalPeerError::Malformed("ping"))?;
+        if num_pong_bytes >= PING_IGNORE_THRESHOLD {
+            return Ok(());
+        }
+        let mut msg = MSG_TYPE_PONG.to_be_bytes().to_vec();
+        msg.extend(num_pong_bytes.to_be_bytes());
+        msg.extend(vec![0u8; num_pong_bytes as usize]);
+        self.session.send_raw_message(&msg)?;
+        Ok(())
+    }
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Option<u16> {
+    let bytes = msg.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn message_type(msg: &[u8]) -> Option<u16> { read_u16(msg, 0) }
+
+/// Constructs `init` message without global features and TLV extensions
+fn init_message(feature_bits: &[u16]) -> Vec<u8> {
+    let len = feature_bits
+        .iter()
+        .max()
+        .map(|bit| *bit as usize / 8 + 1)
+        .unwrap_or(0);
+    let mut features = vec![0u8; len];
+    for bit in feature_bits {
+        let bit = *bit as usize;
+        features[len - 1 - bit / 8] |= 1 << (bit % 8);
+    }
+    let mut msg = MSG_TYPE_INIT.to_be_bytes().to_vec();
+    msg.extend(0u16.to_be_bytes());
+    msg.extend((len as u16).to_be_bytes());
+    msg.extend(features);
+    msg
+}
+
+/// Parses `init` message, returning local features merged with the global
+/// ones as required by BOLT-1
+fn parse_init(msg: &[u8]) -> Result<Vec<u8>, MinimalPeerError> {
+    let malformed = || MinimalPeerError::Malformed("init");
+    let global_len = read_u16(msg, 2).ok_or_else(malformed)? as usize;
+    let global = msg.get(4..4 + global_len).ok_or_else(malformed)?;
+    let pos = 4 + global_len;
+    let len = read_u16(msg, pos).ok_or_else(malformed)? as usize;
+    let local = msg.get(pos + 2..pos + 2 + len).ok_or_else(malformed)?;
+    let mut features = vec![0u8; len.max(global_len)];
+    let offset = features.len() - local.len();
+    features[offset..].copy_from_slice(local);
+    let offset = features.len() - global.len();
+    for (no, byte) in global.iter().enumerate() {
+        features[offset + no] |= byte;
+    }
+    Ok(features)
+}
+
+fn parse_error(msg: &[u8]) -> MinimalPeerError {
+    let data = read_u16(msg, 34).and_then(|len| msg.get(36..36 + len as usize));
+    match data {
+        Some(data) => MinimalPeerError::PeerError(
+            String::from_utf8_lossy(data).into_owned(),
+        ),
+        None => MinimalPeerError::Malformed("error"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn init_roundtrip() {
+        let msg = init_message(&[0, 9, 13]);
+        assert_eq!(msg, vec![0x00, 0x10, 0x00, 0x00, 0x00, 0x02, 0x22, 0x01]);
+        assert_eq!(parse_init(&msg).unwrap(), vec![0x22, 0x01]);
+        assert_eq!(parse_init(&init_message(&[])).unwrap(), Vec::<u8>::new());
+        assert!(matches!(
+            parse_init(&msg[..7]),
+            Err(MinimalPeerError::Malformed("init"))
+        ));
+    }
+
+    #[test]
+    fn init_global_features() {
+        // Global features 0x0100 (bit 8), local features 0x02 (bit 1)
+        let msg = [0x00, 0x10, 0x00, 0x02, 0x01, 0x00, 0x00, 0x01, 0x02];
+        assert_eq!(parse_init(&msg).unwrap(), vec![0x01, 0x02]);
+    }
+
+    #[test]
+    fn error_message() {
+        let mut msg = MSG_TYPE_ERROR.to_be_bytes().to_vec();
+        msg.extend([0u8; 32]);
+        msg.extend(4u16.to_be_bytes());
+        msg.extend(b"fail");
+        assert!(matches!(
+            parse_error(&msg),
+            MinimalPeerError::PeerError(err) if err == "fail"
+        ));
+        assert!(matches!(
+            parse_error(&msg[..37]),
+            MinimalPeerError::Malformed("error")
+        ));
+    }
+}
@@ -14,20 +14,53 @@
 //! BOLT-8 related structures and functions covering Lightning network
 //! transport layer
 
+#[cfg(feature = "keygen")]
+mod minimal;
+pub mod mux;
 pub mod noise;
+pub mod padding;
+mod peer;
+mod postmortem;
+mod protocol;
 #[allow(clippy::module_inception)]
 mod session;
+mod shared;
 mod transcoders;
+mod version;
 
+#[cfg(feature = "keygen")]
+pub use minimal::{
+    MinimalPeer, MinimalPeerError, DEFAULT_PING_INTERVAL, MSG_TYPE_ERROR,
+    MSG_TYPE_INIT, MSG_TYPE_PING, MSG_TYPE_PONG,
+};
+pub use mux::{
+    ChannelHandle, Multiplexer, MuxError, MUX_DEFAULT_WINDOW, MUX_HEADER_LEN,
+    MUX_HELLO_LEN, MUX_MAGIC, MUX_MAX_PENDING_CHANNELS,
+};
 pub use noise::{
     HandshakeError, NoiseDecryptor, NoiseEncryptor, NoiseTranscoder,
 };
+pub use padding::{PaddingPolicy, PADDING_TRAILER_LEN};
+pub use peer::{
+    PeerInfo, PeerInfoError, PEER_INFO_MAX_ADDRS, PEER_INFO_MAX_ALIAS_LEN,
+    PEER_INFO_VERSION,
+};
+pub use postmortem::{
+    FrameDirection, FrameLog, FrameRecord, SessionPostMortem,
+    FRAME_LOG_DEFAULT_CAPACITY,
+};
+pub use protocol::{Direction, ProtocolError, ProtocolStateMachine};
 pub use session::{
     BrontideSession, BrontozaurSession, Receiver, RecvMessage, SendMessage,
     SendRecvMessage, Sender, Session, Split,
 };
 #[cfg(feature = "zmq")]
-pub use session::{LocalSession, RpcSession};
+pub use session::{LocalSession, RecvManyError, RpcSession};
+pub use shared::{SendAck, SharedSender, SharedSession};
 pub use transcoders::{
     Decrypt, DecryptionError, Encrypt, PlainTranscoder, Transcode,
 };
+pub use version::{
+    VersionError, VersionRange, VersionedSession, VERSION_MAGIC,
+    VERSION_MSG_LEN,
+};
@@ -0,0 +1,439 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Multiplexing of independent logical channels over a single session.
+//!
+//! Multiplexing is enabled by both peers with [`Multiplexer::negotiate`],
+//! which exchanges hello messages consisting of [`MUX_MAGIC`] followed by
+//! the receive window of the peer (big-endian `u32`). Afterwards each message
+//! sent over the session starts with [`MUX_HEADER_LEN`]-byte header made of
+//! the channel id (big-endian `u16`) and the frame kind:
+//! - `0x00`: application message sent over the channel;
+//! - `0x01`: credit update, followed by the number of messages (big-endian
+//!   `u32`) consumed by the application since the previous update;
+//! - `0x02`: channel close; no messages are sent over the channel by the peer
+//!   after it.
+//!
+//! Each side may have at most `window` messages sent over a channel and not
+//! yet consumed by the remote application; sending blocks once the credits
+//! are exhausted, so one busy channel can't starve the others. Messages
+//! sent over channels not yet opened locally are queued as well, but for no
+//! more than [`MUX_MAX_PENDING_CHANNELS`] channels at a time. Sessions
+//! which do not negotiate multiplexing are not affected in any way.
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use super::{RecvMessage, SendMessage, SendRecvMessage, Split};
+use crate::transport::{self, Error, RoutedFrame};
+
+/// Magic bytes starting the multiplexing hello message
+pub const MUX_MAGIC: [u8; 4] = *b"I2MX";
+
+/// Length of the multiplexing hello message
+pub const MUX_HELLO_LEN: usize = MUX_MAGIC.len() + 4;
+
+/// Length of the header prepended to each multiplexed message
+pub const MUX_HEADER_LEN: usize = 3;
+
+/// Default number of unread messages queued per channel
+pub const MUX_DEFAULT_WINDOW: u32 = 64;
+
+/// Maximum number of channels which are used by the remote peer, but are not
+/// yet opened locally. Messages for more channels fail the session.
+pub const MUX_MAX_PENDING_CHANNELS: usize = 16;
+
+const KIND_DATA: u8 = 0x00;
+const KIND_CREDIT: u8 = 0x01;
+const KIND_CLOSE: u8 = 0x02;
+
+/// Errors of session multiplexing
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum MuxError {
+    /// remote peer does not support session multiplexing
+    Unsupported,
+
+    /// malformed multiplexing hello message of {0} bytes
+    Malformed(usize),
+
+    /// channel {0} is already open
+    ChannelInUse(u16),
+
+    #[display(inner)]
+    #[from]
+    Transport(transport::Error),
+}
+
+#[derive(Default)]
+struct Channel {
+    queue: VecDeque<Vec<u8>>,
+    // Messages which may be sent to the remote peer
+    credits: u32,
+    // Messages consumed by the application and not yet reported to the
+    // remote peer
+    consumed: u32,
+    open: bool,
+    local_closed: bool,
+    remote_closed: bool,
+}
+
+struct State {
+    channels: HashMap<u16, Channel>,
+    window: u32,
+    peer_window: u32,
+    error: Option<Error>,
+}
+
+impl State {
+    fn pending_channels(&self) -> usize {
+        self.channels
+            .values()
+            .filter(|channel| !channel.open && !channel.local_closed)
+            .count()
+    }
+
+    fn channel(&mut self, id: u16) -> &mut Channel {
+        let credits = self.peer_window;
+        self.channels.entry(id).or_insert_with(|| Channel {
+            credits,
+            ..Channel::default()
+        })
+    }
+
+    fn open_channel(&mut self, id: u16) -> &mut Channel {
+        self.channels
+            .get_mut(&id)
+            .expect("state of an open channel is kept until it is closed")
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    sender: Mutex<Box<dyn SendMessage + Send>>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().expect("poisoned multiplexer lock")
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed.wait(guard).expect("poisoned multiplexer lock")
+    }
+
+    fn send_frame(
+        &self,
+        id: u16,
+        kind: u8,
+        payload: &[u8],
+    ) -> Result<usize, Error> {
+        let mut frame = Vec::with_capacity(MUX_HEADER_LEN + payload.len());
+        frame.extend(id.to_be_bytes());
+        frame.push(kind);
+        frame.extend_from_slice(payload);
+        self.sender
+            .lock()
+            .expect("poisoned multiplexer lock")
+            .send_raw_message(&frame)
+    }
+
+    fn dispatch(&self, msg: Vec<u8>) -> Result<(), Error> {
+        if msg.len() < MUX_HEADER_LEN {
+            return Err(Error::FrameBroken("no multiplexed channel header"));
+        }
+        let id = u16::from_be_bytes([msg[0], msg[1]]);
+        let mut state = self.lock();
+        if !state.channels.contains_key(&id)
+            && state.pending_channels() >= MUX_MAX_PENDING_CHANNELS
+        {
+            return Err(Error::FrameBroken("too many channels are not opened"));
+        }
+        let window = state.window;
+        let channel = state.channel(id);
+        match (msg[2], &msg[MUX_HEADER_LEN..]) {
+            (KIND_DATA, _) if channel.local_closed => {}
+            (KIND_DATA, _)
+                if channel.queue.len() as u32 + channel.consumed >= window =>
+            {
+                return Err(Error::FrameBroken("channel credit exceeded"))
+            }
+            (KIND_DATA, data) => channel.queue.push_back(data.to_vec()),
+            (KIND_CREDIT, &[a, b, c, d]) => {
+                let credits = u32::from_be_bytes([a, b, c, d]);
+                channel.credits = channel.credits.saturating_add(credits);
+            }
+            (KIND_CLOSE, []) => {
+                channel.remote_closed = true;
+                if channel.local_closed {
+                    state.channels.remove(&id);
+                }
+            }
+            _ => return Err(Error::FrameBroken("malformed multiplexed frame")),
+        }
+        drop(state);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    fn fail(&self, err: Error) {
+        let mut state = self.lock();
+        state.error.get_or_insert(err);
+        drop(state);
+        self.changed.notify_all();
+    }
+}
+
+/// Session split into independent logical channels.
+///
+/// Messages are received by a dedicated thread, which runs until the
+/// underlying connection fails or is closed, and are queued to the channels
+/// they belong to. Dropping the multiplexer shuts the connection down (see
+/// [`Multiplexer::shutdown`]); channels which are still open fail afterwards.
+pub struct Multiplexer {
+    shared: Arc<Shared>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Multiplexer {
+    /// Exchanges hello messages with the remote peer and starts multiplexing
+    /// the session, allowing at most `window` unread messages per channel.
+    ///
+    /// If the remote peer does not negotiate multiplexing, returns
+    /// [`MuxError::Unsupported`]; the first message received from the peer is
+    /// lost in this case.
+    pub fn negotiate<S>(mut session: S, window: u32) -> Result<Self, MuxError>
+    where
+        S: SendRecvMessage + Split,
+    {
+        let window = window.max(1);
+        let mut hello = MUX_MAGIC.to_vec();
+        hello.extend(window.to_be_bytes());
+        session.send_raw_message(&hello)?;
+
+        let msg = session.recv_raw_message()?;
+        if !msg.starts_with(&MUX_MAGIC) {
+            return Err(MuxError::Unsupported);
+        }
+        let peer_window = match msg[MUX_MAGIC.len()..] {
+            [a, b, c, d] if [a, b, c, d] != [0u8; 4] => {
+                u32::from_be_bytes([a, b, c, d])
+            }
+            _ => return Err(MuxError::Malformed(msg.len())),
+        };
+
+        let (receiver, sender) = session.split();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                channels: HashMap::new(),
+                window,
+                peer_window,
+                error: None,
+            }),
+            changed: Condvar::new(),
+            sender: Mutex::new(sender),
+        });
+        let reader = shared.clone();
+        let reader = std::thread::spawn(move || run(receiver, &reader));
+        Ok(Multiplexer {
+            shared,
+            reader: Some(reader),
+        })
+    }
+
+    /// Opens channel with the given id. Messages sent by the remote peer
+    /// over the channel before it was opened are not lost. The id may be
+    /// reused once the channel is closed by both peers.
+    pub fn open_channel(&self, id: u16) -> Result<ChannelHandle, MuxError> {
+        let mut state = self.shared.lock();
+        if let Some(err) = &state.error {
+            return Err(err.clone().into());
+        }
+        let channel = state.channel(id);
+        if channel.open || channel.local_closed {
+            return Err(MuxError::ChannelInUse(id));
+        }
+        channel.open = true;
+        Ok(ChannelHandle {
+            id,
+            shared: self.shared.clone(),
+            closed: false,
+        })
+    }
+
+    /// Returns error which has terminated the multiplexed session, if any
+    pub fn error(&self) -> Option<Error> { self.shared.lock().error.clone() }
+
+    /// Shuts the underlying connection down and waits for the receiving
+    /// thread to complete.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the error of [`SendMessage::shutdown`] if the connection
+    /// can't be shut down; in this case the receiving thread runs until the
+    /// remote peer closes the connection.
+    pub fn shutdown(mut self) -> Result<(), Error> { self.close() }
+
+    fn close(&mut self) -> Result<(), Error> {
+        let reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return Ok(()),
+        };
+        self.shared
+            .sender
+            .lock()
+            .expect("poisoned multiplexer lock")
+            .shutdown()?;
+        // The thread terminates only by failing, which is recorded in the
+        // shared state
+        let _ = reader.join();
+        Ok(())
+    }
+}
+
+impl Drop for Multiplexer {
+    fn drop(&mut self) { let _ = self.close(); }
+}
+
+fn run(mut receiver: Box<dyn RecvMessage + Send>, shared: &Shared) {
+    loop {
+        let res = receiver
+            .recv_raw_message()
+            .and_then(|msg| shared.dispatch(msg));
+        if let Err(err) = res {
+            shared.fail(err);
+            break;
+        }
+    }
+}
+
+/// Logical channel of a [`Multiplexer`]. Dropping the handle closes the
+/// channel.
+pub struct ChannelHandle {
+    id: u16,
+    shared: Arc<Shared>,
+    closed: bool,
+}
+
+impl ChannelHandle {
+    /// Returns channel id
+    #[inline]
+    pub fn id(&self) -> u16 { self.id }
+
+    /// Closes the channel, discarding unread messages, and notifies the
+    /// remote peer.
+    pub fn close(mut self) -> Result<(), Error> { self.close_channel() }
+
+    fn close_channel(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        let mut state = self.shared.lock();
+        let channel = state.open_channel(self.id);
+        channel.local_closed = true;
+        channel.queue.clear();
+        if channel.remote_closed {
+            state.channels.remove(&self.id);
+        }
+        drop(state);
+        self.shared.send_frame(self.id, KIND_CLOSE, &[]).map(|_| ())
+    }
+}
+
+impl Drop for ChannelHandle {
+    fn drop(&mut self) {
+        // The remote peer learns about the closed channel from the failed
+        // connection anyway
+        let _ = self.close_channel();
+    }
+}
+
+impl SendRecvMessage for ChannelHandle {
+    /// Receives next message sent over the channel, blocking until it
+    /// arrives. Returns [`Error::ChannelClosed`] once all messages sent
+    /// before the remote peer has closed the channel are received.
+    fn recv_raw_message(&mut self) -> Result<Vec<u8>, Error> {
+        let mut state = self.shared.lock();
+        let msg = loop {
+            let channel = state.open_channel(self.id);
+            if let Some(msg) = channel.queue.pop_front() {
+                break msg;
+            }
+            if channel.remote_closed {
+                return Err(Error::ChannelClosed(self.id));
+            }
+            if let Some(err) = &state.error {
+                return Err(err.clone());
+            }
+            state = self.shared.wait(state);
+        };
+        let window = state.window;
+        let channel = state.open_channel(self.id);
+        channel.consumed += 1;
+        let consumed = channel.consumed;
+        if consumed < (window / 2).max(1) {
+            return Ok(msg);
+        }
+        channel.consumed = 0;
+        drop(state);
+        self.shared.send_frame(
+            self.id,
+            KIND_CREDIT,
+            &consumed.to_be_bytes(),
+        )?;
+        Ok(msg)
+    }
+
+    /// Sends message over the channel, blocking while the remote peer has
+    /// not consumed previously sent messages.
+    fn send_raw_message(&mut self, raw: &[u8]) -> Result<usize, Error> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(err) = &state.error {
+                return Err(err.clone());
+            }
+            let channel = state.open_channel(self.id);
+            if channel.remote_closed {
+                return Err(Error::ChannelClosed(self.id));
+            }
+            if channel.credits > 0 {
+                channel.credits -= 1;
+                break;
+            }
+            state = self.shared.wait(state);
+        }
+        drop(state);
+        self.shared.send_frame(self.id, KIND_DATA, raw)
+    }
+
+    fn recv_routed_message(&mut self) -> Result<RoutedFrame, Error> {
+        unimplemented!("multiplexed channels do not support routing")
+    }
+
+    fn send_routed_message(
+        &mut self,
+        _source: &[u8],
+        _route: &[u8],
+        _dest: &[u8],
+        _raw: &[u8],
+    ) -> Result<usize, Error> {
+        unimplemented!("multiplexed channels do not support routing")
+    }
+
+    #[inline]
+    fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
+}
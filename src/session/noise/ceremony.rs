@@ -17,9 +17,8 @@
 
 use std::{cmp, ops};
 
-pub const ACT_ONE_LENGTH: usize = 50;
-pub const ACT_TWO_LENGTH: usize = 50;
-pub const ACT_THREE_LENGTH: usize = 66;
+pub use crate::consts::{ACT_ONE_LENGTH, ACT_THREE_LENGTH, ACT_TWO_LENGTH};
+
 pub const EMPTY_ACT_ONE: ActOne = [0; ACT_ONE_LENGTH];
 pub const EMPTY_ACT_TWO: ActTwo = [0; ACT_TWO_LENGTH];
 pub const EMPTY_ACT_THREE: ActThree = [0; ACT_THREE_LENGTH];
@@ -122,6 +121,9 @@ impl ActBuilder {
     pub fn is_finished(&self) -> bool {
         self.write_pos == self.partial_act.len()
     }
+
+    /// Returns number of bytes still required to finish building the Act
+    pub fn remaining(&self) -> usize { self.partial_act.len() - self.write_pos }
 }
 
 #[cfg(test)]
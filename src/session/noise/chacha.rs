@@ -14,10 +14,9 @@
 use chacha20poly1305::aead::{Aead, NewAead, Payload};
 use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 
+pub use crate::consts::MAC_SIZE as TAG_SIZE;
 use crate::noise::EncryptionError; /* Or `XChaCha20Poly1305` */
 
-pub const TAG_SIZE: usize = 16;
-
 // Encrypt a plaintext with associated data using the key and nonce.
 // Returns the encrypted msg by mutating cipher_text array
 pub fn encrypt(
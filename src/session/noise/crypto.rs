@@ -0,0 +1,451 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Cryptographic backend of the Noise_XK handshake.
+//!
+//! All elliptic curve operations, key derivations and AEAD operations
+//! performed during the handshake go through [`HandshakeCrypto`], so they may
+//! be moved to a hardware security module. [`SoftwareCrypto`] is the default
+//! backend using `secp256k1` and `chacha20poly1305` crates. Encryption of the
+//! transport messages after the handshake is always done in software.
+
+use std::fmt::Debug;
+
+use bitcoin_hashes::sha256::Hash as Sha256;
+use bitcoin_hashes::Hash;
+use secp256k1::{PublicKey, SecretKey, SECP256K1};
+
+use super::transcoder::SymmetricKey;
+use super::{chacha, hkdf, EncryptionError, HandshakeError};
+
+/// Role of the local private key used in a handshake operation
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum KeyRole {
+    /// Static (node) key
+    #[display("static")]
+    Static,
+
+    /// Ephemeral key generated for a single handshake
+    #[display("ephemeral")]
+    Ephemeral,
+}
+
+/// Cryptographic operations required by the Noise_XK handshake.
+///
+/// Private keys are always provided together with their [`KeyRole`], so
+/// implementations backed by a secure element may use the role to select the
+/// key stored in the device.
+pub trait HandshakeCrypto: Debug + Send {
+    /// Computes public key for the local private key.
+    fn public_key(&self, role: KeyRole, private_key: &SecretKey) -> PublicKey;
+
+    /// Computes ECDH shared secret between the local private key and the
+    /// remote public key as defined in BOLT-8 (SHA256 of the compressed
+    /// shared point).
+    fn ecdh(
+        &self,
+        role: KeyRole,
+        private_key: &SecretKey,
+        public_key: &PublicKey,
+    ) -> Result<SymmetricKey, HandshakeError>;
+
+    /// Derives a pair of keys with HKDF-SHA256 using zero-length info.
+    fn hkdf(&self, salt: &[u8], ikm: &[u8]) -> (SymmetricKey, SymmetricKey);
+
+    /// Encrypts plaintext with ChaCha20-Poly1305, writing ciphertext followed
+    /// by MAC into `ciphertext`.
+    fn seal(
+        &self,
+        key: &SymmetricKey,
+        nonce: u64,
+        associated_data: &[u8],
+        plaintext: &[u8],
+        ciphertext: &mut [u8],
+    ) -> Result<(), EncryptionError>;
+
+    /// Decrypts ciphertext followed by MAC with ChaCha20-Poly1305, writing
+    /// result into `plaintext`.
+    fn open(
+        &self,
+        key: &SymmetricKey,
+        nonce: u64,
+        associated_data: &[u8],
+        ciphertext: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), EncryptionError>;
+}
+
+/// Default handshake backend performing all operations in software.
+///
+/// Elliptic curve operations use the global pre-allocated `secp256k1`
+/// context instead of creating a new one per operation, since context
+/// creation is much more expensive than the operations themselves. The
+/// global context is immutable after its creation and is `Sync`, so it is
+/// safely shared by handshakes running in parallel threads.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SoftwareCrypto;
+
+impl HandshakeCrypto for SoftwareCrypto {
+    fn public_key(&self, _: KeyRole, private_key: &SecretKey) -> PublicKey {
+        PublicKey::from_secret_key(SECP256K1, private_key)
+    }
+
+    fn ecdh(
+        &self,
+        _: KeyRole,
+        private_key: &SecretKey,
+        public_key: &PublicKey,
+    ) -> Result<SymmetricKey, HandshakeError> {
+        let scalar =
+            secp256k1::Scalar::from_be_bytes(private_key.secret_bytes())?;
+        let preimage = public_key
+            .mul_tweak(SECP256K1, &scalar)
+            .expect("invalid multiplication")
+            .serialize();
+        Ok(Sha256::hash(&preimage).into_inner())
+    }
+
+    fn hkdf(&self, salt: &[u8], ikm: &[u8]) -> (SymmetricKey, SymmetricKey) {
+        hkdf::derive(salt, ikm)
+    }
+
+    fn seal(
+        &self,
+        key: &SymmetricKey,
+        nonce: u64,
+        associated_data: &[u8],
+        plaintext: &[u8],
+        ciphertext: &mut [u8],
+    ) -> Result<(), EncryptionError> {
+        chacha::encrypt(key, nonce, associated_data, plaintext, ciphertext)
+    }
+
+    fn open(
+        &self,
+        key: &SymmetricKey,
+        nonce: u64,
+        associated_data: &[u8],
+        ciphertext: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), EncryptionError> {
+        chacha::decrypt(key, nonce, associated_data, ciphertext, plaintext)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::noise::ResumableHandshake;
+
+    /// Backend recording all operations before delegating them to
+    /// [`SoftwareCrypto`]
+    #[derive(Debug, Default)]
+    struct Logging(Arc<Mutex<Vec<String>>>);
+
+    impl Logging {
+        fn log(&self, op: impl ToString) {
+            self.0.lock().unwrap().push(op.to_string());
+        }
+    }
+
+    impl HandshakeCrypto for Logging {
+        fn public_key(&self, role: KeyRole, key: &SecretKey) -> PublicKey {
+            self.log(format!("public_key({})", role));
+            SoftwareCrypto.public_key(role, key)
+        }
+
+        fn ecdh(
+            &self,
+            role: KeyRole,
+            private_key: &SecretKey,
+            public_key: &PublicKey,
+        ) -> Result<SymmetricKey, HandshakeError> {
+            self.log(format!("ecdh({})", role));
+            SoftwareCrypto.ecdh(role, private_key, public_key)
+        }
+
+        fn hkdf(
+            &self,
+            salt: &[u8],
+            ikm: &[u8],
+        ) -> (SymmetricKey, SymmetricKey) {
+            self.log("hkdf");
+            SoftwareCrypto.hkdf(salt, ikm)
+        }
+
+        fn seal(
+            &self,
+            key: &SymmetricKey,
+            nonce: u64,
+            ad: &[u8],
+            plaintext: &[u8],
+            ciphertext: &mut [u8],
+        ) -> Result<(), EncryptionError> {
+            self.log("seal");
+            SoftwareCrypto.seal(key, nonce, ad, plaintext, ciphertext)
+        }
+
+        fn open(
+            &self,
+            key: &SymmetricKey,
+            nonce: u64,
+            ad: &[u8],
+            ciphertext: &[u8],
+            plaintext: &mut [u8],
+        ) -> Result<(), EncryptionError> {
+            self.log("open");
+            SoftwareCrypto.open(key, nonce, ad, ciphertext, plaintext)
+        }
+    }
+
+    fn key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn exchange(
+        from: &mut ResumableHandshake<2>,
+        to: &mut ResumableHandshake<2>,
+    ) -> Vec<u8> {
+        let act = from.next_act().unwrap();
+        assert_eq!(to.push_bytes(&act).unwrap(), act.len());
+        act.to_vec()
+    }
+
+    #[test]
+    fn logging_backend() {
+        let initiator_log = Logging::default();
+        let responder_log = Logging::default();
+        let initiator_ops = initiator_log.0.clone();
+        let responder_ops = responder_log.0.clone();
+
+        let responder_pubkey =
+            SoftwareCrypto.public_key(KeyRole::Static, &key(0x21));
+        let mut initiator = ResumableHandshake::<2>::initiator_with_crypto(
+            &key(0x11),
+            &responder_pubkey,
+            &key(0x12),
+            Box::new(initiator_log),
+        )
+        .unwrap();
+        let mut responder = ResumableHandshake::<2>::responder_with_crypto(
+            &key(0x21),
+            &key(0x22),
+            Box::new(responder_log),
+        );
+        exchange(&mut initiator, &mut responder);
+        exchange(&mut responder, &mut initiator);
+        exchange(&mut initiator, &mut responder);
+
+        let mut initiator = initiator.into_transcoder().unwrap();
+        let mut responder = responder.into_transcoder().unwrap();
+        let frame = initiator.encrypt_buf(b"hello").unwrap();
+        assert_eq!(
+            responder
+                .decrypt_single_message(Some(&frame))
+                .unwrap()
+                .unwrap(),
+            b"hello"
+        );
+
+        assert_eq!(*initiator_ops.lock().unwrap(), [
+            // initialization
+            "public_key(static)",
+            "public_key(ephemeral)",
+            // act one: es
+            "ecdh(ephemeral)",
+            "hkdf",
+            "seal",
+            // act two: ee
+            "ecdh(ephemeral)",
+            "hkdf",
+            "open",
+            // act three: encrypted static key, se, final MAC and split
+            "seal",
+            "ecdh(static)",
+            "hkdf",
+            "seal",
+            "hkdf",
+        ]);
+        assert_eq!(*responder_ops.lock().unwrap(), [
+            // initialization
+            "public_key(static)",
+            "public_key(ephemeral)",
+            // act one: es
+            "ecdh(static)",
+            "hkdf",
+            "open",
+            // act two: ee
+            "ecdh(ephemeral)",
+            "hkdf",
+            "seal",
+            // act three: initiator static key, se, final MAC and split
+            "open",
+            "ecdh(ephemeral)",
+            "hkdf",
+            "open",
+            "hkdf",
+        ]);
+    }
+
+    /// Backend creating a new `secp256k1` context for each operation, as
+    /// [`SoftwareCrypto`] did before switching to the global context
+    #[derive(Debug, Default)]
+    struct PerCallContext;
+
+    impl HandshakeCrypto for PerCallContext {
+        fn public_key(&self, _: KeyRole, key: &SecretKey) -> PublicKey {
+            PublicKey::from_secret_key(&secp256k1::Secp256k1::new(), key)
+        }
+
+        fn ecdh(
+            &self,
+            _: KeyRole,
+            private_key: &SecretKey,
+            public_key: &PublicKey,
+        ) -> Result<SymmetricKey, HandshakeError> {
+            let curve = secp256k1::Secp256k1::new();
+            let scalar =
+                secp256k1::Scalar::from_be_bytes(private_key.secret_bytes())?;
+            let preimage = public_key
+                .mul_tweak(&curve, &scalar)
+                .expect("invalid multiplication")
+                .serialize();
+            Ok(Sha256::hash(&preimage).into_inner())
+        }
+
+        fn hkdf(
+            &self,
+            salt: &[u8],
+            ikm: &[u8],
+        ) -> (SymmetricKey, SymmetricKey) {
+            SoftwareCrypto.hkdf(salt, ikm)
+        }
+
+        fn seal(
+            &self,
+            key: &SymmetricKey,
+            nonce: u64,
+            ad: &[u8],
+            plaintext: &[u8],
+            ciphertext: &mut [u8],
+        ) -> Result<(), EncryptionError> {
+            SoftwareCrypto.seal(key, nonce, ad, plaintext, ciphertext)
+        }
+
+        fn open(
+            &self,
+            key: &SymmetricKey,
+            nonce: u64,
+            ad: &[u8],
+            ciphertext: &[u8],
+            plaintext: &mut [u8],
+        ) -> Result<(), EncryptionError> {
+            SoftwareCrypto.open(key, nonce, ad, ciphertext, plaintext)
+        }
+    }
+
+    /// Runs a complete handshake for the given keys seed, returning all acts
+    /// followed by the first message encrypted by each of the sides
+    fn transcript(
+        seed: u8,
+        initiator_crypto: Box<dyn HandshakeCrypto>,
+        responder_crypto: Box<dyn HandshakeCrypto>,
+    ) -> Vec<Vec<u8>> {
+        let responder_pubkey =
+            SoftwareCrypto.public_key(KeyRole::Static, &key(seed + 2));
+        let mut initiator = ResumableHandshake::<2>::initiator_with_crypto(
+            &key(seed),
+            &responder_pubkey,
+            &key(seed + 1),
+            initiator_crypto,
+        )
+        .unwrap();
+        let mut responder = ResumableHandshake::<2>::responder_with_crypto(
+            &key(seed + 2),
+            &key(seed + 3),
+            responder_crypto,
+        );
+        let mut acts = vec![
+            exchange(&mut initiator, &mut responder),
+            exchange(&mut responder, &mut initiator),
+            exchange(&mut initiator, &mut responder),
+        ];
+        let mut initiator = initiator.into_transcoder().unwrap();
+        let mut responder = responder.into_transcoder().unwrap();
+        acts.push(initiator.encrypt_buf(b"ping").unwrap());
+        acts.push(responder.encrypt_buf(b"pong").unwrap());
+        acts
+    }
+
+    #[test]
+    fn shared_context_transcript() {
+        for seed in [0x01, 0x11, 0x41, 0xa1] {
+            let expected = transcript(
+                seed,
+                Box::new(PerCallContext),
+                Box::new(PerCallContext),
+            );
+            assert_eq!(
+                transcript(
+                    seed,
+                    Box::new(SoftwareCrypto),
+                    Box::new(SoftwareCrypto)
+                ),
+                expected
+            );
+            assert_eq!(
+                transcript(
+                    seed,
+                    Box::new(SoftwareCrypto),
+                    Box::new(PerCallContext)
+                ),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn parallel_handshakes() {
+        let expected: Vec<_> = (1..=8u8)
+            .map(|seed| {
+                transcript(
+                    seed * 4,
+                    Box::new(PerCallContext),
+                    Box::new(PerCallContext),
+                )
+            })
+            .collect();
+        let threads: Vec<_> = (1..=8u8)
+            .map(|seed| {
+                std::thread::spawn(move || {
+                    (0..16)
+                        .map(|_| {
+                            transcript(
+                                seed * 4,
+                                Box::new(SoftwareCrypto),
+                                Box::new(SoftwareCrypto),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for (thread, expected) in threads.into_iter().zip(expected) {
+            for transcript in thread.join().unwrap() {
+                assert_eq!(transcript, expected);
+            }
+        }
+    }
+}
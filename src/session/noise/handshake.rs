@@ -20,9 +20,15 @@ use super::ceremony::{
     EMPTY_ACT_ONE, EMPTY_ACT_THREE, EMPTY_ACT_TWO,
 };
 use super::transcoder::{NoiseTranscoder, SymmetricKey};
-use super::{chacha, hkdf};
+use crate::consts::{ACT_VERSION_SIZE, MAC_SIZE, PUBKEY_SIZE};
 use crate::noise::EncryptionError;
 
+/// End of the public key part of the act message (after the version byte)
+const ACT_PUBKEY_END: usize = ACT_VERSION_SIZE + PUBKEY_SIZE;
+
+/// End of the encrypted static public key with its MAC in the act three
+const ACT_THREE_PUBKEY_END: usize = ACT_PUBKEY_END + MAC_SIZE;
+
 // Alias type to help differentiate between temporary key and chaining key when
 // passing bytes around
 type ChainingKey = [u8; 32];
@@ -50,6 +56,11 @@ pub enum HandshakeError {
 
     #[from]
     InvalidSecretKey(secp256k1::scalar::OutOfRangeError),
+
+    /// Remote peer static key matches the local one, i.e. the node is
+    /// connecting to itself.
+    #[display("self-connection attempt: remote node key matches local one")]
+    SelfConnection,
 }
 
 #[derive(Debug)]
@@ -68,21 +79,53 @@ impl<const LEN_SIZE: usize> HandshakeState<LEN_SIZE> {
         initiator_static_private_key: &SecretKey,
         responder_static_public_key: &PublicKey,
         initiator_ephemeral_private_key: &SecretKey,
+    ) -> Self {
+        Self::new_initiator_with_crypto(
+            initiator_static_private_key,
+            responder_static_public_key,
+            initiator_ephemeral_private_key,
+            Box::new(SoftwareCrypto),
+        )
+    }
+    pub fn new_responder(
+        responder_static_private_key: &SecretKey,
+        responder_ephemeral_private_key: &SecretKey,
+    ) -> Self {
+        Self::new_responder_with_crypto(
+            responder_static_private_key,
+            responder_ephemeral_private_key,
+            Box::new(SoftwareCrypto),
+        )
+    }
+
+    /// Starts initiator handshake performing all cryptographic operations
+    /// with the provided backend.
+    pub fn new_initiator_with_crypto(
+        initiator_static_private_key: &SecretKey,
+        responder_static_public_key: &PublicKey,
+        initiator_ephemeral_private_key: &SecretKey,
+        crypto: Box<dyn HandshakeCrypto>,
     ) -> Self {
         HandshakeState::InitiatorStarting(InitiatorStartingState::new(
             *initiator_static_private_key,
             *initiator_ephemeral_private_key,
             *responder_static_public_key,
+            crypto,
         ))
     }
-    pub fn new_responder(
+
+    /// Starts responder handshake performing all cryptographic operations
+    /// with the provided backend.
+    pub fn new_responder_with_crypto(
         responder_static_private_key: &SecretKey,
         responder_ephemeral_private_key: &SecretKey,
+        crypto: Box<dyn HandshakeCrypto>,
     ) -> Self {
         HandshakeState::ResponderAwaitingActOne(
             ResponderAwaitingActOneState::new(
                 *responder_static_private_key,
                 *responder_ephemeral_private_key,
+                crypto,
             ),
         )
     }
@@ -114,11 +157,165 @@ impl<const LEN_SIZE: usize> HandshakeState<LEN_SIZE> {
 
     pub fn data_len(&self) -> usize {
         match self {
-            HandshakeState::InitiatorStarting(_) => 50,
-            HandshakeState::ResponderAwaitingActOne(_) => 50,
-            HandshakeState::InitiatorAwaitingActTwo(_) => 50,
-            HandshakeState::ResponderAwaitingActThree(_) => 66,
-            HandshakeState::Complete(_) => 66,
+            HandshakeState::InitiatorStarting(_) => ACT_ONE_LENGTH,
+            HandshakeState::ResponderAwaitingActOne(_) => ACT_ONE_LENGTH,
+            HandshakeState::InitiatorAwaitingActTwo(_) => ACT_TWO_LENGTH,
+            HandshakeState::ResponderAwaitingActThree(_) => ACT_THREE_LENGTH,
+            HandshakeState::Complete(_) => ACT_THREE_LENGTH,
+        }
+    }
+
+    /// Returns number of bytes still required to complete the act awaited
+    /// from the remote peer, taking into account already buffered partial
+    /// input. Zero if the state does not await any input.
+    pub fn needs_bytes(&self) -> usize {
+        match self {
+            HandshakeState::InitiatorStarting(_) => 0,
+            HandshakeState::ResponderAwaitingActOne(state) => {
+                state.act_one_builder.remaining()
+            }
+            HandshakeState::InitiatorAwaitingActTwo(state) => {
+                state.act_two_builder.remaining()
+            }
+            HandshakeState::ResponderAwaitingActThree(state) => {
+                state.act_three_builder.remaining()
+            }
+            HandshakeState::Complete(_) => 0,
+        }
+    }
+}
+
+/// Resumable handshake driver for nonblocking I/O.
+///
+/// Input received from the remote peer in any portions is provided with
+/// [`ResumableHandshake::push_bytes`], which buffers it until the whole act
+/// is available. Acts which have to be sent to the remote peer are taken with
+/// [`ResumableHandshake::next_act`]. Since the driver keeps all the state
+/// between the calls, a read failing with `WouldBlock` may simply be retried
+/// later.
+#[derive(Debug)]
+pub struct ResumableHandshake<const LEN_SIZE: usize> {
+    // `None` only after a failure, which is terminal for the handshake
+    state: Option<HandshakeState<LEN_SIZE>>,
+    outgoing: Option<Act>,
+}
+
+impl<const LEN_SIZE: usize> ResumableHandshake<LEN_SIZE> {
+    /// Starts handshake on the initiator side, preparing act one for sending.
+    pub fn initiator(
+        initiator_static_private_key: &SecretKey,
+        responder_static_public_key: &PublicKey,
+        initiator_ephemeral_private_key: &SecretKey,
+    ) -> Result<Self, HandshakeError> {
+        Self::initiator_with_crypto(
+            initiator_static_private_key,
+            responder_static_public_key,
+            initiator_ephemeral_private_key,
+            Box::new(SoftwareCrypto),
+        )
+    }
+
+    /// Starts handshake on the responder side, awaiting act one.
+    pub fn responder(
+        responder_static_private_key: &SecretKey,
+        responder_ephemeral_private_key: &SecretKey,
+    ) -> Self {
+        Self::responder_with_crypto(
+            responder_static_private_key,
+            responder_ephemeral_private_key,
+            Box::new(SoftwareCrypto),
+        )
+    }
+
+    /// Starts handshake on the initiator side using the provided
+    /// cryptographic backend, preparing act one for sending.
+    pub fn initiator_with_crypto(
+        initiator_static_private_key: &SecretKey,
+        responder_static_public_key: &PublicKey,
+        initiator_ephemeral_private_key: &SecretKey,
+        crypto: Box<dyn HandshakeCrypto>,
+    ) -> Result<Self, HandshakeError> {
+        let (act, state) = HandshakeState::new_initiator_with_crypto(
+            initiator_static_private_key,
+            responder_static_public_key,
+            initiator_ephemeral_private_key,
+            crypto,
+        )
+        .next(&[])?;
+        Ok(ResumableHandshake {
+            state: Some(state),
+            outgoing: act,
+        })
+    }
+
+    /// Starts handshake on the responder side using the provided
+    /// cryptographic backend, awaiting act one.
+    pub fn responder_with_crypto(
+        responder_static_private_key: &SecretKey,
+        responder_ephemeral_private_key: &SecretKey,
+        crypto: Box<dyn HandshakeCrypto>,
+    ) -> Self {
+        ResumableHandshake {
+            state: Some(HandshakeState::new_responder_with_crypto(
+                responder_static_private_key,
+                responder_ephemeral_private_key,
+                crypto,
+            )),
+            outgoing: None,
+        }
+    }
+
+    /// Returns number of bytes required to complete the currently awaited
+    /// act. Zero if the handshake does not await input (it is complete, has
+    /// failed or has an act to send first).
+    pub fn needs_bytes(&self) -> usize {
+        match (&self.state, &self.outgoing) {
+            (Some(state), None) => state.needs_bytes(),
+            _ => 0,
+        }
+    }
+
+    /// Buffers input from the remote peer, advancing the handshake once the
+    /// awaited act is complete. Consumes at most [`Self::needs_bytes`] bytes
+    /// and returns the number of consumed bytes; the rest of the input (if
+    /// any) belongs to the data following the handshake or to the next act,
+    /// which must be pushed after the pending act is sent.
+    pub fn push_bytes(
+        &mut self,
+        input: &[u8],
+    ) -> Result<usize, HandshakeError> {
+        let len = self.needs_bytes().min(input.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let state = self.state.take().ok_or_else(|| {
+            HandshakeError::Other(String::from("handshake has already failed"))
+        })?;
+        let (act, state) = state.next(&input[..len])?;
+        self.state = Some(state);
+        self.outgoing = act;
+        Ok(len)
+    }
+
+    /// Takes the act which has to be sent to the remote peer, if any.
+    #[inline]
+    pub fn next_act(&mut self) -> Option<Act> { self.outgoing.take() }
+
+    /// Detects whether the handshake is complete. The last act still may be
+    /// pending for sending.
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, Some(HandshakeState::Complete(_)))
+    }
+
+    /// Returns transcoder for the session if the handshake is complete and
+    /// there are no pending acts left for sending.
+    pub fn into_transcoder(self) -> Option<NoiseTranscoder<LEN_SIZE>> {
+        match (self.state, self.outgoing) {
+            (Some(HandshakeState::Complete(transcoder)), None) => {
+                Some(transcoder)
+            }
+            _ => None,
         }
     }
 }
@@ -133,17 +330,20 @@ pub struct InitiatorStartingState {
     responder_static_public_key: PublicKey,
     chaining_key: Sha256,
     hash: Sha256,
+    crypto: Box<dyn HandshakeCrypto>,
 }
 
 // Handshake state of the Responder prior to receiving Act 1
 #[derive(Debug)]
 pub struct ResponderAwaitingActOneState {
     responder_static_private_key: SecretKey,
+    responder_static_public_key: PublicKey,
     responder_ephemeral_private_key: SecretKey,
     responder_ephemeral_public_key: PublicKey,
     chaining_key: Sha256,
     hash: Sha256,
     act_one_builder: ActBuilder,
+    crypto: Box<dyn HandshakeCrypto>,
 }
 
 // Handshake state of the Initiator prior to receiving Act 2
@@ -156,16 +356,19 @@ pub struct InitiatorAwaitingActTwoState {
     chaining_key: ChainingKey,
     hash: Sha256,
     act_two_builder: ActBuilder,
+    crypto: Box<dyn HandshakeCrypto>,
 }
 
 // Handshake state of the Responder prior to receiving Act 3
 #[derive(Debug)]
 pub struct ResponderAwaitingActThreeState {
     hash: Sha256,
+    responder_static_public_key: PublicKey,
     responder_ephemeral_private_key: SecretKey,
     chaining_key: ChainingKey,
     temporary_key: [u8; 32],
     act_three_builder: ActBuilder,
+    crypto: Box<dyn HandshakeCrypto>,
 }
 
 impl InitiatorStartingState {
@@ -173,13 +376,14 @@ impl InitiatorStartingState {
         initiator_static_private_key: SecretKey,
         initiator_ephemeral_private_key: SecretKey,
         responder_static_public_key: PublicKey,
+        crypto: Box<dyn HandshakeCrypto>,
     ) -> Self {
         let initiator_static_public_key =
-            private_key_to_public_key(&initiator_static_private_key);
+            crypto.public_key(KeyRole::Static, &initiator_static_private_key);
         let (hash, chaining_key) =
             initialize_handshake_state(&responder_static_public_key);
-        let initiator_ephemeral_public_key =
-            private_key_to_public_key(&initiator_ephemeral_private_key);
+        let initiator_ephemeral_public_key = crypto
+            .public_key(KeyRole::Ephemeral, &initiator_ephemeral_private_key);
         InitiatorStartingState {
             initiator_static_private_key,
             initiator_static_public_key,
@@ -188,6 +392,7 @@ impl InitiatorStartingState {
             responder_static_public_key,
             chaining_key,
             hash,
+            crypto,
         }
     }
 
@@ -211,10 +416,12 @@ impl InitiatorStartingState {
         let responder_static_public_key = self.responder_static_public_key;
         let chaining_key = self.chaining_key;
         let hash = self.hash;
+        let crypto = self.crypto;
 
         // serialize act one
         let mut act_one = EMPTY_ACT_ONE;
         let (hash, chaining_key, _) = calculate_act_message(
+            crypto.as_ref(),
             &initiator_ephemeral_private_key,
             &initiator_ephemeral_public_key,
             &responder_static_public_key,
@@ -234,6 +441,7 @@ impl InitiatorStartingState {
                     chaining_key,
                     hash,
                     act_two_builder: ActBuilder::new(Act::Two(EMPTY_ACT_TWO)),
+                    crypto,
                 },
             ),
         ))
@@ -244,21 +452,24 @@ impl ResponderAwaitingActOneState {
     pub fn new(
         responder_static_private_key: SecretKey,
         responder_ephemeral_private_key: SecretKey,
+        crypto: Box<dyn HandshakeCrypto>,
     ) -> Self {
         let responder_static_public_key =
-            private_key_to_public_key(&responder_static_private_key);
+            crypto.public_key(KeyRole::Static, &responder_static_private_key);
         let (hash, chaining_key) =
             initialize_handshake_state(&responder_static_public_key);
-        let responder_ephemeral_public_key =
-            private_key_to_public_key(&responder_ephemeral_private_key);
+        let responder_ephemeral_public_key = crypto
+            .public_key(KeyRole::Ephemeral, &responder_ephemeral_private_key);
 
         ResponderAwaitingActOneState {
             responder_static_private_key,
+            responder_static_public_key,
             responder_ephemeral_private_key,
             responder_ephemeral_public_key,
             chaining_key,
             hash,
             act_one_builder: ActBuilder::new(Act::One(EMPTY_ACT_ONE)),
+            crypto,
         }
     }
 
@@ -287,6 +498,8 @@ impl ResponderAwaitingActOneState {
                 HandshakeState::ResponderAwaitingActOne(Self {
                     responder_static_private_key: self
                         .responder_static_private_key,
+                    responder_static_public_key: self
+                        .responder_static_public_key,
                     responder_ephemeral_private_key: self
                         .responder_ephemeral_private_key,
                     responder_ephemeral_public_key: self
@@ -294,22 +507,27 @@ impl ResponderAwaitingActOneState {
                     chaining_key: self.chaining_key,
                     hash: self.hash,
                     act_one_builder,
+                    crypto: self.crypto,
                 }),
             ));
         }
 
         let hash = self.hash;
         let responder_static_private_key = self.responder_static_private_key;
+        let responder_static_public_key = self.responder_static_public_key;
         let chaining_key = self.chaining_key;
         let responder_ephemeral_private_key =
             self.responder_ephemeral_private_key;
         let responder_ephemeral_public_key =
             self.responder_ephemeral_public_key;
+        let crypto = self.crypto;
         let act_one = Act::from(act_one_builder);
 
         let (initiator_ephemeral_public_key, hash, chaining_key, _) =
             process_act_message(
+                crypto.as_ref(),
                 &act_one,
+                KeyRole::Static,
                 &responder_static_private_key,
                 chaining_key.into_inner(),
                 hash,
@@ -317,6 +535,7 @@ impl ResponderAwaitingActOneState {
 
         let mut act_two = EMPTY_ACT_TWO;
         let (hash, chaining_key, temporary_key) = calculate_act_message(
+            crypto.as_ref(),
             &responder_ephemeral_private_key,
             &responder_ephemeral_public_key,
             &initiator_ephemeral_public_key,
@@ -330,12 +549,14 @@ impl ResponderAwaitingActOneState {
             HandshakeState::ResponderAwaitingActThree(
                 ResponderAwaitingActThreeState {
                     hash,
+                    responder_static_public_key,
                     responder_ephemeral_private_key,
                     chaining_key,
                     temporary_key,
                     act_three_builder: ActBuilder::new(Act::Three(
                         EMPTY_ACT_THREE,
                     )),
+                    crypto,
                 },
             ),
         ))
@@ -377,6 +598,7 @@ impl InitiatorAwaitingActTwoState {
                     chaining_key: self.chaining_key,
                     hash: self.hash,
                     act_two_builder,
+                    crypto: self.crypto,
                 }),
             ));
         }
@@ -388,11 +610,14 @@ impl InitiatorAwaitingActTwoState {
         let responder_static_public_key = self.responder_static_public_key;
         let hash = self.hash;
         let chaining_key = self.chaining_key;
+        let crypto = self.crypto;
         let act_two = Act::from(act_two_builder);
 
         let (responder_ephemeral_public_key, hash, chaining_key, temporary_key) =
             process_act_message(
+                crypto.as_ref(),
                 &act_two,
+                KeyRole::Ephemeral,
                 &initiator_ephemeral_private_key,
                 chaining_key,
                 hash,
@@ -402,37 +627,41 @@ impl InitiatorAwaitingActTwoState {
 
         // start serializing act three
         // 1. c = encryptWithAD(temp_k2, 1, h, s.pub.serializeCompressed())
-        chacha::encrypt(
+        crypto.seal(
             &temporary_key,
             1,
             &hash,
             &initiator_static_public_key.serialize(),
-            &mut act_three[1..50],
+            &mut act_three[ACT_VERSION_SIZE..ACT_THREE_PUBKEY_END],
         )?;
 
         // 2. h = SHA-256(h || c)
-        let hash = concat_then_sha256!(hash, act_three[1..50]);
+        let hash = concat_then_sha256!(
+            hash,
+            act_three[ACT_VERSION_SIZE..ACT_THREE_PUBKEY_END]
+        );
 
         // 3. se = ECDH(s.priv, re)
-        let ecdh = ecdh(
+        let ecdh = crypto.ecdh(
+            KeyRole::Static,
             &initiator_static_private_key,
             &responder_ephemeral_public_key,
         )?;
 
         // 4. ck, temp_k3 = HKDF(ck, se)
-        let (chaining_key, temporary_key) = hkdf::derive(&chaining_key, &ecdh);
+        let (chaining_key, temporary_key) = crypto.hkdf(&chaining_key, &ecdh);
 
         // 5. t = encryptWithAD(temp_k3, 0, h, zero)
-        chacha::encrypt(
+        crypto.seal(
             &temporary_key,
             0,
             &hash,
             &[0; 0],
-            &mut act_three[50..],
+            &mut act_three[ACT_THREE_PUBKEY_END..],
         )?;
 
         // 6. sk, rk = HKDF(ck, zero)
-        let (sending_key, receiving_key) = hkdf::derive(&chaining_key, &[0; 0]);
+        let (sending_key, receiving_key) = crypto.hkdf(&chaining_key, &[0; 0]);
 
         // 7. rn = 0, sn = 0
         // - done by Conduit
@@ -469,20 +698,25 @@ impl ResponderAwaitingActThreeState {
                 None,
                 HandshakeState::ResponderAwaitingActThree(Self {
                     hash: self.hash,
+                    responder_static_public_key: self
+                        .responder_static_public_key,
                     responder_ephemeral_private_key: self
                         .responder_ephemeral_private_key,
                     chaining_key: self.chaining_key,
                     temporary_key: self.temporary_key,
                     act_three_builder,
+                    crypto: self.crypto,
                 }),
             ));
         }
 
         let hash = self.hash;
         let temporary_key = self.temporary_key;
+        let responder_static_public_key = self.responder_static_public_key;
         let responder_ephemeral_private_key =
             self.responder_ephemeral_private_key;
         let chaining_key = self.chaining_key;
+        let crypto = self.crypto;
 
         // 1. Read exactly 66 bytes from the network buffer
         let act_three_bytes = Act::from(act_three_builder);
@@ -490,8 +724,9 @@ impl ResponderAwaitingActThreeState {
 
         // 2. Parse the read message (m) into v, c, and t
         let version = act_three_bytes[0];
-        let tagged_encrypted_pubkey = &act_three_bytes[1..50];
-        let chacha_tag = &act_three_bytes[50..];
+        let tagged_encrypted_pubkey =
+            &act_three_bytes[ACT_VERSION_SIZE..ACT_THREE_PUBKEY_END];
+        let chacha_tag = &act_three_bytes[ACT_THREE_PUBKEY_END..];
 
         // 3. If v is an unrecognized handshake version, then the responder MUST
         // abort the connection attempt.
@@ -503,8 +738,8 @@ impl ResponderAwaitingActThreeState {
         }
 
         // 4. rs = decryptWithAD(temp_k2, 1, h, c)
-        let mut remote_pubkey = [0; 33];
-        chacha::decrypt(
+        let mut remote_pubkey = [0; PUBKEY_SIZE];
+        crypto.open(
             &temporary_key,
             1,
             &hash,
@@ -524,16 +759,26 @@ impl ResponderAwaitingActThreeState {
         let hash = concat_then_sha256!(hash, tagged_encrypted_pubkey);
 
         // 6. se = ECDH(e.priv, rs)
-        let ecdh = ecdh(&responder_ephemeral_private_key, &initiator_pubkey)?;
+        let ecdh = crypto.ecdh(
+            KeyRole::Ephemeral,
+            &responder_ephemeral_private_key,
+            &initiator_pubkey,
+        )?;
 
         // 7. ck, temp_k3 = HKDF(ck, se)
-        let (chaining_key, temporary_key) = hkdf::derive(&chaining_key, &ecdh);
+        let (chaining_key, temporary_key) = crypto.hkdf(&chaining_key, &ecdh);
 
         // 8. p = decryptWithAD(temp_k3, 0, h, t)
-        chacha::decrypt(&temporary_key, 0, &hash, chacha_tag, &mut [0; 0])?;
+        crypto.open(&temporary_key, 0, &hash, chacha_tag, &mut [0; 0])?;
+
+        // Abort if the node has connected to itself, i.e. the now
+        // authenticated initiator key is our own static key
+        if initiator_pubkey == responder_static_public_key {
+            return Err(HandshakeError::SelfConnection);
+        }
 
         // 9. rk, sk = HKDF(ck, zero)
-        let (receiving_key, sending_key) = hkdf::derive(&chaining_key, &[0; 0]);
+        let (receiving_key, sending_key) = crypto.hkdf(&chaining_key, &[0; 0]);
 
         // 10. rn = 0, sn = 0
         // - done by Conduit
@@ -580,6 +825,7 @@ fn initialize_handshake_state(
 // process both https://github.com/lightningnetwork/lightning-rfc/blob/master/08-transport.md#act-one (sender)
 // https://github.com/lightningnetwork/lightning-rfc/blob/master/08-transport.md#act-two (sender)
 fn calculate_act_message(
+    crypto: &dyn HandshakeCrypto,
     local_private_ephemeral_key: &SecretKey,
     local_public_ephemeral_key: &PublicKey,
     remote_public_key: &PublicKey,
@@ -594,22 +840,33 @@ fn calculate_act_message(
 
     // 3. ACT1: es = ECDH(e.priv, rs)
     // 3. ACT2: es = ECDH(e.priv, re)
-    let ecdh = ecdh(local_private_ephemeral_key, remote_public_key)?;
+    let ecdh = crypto.ecdh(
+        KeyRole::Ephemeral,
+        local_private_ephemeral_key,
+        remote_public_key,
+    )?;
 
     // 4. ACT1: ck, temp_k1 = HKDF(ck, es)
     // 4. ACT2: ck, temp_k2 = HKDF(ck, ee)
-    let (chaining_key, temporary_key) = hkdf::derive(&chaining_key, &ecdh);
+    let (chaining_key, temporary_key) = crypto.hkdf(&chaining_key, &ecdh);
 
     // 5. ACT1: c = encryptWithAD(temp_k1, 0, h, zero)
     // 5. ACT2: c = encryptWithAD(temp_k2, 0, h, zero)
-    chacha::encrypt(&temporary_key, 0, &hash, &[0; 0], &mut act_out[34..])?;
+    crypto.seal(
+        &temporary_key,
+        0,
+        &hash,
+        &[0; 0],
+        &mut act_out[ACT_PUBKEY_END..],
+    )?;
 
     // 6. h = SHA-256(h || c)
-    let hash = concat_then_sha256!(hash, &act_out[34..]);
+    let hash = concat_then_sha256!(hash, &act_out[ACT_PUBKEY_END..]);
 
     // Send m = 0 || e.pub.serializeCompressed() || c
     act_out[0] = 0;
-    act_out[1..34].copy_from_slice(&serialized_local_public_key);
+    act_out[ACT_VERSION_SIZE..ACT_PUBKEY_END]
+        .copy_from_slice(&serialized_local_public_key);
 
     Ok((hash, chaining_key, temporary_key))
 }
@@ -618,7 +875,9 @@ fn calculate_act_message(
 // process both https://github.com/lightningnetwork/lightning-rfc/blob/master/08-transport.md#act-one (receiver)
 // https://github.com/lightningnetwork/lightning-rfc/blob/master/08-transport.md#act-two (receiver)
 fn process_act_message(
+    crypto: &dyn HandshakeCrypto,
     act_bytes: &[u8],
+    local_key_role: KeyRole,
     local_private_key: &SecretKey,
     chaining_key: ChainingKey,
     hash: Sha256,
@@ -631,8 +890,9 @@ fn process_act_message(
 
     // 2.Parse the read message (m) into v, re, and c
     let version = act_bytes[0];
-    let ephemeral_public_key_bytes = &act_bytes[1..34];
-    let chacha_tag = &act_bytes[34..];
+    let ephemeral_public_key_bytes =
+        &act_bytes[ACT_VERSION_SIZE..ACT_PUBKEY_END];
+    let chacha_tag = &act_bytes[ACT_PUBKEY_END..];
 
     let ephemeral_public_key = if let Ok(public_key) =
         PublicKey::from_slice(ephemeral_public_key_bytes)
@@ -656,15 +916,19 @@ fn process_act_message(
 
     // 5. Act1: es = ECDH(s.priv, re)
     // 5. Act2: ee = ECDH(e.priv, ee)
-    let ecdh = ecdh(local_private_key, &ephemeral_public_key)?;
+    let ecdh = crypto.ecdh(
+        local_key_role,
+        local_private_key,
+        &ephemeral_public_key,
+    )?;
 
     // 6. Act1: ck, temp_k1 = HKDF(ck, es)
     // 6. Act2: ck, temp_k2 = HKDF(ck, ee)
-    let (chaining_key, temporary_key) = hkdf::derive(&chaining_key, &ecdh);
+    let (chaining_key, temporary_key) = crypto.hkdf(&chaining_key, &ecdh);
 
     // 7. Act1: p = decryptWithAD(temp_k1, 0, h, c)
     // 7. Act2: p = decryptWithAD(temp_k2, 0, h, c)
-    chacha::decrypt(&temporary_key, 0, &hash, chacha_tag, &mut [0; 0])?;
+    crypto.open(&temporary_key, 0, &hash, chacha_tag, &mut [0; 0])?;
 
     // 8. h = SHA-256(h || c)
     let hash = concat_then_sha256!(hash, chacha_tag);
@@ -672,25 +936,6 @@ fn process_act_message(
     Ok((ephemeral_public_key, hash, chaining_key, temporary_key))
 }
 
-fn private_key_to_public_key(private_key: &SecretKey) -> PublicKey {
-    let curve = secp256k1::Secp256k1::new();
-
-    PublicKey::from_secret_key(&curve, private_key)
-}
-
-fn ecdh(
-    private_key: &SecretKey,
-    public_key: &PublicKey,
-) -> Result<SymmetricKey, secp256k1::scalar::OutOfRangeError> {
-    let curve = secp256k1::Secp256k1::new();
-    let scalar = secp256k1::Scalar::from_be_bytes(private_key.secret_bytes())?;
-    let preimage = public_key
-        .mul_tweak(&curve, &scalar)
-        .expect("invalid multiplication")
-        .serialize();
-    Ok(concat_then_sha256!(preimage).into_inner())
-}
-
 #[cfg(test)]
 // Reference RFC test vectors for hard-coded values
 // https://github.com/lightningnetwork/lightning-rfc/blob/master/08-transport.md#appendix-a-transport-test-vectors
@@ -736,10 +981,12 @@ mod test {
                 initiator_static_private_key,
                 initiator_ephemeral_private_key,
                 responder_static_public_key,
+                Box::new(SoftwareCrypto),
             );
             let responder = ResponderAwaitingActOneState::new(
                 responder_static_private_key,
                 responder_ephemeral_private_key,
+                Box::new(SoftwareCrypto),
             );
 
             TestCtx {
@@ -1107,6 +1354,44 @@ mod test {
         );
     }
 
+    // Responder::AwaitingActThree -> Error (self-connection)
+    #[test]
+    fn awaiting_act_three_self_connection() {
+        let curve = secp256k1::Secp256k1::new();
+        let static_private_key =
+            SecretKey::from_slice(&[0x_21_u8; 32]).unwrap();
+        let static_public_key =
+            PublicKey::from_secret_key(&curve, &static_private_key);
+
+        let initiator = HandshakeState::<2>::new_initiator(
+            &static_private_key,
+            &static_public_key,
+            &SecretKey::from_slice(&[0x_12_u8; 32]).unwrap(),
+        );
+        let responder = HandshakeState::<2>::new_responder(
+            &static_private_key,
+            &SecretKey::from_slice(&[0x_22_u8; 32]).unwrap(),
+        );
+
+        let (act1, awaiting_act_two_state) = do_next_or_panic!(initiator, &[]);
+        let (act2, awaiting_act_three_state) =
+            do_next_or_panic!(responder, &act1);
+        let (act3, complete_state) =
+            do_next_or_panic!(awaiting_act_two_state, &act2);
+
+        // Initiator learns about self-connection from the transcoder remote
+        // key
+        if let Complete(transcoder) = complete_state {
+            assert_eq!(transcoder.remote_pubkey(), static_public_key);
+        } else {
+            panic!();
+        }
+        assert_eq!(
+            awaiting_act_three_state.next(&act3).err().unwrap(),
+            HandshakeError::SelfConnection
+        );
+    }
+
     // Initiator::Complete -> Error
     #[test]
     #[should_panic(expected = "nothing to process")]
@@ -1165,4 +1450,90 @@ mod test {
         assert_eq!(act3.as_ref().to_hex(),
 				   "00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba");
     }
+
+    fn resumable_pair() -> (ResumableHandshake<2>, ResumableHandshake<2>) {
+        let curve = secp256k1::Secp256k1::new();
+        let responder_key = SecretKey::from_slice(&[0x_21_u8; 32]).unwrap();
+        let initiator = ResumableHandshake::initiator(
+            &SecretKey::from_slice(&[0x_11_u8; 32]).unwrap(),
+            &PublicKey::from_secret_key(&curve, &responder_key),
+            &SecretKey::from_slice(&[0x_12_u8; 32]).unwrap(),
+        )
+        .unwrap();
+        let responder = ResumableHandshake::responder(
+            &responder_key,
+            &SecretKey::from_slice(&[0x_22_u8; 32]).unwrap(),
+        );
+        (initiator, responder)
+    }
+
+    // Pushes act split at the given positions, checking that the handshake
+    // does not advance until the last byte is provided
+    fn feed(
+        handshake: &mut ResumableHandshake<2>,
+        act: &[u8],
+        splits: &[usize],
+    ) {
+        let mut pos = 0;
+        for end in splits.iter().copied().chain([act.len()]) {
+            assert_eq!(handshake.needs_bytes(), act.len() - pos);
+            assert!(handshake.next_act().is_none());
+            assert_eq!(
+                handshake.push_bytes(&act[pos..end]).unwrap(),
+                end - pos
+            );
+            pos = end;
+        }
+        assert_eq!(handshake.needs_bytes(), 0);
+    }
+
+    // Runs resumable handshake, returning debug representation of the
+    // resulting initiator and responder transcoders
+    fn run_resumable(splits: impl Fn(usize) -> Vec<usize>) -> (String, String) {
+        let test_ctx = TestCtx::new();
+        let (mut initiator, mut responder) = resumable_pair();
+
+        let act1 = initiator.next_act().unwrap();
+        assert_eq!(act1.as_ref(), test_ctx.valid_act1.as_slice());
+        feed(&mut responder, &act1, &splits(act1.len()));
+
+        let act2 = responder.next_act().unwrap();
+        assert_eq!(act2.as_ref(), test_ctx.valid_act2.as_slice());
+        feed(&mut initiator, &act2, &splits(act2.len()));
+        assert!(initiator.is_complete());
+
+        let act3 = initiator.next_act().unwrap();
+        assert_eq!(act3.as_ref(), test_ctx.valid_act3.as_slice());
+        feed(&mut responder, &act3, &splits(act3.len()));
+        assert!(responder.is_complete());
+        assert!(responder.next_act().is_none());
+
+        (
+            format!("{:?}", initiator.into_transcoder().unwrap()),
+            format!("{:?}", responder.into_transcoder().unwrap()),
+        )
+    }
+
+    #[test]
+    fn resumable_partial_acts() {
+        let reference = run_resumable(|_| vec![]);
+        assert_eq!(run_resumable(|len| (1..len).collect()), reference);
+        for split in 0..=ACT_THREE_LENGTH {
+            assert_eq!(run_resumable(|len| vec![split.min(len)]), reference);
+        }
+    }
+
+    #[test]
+    fn resumable_leaves_excess_input() {
+        let test_ctx = TestCtx::new();
+        let (_, mut responder) = resumable_pair();
+        let mut input = test_ctx.valid_act1.clone();
+        input.extend([0xFF; 10]);
+        assert_eq!(responder.push_bytes(&input).unwrap(), ACT_ONE_LENGTH);
+        assert_eq!(responder.needs_bytes(), 0);
+        assert_eq!(responder.push_bytes(&[0xFF]).unwrap(), 0);
+        assert!(responder.next_act().is_some());
+        assert_eq!(responder.needs_bytes(), ACT_THREE_LENGTH);
+        assert!(responder.into_transcoder().is_none());
+    }
 }
@@ -12,13 +12,17 @@
 // If not, see <https://opensource.org/licenses/MIT>.
 
 mod ceremony;
+// Raw AEAD primitives used by the handshake and transcoder; not a stable API
+#[doc(hidden)]
 pub mod chacha;
+mod crypto;
 mod handshake;
 mod hkdf;
 mod transcoder;
 
-pub use handshake::{HandshakeError, HandshakeState};
+pub use crypto::{HandshakeCrypto, KeyRole, SoftwareCrypto};
+pub use handshake::{HandshakeError, HandshakeState, ResumableHandshake};
 pub use transcoder::{
-    EncryptionError, FramingProtocol, NoiseDecryptor, NoiseEncryptor,
-    NoiseTranscoder, KEY_ROTATION_PERIOD,
+    EncryptionError, FramePart, FramingProtocol, NoiseDecryptor,
+    NoiseEncryptor, NoiseTranscoder, KEY_ROTATION_PERIOD,
 };
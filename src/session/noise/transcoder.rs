@@ -18,8 +18,15 @@ use amplify::Bipolar;
 
 use super::handshake::HandshakeError;
 use super::{chacha, hkdf};
+pub use crate::consts::KEY_ROTATION_PERIOD;
+use crate::consts::{
+    BRONTIDE_LEN_SIZE, BRONTIDE_MSG_MAX_LEN, BRONTOZAUR_LEN_SIZE,
+    BRONTOZAUR_MSG_MAX_LEN,
+};
 #[cfg(feature = "keygen")]
-use crate::session::noise::HandshakeState;
+use crate::session::noise::{
+    HandshakeCrypto, KeyRole, ResumableHandshake, SoftwareCrypto,
+};
 use crate::session::transcoders::{Decrypt, Encrypt, Transcode};
 #[cfg(feature = "keygen")]
 use crate::{transport, DuplexConnection};
@@ -35,8 +42,8 @@ pub enum FramingProtocol {
 impl From<usize> for FramingProtocol {
     fn from(len: usize) -> Self {
         match len {
-            2 => FramingProtocol::Brontide,
-            3 => FramingProtocol::Brontozaur,
+            BRONTIDE_LEN_SIZE => FramingProtocol::Brontide,
+            BRONTOZAUR_LEN_SIZE => FramingProtocol::Brontozaur,
             _ => unreachable!("invalid Noise_XK protocol ids"),
         }
     }
@@ -45,17 +52,28 @@ impl From<usize> for FramingProtocol {
 impl FramingProtocol {
     pub const fn message_len_size(self) -> usize {
         match self {
-            FramingProtocol::Brontide => 2,
-            FramingProtocol::Brontozaur => 3,
+            FramingProtocol::Brontide => BRONTIDE_LEN_SIZE,
+            FramingProtocol::Brontozaur => BRONTOZAUR_LEN_SIZE,
         }
     }
 
     pub const fn header_size(self) -> usize {
         self.message_len_size() + chacha::TAG_SIZE
     }
-}
 
-pub const KEY_ROTATION_PERIOD: u32 = 1000;
+    /// Size of the encrypted frame in excess of the message payload
+    pub const fn frame_overhead(self) -> usize {
+        self.header_size() + chacha::TAG_SIZE
+    }
+
+    /// Maximum length of the message which can be put into a single frame
+    pub const fn max_message_len(self) -> usize {
+        match self {
+            FramingProtocol::Brontide => BRONTIDE_MSG_MAX_LEN,
+            FramingProtocol::Brontozaur => BRONTOZAUR_MSG_MAX_LEN,
+        }
+    }
+}
 
 #[derive(
     Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error,
@@ -73,6 +91,26 @@ pub enum EncryptionError {
 
     /// message provided for a Noise protocol has incorrect length
     ExpectedMessageLenMismatch,
+
+    /// MAC verification failed for the {part} of the incoming message with
+    /// nonce {counter}; the stream is corrupted or desynchronized.
+    MacFailure { counter: u32, part: FramePart },
+
+    /// transcoder is poisoned by a previous MAC failure or by a failure to
+    /// send an encrypted frame.
+    Poisoned,
+}
+
+/// Part of the Noise_XK frame, used for error reporting
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum FramePart {
+    /// Encrypted message length prefix
+    #[display("length prefix")]
+    LengthPrefix,
+
+    /// Encrypted message body
+    #[display("body")]
+    Body,
 }
 
 #[derive(Debug)]
@@ -82,6 +120,39 @@ pub struct NoiseEncryptor<const LEN_SIZE: usize> {
     sending_chaining_key: SymmetricKey,
     sending_nonce: u32,
     remote_pubkey: secp256k1::PublicKey,
+    // Set once an encrypted frame was not delivered to the remote peer, so
+    // its receiving nonce lags behind ours
+    poisoned: bool,
+    #[cfg(debug_assertions)]
+    send_log: SendLog,
+}
+
+/// Rolling hash over the lengths of a sequence of frames
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+struct FrameLenHash {
+    frames: u64,
+    hash: u64,
+}
+
+#[cfg(debug_assertions)]
+impl FrameLenHash {
+    fn update(&mut self, len: usize) {
+        self.frames += 1;
+        // FNV-1a step over the whole length value
+        self.hash = (self.hash ^ len as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+}
+
+/// Debug-build safety net against send path desynchronization: tracks frames
+/// produced by the encryptor and frames reported as handed to the transport.
+/// If they ever diverge, the nonce has advanced past data which never reached
+/// the remote peer.
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone, Default, Debug)]
+struct SendLog {
+    encrypted: FrameLenHash,
+    sent: FrameLenHash,
 }
 
 impl<const LEN_SIZE: usize> NoiseEncryptor<LEN_SIZE> {
@@ -89,16 +160,74 @@ impl<const LEN_SIZE: usize> NoiseEncryptor<LEN_SIZE> {
         LEN_SIZE + chacha::TAG_SIZE;
     const MESSAGE_LEN_SIZE: usize = LEN_SIZE;
 
+    /// Encrypts the message into a frame. The sending nonce advances only if
+    /// the whole frame was encrypted successfully.
     pub fn encrypt_buf(
         &mut self,
         buffer: &[u8],
+    ) -> Result<Vec<u8>, EncryptionError> {
+        if self.poisoned {
+            return Err(EncryptionError::Poisoned);
+        }
+        let key = self.sending_key;
+        let chaining_key = self.sending_chaining_key;
+        let nonce = self.sending_nonce;
+        let frame = self.encrypt_frame(buffer).map_err(|err| {
+            // Encryption of the length prefix may have already advanced the
+            // nonce and rotated the key
+            self.sending_key = key;
+            self.sending_chaining_key = chaining_key;
+            self.sending_nonce = nonce;
+            err
+        })?;
+        #[cfg(debug_assertions)]
+        self.send_log.encrypted.update(frame.len());
+        Ok(frame)
+    }
+
+    /// Registers the frame produced by [`NoiseEncryptor::encrypt_buf`] as
+    /// fully handed to the transport.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, if the frame is not the last one produced by the
+    /// encryptor, or some earlier frame was never reported as sent. This
+    /// means that the sending nonce has advanced past data which did not
+    /// reach the remote peer, so it will fail to decrypt subsequent frames.
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub fn frame_sent(&mut self, frame: &[u8]) {
+        #[cfg(debug_assertions)]
+        {
+            let log = &mut self.send_log;
+            log.sent.update(frame.len());
+            assert!(
+                log.sent == log.encrypted,
+                "noise send path desynchronized at sending nonce {}: {} \
+                 frames were encrypted while {} frames were sent, or the sent \
+                 frame of {} bytes was not the last one encrypted",
+                self.sending_nonce,
+                log.encrypted.frames,
+                log.sent.frames,
+                frame.len()
+            );
+        }
+    }
+
+    /// Detects whether the encryptor is poisoned by a frame which has failed
+    /// to be sent.
+    #[inline]
+    pub fn is_poisoned(&self) -> bool { self.poisoned }
+
+    fn encrypt_frame(
+        &mut self,
+        buffer: &[u8],
     ) -> Result<Vec<u8>, EncryptionError> {
         let length = buffer.len();
         let length_bytes = match FramingProtocol::from(LEN_SIZE) {
-            FramingProtocol::Brontide if length > u16::MAX as usize => {
+            FramingProtocol::Brontide if length > BRONTIDE_MSG_MAX_LEN => {
                 return Err(EncryptionError::ExceedingMaxLength(length))
             }
-            FramingProtocol::Brontozaur if length > u24::MAX.into_usize() => {
+            FramingProtocol::Brontozaur if length > BRONTOZAUR_MSG_MAX_LEN => {
                 return Err(EncryptionError::ExceedingMaxLength(length))
             }
             FramingProtocol::Brontide => (length as u16).to_be_bytes().to_vec(),
@@ -152,6 +281,30 @@ impl<const LEN_SIZE: usize> Encrypt for NoiseEncryptor<LEN_SIZE> {
             Err(_) => Vec::new(),
         }
     }
+
+    #[inline]
+    fn frame_sent(&mut self, frame: &[u8]) {
+        // Failed encryption produces an empty frame, which is not logged
+        if !frame.is_empty() {
+            NoiseEncryptor::frame_sent(self, frame)
+        }
+    }
+
+    #[inline]
+    fn poison(&mut self) { self.poisoned = true; }
+
+    #[inline]
+    fn is_poisoned(&self) -> bool { self.poisoned }
+
+    #[inline]
+    fn frame_overhead(&self) -> usize {
+        FramingProtocol::from(LEN_SIZE).frame_overhead()
+    }
+
+    #[inline]
+    fn max_payload_len(&self) -> usize {
+        FramingProtocol::from(LEN_SIZE).max_message_len()
+    }
 }
 
 #[derive(Debug)]
@@ -177,6 +330,11 @@ impl<const LEN_SIZE: usize> NoiseDecryptor<LEN_SIZE> {
         read_buffer.extend_from_slice(data);
     }
 
+    /// Detects whether a MAC verification failure has happened before, in
+    /// which case the decryptor refuses to process any further data.
+    #[inline]
+    pub fn is_poisoned(&self) -> bool { self.poisoned }
+
     /// Decrypt a single message. If data containing more than one message has
     /// been received, only the first message will be returned, and the rest
     /// stored in the internal buffer. If a message pending in the buffer
@@ -186,6 +344,10 @@ impl<const LEN_SIZE: usize> NoiseDecryptor<LEN_SIZE> {
         &mut self,
         new_data: Option<&[u8]>,
     ) -> Result<Option<Vec<u8>>, EncryptionError> {
+        if self.poisoned {
+            return Err(EncryptionError::Poisoned);
+        }
+
         let mut read_buffer = if let Some(buffer) = self.read_buffer.take() {
             buffer
         } else {
@@ -196,7 +358,12 @@ impl<const LEN_SIZE: usize> NoiseDecryptor<LEN_SIZE> {
             read_buffer.extend_from_slice(data);
         }
 
-        let (current_message, offset) = self.decrypt_buf(&read_buffer[..])?;
+        let res = self.decrypt_buf(&read_buffer[..]);
+        if let Err(EncryptionError::MacFailure { .. }) = res {
+            // The stream is desynchronized and can't be trusted anymore
+            self.poisoned = true;
+        }
+        let (current_message, offset) = res?;
         read_buffer.drain(..offset); // drain the read buffer
         self.read_buffer = Some(read_buffer); // assign the new value to the built-in buffer
         Ok(current_message)
@@ -219,6 +386,7 @@ impl<const LEN_SIZE: usize> NoiseDecryptor<LEN_SIZE> {
             let encrypted_length =
                 &buffer[0..Self::TAGGED_MESSAGE_LENGTH_HEADER_SIZE];
 
+            let counter = self.receiving_nonce;
             let mut decrypt =
                 |length_bytes: &mut [u8]| -> Result<(), EncryptionError> {
                     chacha::decrypt(
@@ -227,7 +395,10 @@ impl<const LEN_SIZE: usize> NoiseDecryptor<LEN_SIZE> {
                         &[0; 0],
                         encrypted_length,
                         length_bytes,
-                    )?;
+                    )
+                    .map_err(|err| {
+                        mac_failure(err, counter, FramePart::LengthPrefix)
+                    })?;
                     self.increment_nonce();
                     Ok(())
                 };
@@ -262,13 +433,15 @@ impl<const LEN_SIZE: usize> NoiseDecryptor<LEN_SIZE> {
             &buffer[Self::TAGGED_MESSAGE_LENGTH_HEADER_SIZE..message_end_index];
         let mut message = vec![0u8; message_length];
 
+        let counter = self.receiving_nonce;
         chacha::decrypt(
             &self.receiving_key,
             self.receiving_nonce as u64,
             &[0; 0],
             encrypted_message,
             &mut message,
-        )?;
+        )
+        .map_err(|err| mac_failure(err, counter, FramePart::Body))?;
 
         self.increment_nonce();
 
@@ -307,6 +480,19 @@ impl<const LEN_SIZE: usize> NoiseDecryptor<LEN_SIZE> {
     }
 }
 
+fn mac_failure(
+    err: EncryptionError,
+    counter: u32,
+    part: FramePart,
+) -> EncryptionError {
+    match err {
+        EncryptionError::ChaCha => {
+            EncryptionError::MacFailure { counter, part }
+        }
+        err => err,
+    }
+}
+
 impl<const LEN_SIZE: usize> Iterator for NoiseDecryptor<LEN_SIZE> {
     type Item = Result<Option<Vec<u8>>, EncryptionError>;
 
@@ -351,64 +537,104 @@ pub struct NoiseTranscoder<const LEN_SIZE: usize> {
     pub decryptor: NoiseDecryptor<LEN_SIZE>,
 }
 
+/// Runs handshake over a blocking connection, reading exactly the number of
+/// bytes required to complete each act.
+#[cfg(feature = "keygen")]
+fn drive_handshake<const LEN_SIZE: usize>(
+    mut handshake: ResumableHandshake<LEN_SIZE>,
+    connection: &mut impl DuplexConnection,
+) -> Result<NoiseTranscoder<LEN_SIZE>, transport::Error> {
+    loop {
+        if let Some(act) = handshake.next_act() {
+            connection.as_sender().send_raw(&act)?;
+        }
+        if handshake.is_complete() {
+            break;
+        }
+        let data =
+            connection.as_receiver().recv_raw(handshake.needs_bytes())?;
+        handshake.push_bytes(&data)?;
+    }
+    Ok(handshake
+        .into_transcoder()
+        .expect("complete handshake without pending acts"))
+}
+
 impl<const LEN_SIZE: usize> NoiseTranscoder<LEN_SIZE> {
     #[cfg(feature = "keygen")]
     pub fn new_initiator(
         local_key: secp256k1::SecretKey,
         remote_key: secp256k1::PublicKey,
         connection: &mut impl DuplexConnection,
+    ) -> Result<Self, transport::Error> {
+        Self::new_initiator_with_crypto(
+            local_key,
+            remote_key,
+            connection,
+            Box::new(SoftwareCrypto),
+        )
+    }
+
+    #[cfg(feature = "keygen")]
+    pub fn new_responder(
+        local_key: secp256k1::SecretKey,
+        connection: &mut impl DuplexConnection,
+    ) -> Result<Self, transport::Error> {
+        Self::new_responder_with_crypto(
+            local_key,
+            connection,
+            Box::new(SoftwareCrypto),
+        )
+    }
+
+    /// Performs initiator handshake over the connection, running all
+    /// handshake cryptographic operations with the provided backend.
+    #[cfg(feature = "keygen")]
+    pub fn new_initiator_with_crypto(
+        local_key: secp256k1::SecretKey,
+        remote_key: secp256k1::PublicKey,
+        connection: &mut impl DuplexConnection,
+        crypto: Box<dyn HandshakeCrypto>,
     ) -> Result<Self, transport::Error> {
         use secp256k1::rand::thread_rng;
 
+        let local_pubkey = crypto.public_key(KeyRole::Static, &local_key);
         let mut rng = thread_rng();
         let ephemeral_key = secp256k1::SecretKey::new(&mut rng);
-        let mut handshake = HandshakeState::new_initiator(
+        let handshake = ResumableHandshake::initiator_with_crypto(
             &local_key,
             &remote_key,
             &ephemeral_key,
-        );
-
-        let mut data = vec![];
-        loop {
-            let (act, h) = handshake.next(&data)?;
-            handshake = h;
-            if let Some(ref act) = act {
-                connection.as_sender().send_raw(act)?;
-                if let HandshakeState::Complete(transcoder) = handshake {
-                    break Ok(transcoder);
-                }
-                data =
-                    connection.as_receiver().recv_raw(handshake.data_len())?;
-            }
+            crypto,
+        )?;
+        let transcoder = drive_handshake(handshake, connection)?;
+        // We know the remote key since act 2, but abort only after sending
+        // act 3, so the responder is able to detect the self-connection on
+        // its side as well
+        if remote_key == local_pubkey {
+            return Err(HandshakeError::SelfConnection.into());
         }
+        Ok(transcoder)
     }
 
+    /// Performs responder handshake over the connection, running all
+    /// handshake cryptographic operations with the provided backend.
     #[cfg(feature = "keygen")]
-    pub fn new_responder(
+    pub fn new_responder_with_crypto(
         local_key: secp256k1::SecretKey,
         connection: &mut impl DuplexConnection,
+        crypto: Box<dyn HandshakeCrypto>,
     ) -> Result<Self, transport::Error> {
         use secp256k1::rand::thread_rng;
 
         let mut rng = thread_rng();
         let ephemeral_key = secp256k1::SecretKey::new(&mut rng);
-        let mut handshake =
-            HandshakeState::new_responder(&local_key, &ephemeral_key);
-
-        let mut data =
-            connection.as_receiver().recv_raw(handshake.data_len())?;
-        loop {
-            let (act, h) = handshake.next(&data)?;
-            handshake = h;
-            if let HandshakeState::Complete(transcoder) = handshake {
-                break Ok(transcoder);
-            }
-            if let Some(act) = act {
-                connection.as_sender().send_raw(&act)?;
-                data =
-                    connection.as_receiver().recv_raw(handshake.data_len())?;
-            }
-        }
+        let handshake = ResumableHandshake::responder_with_crypto(
+            &local_key,
+            &ephemeral_key,
+            crypto,
+        );
+        drive_handshake(handshake, connection)
     }
 
     /// Instantiate a new Conduit with specified sending and receiving keys
@@ -424,6 +650,9 @@ impl<const LEN_SIZE: usize> NoiseTranscoder<LEN_SIZE> {
                 sending_chaining_key: chaining_key,
                 sending_nonce: 0,
                 remote_pubkey,
+                poisoned: false,
+                #[cfg(debug_assertions)]
+                send_log: SendLog::default(),
             },
             decryptor: NoiseDecryptor {
                 receiving_key,
@@ -489,6 +718,23 @@ impl<const LEN_SIZE: usize> Encrypt for NoiseTranscoder<LEN_SIZE> {
             Err(_) => Vec::new(),
         }
     }
+
+    #[inline]
+    fn frame_sent(&mut self, frame: &[u8]) {
+        Encrypt::frame_sent(&mut self.encryptor, frame)
+    }
+
+    #[inline]
+    fn poison(&mut self) { self.encryptor.poison() }
+
+    #[inline]
+    fn is_poisoned(&self) -> bool { Encrypt::is_poisoned(&self.encryptor) }
+
+    #[inline]
+    fn frame_overhead(&self) -> usize { self.encryptor.frame_overhead() }
+
+    #[inline]
+    fn max_payload_len(&self) -> usize { self.encryptor.max_payload_len() }
 }
 
 impl<const LEN_SIZE: usize> Decrypt for NoiseTranscoder<LEN_SIZE> {
@@ -532,7 +778,6 @@ mod tests {
     use secp256k1::SECP256K1;
 
     use super::*;
-    use crate::BRONTIDE_MSG_MAX_LEN;
 
     fn setup_peers() -> (
         NoiseTranscoder<{ FramingProtocol::Brontide.message_len_size() }>,
@@ -675,7 +920,10 @@ mod tests {
                 .decrypt_single_message(Some(&encrypted))
                 .err()
                 .unwrap(),
-            chacha20poly1305::aead::Error.into()
+            EncryptionError::MacFailure {
+                counter: 0,
+                part: FramePart::LengthPrefix
+            }
         );
     }
 
@@ -708,7 +956,10 @@ mod tests {
         connected_peer.decryptor.receiving_key = [0; 32];
         assert_eq!(
             connected_peer.decryptor.next().unwrap().err().unwrap(),
-            chacha20poly1305::aead::Error.into()
+            EncryptionError::MacFailure {
+                counter: 0,
+                part: FramePart::LengthPrefix
+            }
         );
         assert_eq!(connected_peer.decryptor.next(), None);
     }
@@ -726,7 +977,10 @@ mod tests {
         connected_peer.decryptor.receiving_key = [0; 32];
         assert_eq!(
             connected_peer.decryptor.next().unwrap().err().unwrap(),
-            chacha20poly1305::aead::Error.into()
+            EncryptionError::MacFailure {
+                counter: 2,
+                part: FramePart::LengthPrefix
+            }
         );
         assert_eq!(connected_peer.decryptor.next(), None);
     }
@@ -751,7 +1005,10 @@ mod tests {
         connected_peer.decryptor.receiving_key = [0; 32];
         assert_eq!(
             connected_peer.decryptor.next().unwrap().err().unwrap(),
-            chacha20poly1305::aead::Error.into()
+            EncryptionError::MacFailure {
+                counter: 2,
+                part: FramePart::LengthPrefix
+            }
         );
 
         // Restore the receiving key, do a read and ensure None is returned
@@ -778,7 +1035,10 @@ mod tests {
         connected_peer.decryptor.receiving_key = [0; 32];
         assert_eq!(
             connected_peer.decryptor.next().unwrap().err().unwrap(),
-            chacha20poly1305::aead::Error.into()
+            EncryptionError::MacFailure {
+                counter: 2,
+                part: FramePart::LengthPrefix
+            }
         );
 
         // Restore the receiving key, do a read and ensure None is returned
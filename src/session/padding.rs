@@ -0,0 +1,219 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Padding of plaintext messages to fixed bucket sizes before encryption,
+//! hiding exact message lengths from the observers of encrypted traffic.
+//!
+//! A padded message consists of the original message, zero fill and a
+//! trailer holding the length of the original message as a 4-byte
+//! big-endian integer:
+//!
+//! ```text
+//! | message | 0x00 .. 0x00 | message length: u32 BE |
+//! ```
+//!
+//! Since every message sent with padding enabled carries the trailer, the
+//! scheme does not depend on the message contents. Padding is not negotiated:
+//! both peers must be configured with some padding policy other than
+//! [`PaddingPolicy::None`], otherwise the receiving side fails with
+//! [`Error::InvalidPadding`], which is fatal for the session. The buckets
+//! themselves do not need to match, since the receiver relies on the trailer
+//! only.
+
+use crate::transport::Error;
+
+/// Number of bytes of the padding trailer storing the original message length
+pub const PADDING_TRAILER_LEN: usize = 4;
+
+/// Policy for padding messages of an encrypted session
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PaddingPolicy {
+    /// Messages are sent as is
+    None,
+
+    /// Messages are padded to the smallest of the bucket sizes which fits the
+    /// message with the padding trailer. Messages which do not fit into any
+    /// of the buckets are padded to the maximum message size of the session.
+    Buckets(&'static [usize]),
+
+    /// Messages are padded to the nearest multiple of the given size, but no
+    /// more than the maximum message size of the session
+    Fixed(usize),
+}
+
+impl Default for PaddingPolicy {
+    fn default() -> Self { PaddingPolicy::None }
+}
+
+impl PaddingPolicy {
+    /// Detects whether the policy pads messages
+    #[inline]
+    pub fn is_enabled(self) -> bool { self != PaddingPolicy::None }
+
+    /// Computes length of a message of `len` bytes after padding, provided
+    /// that the session is able to send messages of at most `max_len` bytes.
+    /// Returns `None` if the padded message does not fit into `max_len`.
+    pub fn padded_len(self, len: usize, max_len: usize) -> Option<usize> {
+        let min = if self.is_enabled() {
+            len.checked_add(PADDING_TRAILER_LEN)?
+        } else {
+            len
+        };
+        if min > max_len {
+            return None;
+        }
+        let padded = match self {
+            PaddingPolicy::None | PaddingPolicy::Fixed(0) => min,
+            PaddingPolicy::Buckets(buckets) => buckets
+                .iter()
+                .copied()
+                .filter(|bucket| *bucket >= min && *bucket <= max_len)
+                .min()
+                .unwrap_or(max_len),
+            PaddingPolicy::Fixed(size) => match min % size {
+                0 => min,
+                rem => min.saturating_add(size - rem).min(max_len),
+            },
+        };
+        Some(padded)
+    }
+
+    /// Pads the message according to the policy.
+    ///
+    /// # Errors
+    /// [`Error::FrameTooLargeForTransport`] if the padded message does not
+    /// fit into `max_len` bytes.
+    pub fn pad(self, msg: &[u8], max_len: usize) -> Result<Vec<u8>, Error> {
+        if !self.is_enabled() {
+            return Ok(msg.to_vec());
+        }
+        let padded_len = self.padded_len(msg.len(), max_len).ok_or(
+            Error::FrameTooLargeForTransport {
+                len: msg.len(),
+                max: max_len.saturating_sub(PADDING_TRAILER_LEN),
+            },
+        )?;
+        let mut padded = Vec::with_capacity(padded_len);
+        padded.extend_from_slice(msg);
+        padded.resize(padded_len - PADDING_TRAILER_LEN, 0u8);
+        padded.extend((msg.len() as u32).to_be_bytes());
+        Ok(padded)
+    }
+
+    /// Removes padding from the received message.
+    ///
+    /// # Errors
+    /// [`Error::InvalidPadding`] if the message is shorter than the trailer,
+    /// the trailer length exceeds the message length or the padding contains
+    /// non-zero bytes.
+    pub fn unpad(self, mut msg: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if !self.is_enabled() {
+            return Ok(msg);
+        }
+        let fill_end = msg
+            .len()
+            .checked_sub(PADDING_TRAILER_LEN)
+            .ok_or(Error::InvalidPadding("no length trailer"))?;
+        let mut trailer = [0u8; PADDING_TRAILER_LEN];
+        trailer.copy_from_slice(&msg[fill_end..]);
+        let len = u32::from_be_bytes(trailer) as usize;
+        if len > fill_end {
+            return Err(Error::InvalidPadding("length exceeds message size"));
+        }
+        if msg[len..fill_end].iter().any(|byte| *byte != 0) {
+            return Err(Error::InvalidPadding("non-zero fill"));
+        }
+        msg.truncate(len);
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAX: usize = 0xFFFF;
+
+    fn roundtrip(policy: PaddingPolicy, len: usize, max: usize) -> usize {
+        let msg = vec![0xA5; len];
+        let padded = policy.pad(&msg, max).unwrap();
+        assert_eq!(Some(padded.len()), policy.padded_len(len, max));
+        assert_eq!(policy.unpad(padded.clone()).unwrap(), msg);
+        padded.len()
+    }
+
+    #[test]
+    fn no_padding() {
+        let policy = PaddingPolicy::default();
+        assert!(!policy.is_enabled());
+        assert_eq!(roundtrip(policy, 0, MAX), 0);
+        assert_eq!(roundtrip(policy, 13, MAX), 13);
+        assert_eq!(policy.padded_len(MAX + 1, MAX), None);
+    }
+
+    #[test]
+    fn buckets() {
+        let policy = PaddingPolicy::Buckets(&[256, 32, 64]);
+        assert_eq!(roundtrip(policy, 0, MAX), 32);
+        assert_eq!(roundtrip(policy, 28, MAX), 32);
+        assert_eq!(roundtrip(policy, 29, MAX), 64);
+        assert_eq!(roundtrip(policy, 60, MAX), 64);
+        assert_eq!(roundtrip(policy, 61, MAX), 256);
+        assert_eq!(roundtrip(policy, 252, MAX), 256);
+        // Above the largest bucket
+        assert_eq!(roundtrip(policy, 253, MAX), MAX);
+        assert_eq!(roundtrip(policy, MAX - 4, MAX), MAX);
+        // Buckets exceeding the maximum message size are ignored
+        assert_eq!(roundtrip(policy, 61, 100), 100);
+    }
+
+    #[test]
+    fn fixed() {
+        let policy = PaddingPolicy::Fixed(256);
+        assert_eq!(roundtrip(policy, 0, MAX), 256);
+        assert_eq!(roundtrip(policy, 252, MAX), 256);
+        assert_eq!(roundtrip(policy, 253, MAX), 512);
+        assert_eq!(roundtrip(policy, 508, MAX), 512);
+        assert_eq!(roundtrip(policy, 253, 300), 300);
+        assert_eq!(roundtrip(PaddingPolicy::Fixed(0), 10, MAX), 14);
+    }
+
+    #[test]
+    fn oversized() {
+        let policy = PaddingPolicy::Fixed(256);
+        assert_eq!(policy.padded_len(297, 300), None);
+        assert_eq!(
+            policy.pad(&[0u8; 297], 300),
+            Err(Error::FrameTooLargeForTransport { len: 297, max: 296 })
+        );
+        assert_eq!(roundtrip(policy, 296, 300), 300);
+    }
+
+    #[test]
+    fn malformed() {
+        let policy = PaddingPolicy::Fixed(16);
+        assert_eq!(
+            policy.unpad(vec![0, 0, 0]),
+            Err(Error::InvalidPadding("no length trailer"))
+        );
+        assert_eq!(
+            policy.unpad(vec![0xA5, 0, 0, 0, 2]),
+            Err(Error::InvalidPadding("length exceeds message size"))
+        );
+        assert_eq!(
+            policy.unpad(vec![0xA5, 0x01, 0, 0, 0, 1]),
+            Err(Error::InvalidPadding("non-zero fill"))
+        );
+        assert_eq!(policy.unpad(vec![0xA5, 0, 0, 0, 0, 1]), Ok(vec![0xA5]));
+    }
+}
@@ -0,0 +1,467 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Complete description of a remote peer which can be persisted or
+//! transmitted as a single record.
+
+use std::cmp::Ordering;
+use std::io;
+
+use inet2_addr::{InetSocketAddrExt, NodeId};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Version of [`PeerInfo`] strict encoding
+pub const PEER_INFO_VERSION: u8 = 1;
+/// Maximum number of addresses in [`PeerInfo`]
+pub const PEER_INFO_MAX_ADDRS: usize = 16;
+/// Maximum length of [`PeerInfo`] alias, in bytes
+pub const PEER_INFO_MAX_ALIAS_LEN: usize = 32;
+
+/// Errors of [`PeerInfo`] validation
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PeerInfoError {
+    /// peer info contains {0} addresses, while at most 16 are allowed
+    TooManyAddrs(usize),
+
+    /// peer alias is {0} bytes long, exceeding 32 bytes limit
+    AliasTooLong(usize),
+
+    /// address {0} is listed more than once
+    DuplicateAddr(InetSocketAddrExt),
+}
+
+/// Peer information: node id, list of addresses, feature vector and an
+/// optional alias.
+///
+/// Strict encoding of the structure starts with [`PEER_INFO_VERSION`] byte;
+/// decoding fails for other versions and for records which do not pass
+/// [`PeerInfo::validate`]. Addresses are always encoded in the canonical
+/// order (see [`InetSocketAddrExt::canonical_cmp`]), so two nodes holding
+/// the same set of addresses produce identical encodings regardless of the
+/// order in which the addresses were added. Deserialization with serde
+/// validates the records as well.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", try_from = "PeerInfoUnchecked")
+)]
+pub struct PeerInfo {
+    /// Node id of the peer
+    pub node_id: NodeId,
+
+    /// Peer addresses. Their order is not preserved by strict encoding.
+    pub addrs: Vec<InetSocketAddrExt>,
+
+    /// Feature vector, in BOLT-9 big-endian bit order
+    pub features: Vec<u8>,
+
+    /// Human-readable peer alias
+    pub alias: Option<String>,
+}
+
+/// Peer information as it is deserialized, before validation
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct PeerInfoUnchecked {
+    node_id: NodeId,
+    addrs: Vec<InetSocketAddrExt>,
+    features: Vec<u8>,
+    alias: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PeerInfoUnchecked> for PeerInfo {
+    type Error = PeerInfoError;
+
+    fn try_from(info: PeerInfoUnchecked) -> Result<Self, Self::Error> {
+        PeerInfo::new(info.node_id, info.addrs, info.features, info.alias)
+    }
+}
+
+impl PeerInfo {
+    /// Constructs validated peer information.
+    pub fn new(
+        node_id: NodeId,
+        addrs: Vec<InetSocketAddrExt>,
+        features: Vec<u8>,
+        alias: Option<String>,
+    ) -> Result<Self, PeerInfoError> {
+        let info = PeerInfo {
+            node_id,
+            addrs,
+            features,
+            alias,
+        };
+        info.validate()?;
+        Ok(info)
+    }
+
+    /// Sorts addresses in the canonical order and removes duplicates.
+    pub fn canonicalize(&mut self) {
+        self.addrs.sort_by(InetSocketAddrExt::canonical_cmp);
+        self.addrs.dedup();
+    }
+
+    /// Checks whether addresses are listed in the canonical order without
+    /// duplicates, i.e. whether [`PeerInfo::canonicalize`] will not change
+    /// the structure.
+    pub fn is_canonical(&self) -> bool {
+        self.addrs
+            .windows(2)
+            .all(|pair| pair[0].canonical_cmp(&pair[1]) == Ordering::Less)
+    }
+
+    /// Checks that the number of addresses and alias length do not exceed
+    /// [`PEER_INFO_MAX_ADDRS`] and [`PEER_INFO_MAX_ALIAS_LEN`], and that no
+    /// address is listed twice.
+    pub fn validate(&self) -> Result<(), PeerInfoError> {
+        if self.addrs.len() > PEER_INFO_MAX_ADDRS {
+            return Err(PeerInfoError::TooManyAddrs(self.addrs.len()));
+        }
+        if let Some(len) = self.alias.as_ref().map(String::len) {
+            if len > PEER_INFO_MAX_ALIAS_LEN {
+                return Err(PeerInfoError::AliasTooLong(len));
+            }
+        }
+        for (no, addr) in self.addrs.iter().enumerate() {
+            if self.addrs[..no].contains(addr) {
+                return Err(PeerInfoError::DuplicateAddr(*addr));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StrictEncode for PeerInfo {
+    fn strict_encode<E: io::Write>(
+        &self,
+        mut e: E,
+    ) -> Result<usize, strict_encoding::Error> {
+        // Duplicates are kept, so that the decoder still rejects them
+        let mut addrs = self.addrs.clone();
+        addrs.sort_by(InetSocketAddrExt::canonical_cmp);
+        Ok(PEER_INFO_VERSION.strict_encode(&mut e)?
+            + self.node_id.strict_encode(&mut e)?
+            + addrs.strict_encode(&mut e)?
+            + self.features.strict_encode(&mut e)?
+            + self.alias.strict_encode(&mut e)?)
+    }
+}
+
+impl StrictDecode for PeerInfo {
+    fn strict_decode<D: io::Read>(
+        mut d: D,
+    ) -> Result<Self, strict_encoding::Error> {
+        let version = u8::strict_decode(&mut d)?;
+        if version != PEER_INFO_VERSION {
+            return Err(strict_encoding::Error::EnumValueNotKnown(
+                "PeerInfo version",
+                version as usize,
+            ));
+        }
+        let info = PeerInfo {
+            node_id: NodeId::strict_decode(&mut d)?,
+            addrs: StrictDecode::strict_decode(&mut d)?,
+            features: StrictDecode::strict_decode(&mut d)?,
+            alias: StrictDecode::strict_decode(&mut d)?,
+        };
+        info.validate().map_err(|err| {
+            strict_encoding::Error::DataIntegrityError(err.to_string())
+        })?;
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use inet2_addr::{InetSocketAddr, Transport};
+    use strict_encoding::{strict_deserialize, strict_serialize};
+
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/peer_info.txt");
+    const NODE_ID: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn addr(s: &str) -> InetSocketAddrExt {
+        InetSocketAddrExt::with(
+            Transport::Tcp,
+            InetSocketAddr::from_str(s).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn peer_info() -> PeerInfo {
+        PeerInfo::new(
+            NodeId::from_str(NODE_ID).unwrap(),
+            vec![addr("127.0.0.1:9735"), addr("[::1]:9735")],
+            vec![0x22, 0x00],
+            Some(s!("alice")),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn peer_info_roundtrip() {
+        let info = peer_info();
+        let data = strict_serialize(&info).unwrap();
+        assert_eq!(data[0], PEER_INFO_VERSION);
+        assert_eq!(strict_deserialize::<PeerInfo>(&data).unwrap(), info);
+
+        let info = PeerInfo::new(
+            NodeId::from_str(NODE_ID).unwrap(),
+            vec![],
+            vec![],
+            None,
+        )
+        .unwrap();
+        let data = strict_serialize(&info).unwrap();
+        assert_eq!(strict_deserialize::<PeerInfo>(&data).unwrap(), info);
+    }
+
+    #[test]
+    fn peer_info_format() {
+        // Version, node id, no addresses, two feature bytes, alias "alice"
+        let mut fixture = vec![0x01];
+        fixture.extend(
+            NodeId::from_str(NODE_ID).unwrap().public_key().serialize(),
+        );
+        fixture.extend([0x00, 0x00]);
+        fixture.extend([0x02, 0x00, 0x22, 0x00]);
+        fixture.extend([0x01, 0x05, 0x00]);
+        fixture.extend(b"alice");
+
+        let info = PeerInfo {
+            addrs: vec![],
+            ..peer_info()
+        };
+        assert_eq!(strict_serialize(&info).unwrap(), fixture);
+        assert_eq!(strict_deserialize::<PeerInfo>(&fixture).unwrap(), info);
+
+        fixture[0] = 0x02;
+        assert!(matches!(
+            strict_deserialize::<PeerInfo>(&fixture),
+            Err(strict_encoding::Error::EnumValueNotKnown(
+                "PeerInfo version",
+                2
+            ))
+        ));
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|pos| u8::from_str_radix(&s[pos..pos + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn peer_info_fixture() {
+        let records = FIXTURE
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with(';'))
+            .map(unhex)
+            .collect::<Vec<_>>();
+        let expected = vec![
+            peer_info(),
+            PeerInfo::new(
+                NodeId::from_str(NODE_ID).unwrap(),
+                vec![],
+                vec![],
+                None,
+            )
+            .unwrap(),
+            PeerInfo::new(
+                NodeId::from_str(NODE_ID).unwrap(),
+                vec![
+                    addr("192.168.1.1:80"),
+                    InetSocketAddrExt::udp([10, 0, 0, 1].into(), 9735),
+                ],
+                vec![0x02],
+                None,
+            )
+            .unwrap(),
+        ];
+        assert_eq!(records.len(), expected.len());
+        for (data, info) in records.into_iter().zip(expected) {
+            assert_eq!(strict_serialize(&info).unwrap(), data);
+            assert_eq!(strict_deserialize::<PeerInfo>(&data).unwrap(), info);
+        }
+    }
+
+    #[test]
+    fn peer_info_validation() {
+        let info = peer_info();
+
+        let mut invalid = info.clone();
+        invalid.addrs.push(addr("127.0.0.1:9735"));
+        assert_eq!(
+            invalid.validate(),
+            Err(PeerInfoError::DuplicateAddr(addr("127.0.0.1:9735")))
+        );
+        assert!(strict_deserialize::<PeerInfo>(
+            &strict_serialize(&invalid).unwrap()
+        )
+        .is_err());
+
+        let mut invalid = info.clone();
+        invalid.addrs = (0..=PEER_INFO_MAX_ADDRS as u16)
+            .map(|port| addr(&format!("127.0.0.1:{}", 9000 + port)))
+            .collect();
+        assert_eq!(
+            invalid.validate(),
+            Err(PeerInfoError::TooManyAddrs(PEER_INFO_MAX_ADDRS + 1))
+        );
+        invalid.addrs.pop();
+        assert_eq!(invalid.validate(), Ok(()));
+
+        let mut invalid = info;
+        invalid.alias = Some("a".repeat(PEER_INFO_MAX_ALIAS_LEN + 1));
+        assert_eq!(
+            invalid.validate(),
+            Err(PeerInfoError::AliasTooLong(PEER_INFO_MAX_ALIAS_LEN + 1))
+        );
+        invalid.alias = Some("a".repeat(PEER_INFO_MAX_ALIAS_LEN));
+        assert_eq!(invalid.validate(), Ok(()));
+    }
+
+    #[test]
+    fn peer_info_error_display() {
+        assert_eq!(
+            PeerInfoError::TooManyAddrs(PEER_INFO_MAX_ADDRS + 1).to_string(),
+            format!(
+                "peer info contains {} addresses, while at most {} are allowed",
+                PEER_INFO_MAX_ADDRS + 1,
+                PEER_INFO_MAX_ADDRS
+            )
+        );
+        assert_eq!(
+            PeerInfoError::AliasTooLong(PEER_INFO_MAX_ALIAS_LEN + 1)
+                .to_string(),
+            format!(
+                "peer alias is {} bytes long, exceeding {} bytes limit",
+                PEER_INFO_MAX_ALIAS_LEN + 1,
+                PEER_INFO_MAX_ALIAS_LEN
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn peer_info_deserialize_validates() {
+        let info = peer_info();
+        let unchecked = |addrs: Vec<InetSocketAddrExt>| PeerInfoUnchecked {
+            node_id: info.node_id,
+            addrs,
+            features: info.features.clone(),
+            alias: info.alias.clone(),
+        };
+        assert_eq!(
+            PeerInfo::try_from(unchecked(info.addrs.clone())),
+            Ok(info.clone())
+        );
+        assert_eq!(
+            PeerInfo::try_from(unchecked(
+                (0..=PEER_INFO_MAX_ADDRS as u16)
+                    .map(|port| addr(&format!("127.0.0.1:{}", 9000 + port)))
+                    .collect()
+            )),
+            Err(PeerInfoError::TooManyAddrs(PEER_INFO_MAX_ADDRS + 1))
+        );
+    }
+
+    fn permutations(
+        addrs: Vec<InetSocketAddrExt>,
+    ) -> Vec<Vec<InetSocketAddrExt>> {
+        if addrs.len() <= 1 {
+            return vec![addrs];
+        }
+        let mut all = vec![];
+        for no in 0..addrs.len() {
+            let mut rest = addrs.clone();
+            let first = rest.remove(no);
+            for mut perm in permutations(rest) {
+                perm.insert(0, first);
+                all.push(perm);
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn peer_info_canonical_encoding() {
+        let addrs = vec![
+            addr("[::1]:9735"),
+            InetSocketAddrExt::udp([10, 0, 0, 1].into(), 9735),
+            addr("127.0.0.1:9736"),
+            addr("127.0.0.1:9735"),
+            addr("192.168.1.1:80"),
+        ];
+        let mut canonical = PeerInfo {
+            addrs: addrs.clone(),
+            ..peer_info()
+        };
+        assert!(!canonical.is_canonical());
+        canonical.canonicalize();
+        assert!(canonical.is_canonical());
+        assert_eq!(canonical.addrs, vec![
+            addr("127.0.0.1:9735"),
+            addr("127.0.0.1:9736"),
+            addr("192.168.1.1:80"),
+            addr("[::1]:9735"),
+            InetSocketAddrExt::udp([10, 0, 0, 1].into(), 9735),
+        ]);
+        let expected = strict_serialize(&canonical).unwrap();
+
+        let perms = permutations(addrs);
+        assert_eq!(perms.len(), 120);
+        for addrs in perms {
+            let info = PeerInfo {
+                addrs,
+                ..peer_info()
+            };
+            let data = strict_serialize(&info).unwrap();
+            assert_eq!(data, expected);
+            assert_eq!(
+                strict_deserialize::<PeerInfo>(&data).unwrap(),
+                canonical
+            );
+        }
+    }
+
+    #[test]
+    fn peer_info_canonicalize_dedup() {
+        let mut info = PeerInfo {
+            addrs: vec![
+                addr("[::1]:9735"),
+                addr("127.0.0.1:9735"),
+                addr("[::1]:9735"),
+            ],
+            ..peer_info()
+        };
+        assert!(!info.is_canonical());
+        assert!(info.validate().is_err());
+        info.canonicalize();
+        assert_eq!(info.addrs, vec![
+            addr("127.0.0.1:9735"),
+            addr("[::1]:9735")
+        ]);
+        assert!(info.is_canonical());
+        assert_eq!(info.validate(), Ok(()));
+    }
+}
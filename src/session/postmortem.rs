@@ -0,0 +1,215 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Ring buffer of recently sent and received frames, which is attached to
+//! session-fatal errors to make bug reports actionable.
+//!
+//! Frame logging is disabled by default; enable it with
+//! [`super::Session::enable_frame_log`].
+
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::time::SystemTime;
+
+use crate::transport::Error;
+
+/// Default number of frames kept by [`FrameLog`]
+pub const FRAME_LOG_DEFAULT_CAPACITY: usize = 32;
+
+/// Direction in which a logged frame was transferred
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum FrameDirection {
+    /// Frame was sent to the remote peer
+    #[display("sent")]
+    Sent,
+
+    /// Frame was received from the remote peer
+    #[display("received")]
+    Received,
+}
+
+/// Metadata of a single frame recorded by [`FrameLog`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FrameRecord {
+    /// Direction of the frame
+    pub direction: FrameDirection,
+
+    /// Length of the plaintext message carried by the frame
+    pub len: usize,
+
+    /// Message type id, read from the first two bytes of the message, if the
+    /// message is long enough
+    pub type_id: Option<u16>,
+
+    /// Time at which the frame was sent or received
+    pub timestamp: SystemTime,
+
+    /// Beginning of the plaintext message, if payload capture is enabled (see
+    /// [`FrameLog::with_payloads`])
+    pub payload: Option<Vec<u8>>,
+}
+
+impl Display for FrameRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} bytes", self.direction, self.len)?;
+        if let Some(type_id) = self.type_id {
+            write!(f, ", type {}", type_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fixed-capacity log of the most recent frames of a session
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FrameLog {
+    capacity: usize,
+    payload_cap: Option<usize>,
+    records: VecDeque<FrameRecord>,
+}
+
+impl Default for FrameLog {
+    fn default() -> Self { FrameLog::with_capacity(FRAME_LOG_DEFAULT_CAPACITY) }
+}
+
+impl FrameLog {
+    /// Constructs log keeping [`FRAME_LOG_DEFAULT_CAPACITY`] most recent
+    /// frames without their payloads
+    #[inline]
+    pub fn new() -> Self { FrameLog::default() }
+
+    /// Constructs log keeping `capacity` most recent frames without their
+    /// payloads
+    pub fn with_capacity(capacity: usize) -> Self {
+        FrameLog {
+            capacity,
+            payload_cap: None,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Enables capture of the first `max_len` bytes of each message payload
+    pub fn with_payloads(mut self, max_len: usize) -> Self {
+        self.payload_cap = Some(max_len);
+        self
+    }
+
+    /// Returns maximum number of frames kept by the log
+    #[inline]
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    /// Returns recorded frames, from the oldest to the most recent one
+    #[inline]
+    pub fn records(&self) -> impl Iterator<Item = &FrameRecord> {
+        self.records.iter()
+    }
+
+    /// Records frame carrying plaintext `message`, evicting the oldest
+    /// record if the log is full
+    pub fn record(&mut self, direction: FrameDirection, message: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        let type_id = match message {
+            [hi, lo, ..] => Some(u16::from_be_bytes([*hi, *lo])),
+            _ => None,
+        };
+        let payload = self
+            .payload_cap
+            .map(|cap| message[..message.len().min(cap)].to_vec());
+        self.records.push_back(FrameRecord {
+            direction,
+            len: message.len(),
+            type_id,
+            timestamp: SystemTime::now(),
+            payload,
+        });
+    }
+
+    /// Returns copy of the recorded frames, from the oldest to the most
+    /// recent one
+    pub fn snapshot(&self) -> Vec<FrameRecord> {
+        self.records.iter().cloned().collect()
+    }
+}
+
+/// Information about a session which has failed with a session-fatal error
+/// (see [`Error::is_session_fatal`])
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SessionPostMortem {
+    /// Error which has terminated the session
+    pub error: Error,
+
+    /// Frames sent and received before the failure, from the oldest to the
+    /// most recent one
+    pub recent_frames: Vec<FrameRecord>,
+}
+
+impl Display for SessionPostMortem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "session failed: {}", self.error)?;
+        if self.recent_frames.is_empty() {
+            return f.write_str("; no frames were recorded");
+        }
+        f.write_str("; recent frames:")?;
+        for record in &self.recent_frames {
+            write!(f, "\n- {}", record)?;
+        }
+        Ok(())
+    }
+}
+
+impl SessionPostMortem {
+    /// Returns frames sent and received before the failure
+    #[inline]
+    pub fn recent_frames(&self) -> &[FrameRecord] { &self.recent_frames }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ring_buffer() {
+        let mut log = FrameLog::with_capacity(2);
+        log.record(FrameDirection::Sent, &[0x00, 0x10, 0xFF]);
+        log.record(FrameDirection::Received, &[0x00]);
+        log.record(FrameDirection::Received, &[0x00, 0x12]);
+        let records = log.snapshot();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, FrameDirection::Received);
+        assert_eq!(records[0].len, 1);
+        assert_eq!(records[0].type_id, None);
+        assert_eq!(records[1].type_id, Some(18));
+        assert_eq!(records[1].payload, None);
+        assert_eq!(records[1].to_string(), "received 2 bytes, type 18");
+
+        let mut log = FrameLog::with_capacity(0);
+        log.record(FrameDirection::Sent, &[0x00, 0x10]);
+        assert_eq!(log.records().count(), 0);
+    }
+
+    #[test]
+    fn payload_capture() {
+        let mut log = FrameLog::new().with_payloads(3);
+        assert_eq!(log.capacity(), FRAME_LOG_DEFAULT_CAPACITY);
+        log.record(FrameDirection::Sent, b"Some message");
+        log.record(FrameDirection::Sent, b"ab");
+        let records = log.snapshot();
+        assert_eq!(records[0].payload, Some(b"Som".to_vec()));
+        assert_eq!(records[0].len, 12);
+        assert_eq!(records[1].payload, Some(b"ab".to_vec()));
+    }
+}
@@ -0,0 +1,351 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Session wrapper enforcing application protocol grammar, defined as a
+//! state-transition table over message types.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use crate::presentation::{EncodingType, TypeId};
+use crate::session::SendRecvMessage;
+use crate::transport;
+
+/// Direction of a message in respect to the local peer
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum Direction {
+    /// Message sent by the local peer
+    #[display("send")]
+    Send,
+
+    /// Message received from the remote peer
+    #[display("recv")]
+    Recv,
+}
+
+/// Errors of the protocol state machine
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ProtocolError {
+    /// message of type {type_id} can't be sent in protocol state {state}
+    ProtocolViolation { state: String, type_id: TypeId },
+
+    /// unexpected message of type {type_id} received in protocol state
+    /// {state}
+    UnexpectedMessage { state: String, type_id: TypeId },
+
+    /// message is too short to contain type id
+    NoTypeId,
+
+    #[display(inner)]
+    #[from]
+    Transport(transport::Error),
+}
+
+/// Session wrapper which tracks application protocol state and checks each
+/// sent and received message against a state-transition table mapping
+/// `(state, message type, direction)` to the next state.
+///
+/// Sending a message which is not allowed in the current state fails with
+/// [`ProtocolError::ProtocolViolation`] without sending anything. Receiving
+/// such message returns [`ProtocolError::UnexpectedMessage`]. In both cases
+/// the state remains unchanged.
+pub struct ProtocolStateMachine<S, T>
+where
+    S: Copy + Ord + Debug,
+    T: SendRecvMessage,
+{
+    session: T,
+    encoding: EncodingType,
+    transitions: BTreeMap<(S, TypeId, Direction), S>,
+    state: S,
+}
+
+impl<S, T> ProtocolStateMachine<S, T>
+where
+    S: Copy + Ord + Debug,
+    T: SendRecvMessage,
+{
+    /// Wraps session into the state machine starting at `initial` state.
+    /// Message type ids are read from the first two bytes of each message
+    /// according to the `encoding`.
+    pub fn new(
+        session: T,
+        encoding: EncodingType,
+        initial: S,
+        transitions: impl IntoIterator<Item = ((S, TypeId, Direction), S)>,
+    ) -> Self {
+        Self {
+            session,
+            encoding,
+            transitions: transitions.into_iter().collect(),
+            state: initial,
+        }
+    }
+
+    /// Returns current protocol state.
+    #[inline]
+    pub fn state(&self) -> S { self.state }
+
+    /// Returns state-transition table.
+    #[inline]
+    pub fn transitions(&self) -> &BTreeMap<(S, TypeId, Direction), S> {
+        &self.transitions
+    }
+
+    /// Lists message types which are allowed in the current state for the
+    /// given direction.
+    pub fn allowed_types(
+        &self,
+        direction: Direction,
+    ) -> impl Iterator<Item = TypeId> + '_ {
+        let state = self.state;
+        self.transitions
+            .keys()
+            .filter(move |(s, _, d)| *s == state && *d == direction)
+            .map(|(_, type_id, _)| *type_id)
+    }
+
+    /// Returns reference to the wrapped session.
+    #[inline]
+    pub fn as_session(&self) -> &T { &self.session }
+
+    /// Releases the wrapped session.
+    #[inline]
+    pub fn into_session(self) -> T { self.session }
+
+    /// Sends raw message if it is allowed in the current state, and moves
+    /// the state machine to the next state.
+    pub fn send_raw_message(
+        &mut self,
+        raw: &[u8],
+    ) -> Result<usize, ProtocolError> {
+        let type_id = self.type_id(raw)?;
+        let next =
+            self.next_state(type_id, Direction::Send).ok_or_else(|| {
+                ProtocolError::ProtocolViolation {
+                    state: format!("{:?}", self.state),
+                    type_id,
+                }
+            })?;
+        let len = self.session.send_raw_message(raw)?;
+        self.state = next;
+        Ok(len)
+    }
+
+    /// Receives raw message and moves the state machine to the next state,
+    /// unless the message is not expected in the current state.
+    pub fn recv_raw_message(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        let raw = self.session.recv_raw_message()?;
+        let type_id = self.type_id(&raw)?;
+        self.state =
+            self.next_state(type_id, Direction::Recv).ok_or_else(|| {
+                ProtocolError::UnexpectedMessage {
+                    state: format!("{:?}", self.state),
+                    type_id,
+                }
+            })?;
+        Ok(raw)
+    }
+
+    fn next_state(&self, type_id: TypeId, direction: Direction) -> Option<S> {
+        self.transitions
+            .get(&(self.state, type_id, direction))
+            .copied()
+    }
+
+    fn type_id(&self, raw: &[u8]) -> Result<TypeId, ProtocolError> {
+        if raw.len() < 2 {
+            return Err(ProtocolError::NoTypeId);
+        }
+        let data = [raw[0], raw[1]];
+        Ok(TypeId::from(match self.encoding {
+            EncodingType::Lightning => u16::from_be_bytes(data),
+            EncodingType::Strict => u16::from_le_bytes(data),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::any::Any;
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::transport::RoutedFrame;
+
+    /// Session which receives back all the messages sent to it
+    #[derive(Default)]
+    struct Loopback(VecDeque<Vec<u8>>);
+
+    impl SendRecvMessage for Loopback {
+        fn recv_raw_message(&mut self) -> Result<Vec<u8>, transport::Error> {
+            self.0.pop_front().ok_or(transport::Error::ServiceOffline)
+        }
+
+        fn send_raw_message(
+            &mut self,
+            raw: &[u8],
+        ) -> Result<usize, transport::Error> {
+            self.0.push_back(raw.to_vec());
+            Ok(raw.len())
+        }
+
+        fn recv_routed_message(
+            &mut self,
+        ) -> Result<RoutedFrame, transport::Error> {
+            unreachable!()
+        }
+
+        fn send_routed_message(
+            &mut self,
+            _: &[u8],
+            _: &[u8],
+            _: &[u8],
+            _: &[u8],
+        ) -> Result<usize, transport::Error> {
+            unreachable!()
+        }
+
+        fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
+    }
+
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+    enum State {
+        Init,
+        Hello,
+        Authed,
+        Streaming,
+    }
+
+    const HELLO: u16 = 1;
+    const AUTH: u16 = 3;
+    const DATA: u16 = 5;
+
+    // Worked example: hello → authed → streaming, where the loopback session
+    // makes us receive each message we send.
+    fn machine() -> ProtocolStateMachine<State, Loopback> {
+        ProtocolStateMachine::new(
+            Loopback::default(),
+            EncodingType::Lightning,
+            State::Init,
+            [
+                (
+                    (State::Init, TypeId::from(HELLO), Direction::Send),
+                    State::Hello,
+                ),
+                (
+                    (State::Hello, TypeId::from(HELLO), Direction::Recv),
+                    State::Hello,
+                ),
+                (
+                    (State::Hello, TypeId::from(AUTH), Direction::Send),
+                    State::Authed,
+                ),
+                (
+                    (State::Authed, TypeId::from(AUTH), Direction::Recv),
+                    State::Streaming,
+                ),
+                (
+                    (State::Streaming, TypeId::from(DATA), Direction::Send),
+                    State::Streaming,
+                ),
+                (
+                    (State::Streaming, TypeId::from(DATA), Direction::Recv),
+                    State::Streaming,
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn legal_flow() {
+        let mut machine = machine();
+        assert_eq!(machine.state(), State::Init);
+        assert_eq!(
+            machine.allowed_types(Direction::Send).collect::<Vec<_>>(),
+            vec![TypeId::from(HELLO)]
+        );
+
+        machine.send_raw_message(&[0, 1]).unwrap();
+        assert_eq!(machine.recv_raw_message().unwrap(), vec![0, 1]);
+        assert_eq!(machine.state(), State::Hello);
+
+        machine.send_raw_message(&[0, 3, 0xAA]).unwrap();
+        assert_eq!(machine.recv_raw_message().unwrap(), vec![0, 3, 0xAA]);
+        assert_eq!(machine.state(), State::Streaming);
+
+        for _ in 0..3 {
+            machine.send_raw_message(&[0, 5, 1, 2, 3]).unwrap();
+            machine.recv_raw_message().unwrap();
+        }
+        assert_eq!(machine.state(), State::Streaming);
+        assert_eq!(machine.transitions().len(), 6);
+    }
+
+    #[test]
+    fn illegal_send() {
+        let mut machine = machine();
+        assert_eq!(
+            machine.send_raw_message(&[0, 5]).unwrap_err(),
+            ProtocolError::ProtocolViolation {
+                state: s!("Init"),
+                type_id: TypeId::from(DATA)
+            }
+        );
+        assert_eq!(machine.state(), State::Init);
+        // Nothing must be sent
+        assert!(machine.as_session().0.is_empty());
+
+        assert_eq!(
+            machine.send_raw_message(&[0]).unwrap_err(),
+            ProtocolError::NoTypeId
+        );
+    }
+
+    #[test]
+    fn unexpected_recv() {
+        let mut machine = machine();
+        machine.send_raw_message(&[0, 1]).unwrap();
+        machine.recv_raw_message().unwrap();
+        machine.send_raw_message(&[0, 3]).unwrap();
+        machine.recv_raw_message().unwrap();
+
+        // Remote peer sends hello once again while we are streaming
+        machine.session.0.push_back(vec![0, 1]);
+        assert_eq!(
+            machine.recv_raw_message().unwrap_err(),
+            ProtocolError::UnexpectedMessage {
+                state: s!("Streaming"),
+                type_id: TypeId::from(HELLO)
+            }
+        );
+        assert_eq!(machine.state(), State::Streaming);
+    }
+
+    #[test]
+    fn strict_encoded_type_id() {
+        let mut machine = ProtocolStateMachine::new(
+            Loopback::default(),
+            EncodingType::Strict,
+            State::Init,
+            [(
+                (State::Init, TypeId::from(HELLO), Direction::Send),
+                State::Hello,
+            )],
+        );
+        assert!(machine.send_raw_message(&[0, 1]).is_err());
+        machine.send_raw_message(&[1, 0]).unwrap();
+        assert_eq!(machine.state(), State::Hello);
+    }
+}
@@ -24,9 +24,13 @@ use inet2_addr::InetSocketAddr;
 #[cfg(feature = "zmq")]
 use inet2_addr::ServiceAddr;
 
+use super::padding::PaddingPolicy;
+use super::postmortem::{FrameDirection, FrameLog, SessionPostMortem};
 use super::{Decrypt, Encrypt, Transcode};
 use crate::session::noise::FramingProtocol;
 use crate::session::{noise, PlainTranscoder};
+#[cfg(feature = "keygen")]
+use crate::transport::guard::ConnectGuard;
 use crate::transport::{
     encrypted, unencrypted, DuplexConnection, Error, RecvFrame, RoutedFrame,
     SendFrame,
@@ -52,6 +56,20 @@ pub trait SendRecvMessage {
     fn into_any(self: Box<Self>) -> Box<dyn Any>;
 }
 
+/// Error of [`Session::recv_many`] returned when receiving or decrypting one
+/// of the messages in a batch fails. Keeps all messages received before the
+/// failure.
+#[cfg(feature = "zmq")]
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display("{error}")]
+pub struct RecvManyError {
+    /// Messages successfully received before the failure
+    pub received: Vec<Vec<u8>>,
+
+    /// Error which has interrupted the batch
+    pub error: Error,
+}
+
 pub trait Split {
     fn split(
         self,
@@ -82,6 +100,14 @@ pub trait SendMessage {
         // this pattern can't work
         panic!("Multipeer sockets are not possible with the chosen transport")
     }
+
+    /// Writes messages buffered by the underlying transport, if any.
+    fn flush(&mut self) -> Result<(), Error> { Ok(()) }
+
+    /// Shuts the underlying connection down; see [`SendFrame::shutdown`].
+    fn shutdown(&mut self) -> Result<(), Error> {
+        Err(Error::SocketIo(std::io::ErrorKind::Unsupported))
+    }
 }
 
 pub struct Session<T, C>
@@ -95,6 +121,9 @@ where
 {
     pub(self) transcoder: T,
     pub(self) connection: C,
+    pub(self) frame_log: Option<FrameLog>,
+    pub(self) post_mortem: Option<SessionPostMortem>,
+    pub(self) padding: PaddingPolicy,
 }
 
 pub struct Receiver<D, R>
@@ -104,6 +133,7 @@ where
 {
     pub(self) decryptor: D,
     pub(self) input: R,
+    pub(self) padding: PaddingPolicy,
 }
 
 pub struct Sender<E, S>
@@ -113,6 +143,7 @@ where
 {
     pub(self) encryptor: E,
     pub(self) output: S,
+    pub(self) padding: PaddingPolicy,
 }
 
 // Private trait used to avoid code duplication below
@@ -141,21 +172,30 @@ where
 {
     #[inline]
     fn recv_raw_message(&mut self) -> Result<Vec<u8>, Error> {
-        let reader = self.connection.as_receiver();
-        Ok(self.transcoder.decrypt(reader.recv_frame()?)?)
+        let frame = self.connection.as_receiver().recv_frame();
+        let res = frame.and_then(|frame| Ok(self.transcoder.decrypt(frame)?));
+        self.logged_recv(res)
     }
 
     #[inline]
     fn send_raw_message(&mut self, raw: &[u8]) -> Result<usize, Error> {
+        check_payload_size(raw, self.max_frame_size())?;
         let writer = self.connection.as_sender();
-        writer.send_frame(&self.transcoder.encrypt(raw))
+        let res = send_encrypted(&mut self.transcoder, raw, |frame| {
+            writer.send_frame(frame)
+        });
+        self.logged_send(raw, res)
     }
 
     #[inline]
     fn recv_routed_message(&mut self) -> Result<RoutedFrame, Error> {
-        let reader = self.connection.as_receiver();
-        let mut routed_frame = reader.recv_routed()?;
-        routed_frame.msg = self.transcoder.decrypt(routed_frame.msg)?;
+        let res = self.connection.as_receiver().recv_routed();
+        let mut routed_frame = res.map_err(|err| self.fail(err))?;
+        let msg = self
+            .transcoder
+            .decrypt(routed_frame.msg)
+            .map_err(Error::from);
+        routed_frame.msg = self.logged_recv(msg)?;
         Ok(routed_frame)
     }
 
@@ -168,7 +208,10 @@ where
         raw: &[u8],
     ) -> Result<usize, Error> {
         let writer = self.connection.as_sender();
-        writer.send_routed(source, route, dest, &self.transcoder.encrypt(raw))
+        let res = send_encrypted(&mut self.transcoder, raw, |frame| {
+            writer.send_routed(source, route, dest, frame)
+        });
+        self.logged_send(raw, res)
     }
 }
 
@@ -199,10 +242,55 @@ impl SendRecvMessage for Session<PlainTranscoder, unencrypted::Connection> {
     fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
 }
 
+/// Encrypts and sends the message. If the frame is not delivered, the
+/// encryptor is poisoned: its nonce has already advanced past the lost frame,
+/// so the remote peer would not be able to decrypt any further frames.
+fn send_encrypted(
+    encryptor: &mut impl Encrypt,
+    raw: &[u8],
+    send: impl FnOnce(&[u8]) -> Result<usize, Error>,
+) -> Result<usize, Error> {
+    if encryptor.is_poisoned() {
+        return Err(Error::SessionPoisoned);
+    }
+    let frame = encryptor.encrypt(raw);
+    match send(&frame) {
+        Ok(len) => {
+            encryptor.frame_sent(&frame);
+            Ok(len)
+        }
+        Err(err) => {
+            encryptor.poison();
+            Err(err)
+        }
+    }
+}
+
+fn max_payload_size(encryptor: &impl Encrypt, max_frame_size: usize) -> usize {
+    max_frame_size
+        .saturating_sub(encryptor.frame_overhead())
+        .min(encryptor.max_payload_len())
+}
+
+fn check_payload_size(raw: &[u8], max: usize) -> Result<(), Error> {
+    if raw.len() > max {
+        return Err(Error::FrameTooLargeForTransport {
+            len: raw.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
 fn recv_noise_message<const LEN_SIZE: usize>(
     reader: &mut dyn RecvFrame,
     decrypt: &mut NoiseDecryptor<LEN_SIZE>,
 ) -> Result<Vec<u8>, Error> {
+    // Once MAC verification has failed, the stream is desynchronized and we
+    // must not read from it anymore
+    if decrypt.is_poisoned() {
+        return Err(Error::SessionPoisoned);
+    }
     // Reading & decrypting length
     let encrypted_len = reader.recv_frame()?;
     decrypt.decrypt(encrypted_len)?;
@@ -213,17 +301,33 @@ fn recv_noise_message<const LEN_SIZE: usize>(
     Ok(payload)
 }
 
-impl<const LEN_SIZE: usize> SendRecvMessage
-    for Session<NoiseTranscoder<LEN_SIZE>, encrypted::Connection<LEN_SIZE>>
+impl<C, const LEN_SIZE: usize> SendRecvMessage
+    for Session<NoiseTranscoder<LEN_SIZE>, C>
+where
+    C: DuplexConnection + Bipolar + 'static,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
 {
     fn recv_raw_message(&mut self) -> Result<Vec<u8>, Error> {
+        let padding = self.padding;
         let reader = self.connection.as_receiver();
-        recv_noise_message(reader, &mut self.transcoder.decryptor)
+        let res = recv_noise_message(reader, &mut self.transcoder.decryptor)
+            .and_then(|msg| padding.unpad(msg));
+        self.logged_recv(res)
     }
 
-    #[inline]
     fn send_raw_message(&mut self, raw: &[u8]) -> Result<usize, Error> {
-        InternalSession::send_raw_message(self, raw)
+        if self.transcoder.decryptor.is_poisoned() {
+            return Err(self.fail(Error::SessionPoisoned));
+        }
+        if !self.padding.is_enabled() {
+            return InternalSession::send_raw_message(self, raw);
+        }
+        let padded = self
+            .padding
+            .pad(raw, self.max_frame_size())
+            .map_err(|err| self.fail(err))?;
+        InternalSession::send_raw_message(self, &padded)
     }
     fn recv_routed_message(&mut self) -> Result<RoutedFrame, Error> {
         unimplemented!(
@@ -290,9 +394,18 @@ where
     ) -> (Box<dyn RecvMessage + Send>, Box<dyn SendMessage + Send>) {
         let (decryptor, encryptor) = self.transcoder.split();
         let (input, output) = Bipolar::split(self.connection);
+        let padding = self.padding;
         (
-            Box::new(Receiver { decryptor, input }),
-            Box::new(Sender { encryptor, output }),
+            Box::new(Receiver {
+                decryptor,
+                input,
+                padding,
+            }),
+            Box::new(Sender {
+                encryptor,
+                output,
+                padding,
+            }),
         )
     }
 }
@@ -313,11 +426,140 @@ pub type RpcSession = Session<
     zeromq::Connection,
 >;
 
-impl<const LEN_SIZE: usize>
-    Session<NoiseTranscoder<LEN_SIZE>, encrypted::Connection<LEN_SIZE>>
+impl<T, C> Session<T, C>
+where
+    T: Transcode,
+    T::Left: Decrypt,
+    T::Right: Encrypt,
+    C: DuplexConnection + Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    /// Constructs session from an already established connection, which may
+    /// be any custom transport implementing [`DuplexConnection`], and a
+    /// transcoder (for instance, one produced by a completed Noise_XK
+    /// handshake).
+    pub fn with_transport(connection: C, transcoder: T) -> Self {
+        Self {
+            transcoder,
+            connection,
+            frame_log: None,
+            post_mortem: None,
+            padding: PaddingPolicy::None,
+        }
+    }
+
+    /// Returns maximum size of a message which can be sent in a single frame
+    /// over the session. The value is derived from the frame size limit of
+    /// the underlying transport, excluding framing and encryption overhead,
+    /// and never exceeds the message size limit of the transcoder.
+    pub fn max_frame_size(&self) -> usize {
+        max_payload_size(&self.transcoder, self.connection.max_frame_size())
+    }
+
+    /// Writes messages buffered by the underlying transport, if any (see
+    /// [`crate::transport::coalescing`]).
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.connection.as_sender().flush()
+    }
+
+    /// Starts recording metadata of the frames sent and received by the
+    /// session into the provided log, replacing the previous one. The log is
+    /// attached to [`Session::post_mortem`] once the session fails with a
+    /// session-fatal error. Frames are not recorded after the session is
+    /// split.
+    pub fn enable_frame_log(&mut self, log: FrameLog) {
+        self.frame_log = Some(log)
+    }
+
+    /// Stops recording frames and returns the log, if it was enabled
+    pub fn disable_frame_log(&mut self) -> Option<FrameLog> {
+        self.frame_log.take()
+    }
+
+    /// Returns log of the recent frames, if frame logging is enabled
+    #[inline]
+    pub fn frame_log(&self) -> Option<&FrameLog> { self.frame_log.as_ref() }
+
+    /// Returns information about the first session-fatal error together with
+    /// the frames preceding it. Available only if frame logging was enabled
+    /// at the moment of the failure.
+    #[inline]
+    pub fn post_mortem(&self) -> Option<&SessionPostMortem> {
+        self.post_mortem.as_ref()
+    }
+
+    fn log_frame(&mut self, direction: FrameDirection, msg: &[u8]) {
+        if let Some(log) = &mut self.frame_log {
+            log.record(direction, msg);
+        }
+    }
+
+    /// Takes snapshot of the frame log if the error has terminated the
+    /// session
+    fn fail(&mut self, err: Error) -> Error {
+        if let Some(log) = &self.frame_log {
+            let fatal = err.is_session_fatal()
+                || Encrypt::is_poisoned(&self.transcoder);
+            if self.post_mortem.is_none() && fatal {
+                self.post_mortem = Some(SessionPostMortem {
+                    error: err.clone(),
+                    recent_frames: log.snapshot(),
+                });
+            }
+        }
+        err
+    }
+
+    fn logged_recv(
+        &mut self,
+        res: Result<Vec<u8>, Error>,
+    ) -> Result<Vec<u8>, Error> {
+        match res {
+            Ok(msg) => {
+                self.log_frame(FrameDirection::Received, &msg);
+                Ok(msg)
+            }
+            Err(err) => Err(self.fail(err)),
+        }
+    }
+
+    fn logged_send(
+        &mut self,
+        raw: &[u8],
+        res: Result<usize, Error>,
+    ) -> Result<usize, Error> {
+        match res {
+            Ok(len) => {
+                self.log_frame(FrameDirection::Sent, raw);
+                Ok(len)
+            }
+            Err(err) => Err(self.fail(err)),
+        }
+    }
+}
+
+impl<C, const LEN_SIZE: usize> Session<NoiseTranscoder<LEN_SIZE>, C>
+where
+    C: DuplexConnection + Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
 {
     #[inline]
     pub fn remote_id(&self) -> NodeId { self.transcoder.remote_pubkey().into() }
+
+    /// Sets policy for padding messages sent and received over the session.
+    /// Padding is not negotiated, so both peers must enable it (see
+    /// [`crate::session::padding`] for the details). The policy is inherited
+    /// by the halves of the split session.
+    #[inline]
+    pub fn set_padding_policy(&mut self, policy: PaddingPolicy) {
+        self.padding = policy
+    }
+
+    /// Returns policy for padding messages
+    #[inline]
+    pub fn padding_policy(&self) -> PaddingPolicy { self.padding }
 }
 
 #[cfg(feature = "keygen")]
@@ -337,6 +579,18 @@ impl BrontideSession {
         BrontideSession::connect_tcp_encrypted(local_key, remote_node)
     }
 
+    pub fn connect_guarded(
+        local_key: secp256k1::SecretKey,
+        remote_node: NodeAddr,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        BrontideSession::connect_guarded_tcp_encrypted(
+            local_key,
+            remote_node,
+            guard,
+        )
+    }
+
     pub fn accept(
         local_key: secp256k1::SecretKey,
         listener: &TcpListener,
@@ -374,6 +628,18 @@ impl BrontozaurSession {
         BrontozaurSession::connect_tcp_encrypted(local_key, remote_node)
     }
 
+    pub fn connect_guarded(
+        local_key: secp256k1::SecretKey,
+        remote_node: NodeAddr,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        BrontozaurSession::connect_guarded_tcp_encrypted(
+            local_key,
+            remote_node,
+            guard,
+        )
+    }
+
     pub fn accept(
         local_key: secp256k1::SecretKey,
         listener: &TcpListener,
@@ -396,6 +662,22 @@ impl LocalSession {
         )
     }
 
+    /// Connects to the `remote` endpoint if the `guard` allows it; see
+    /// [`zeromq::Connection::connect_guarded`].
+    pub fn connect_guarded(
+        zmq_type: zeromq::ZmqSocketType,
+        remote: &ServiceAddr,
+        local: Option<&ServiceAddr>,
+        identity: Option<&[u8]>,
+        context: &zmq::Context,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        let connection = zeromq::Connection::connect_guarded(
+            zmq_type, remote, local, identity, context, guard,
+        )?;
+        Ok(Self::with_transport(connection, PlainTranscoder))
+    }
+
     pub fn with_zmq_socket(
         zmq_type: zeromq::ZmqSocketType,
         socket: zmq::Socket,
@@ -428,6 +710,18 @@ impl RpcSession {
 }
  */
 
+impl<const LEN_SIZE: usize>
+    Session<NoiseTranscoder<LEN_SIZE>, encrypted::Connection<LEN_SIZE>>
+{
+    /// Returns TCP stream underlying the session, which may be used for
+    /// adjusting socket options like read timeout or for shutting the
+    /// connection down.
+    #[inline]
+    pub fn as_tcp_stream(&self) -> &std::net::TcpStream {
+        self.connection.as_stream().as_tcp_stream()
+    }
+}
+
 #[cfg(feature = "keygen")]
 impl<const LEN_SIZE: usize>
     Session<NoiseTranscoder<LEN_SIZE>, encrypted::Connection<LEN_SIZE>>
@@ -448,33 +742,43 @@ impl<const LEN_SIZE: usize>
         local_key: secp256k1::SecretKey,
         remote_node: NodeAddr,
     ) -> Result<Self, Error> {
-        let mut connection =
-            encrypted::Connection::with(stream, remote_node.addr);
-        let transcoder = NoiseTranscoder::new_initiator(
+        Self::connect_with_transport(
+            encrypted::Connection::with(stream, remote_node.addr),
             local_key,
             remote_node.public_key(),
-            &mut connection,
-        )?;
-        Ok(Self {
-            transcoder,
-            connection,
-        })
+        )
+    }
+
+    fn connect_guarded_tcp_encrypted(
+        local_key: secp256k1::SecretKey,
+        remote_node: NodeAddr,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        guard
+            .allow(&remote_node.addr, Some(&remote_node.id))
+            .map_err(Error::ConnectionDenied)?;
+        Self::connect_tcp_encrypted(local_key, remote_node)
     }
 
     fn connect_tcp_encrypted(
         local_key: secp256k1::SecretKey,
         remote_node: NodeAddr,
     ) -> Result<Self, Error> {
-        let mut connection = encrypted::Connection::connect(remote_node.addr)?;
-        let transcoder = NoiseTranscoder::new_initiator(
+        use noise::HandshakeCrypto;
+
+        // Do not even open a socket if we are going to connect to ourselves.
+        // The key is derived by the same crypto backend which will run the
+        // handshake.
+        let local_pubkey = noise::SoftwareCrypto
+            .public_key(noise::KeyRole::Static, &local_key);
+        if remote_node.public_key() == local_pubkey {
+            return Err(noise::HandshakeError::SelfConnection.into());
+        }
+        Self::connect_with_transport(
+            encrypted::Connection::connect(remote_node.addr)?,
             local_key,
             remote_node.public_key(),
-            &mut connection,
-        )?;
-        Ok(Self {
-            transcoder,
-            connection,
-        })
+        )
     }
 
     fn accept_tcp_encrypted(
@@ -489,14 +793,43 @@ impl<const LEN_SIZE: usize>
 
     fn init_tcp_encrypted(
         local_key: secp256k1::SecretKey,
-        mut connection: encrypted::Connection<LEN_SIZE>,
+        connection: encrypted::Connection<LEN_SIZE>,
+    ) -> Result<Self, Error> {
+        Self::accept_with_transport(connection, local_key)
+    }
+}
+
+#[cfg(feature = "keygen")]
+impl<C, const LEN_SIZE: usize> Session<NoiseTranscoder<LEN_SIZE>, C>
+where
+    C: DuplexConnection + Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    /// Runs Noise_XK handshake as an initiator over an already established
+    /// connection with a remote peer having `remote_key` node id.
+    pub fn connect_with_transport(
+        mut connection: C,
+        local_key: secp256k1::SecretKey,
+        remote_key: secp256k1::PublicKey,
+    ) -> Result<Self, Error> {
+        let transcoder = NoiseTranscoder::new_initiator(
+            local_key,
+            remote_key,
+            &mut connection,
+        )?;
+        Ok(Self::with_transport(connection, transcoder))
+    }
+
+    /// Runs Noise_XK handshake as a responder over an already established
+    /// connection.
+    pub fn accept_with_transport(
+        mut connection: C,
+        local_key: secp256k1::SecretKey,
     ) -> Result<Self, Error> {
         let transcoder =
             NoiseTranscoder::new_responder(local_key, &mut connection)?;
-        Ok(Self {
-            transcoder,
-            connection,
-        })
+        Ok(Self::with_transport(connection, transcoder))
     }
 }
 
@@ -556,22 +889,20 @@ impl Session<PlainTranscoder, zeromq::Connection> {
         identity: Option<&[u8]>,
         context: &zmq::Context,
     ) -> Result<Self, Error> {
-        Ok(Self {
-            transcoder: PlainTranscoder,
-            connection: zeromq::Connection::connect(
-                zmq_type, remote, local, identity, context,
-            )?,
-        })
+        let connection = zeromq::Connection::connect(
+            zmq_type, remote, local, identity, context,
+        )?;
+        Ok(Self::with_transport(connection, PlainTranscoder))
     }
 
     fn with_zmq_socket_unencrypted(
         zmq_type: zeromq::ZmqSocketType,
         socket: zmq::Socket,
     ) -> Self {
-        Self {
-            transcoder: PlainTranscoder,
-            connection: zeromq::Connection::with_socket(zmq_type, socket),
-        }
+        Self::with_transport(
+            zeromq::Connection::with_socket(zmq_type, socket),
+            PlainTranscoder,
+        )
     }
 }
 
@@ -584,6 +915,46 @@ where
 {
     pub fn as_socket(&self) -> &zmq::Socket { self.connection.as_socket() }
 
+    /// Receives up to `max` messages in a single call. Blocks until the first
+    /// message arrives and then takes only the messages which are already
+    /// queued in the socket, without waiting for more of them.
+    pub fn recv_many(
+        &mut self,
+        max: usize,
+    ) -> Result<Vec<Vec<u8>>, RecvManyError>
+    where
+        Error: From<T::Error>,
+    {
+        let mut received = Vec::new();
+        let mut flags = 0;
+        while received.len() < max {
+            let frame = match self.connection.as_socket().recv_bytes(flags) {
+                Ok(frame) => frame,
+                Err(zmq::Error::EAGAIN) if flags == zmq::DONTWAIT => break,
+                Err(err) => {
+                    return Err(RecvManyError {
+                        received,
+                        error: err.into(),
+                    })
+                }
+            };
+            match self.transcoder.decrypt(frame) {
+                Ok(msg) => {
+                    self.log_frame(FrameDirection::Received, &msg);
+                    received.push(msg)
+                }
+                Err(err) => {
+                    return Err(RecvManyError {
+                        received,
+                        error: err.into(),
+                    })
+                }
+            }
+            flags = zmq::DONTWAIT;
+        }
+        Ok(received)
+    }
+
     pub fn set_identity(
         &mut self,
         identity: &impl AsRef<[u8]>,
@@ -593,6 +964,50 @@ where
             .set_identity(identity, context)
             .map_err(Error::from)
     }
+
+    /// Switches PUB or SUB session into the topic envelope mode; see
+    /// [`zeromq::Connection::enable_topic_envelope`] for the details. Must be
+    /// done on both publisher and subscriber sides.
+    #[inline]
+    pub fn enable_topic_envelope(&mut self) -> Result<(), Error> {
+        self.connection.enable_topic_envelope()
+    }
+
+    /// Subscribes SUB session to the messages having topic starting with
+    /// `prefix`; an empty prefix subscribes to all messages.
+    #[inline]
+    pub fn subscribe(&mut self, prefix: &[u8]) -> Result<(), Error> {
+        self.connection.subscribe(prefix)
+    }
+
+    /// Subscribes SUB session in topic envelope mode to the messages of the
+    /// given type. Messages of other types are filtered out by the publisher
+    /// and never reach the subscriber.
+    #[inline]
+    pub fn subscribe_type(&mut self, type_id: TypeId) -> Result<(), Error> {
+        self.connection.subscribe_type(type_id)
+    }
+
+    /// Serializes and sends the message. In the topic envelope mode the
+    /// message is published under the topic derived from its type id (see
+    /// [`zeromq::type_topic`]).
+    pub fn send_message(
+        &mut self,
+        msg: &impl TypedEnum,
+    ) -> Result<usize, Error> {
+        let raw = msg.serialize();
+        check_payload_size(&raw, self.max_frame_size())?;
+        let topic = zeromq::type_topic(msg.get_type());
+        let connection = &mut self.connection;
+        let res = send_encrypted(&mut self.transcoder, &raw, |frame| {
+            if connection.is_topic_enveloped() {
+                connection.send_topic_frame(&topic, frame)
+            } else {
+                connection.as_sender().send_frame(frame)
+            }
+        });
+        self.logged_send(&raw, res)
+    }
 }
 
 // Private trait used to avoid code duplication below
@@ -628,12 +1043,15 @@ impl RecvMessage for Receiver<PlainTranscoder, unencrypted::Stream> {
     }
 }
 
-impl<const LEN_SIZE: usize> RecvMessage
-    for Receiver<NoiseDecryptor<LEN_SIZE>, encrypted::Stream<LEN_SIZE>>
+impl<R, const LEN_SIZE: usize> RecvMessage
+    for Receiver<NoiseDecryptor<LEN_SIZE>, R>
+where
+    R: RecvFrame,
 {
     #[inline]
     fn recv_raw_message(&mut self) -> Result<Vec<u8>, Error> {
         recv_noise_message(&mut self.input, &mut self.decryptor)
+            .and_then(|msg| self.padding.unpad(msg))
     }
     fn recv_routed_message(&mut self) -> Result<RoutedFrame, Error> {
         InternalInput::recv_routed_message(self)
@@ -657,7 +1075,20 @@ where
     C: SendFrame,
 {
     fn send_raw_message(&mut self, raw: &[u8]) -> Result<usize, Error> {
-        self.output.send_frame(&self.encryptor.encrypt(raw))
+        let max =
+            max_payload_size(&self.encryptor, self.output.max_frame_size());
+        let padded;
+        let raw = if self.padding.is_enabled() {
+            padded = self.padding.pad(raw, max)?;
+            &padded[..]
+        } else {
+            raw
+        };
+        check_payload_size(raw, max)?;
+        let output = &mut self.output;
+        send_encrypted(&mut self.encryptor, raw, |frame| {
+            output.send_frame(frame)
+        })
     }
     fn send_routed_message(
         &mut self,
@@ -666,20 +1097,162 @@ where
         dest: &[u8],
         raw: &[u8],
     ) -> Result<usize, Error> {
-        let encrypted = self.encryptor.encrypt(raw);
-        self.output.send_routed(source, route, dest, &encrypted)
+        let output = &mut self.output;
+        send_encrypted(&mut self.encryptor, raw, |frame| {
+            output.send_routed(source, route, dest, frame)
+        })
     }
+
+    fn flush(&mut self) -> Result<(), Error> { self.output.flush() }
+
+    fn shutdown(&mut self) -> Result<(), Error> { self.output.shutdown() }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::mpsc;
+
     use super::*;
 
+    /// Receiving half of an in-memory byte pipe used as a custom transport
+    struct PipeIn {
+        chan: mpsc::Receiver<Vec<u8>>,
+        buf: Vec<u8>,
+    }
+
+    /// Sending half of an in-memory byte pipe used as a custom transport
+    struct PipeOut {
+        chan: mpsc::Sender<Vec<u8>>,
+        max_frame_size: usize,
+    }
+
+    struct Pipe {
+        input: PipeIn,
+        output: PipeOut,
+    }
+
+    fn pipe() -> (Pipe, Pipe) {
+        let (tx_a, rx_b) = mpsc::channel();
+        let (tx_b, rx_a) = mpsc::channel();
+        let a = Pipe {
+            input: PipeIn {
+                chan: rx_a,
+                buf: vec![],
+            },
+            output: PipeOut {
+                chan: tx_a,
+                max_frame_size: usize::MAX,
+            },
+        };
+        let b = Pipe {
+            input: PipeIn {
+                chan: rx_b,
+                buf: vec![],
+            },
+            output: PipeOut {
+                chan: tx_b,
+                max_frame_size: usize::MAX,
+            },
+        };
+        (a, b)
+    }
+
+    impl RecvFrame for PipeIn {
+        fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
+            self.recv_raw(FramingProtocol::Brontide.header_size())
+        }
+
+        fn recv_raw(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+            while self.buf.len() < len {
+                let chunk =
+                    self.chan.recv().map_err(|_| Error::ServiceOffline)?;
+                self.buf.extend(chunk);
+            }
+            Ok(self.buf.drain(..len).collect())
+        }
+    }
+
+    impl SendFrame for PipeOut {
+        fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+            self.send_raw(frame)
+        }
+
+        fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+            self.chan
+                .send(raw_frame.to_vec())
+                .map_err(|_| Error::ServiceOffline)?;
+            Ok(raw_frame.len())
+        }
+
+        fn max_frame_size(&self) -> usize { self.max_frame_size }
+    }
+
+    impl DuplexConnection for Pipe {
+        fn as_receiver(&mut self) -> &mut dyn RecvFrame { &mut self.input }
+
+        fn as_sender(&mut self) -> &mut dyn SendFrame { &mut self.output }
+
+        fn split(
+            self,
+        ) -> (Box<dyn RecvFrame + Send>, Box<dyn SendFrame + Send>) {
+            (Box::new(self.input), Box::new(self.output))
+        }
+
+        fn max_frame_size(&self) -> usize { self.output.max_frame_size }
+    }
+
+    impl Bipolar for Pipe {
+        type Left = PipeIn;
+        type Right = PipeOut;
+
+        fn join(input: PipeIn, output: PipeOut) -> Self {
+            Pipe { input, output }
+        }
+
+        fn split(self) -> (PipeIn, PipeOut) { (self.input, self.output) }
+    }
+
+    #[test]
+    #[cfg(feature = "keygen")]
+    fn test_noise_custom_transport() {
+        let secp = secp256k1::Secp256k1::new();
+        let mut rng = secp256k1::rand::thread_rng();
+        let (local_key, local_id) = secp.generate_keypair(&mut rng);
+        let (remote_key, remote_id) = secp.generate_keypair(&mut rng);
+        let (a, b) = pipe();
+
+        let responder = std::thread::spawn(move || {
+            let mut session =
+                Session::<NoiseTranscoder<2>, Pipe>::accept_with_transport(
+                    b, remote_key,
+                )
+                .unwrap();
+            assert_eq!(session.remote_id(), NodeId::from(local_id));
+            let msg = SendRecvMessage::recv_raw_message(&mut session).unwrap();
+            SendRecvMessage::send_raw_message(&mut session, &msg).unwrap();
+        });
+
+        let mut session =
+            Session::<NoiseTranscoder<2>, Pipe>::connect_with_transport(
+                a, local_key, remote_id,
+            )
+            .unwrap();
+        assert_eq!(session.remote_id(), NodeId::from(remote_id));
+
+        let msg = b"Some message";
+        SendRecvMessage::send_raw_message(&mut session, msg).unwrap();
+        assert_eq!(
+            SendRecvMessage::recv_raw_message(&mut session).unwrap(),
+            msg
+        );
+        responder.join().unwrap();
+    }
+
     #[test]
     #[cfg(feature = "zmq")]
     fn test_zmq_no_encryption() {
         let ctx = zmq::Context::new();
-        let locator = ServiceAddr::Inproc(s!("test"));
+        let locator = ServiceAddr::inproc_unique("no-encryption");
         let mut rx = Session::connect_zmq_unencrypted(
             zeromq::ZmqSocketType::Rep,
             &locator,
@@ -705,4 +1278,327 @@ mod test {
         SendRecvMessage::send_raw_message(&mut rx, msg).unwrap();
         assert_eq!(SendRecvMessage::recv_raw_message(&mut tx).unwrap(), msg);
     }
+
+    #[test]
+    #[cfg(feature = "zmq")]
+    fn test_zmq_recv_many() {
+        let count = 10_000u32;
+
+        // All messages are queued before the first read, so high water marks
+        // (1000 by default) must fit them, otherwise the sender blocks
+        let ctx = zmq::Context::new();
+        let pull = ctx.socket(zmq::PULL).unwrap();
+        pull.set_rcvhwm(count as i32 * 2).unwrap();
+        let endpoint =
+            ServiceAddr::inproc_unique("recv-many").zmq_connect_string();
+        pull.bind(&endpoint).unwrap();
+        let push = ctx.socket(zmq::PUSH).unwrap();
+        push.set_sndhwm(count as i32 * 2).unwrap();
+        push.connect(&endpoint).unwrap();
+        let mut rx = Session::with_zmq_socket_unencrypted(
+            zeromq::ZmqSocketType::Pull,
+            pull,
+        );
+        let mut tx = Session::with_zmq_socket_unencrypted(
+            zeromq::ZmqSocketType::Push,
+            push,
+        );
+
+        assert_eq!(rx.recv_many(0).unwrap(), Vec::<Vec<u8>>::new());
+
+        for no in 0..count {
+            SendRecvMessage::send_raw_message(&mut tx, &no.to_le_bytes())
+                .unwrap();
+        }
+        let mut received = vec![];
+        while received.len() < count as usize {
+            let batch = rx.recv_many(64).unwrap();
+            assert!(!batch.is_empty() && batch.len() <= 64);
+            received.extend(batch);
+        }
+        let expected = (0..count)
+            .map(|no| no.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(received, expected);
+
+        // Garbage frame in the middle of the batch
+        SendRecvMessage::send_raw_message(&mut tx, b"first").unwrap();
+        tx.as_socket().send(&b"x"[..], 0).unwrap();
+        SendRecvMessage::send_raw_message(&mut tx, b"last").unwrap();
+        assert_eq!(rx.recv_many(10).unwrap_err(), RecvManyError {
+            received: vec![b"first".to_vec()],
+            error: Error::FrameTooSmall(1)
+        });
+        assert_eq!(rx.recv_many(10).unwrap(), vec![b"last".to_vec()]);
+    }
+
+    fn noise_transcoder<const LEN_SIZE: usize>() -> NoiseTranscoder<LEN_SIZE> {
+        let secp = secp256k1::Secp256k1::new();
+        let key = secp256k1::SecretKey::from_slice(&[0x11; 32]).unwrap();
+        NoiseTranscoder::with(
+            [0u8; 32],
+            [0u8; 32],
+            [0u8; 32],
+            secp256k1::PublicKey::from_secret_key(&secp, &key),
+        )
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        use crate::transport::MAX_FRAME_SIZE;
+
+        // FTCP and Brontide over TCP are limited by the two-byte length prefix
+        assert_eq!(max_payload_size(&PlainTranscoder, MAX_FRAME_SIZE), 0xFFFF);
+        assert_eq!(
+            max_payload_size(&noise_transcoder::<2>(), MAX_FRAME_SIZE),
+            0xFFFF
+        );
+        // Brontozaur has one more length byte, and TCP frame limit applies
+        assert_eq!(
+            max_payload_size(&noise_transcoder::<3>(), MAX_FRAME_SIZE),
+            0xFFFF - 1
+        );
+        // Transport without frame size limit
+        assert_eq!(
+            max_payload_size(&noise_transcoder::<2>(), usize::MAX),
+            0xFFFF
+        );
+        assert_eq!(
+            max_payload_size(&noise_transcoder::<3>(), usize::MAX),
+            0xFFFFFF
+        );
+        assert_eq!(max_payload_size(&PlainTranscoder, 10), 0);
+    }
+
+    #[test]
+    fn test_frame_too_large_for_transport() {
+        let (mut a, b) = pipe();
+        a.output.max_frame_size = 100;
+        let mut tx = Session::with_transport(a, noise_transcoder::<2>());
+        let mut rx = Session::with_transport(b, noise_transcoder::<2>());
+        assert_eq!(tx.max_frame_size(), 100 - 18 - 16);
+
+        assert_eq!(
+            SendRecvMessage::send_raw_message(&mut tx, &[0xA5; 67])
+                .unwrap_err(),
+            Error::FrameTooLargeForTransport { len: 67, max: 66 }
+        );
+        SendRecvMessage::send_raw_message(&mut tx, &[0xA5; 66]).unwrap();
+        assert_eq!(
+            SendRecvMessage::recv_raw_message(&mut rx).unwrap(),
+            vec![0xA5; 66]
+        );
+    }
+
+    #[test]
+    fn test_padding() {
+        let (a, b) = pipe();
+        let mut tx = Session::with_transport(a, noise_transcoder::<2>());
+        tx.set_padding_policy(PaddingPolicy::Buckets(&[64, 256]));
+        assert_eq!(tx.padding_policy(), PaddingPolicy::Buckets(&[64, 256]));
+
+        // Ciphertext lengths on the wire include 18 bytes of the encrypted
+        // length prefix and 16 bytes of the message MAC
+        let mut decryptor = noise_transcoder::<2>();
+        for (len, wire_len) in [(0, 64), (60, 64), (61, 256), (252, 256)] {
+            let msg = vec![0xA5; len];
+            SendRecvMessage::send_raw_message(&mut tx, &msg).unwrap();
+            let frame = b.input.chan.recv().unwrap();
+            assert_eq!(frame.len(), wire_len + 34);
+            let padded = decryptor.decrypt(frame).unwrap();
+            assert_eq!(padded.len(), wire_len);
+            assert_eq!(tx.padding_policy().unpad(padded).unwrap(), msg);
+        }
+
+        // Both halves of the split session keep the policy
+        let (a, b) = pipe();
+        let mut tx = Session::with_transport(a, noise_transcoder::<2>());
+        let mut rx = Session::with_transport(b, noise_transcoder::<2>());
+        tx.set_padding_policy(PaddingPolicy::Fixed(128));
+        rx.set_padding_policy(PaddingPolicy::Fixed(128));
+        let (_, mut sender) = tx.split();
+        let msg = vec![0x5A; 200];
+        sender.send_raw_message(&msg).unwrap();
+        let (mut receiver, _) = rx.split();
+        assert_eq!(receiver.recv_raw_message().unwrap(), msg);
+    }
+
+    #[test]
+    fn test_padding_mismatch() {
+        let (a, b) = pipe();
+        let mut tx = Session::with_transport(a, noise_transcoder::<2>());
+        let mut rx = Session::with_transport(b, noise_transcoder::<2>());
+        rx.set_padding_policy(PaddingPolicy::Fixed(64));
+
+        SendRecvMessage::send_raw_message(&mut tx, b"Some message").unwrap();
+        let err = SendRecvMessage::recv_raw_message(&mut rx).unwrap_err();
+        assert_eq!(err, Error::InvalidPadding("length exceeds message size"));
+        assert!(err.is_session_fatal());
+    }
+
+    #[test]
+    fn test_mac_failure_poisons_session() {
+        use crate::session::noise::{EncryptionError, FramePart};
+        use crate::session::HandshakeError;
+
+        let (a, b) = pipe();
+        let mut rx = Session::with_transport(b, noise_transcoder::<2>());
+
+        // Corrupt a byte of the message body in transit
+        let mut frame = noise_transcoder::<2>()
+            .encrypt_buf(b"Some message")
+            .unwrap();
+        frame[20] ^= 0x01;
+        a.output.chan.send(frame).unwrap();
+
+        assert_eq!(
+            SendRecvMessage::recv_raw_message(&mut rx).unwrap_err(),
+            Error::Handshake(HandshakeError::Encryption(
+                EncryptionError::MacFailure {
+                    counter: 1,
+                    part: FramePart::Body
+                }
+            ))
+        );
+        assert_eq!(
+            SendRecvMessage::recv_raw_message(&mut rx).unwrap_err(),
+            Error::SessionPoisoned
+        );
+        assert_eq!(
+            SendRecvMessage::send_raw_message(&mut rx, b"").unwrap_err(),
+            Error::SessionPoisoned
+        );
+    }
+
+    #[test]
+    fn test_post_mortem() {
+        use crate::session::noise::{EncryptionError, FramePart};
+        use crate::session::HandshakeError;
+
+        let (a, b) = pipe();
+        let mut tx = Session::with_transport(a, noise_transcoder::<2>());
+        let mut rx = Session::with_transport(b, noise_transcoder::<2>());
+        rx.enable_frame_log(FrameLog::with_capacity(3).with_payloads(4));
+
+        for msg in [
+            &[0x00, 0x10, 0x01][..],
+            &[0x00, 0x12],
+            &[0x00, 0x13, 0x00, 0x00, 0xFF],
+            &[0x01, 0x00],
+        ] {
+            SendRecvMessage::send_raw_message(&mut tx, msg).unwrap();
+            assert_eq!(
+                SendRecvMessage::recv_raw_message(&mut rx).unwrap(),
+                msg
+            );
+        }
+        SendRecvMessage::send_raw_message(&mut rx, b"").unwrap();
+        assert!(rx.post_mortem().is_none());
+
+        // Garbage instead of the encrypted length header
+        tx.connection.output.chan.send(vec![0u8; 18]).unwrap();
+        let err = SendRecvMessage::recv_raw_message(&mut rx).unwrap_err();
+        assert_eq!(
+            err,
+            Error::Handshake(HandshakeError::Encryption(
+                EncryptionError::MacFailure {
+                    counter: 8,
+                    part: FramePart::LengthPrefix
+                }
+            ))
+        );
+        assert_eq!(
+            SendRecvMessage::recv_raw_message(&mut rx).unwrap_err(),
+            Error::SessionPoisoned
+        );
+
+        let post_mortem = rx.post_mortem().expect("post-mortem is recorded");
+        assert_eq!(post_mortem.error, err);
+        let frames = post_mortem
+            .recent_frames()
+            .iter()
+            .map(|record| (record.direction, record.len, record.type_id))
+            .collect::<Vec<_>>();
+        assert_eq!(frames, vec![
+            (FrameDirection::Received, 5, Some(0x13)),
+            (FrameDirection::Received, 2, Some(0x100)),
+            (FrameDirection::Sent, 0, None),
+        ]);
+        assert_eq!(
+            post_mortem.recent_frames()[0].payload,
+            Some(vec![0x00, 0x13, 0x00, 0x00])
+        );
+        assert!(tx.post_mortem().is_none());
+    }
+
+    #[test]
+    fn test_send_failure_poisons_session() {
+        let (a, b) = pipe();
+        let mut tx = Session::with_transport(a, noise_transcoder::<2>());
+        let mut rx = Session::with_transport(b, noise_transcoder::<2>());
+
+        SendRecvMessage::send_raw_message(&mut tx, b"first").unwrap();
+        assert_eq!(
+            SendRecvMessage::recv_raw_message(&mut rx).unwrap(),
+            b"first"
+        );
+
+        // The frame is encrypted, but the transport fails to deliver it, so
+        // the nonce of the remote peer lags behind
+        drop(rx);
+        assert_eq!(
+            SendRecvMessage::send_raw_message(&mut tx, b"second").unwrap_err(),
+            Error::ServiceOffline
+        );
+        assert!(Encrypt::is_poisoned(&tx.transcoder));
+        assert_eq!(
+            SendRecvMessage::send_raw_message(&mut tx, b"third").unwrap_err(),
+            Error::SessionPoisoned
+        );
+
+        // Same applies to the sending half of a split session
+        let (_, encryptor) = noise_transcoder::<2>().split();
+        let (a, b) = pipe();
+        let mut sender = Sender {
+            encryptor,
+            output: a.output,
+            padding: PaddingPolicy::None,
+        };
+        drop(b);
+        assert_eq!(
+            sender.send_raw_message(b"first").unwrap_err(),
+            Error::ServiceOffline
+        );
+        assert_eq!(
+            sender.send_raw_message(b"second").unwrap_err(),
+            Error::SessionPoisoned
+        );
+    }
+
+    #[test]
+    fn test_encryption_failure_keeps_nonce() {
+        use crate::consts::BRONTIDE_MSG_MAX_LEN;
+
+        let mut encryptor = noise_transcoder::<2>();
+        let mut decryptor = noise_transcoder::<2>();
+        assert!(encryptor
+            .encrypt_buf(&vec![0u8; BRONTIDE_MSG_MAX_LEN + 1])
+            .is_err());
+        assert!(!Encrypt::is_poisoned(&encryptor));
+        let frame = encryptor.encrypt_buf(b"next").unwrap();
+        assert_eq!(decryptor.decrypt(frame).unwrap(), b"next");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "noise send path desynchronized at sending \
+                               nonce 4: 2 frames were encrypted while 1 \
+                               frames were sent")]
+    fn test_send_desync_detected() {
+        let (a, _b) = pipe();
+        let mut tx = Session::with_transport(a, noise_transcoder::<2>());
+        // Frame which advances the nonce but never reaches the transport
+        tx.transcoder.encrypt_buf(b"lost").unwrap();
+        let _ = SendRecvMessage::send_raw_message(&mut tx, b"Some message");
+    }
 }
@@ -0,0 +1,232 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Sending messages through a single session from multiple threads.
+//!
+//! Sessions (and the underlying ZMQ sockets) are not thread-safe. Instead of
+//! guarding a session with a mutex, [`SharedSession`] moves it to a dedicated
+//! thread which performs all encryption and socket sends, while other
+//! threads queue messages through cloneable [`SharedSender`] handles.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+use super::SendRecvMessage;
+use crate::transport::Error;
+
+/// Receiver for the result of a single message send
+pub type SendAck = mpsc::Receiver<Result<usize, Error>>;
+
+enum Command {
+    Send(Vec<u8>, Option<mpsc::SyncSender<Result<usize, Error>>>),
+    Stop,
+}
+
+/// Session owned by a dedicated sending thread.
+///
+/// Dropping the shared session (or calling [`SharedSession::shutdown`])
+/// flushes all messages queued before that moment and stops the thread;
+/// sends made through the remaining [`SharedSender`]s after that fail with
+/// [`Error::ServiceOffline`].
+pub struct SharedSession<S>
+where
+    S: SendRecvMessage + Send + 'static,
+{
+    sender: SharedSender,
+    worker: Option<JoinHandle<S>>,
+}
+
+/// Cloneable handle for queueing messages to a [`SharedSession`].
+#[derive(Clone)]
+pub struct SharedSender {
+    queue: mpsc::SyncSender<Command>,
+    last_error: Arc<Mutex<Option<Error>>>,
+}
+
+impl<S> SharedSession<S>
+where
+    S: SendRecvMessage + Send + 'static,
+{
+    /// Moves the session into a new sending thread. At most `capacity`
+    /// messages are queued; further sends block until the queue is drained.
+    pub fn spawn(session: S, capacity: usize) -> Self {
+        let (queue, commands) = mpsc::sync_channel(capacity);
+        let last_error = Arc::new(Mutex::new(None));
+        let worker_error = last_error.clone();
+        let worker =
+            std::thread::spawn(move || run(session, commands, &worker_error));
+        SharedSession {
+            sender: SharedSender { queue, last_error },
+            worker: Some(worker),
+        }
+    }
+
+    /// Returns new handle for queueing messages.
+    #[inline]
+    pub fn sender(&self) -> SharedSender { self.sender.clone() }
+
+    /// Sends all queued messages, stops the sending thread and returns the
+    /// session back.
+    pub fn shutdown(mut self) -> S {
+        self.stop()
+            .expect("sending thread is running until shutdown")
+    }
+
+    fn stop(&mut self) -> Option<S> {
+        let worker = self.worker.take()?;
+        // The worker may only be gone if it has panicked
+        let _ = self.sender.queue.send(Command::Stop);
+        match worker.join() {
+            Ok(session) => Some(session),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl<S> Drop for SharedSession<S>
+where
+    S: SendRecvMessage + Send + 'static,
+{
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            self.stop();
+        }
+    }
+}
+
+impl SharedSender {
+    /// Queues message for sending, blocking while the queue is full. Errors
+    /// of the actual send are reported by [`SharedSender::last_error`].
+    pub fn send(&self, msg: impl Into<Vec<u8>>) -> Result<(), Error> {
+        self.queue
+            .send(Command::Send(msg.into(), None))
+            .map_err(|_| Error::ServiceOffline)
+    }
+
+    /// Queues message for sending, blocking while the queue is full, and
+    /// returns receiver for the result of the send. The result is not
+    /// reported by [`SharedSender::last_error`].
+    pub fn send_acknowledged(
+        &self,
+        msg: impl Into<Vec<u8>>,
+    ) -> Result<SendAck, Error> {
+        let (ack, receiver) = mpsc::sync_channel(1);
+        self.queue
+            .send(Command::Send(msg.into(), Some(ack)))
+            .map_err(|_| Error::ServiceOffline)?;
+        Ok(receiver)
+    }
+
+    /// Returns and clears the error of the most recent failed send made
+    /// without acknowledgement.
+    pub fn last_error(&self) -> Option<Error> {
+        self.last_error
+            .lock()
+            .expect("poisoned shared session lock")
+            .take()
+    }
+}
+
+fn run<S: SendRecvMessage>(
+    mut session: S,
+    commands: mpsc::Receiver<Command>,
+    last_error: &Mutex<Option<Error>>,
+) -> S {
+    for command in commands {
+        let (msg, ack) = match command {
+            Command::Send(msg, ack) => (msg, ack),
+            Command::Stop => break,
+        };
+        let res = session.send_raw_message(&msg);
+        match (ack, res) {
+            // Sender may be no longer interested in the result
+            (Some(ack), res) => {
+                let _ = ack.send(res);
+            }
+            (None, Err(err)) => {
+                *last_error.lock().expect("poisoned shared session lock") =
+                    Some(err)
+            }
+            (None, Ok(_)) => {}
+        }
+    }
+    session
+}
+
+#[cfg(all(test, feature = "zmq"))]
+mod test {
+    use inet2_addr::ServiceAddr;
+
+    use super::*;
+    use crate::session::LocalSession;
+    use crate::ZmqSocketType;
+
+    #[test]
+    fn concurrent_senders() {
+        let ctx = zmq::Context::new();
+        let pull = ctx.socket(zmq::PULL).unwrap();
+        let endpoint =
+            ServiceAddr::inproc_unique("shared-session").zmq_connect_string();
+        pull.bind(&endpoint).unwrap();
+        let push = ctx.socket(zmq::PUSH).unwrap();
+        push.connect(&endpoint).unwrap();
+        let mut rx = LocalSession::with_zmq_socket(ZmqSocketType::Pull, pull);
+        let tx = LocalSession::with_zmq_socket(ZmqSocketType::Push, push);
+
+        let threads = 8u8;
+        let count = 500u16;
+        let total = threads as usize * count as usize + 1;
+        let receiver = std::thread::spawn(move || {
+            (0..total)
+                .map(|_| rx.recv_raw_message().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let shared = SharedSession::spawn(tx, 16);
+        let senders = (0..threads)
+            .map(|thread| {
+                let sender = shared.sender();
+                std::thread::spawn(move || {
+                    for no in 0..count {
+                        let mut msg = vec![thread];
+                        msg.extend(no.to_be_bytes());
+                        sender.send(msg).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        let ack = shared.sender().send_acknowledged(&b"last"[..]).unwrap();
+        assert!(ack.recv().unwrap().is_ok());
+        let sender = shared.sender();
+        assert_eq!(sender.last_error(), None);
+
+        // Shutdown flushes the queue and releases the session
+        drop(shared.shutdown());
+        assert_eq!(sender.send(&b"late"[..]), Err(Error::ServiceOffline));
+
+        let mut received = receiver.join().unwrap();
+        assert_eq!(received.pop().unwrap(), b"last");
+        let mut next = vec![0u16; threads as usize];
+        for msg in received {
+            let thread = msg[0] as usize;
+            let no = u16::from_be_bytes([msg[1], msg[2]]);
+            assert_eq!(no, next[thread], "message from thread {}", thread);
+            next[thread] += 1;
+        }
+        assert_eq!(next, vec![count; threads as usize]);
+    }
+}
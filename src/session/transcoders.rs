@@ -15,10 +15,33 @@ use std::borrow::Borrow;
 
 use amplify::Bipolar;
 
-use crate::transport::{Error, FRAME_PREFIX_SIZE, FRAME_SUFFIX_SIZE};
+use crate::consts::{
+    BRONTIDE_LEN_SIZE, FRAME_PREFIX_SIZE, FRAME_SUFFIX_SIZE,
+    MAX_FRAME_PAYLOAD_SIZE,
+};
+use crate::transport::Error;
 
 pub trait Encrypt {
     fn encrypt(&mut self, buffer: impl Borrow<[u8]>) -> Vec<u8>;
+
+    /// Number of bytes added to each of the messages by the encryption
+    /// (length prefix, MACs etc).
+    fn frame_overhead(&self) -> usize { 0 }
+
+    /// Maximum length of a message which can be encrypted into a single frame.
+    fn max_payload_len(&self) -> usize { usize::MAX }
+
+    /// Notifies the encryptor that a frame it has produced was fully handed to
+    /// the transport. Stateful encryptors may use it for consistency checks.
+    fn frame_sent(&mut self, _frame: &[u8]) {}
+
+    /// Marks the encryptor as unusable after a frame it has produced failed
+    /// to be sent. Stateful encryptors must refuse to encrypt further
+    /// messages, since the remote peer would not be able to decrypt them.
+    fn poison(&mut self) {}
+
+    /// Detects whether the encryptor was poisoned with [`Encrypt::poison`].
+    fn is_poisoned(&self) -> bool { false }
 }
 
 pub trait Decrypt {
@@ -52,11 +75,17 @@ impl Encrypt for PlainTranscoder {
         // TODO: (v0.2) check for length value to fit u16
         let len = buffer.len() as u16;
         data.extend(&len.to_be_bytes());
-        data.extend(&[0u8; FRAME_PREFIX_SIZE - 2]);
+        data.extend(&[0u8; FRAME_PREFIX_SIZE - BRONTIDE_LEN_SIZE]);
         data.extend(buffer);
         data.extend(&[0u8; FRAME_SUFFIX_SIZE]);
         data
     }
+
+    #[inline]
+    fn frame_overhead(&self) -> usize { FRAME_PREFIX_SIZE + FRAME_SUFFIX_SIZE }
+
+    #[inline]
+    fn max_payload_len(&self) -> usize { MAX_FRAME_PAYLOAD_SIZE }
 }
 
 impl Decrypt for PlainTranscoder {
@@ -70,10 +99,15 @@ impl Decrypt for PlainTranscoder {
         if frame_len < FRAME_PREFIX_SIZE + FRAME_SUFFIX_SIZE {
             return Err(Error::FrameTooSmall(frame_len));
         }
-        let mut len_buf = [0u8; 2];
-        len_buf.copy_from_slice(&buffer[0..2]);
-        let data_len = u16::from_be_bytes(len_buf);
         let len = frame_len - FRAME_SUFFIX_SIZE;
+        // Without this check the payload length would be truncated to u16
+        // below, accepting frames larger than the encryptor can produce
+        if len - FRAME_PREFIX_SIZE > MAX_FRAME_PAYLOAD_SIZE {
+            return Err(Error::OversizedFrame(frame_len));
+        }
+        let mut len_buf = [0u8; BRONTIDE_LEN_SIZE];
+        len_buf.copy_from_slice(&buffer[0..BRONTIDE_LEN_SIZE]);
+        let data_len = u16::from_be_bytes(len_buf);
         if data_len != (len - FRAME_PREFIX_SIZE) as u16 {
             return Err(Error::InvalidLength {
                 expected: (len - FRAME_PREFIX_SIZE) as u16,
@@ -131,4 +165,20 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn max_frame_payload() {
+        let mut transcoder = PlainTranscoder;
+        let data = vec![0xA5; MAX_FRAME_PAYLOAD_SIZE];
+        let frame = transcoder.encrypt(&data[..]);
+        assert_eq!(transcoder.decrypt(frame).unwrap(), data);
+
+        // Frame with one extra payload byte and length prefix truncated to
+        // zero must not be accepted
+        let frame = vec![0u8; crate::consts::MAX_FRAME_SIZE + 1];
+        assert_eq!(
+            transcoder.decrypt(frame),
+            Err(Error::OversizedFrame(crate::consts::MAX_FRAME_SIZE + 1))
+        );
+    }
 }
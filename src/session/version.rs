@@ -0,0 +1,402 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Optional protocol version negotiation performed over a new session before
+//! any typed messages are exchanged.
+//!
+//! Each side sends a single version message consisting of
+//! [`VERSION_MAGIC`] followed by the minimal and maximal supported protocol
+//! versions (big-endian `u16` each), and then reads the version message of
+//! the remote peer. The negotiation is symmetric, so the same procedure is
+//! used on both connecting and binding sides.
+
+use std::any::Any;
+
+use crate::session::SendRecvMessage;
+use crate::transport::{self, RoutedFrame};
+
+/// Magic bytes starting the version negotiation message
+pub const VERSION_MAGIC: [u8; 4] = *b"I2VN";
+
+/// Length of the version negotiation message
+pub const VERSION_MSG_LEN: usize = VERSION_MAGIC.len() + 4;
+
+/// Inclusive range of supported protocol versions
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{min}..={max}")]
+pub struct VersionRange {
+    /// Minimal supported protocol version
+    pub min: u16,
+
+    /// Maximal supported protocol version
+    pub max: u16,
+}
+
+impl VersionRange {
+    /// Constructs version range, returning `None` if `min` exceeds `max`.
+    pub fn new(min: u16, max: u16) -> Option<VersionRange> {
+        if min > max {
+            return None;
+        }
+        Some(VersionRange { min, max })
+    }
+
+    /// Constructs range consisting of a single version.
+    #[inline]
+    pub fn single(version: u16) -> VersionRange {
+        VersionRange {
+            min: version,
+            max: version,
+        }
+    }
+
+    /// Detects whether the version is in the range.
+    #[inline]
+    pub fn contains(&self, version: u16) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+
+    /// Returns the highest version supported by both ranges, if any.
+    pub fn highest_common(&self, other: VersionRange) -> Option<u16> {
+        let version = self.max.min(other.max);
+        if version < self.min.max(other.min) {
+            return None;
+        }
+        Some(version)
+    }
+
+    /// Serializes version negotiation message advertising the range.
+    pub fn to_message(&self) -> [u8; VERSION_MSG_LEN] {
+        let mut msg = [0u8; VERSION_MSG_LEN];
+        msg[..4].copy_from_slice(&VERSION_MAGIC);
+        msg[4..6].copy_from_slice(&self.min.to_be_bytes());
+        msg[6..].copy_from_slice(&self.max.to_be_bytes());
+        msg
+    }
+
+    /// Parses version negotiation message. Returns `Ok(None)` if the message
+    /// does not start with [`VERSION_MAGIC`], i.e. is not a version message.
+    pub fn from_message(
+        msg: &[u8],
+    ) -> Result<Option<VersionRange>, VersionError> {
+        if !msg.starts_with(&VERSION_MAGIC) {
+            return Ok(None);
+        }
+        if msg.len() != VERSION_MSG_LEN {
+            return Err(VersionError::Malformed(msg.len()));
+        }
+        let min = u16::from_be_bytes([msg[4], msg[5]]);
+        let max = u16::from_be_bytes([msg[6], msg[7]]);
+        VersionRange::new(min, max)
+            .map(Some)
+            .ok_or(VersionError::Malformed(msg.len()))
+    }
+}
+
+/// Errors of protocol version negotiation
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum VersionError {
+    /// no common protocol version: local peer supports versions {ours},
+    /// while the remote peer supports {theirs}
+    VersionMismatch {
+        ours: VersionRange,
+        theirs: VersionRange,
+    },
+
+    /// remote peer does not use protocol version negotiation
+    Unversioned,
+
+    /// malformed version negotiation message of {0} bytes
+    Malformed(usize),
+
+    #[display(inner)]
+    #[from]
+    Transport(transport::Error),
+}
+
+/// Session wrapper which negotiates protocol version with the remote peer
+/// upon its construction.
+///
+/// If the remote peer does not speak the version negotiation envelope and
+/// the fallback to unversioned protocol is allowed, the session is used
+/// without version ([`VersionedSession::protocol_version`] returns `None`).
+/// The remote peer is detected as unversioned either if the first received
+/// message is not a version message (in this case the message is not lost
+/// and is returned by the first call to receive a message), or if receiving
+/// the first message fails with [`transport::Error::TimedOut`], for which
+/// the socket receive timeout must be configured.
+pub struct VersionedSession<T>
+where
+    T: SendRecvMessage,
+{
+    session: T,
+    version: Option<u16>,
+    pending: Option<Vec<u8>>,
+}
+
+impl<T> VersionedSession<T>
+where
+    T: SendRecvMessage,
+{
+    /// Exchanges version messages with the remote peer and selects the
+    /// highest protocol version supported by both peers. If the version
+    /// ranges do not overlap, returns [`VersionError::VersionMismatch`] and
+    /// closes the session.
+    pub fn negotiate(
+        mut session: T,
+        ours: VersionRange,
+        allow_unversioned: bool,
+    ) -> Result<Self, VersionError> {
+        session.send_raw_message(&ours.to_message())?;
+
+        let (version, pending) = match session.recv_raw_message() {
+            Ok(msg) => match VersionRange::from_message(&msg)? {
+                Some(theirs) => (
+                    Some(ours.highest_common(theirs).ok_or(
+                        VersionError::VersionMismatch { ours, theirs },
+                    )?),
+                    None,
+                ),
+                None if allow_unversioned => (None, Some(msg)),
+                None => return Err(VersionError::Unversioned),
+            },
+            Err(transport::Error::TimedOut) if allow_unversioned => {
+                (None, None)
+            }
+            Err(transport::Error::TimedOut) => {
+                return Err(VersionError::Unversioned)
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(VersionedSession {
+            session,
+            version,
+            pending,
+        })
+    }
+
+    /// Returns negotiated protocol version, or `None` if the remote peer is
+    /// unversioned.
+    #[inline]
+    pub fn protocol_version(&self) -> Option<u16> { self.version }
+
+    /// Returns reference to the wrapped session.
+    #[inline]
+    pub fn as_session(&self) -> &T { &self.session }
+
+    /// Releases the wrapped session. A message received during negotiation
+    /// from an unversioned peer and not yet read is lost.
+    #[inline]
+    pub fn into_session(self) -> T { self.session }
+}
+
+impl<T> SendRecvMessage for VersionedSession<T>
+where
+    T: SendRecvMessage + 'static,
+{
+    fn recv_raw_message(&mut self) -> Result<Vec<u8>, transport::Error> {
+        match self.pending.take() {
+            Some(msg) => Ok(msg),
+            None => self.session.recv_raw_message(),
+        }
+    }
+
+    fn send_raw_message(
+        &mut self,
+        raw: &[u8],
+    ) -> Result<usize, transport::Error> {
+        self.session.send_raw_message(raw)
+    }
+
+    fn recv_routed_message(&mut self) -> Result<RoutedFrame, transport::Error> {
+        self.session.recv_routed_message()
+    }
+
+    fn send_routed_message(
+        &mut self,
+        source: &[u8],
+        route: &[u8],
+        dest: &[u8],
+        raw: &[u8],
+    ) -> Result<usize, transport::Error> {
+        self.session.send_routed_message(source, route, dest, raw)
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Session replaying scripted incoming messages and recording sent ones.
+    /// Receiving from an exhausted script times out.
+    #[derive(Default)]
+    struct Scripted {
+        incoming: VecDeque<Vec<u8>>,
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl Scripted {
+        fn with(incoming: impl IntoIterator<Item = Vec<u8>>) -> Self {
+            Scripted {
+                incoming: incoming.into_iter().collect(),
+                sent: vec![],
+            }
+        }
+    }
+
+    impl SendRecvMessage for Scripted {
+        fn recv_raw_message(&mut self) -> Result<Vec<u8>, transport::Error> {
+            self.incoming.pop_front().ok_or(transport::Error::TimedOut)
+        }
+
+        fn send_raw_message(
+            &mut self,
+            raw: &[u8],
+        ) -> Result<usize, transport::Error> {
+            self.sent.push(raw.to_vec());
+            Ok(raw.len())
+        }
+
+        fn recv_routed_message(
+            &mut self,
+        ) -> Result<RoutedFrame, transport::Error> {
+            unreachable!()
+        }
+
+        fn send_routed_message(
+            &mut self,
+            _: &[u8],
+            _: &[u8],
+            _: &[u8],
+            _: &[u8],
+        ) -> Result<usize, transport::Error> {
+            unreachable!()
+        }
+
+        fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
+    }
+
+    fn range(min: u16, max: u16) -> VersionRange {
+        VersionRange::new(min, max).unwrap()
+    }
+
+    #[test]
+    fn message_roundtrip() {
+        let msg = range(1, 0x0203).to_message();
+        assert_eq!(msg, [b'I', b'2', b'V', b'N', 0, 1, 2, 3]);
+        assert_eq!(
+            VersionRange::from_message(&msg),
+            Ok(Some(range(1, 0x0203)))
+        );
+        assert_eq!(VersionRange::from_message(b"hello"), Ok(None));
+        assert_eq!(
+            VersionRange::from_message(&msg[..6]),
+            Err(VersionError::Malformed(6))
+        );
+        // min > max
+        assert_eq!(
+            VersionRange::from_message(&[b'I', b'2', b'V', b'N', 0, 2, 0, 1]),
+            Err(VersionError::Malformed(VERSION_MSG_LEN))
+        );
+        assert_eq!(VersionRange::new(2, 1), None);
+    }
+
+    #[test]
+    fn negotiation() {
+        let remote = range(2, 5).to_message().to_vec();
+        let session = VersionedSession::negotiate(
+            Scripted::with([remote, b"data".to_vec()]),
+            range(1, 3),
+            false,
+        )
+        .unwrap();
+        assert_eq!(session.protocol_version(), Some(3));
+        assert_eq!(session.as_session().sent, vec![range(1, 3)
+            .to_message()
+            .to_vec()]);
+
+        let mut session = session;
+        assert_eq!(session.recv_raw_message().unwrap(), b"data");
+
+        assert_eq!(
+            range(1, 1).highest_common(VersionRange::single(1)),
+            Some(1)
+        );
+        assert_eq!(range(4, 9).highest_common(range(1, 6)), Some(6));
+    }
+
+    #[test]
+    fn mismatch() {
+        let remote = range(4, 5).to_message().to_vec();
+        let err = VersionedSession::negotiate(
+            Scripted::with([remote]),
+            range(1, 3),
+            true,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err, VersionError::VersionMismatch {
+            ours: range(1, 3),
+            theirs: range(4, 5),
+        });
+        assert_eq!(
+            err.to_string(),
+            "no common protocol version: local peer supports versions 1..=3, \
+             while the remote peer supports 4..=5"
+        );
+    }
+
+    #[test]
+    fn unversioned_peer() {
+        // Remote peer is silent until we send something
+        let session =
+            VersionedSession::negotiate(Scripted::default(), range(1, 2), true)
+                .unwrap();
+        assert_eq!(session.protocol_version(), None);
+        assert_eq!(
+            VersionedSession::negotiate(
+                Scripted::default(),
+                range(1, 2),
+                false
+            )
+            .err(),
+            Some(VersionError::Unversioned)
+        );
+
+        // Remote peer starts with a typed message, which must not be lost
+        let mut session = VersionedSession::negotiate(
+            Scripted::with([b"hello".to_vec(), b"world".to_vec()]),
+            range(1, 2),
+            true,
+        )
+        .unwrap();
+        assert_eq!(session.protocol_version(), None);
+        assert_eq!(session.recv_raw_message().unwrap(), b"hello");
+        assert_eq!(session.recv_raw_message().unwrap(), b"world");
+        assert_eq!(
+            VersionedSession::negotiate(
+                Scripted::with([b"hello".to_vec()]),
+                range(1, 2),
+                false
+            )
+            .err(),
+            Some(VersionError::Unversioned)
+        );
+    }
+}
@@ -0,0 +1,482 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Utilities for benchmarks, examples and integration tests.
+//!
+//! [`EchoService`] is a counterpart process which can be run in-process: it
+//! listens on a TCP socket, accepts plaintext or Brontide-encrypted sessions
+//! and sends every received message back verbatim. For soak testing it may
+//! delay and drop messages.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use inet2_addr::InetSocketAddr;
+#[cfg(feature = "keygen")]
+use secp256k1::SecretKey;
+
+use crate::rng::SplitMix64;
+use crate::session::{PlainTranscoder, Session};
+use crate::transport::{unencrypted, Error};
+use crate::SendRecvMessage;
+
+/// Interval of checking whether the service is shut down while waiting for
+/// incoming connections
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Type of sessions accepted by [`EchoService`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EchoMode {
+    /// Plaintext framed TCP sessions
+    Plain,
+
+    /// Brontide-encrypted sessions, authenticated with the given node key
+    #[cfg(feature = "keygen")]
+    Brontide(SecretKey),
+}
+
+/// Configuration of [`EchoService`]
+#[derive(Clone, PartialEq, Debug)]
+pub struct EchoConfig {
+    /// Type of accepted sessions
+    pub mode: EchoMode,
+
+    /// Delay before sending each message back
+    pub delay: Option<Duration>,
+
+    /// Probability of a received message not being sent back, in
+    /// `0.0..=1.0` range
+    pub drop_rate: f64,
+
+    /// Seed for the random number generator deciding on message drops
+    pub seed: u64,
+}
+
+impl EchoConfig {
+    /// Configuration for plaintext sessions without delays and drops
+    pub fn plain() -> Self {
+        EchoConfig {
+            mode: EchoMode::Plain,
+            delay: None,
+            drop_rate: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// Configuration for Brontide-encrypted sessions without delays and drops
+    #[cfg(feature = "keygen")]
+    pub fn brontide(local_key: SecretKey) -> Self {
+        EchoConfig {
+            mode: EchoMode::Brontide(local_key),
+            ..EchoConfig::plain()
+        }
+    }
+}
+
+/// Traffic counted by [`EchoService`]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct EchoStats {
+    /// Number of accepted sessions
+    pub sessions: usize,
+
+    /// Number of received messages
+    pub messages: usize,
+
+    /// Number of received bytes (excluding framing and encryption)
+    pub bytes: usize,
+
+    /// Number of received messages which were not sent back
+    pub dropped: usize,
+}
+
+/// Handle for shutting down [`EchoService`], which may be passed to another
+/// thread (for instance, one handling process signals)
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    /// Requests service shutdown
+    pub fn shutdown(&self) { self.0.store(true, Ordering::SeqCst) }
+
+    /// Checks whether the shutdown was requested
+    pub fn is_shut_down(&self) -> bool { self.0.load(Ordering::SeqCst) }
+}
+
+#[derive(Default)]
+struct Shared {
+    stats: Mutex<EchoStats>,
+    // Streams of the active sessions, indexed by session number, which are
+    // shut down to unblock the workers when the service stops
+    streams: Mutex<HashMap<usize, TcpStream>>,
+    // Numbers of sessions whose workers have completed and can be joined
+    finished: Mutex<Vec<usize>>,
+}
+
+impl Shared {
+    fn count(&self, f: impl FnOnce(&mut EchoStats)) {
+        f(&mut self.stats.lock().expect("poisoned stats lock"))
+    }
+}
+
+/// Service sending back all messages it receives.
+///
+/// Each accepted session is served by a separate thread. The service stops
+/// accepting connections and closes all sessions on [`EchoService::shutdown`],
+/// when requested via [`ShutdownHandle`] or when dropped.
+pub struct EchoService {
+    local_addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl EchoService {
+    /// Binds the service to the given address and starts accepting
+    /// connections. Use zero port to bind to a port assigned by the system,
+    /// which is then reported by [`EchoService::local_addr`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::TorNotSupportedYet`] for onion addresses and with
+    /// I/O errors if the socket can't be bound.
+    pub fn bind(
+        addr: impl Into<InetSocketAddr>,
+        config: EchoConfig,
+    ) -> Result<Self, Error> {
+        let addr = SocketAddr::try_from(addr.into())
+            .map_err(|_| Error::TorNotSupportedYet)?;
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let shutdown = ShutdownHandle::default();
+        let shared = Arc::new(Shared::default());
+        let acceptor = {
+            let shutdown = shutdown.clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                accept(listener, config, &shutdown, &shared)
+            })
+        };
+
+        Ok(EchoService {
+            local_addr,
+            shutdown,
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Returns address the service is bound to
+    #[inline]
+    pub fn local_addr(&self) -> SocketAddr { self.local_addr }
+
+    /// Returns traffic counted so far
+    pub fn stats(&self) -> EchoStats {
+        *self.shared.stats.lock().expect("poisoned stats lock")
+    }
+
+    /// Returns handle which can be used to shut down the service
+    #[inline]
+    pub fn shutdown_handle(&self) -> ShutdownHandle { self.shutdown.clone() }
+
+    /// Waits until the service is shut down via [`ShutdownHandle`] and
+    /// returns the counted traffic.
+    pub fn wait(mut self) -> EchoStats {
+        self.join();
+        self.stats()
+    }
+
+    /// Shuts down the service, closing all sessions, and returns the counted
+    /// traffic.
+    pub fn shutdown(self) -> EchoStats {
+        self.shutdown.shutdown();
+        self.wait()
+    }
+
+    fn join(&mut self) {
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+impl Drop for EchoService {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        self.join();
+    }
+}
+
+fn accept(
+    listener: TcpListener,
+    config: EchoConfig,
+    shutdown: &ShutdownHandle,
+    shared: &Arc<Shared>,
+) {
+    let mut workers = HashMap::<usize, JoinHandle<()>>::new();
+    while !shutdown.is_shut_down() {
+        // Releasing threads of the completed sessions, so long-running
+        // services do not accumulate them
+        let finished = std::mem::take(
+            &mut *shared.finished.lock().expect("poisoned finished lock"),
+        );
+        for session_no in finished {
+            if let Some(worker) = workers.remove(&session_no) {
+                let _ = worker.join();
+            }
+        }
+
+        let (stream, remote_addr) = match listener.accept() {
+            Ok(connection) => connection,
+            // No pending connections or a transient error, like a connection
+            // reset before it was accepted
+            Err(_) => {
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+        };
+        let clone = match stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.try_clone())
+        {
+            Ok(clone) => clone,
+            Err(_) => continue,
+        };
+        let mut session_no = 0;
+        shared.count(|stats| {
+            session_no = stats.sessions;
+            stats.sessions += 1;
+        });
+        shared
+            .streams
+            .lock()
+            .expect("poisoned streams lock")
+            .insert(session_no, clone);
+
+        let rng = SplitMix64::with_seed(config.seed ^ session_no as u64);
+        let worker = {
+            let config = config.clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                let _ = serve(stream, remote_addr, &config, rng, &shared);
+                shared
+                    .finished
+                    .lock()
+                    .expect("poisoned finished lock")
+                    .push(session_no);
+                shared
+                    .streams
+                    .lock()
+                    .expect("poisoned streams lock")
+                    .remove(&session_no);
+            })
+        };
+        workers.insert(session_no, worker);
+    }
+
+    // Unblocking workers waiting for incoming messages
+    for stream in shared
+        .streams
+        .lock()
+        .expect("poisoned streams lock")
+        .values()
+    {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    for worker in workers.into_values() {
+        let _ = worker.join();
+    }
+}
+
+fn serve(
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    config: &EchoConfig,
+    mut rng: SplitMix64,
+    shared: &Shared,
+) -> Result<(), Error> {
+    let mut session: Box<dyn SendRecvMessage> = match config.mode {
+        EchoMode::Plain => Box::new(Session::with_transport(
+            unencrypted::Connection::with(stream, remote_addr.into()),
+            PlainTranscoder,
+        )),
+        #[cfg(feature = "keygen")]
+        EchoMode::Brontide(local_key) => {
+            Box::new(crate::session::BrontideSession::with(
+                stream,
+                local_key,
+                remote_addr.into(),
+            )?)
+        }
+    };
+    loop {
+        let msg = match session.recv_raw_message() {
+            // Idle session
+            Err(Error::TimedOut) => continue,
+            res => res?,
+        };
+        let drop = rng.next_f64() < config.drop_rate;
+        shared.count(|stats| {
+            stats.messages += 1;
+            stats.bytes += msg.len();
+            if drop {
+                stats.dropped += 1;
+            }
+        });
+        if drop {
+            continue;
+        }
+        if let Some(delay) = config.delay {
+            std::thread::sleep(delay);
+        }
+        session.send_raw_message(&msg)?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    type PlainSession = Session<PlainTranscoder, unencrypted::Connection>;
+
+    fn localhost() -> SocketAddr { SocketAddr::from(([127, 0, 0, 1], 0)) }
+
+    fn connect(service: &EchoService) -> PlainSession {
+        Session::with_transport(
+            unencrypted::Connection::connect(service.local_addr().into())
+                .unwrap(),
+            PlainTranscoder,
+        )
+    }
+
+    #[test]
+    fn plain_echo() {
+        let service =
+            EchoService::bind(localhost(), EchoConfig::plain()).unwrap();
+        let mut first = connect(&service);
+        let mut second = connect(&service);
+        for no in 0..10u8 {
+            let msg = vec![no; no as usize + 1];
+            first.send_raw_message(&msg).unwrap();
+            assert_eq!(first.recv_raw_message().unwrap(), msg);
+            second.send_raw_message(b"ping").unwrap();
+            assert_eq!(second.recv_raw_message().unwrap(), b"ping");
+        }
+
+        let stats = service.shutdown();
+        assert_eq!(stats, EchoStats {
+            sessions: 2,
+            messages: 20,
+            bytes: 55 + 40,
+            dropped: 0,
+        });
+        assert!(first.recv_raw_message().is_err());
+    }
+
+    #[test]
+    fn delay_and_drops() {
+        let service = EchoService::bind(localhost(), EchoConfig {
+            delay: Some(Duration::from_millis(50)),
+            ..EchoConfig::plain()
+        })
+        .unwrap();
+        let mut session = connect(&service);
+        let started = Instant::now();
+        session.send_raw_message(b"ping").unwrap();
+        assert_eq!(session.recv_raw_message().unwrap(), b"ping");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        drop(service);
+
+        let service = EchoService::bind(localhost(), EchoConfig {
+            drop_rate: 1.0,
+            ..EchoConfig::plain()
+        })
+        .unwrap();
+        let mut session = connect(&service);
+        session.send_raw_message(b"ping").unwrap();
+        session.send_raw_message(b"ping").unwrap();
+        while service.stats().messages < 2 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(service.stats().dropped, 2);
+    }
+
+    #[test]
+    fn shutdown_handle() {
+        let service =
+            EchoService::bind(localhost(), EchoConfig::plain()).unwrap();
+        let handle = service.shutdown_handle();
+        let mut session = connect(&service);
+        let waiter = std::thread::spawn(move || service.wait());
+        session.send_raw_message(b"ping").unwrap();
+        assert_eq!(session.recv_raw_message().unwrap(), b"ping");
+
+        handle.shutdown();
+        assert!(handle.is_shut_down());
+        assert_eq!(waiter.join().unwrap().messages, 1);
+    }
+
+    #[test]
+    fn completed_sessions_released() {
+        let service =
+            EchoService::bind(localhost(), EchoConfig::plain()).unwrap();
+        for _ in 0..3 {
+            let mut session = connect(&service);
+            session.send_raw_message(b"ping").unwrap();
+            assert_eq!(session.recv_raw_message().unwrap(), b"ping");
+        }
+
+        let started = Instant::now();
+        let released = || {
+            service.shared.streams.lock().unwrap().is_empty()
+                && service.shared.finished.lock().unwrap().is_empty()
+        };
+        while !released() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(service.shutdown().sessions, 3);
+    }
+
+    #[cfg(feature = "keygen")]
+    #[test]
+    fn brontide_echo() {
+        use inet2_addr::{LocalNode, NodeAddr};
+
+        use crate::session::BrontideSession;
+
+        let secp = secp256k1::Secp256k1::new();
+        let server = LocalNode::new(&secp);
+        let client = LocalNode::new(&secp);
+        let service = EchoService::bind(
+            localhost(),
+            EchoConfig::brontide(server.private_key()),
+        )
+        .unwrap();
+
+        let remote = NodeAddr::new(server.node_id(), service.local_addr());
+        let mut session =
+            BrontideSession::connect(client.private_key(), remote).unwrap();
+        session.send_raw_message(b"hello").unwrap();
+        assert_eq!(session.recv_raw_message().unwrap(), b"hello");
+        assert_eq!(service.shutdown().bytes, 5);
+    }
+}
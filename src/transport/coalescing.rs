@@ -0,0 +1,545 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Coalescing of small writes for stream-based transports.
+//!
+//! With `TCP_NODELAY` enabled each frame is sent in a separate packet, so a
+//! burst of small messages produces many tiny packets. [`CoalescingConnection`]
+//! buffers frames for at most [`Coalescing::max_delay`] or until
+//! [`Coalescing::max_bytes`] are accumulated, and then writes all of them with
+//! a single call to the underlying transport. Pending frames are always
+//! flushed before receiving, so request/response patterns do not deadlock
+//! waiting for a request which is still in the buffer.
+//!
+//! Frames are concatenated, so the wrapper must be used only with
+//! stream-based transports (like TCP), and not with message-based ones (like
+//! ZMQ).
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use amplify::Bipolar;
+
+use super::{DuplexConnection, Error, RecvFrame, RoutedFrame, SendFrame};
+
+/// Configuration of write coalescing
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", default)
+)]
+pub struct Coalescing {
+    /// Maximum time a frame may stay in the buffer. Zero disables coalescing.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::duration"))]
+    pub max_delay: Duration,
+
+    /// Number of buffered bytes which triggers immediate write. Zero disables
+    /// coalescing.
+    pub max_bytes: usize,
+}
+
+impl Coalescing {
+    /// Writes each frame immediately; this is the default.
+    #[inline]
+    pub fn immediate() -> Self { Coalescing::default() }
+
+    /// Buffers frames for at most `max_delay` or until `max_bytes` are
+    /// accumulated.
+    #[inline]
+    pub fn with(max_delay: Duration, max_bytes: usize) -> Self {
+        Coalescing {
+            max_delay,
+            max_bytes,
+        }
+    }
+
+    /// Detects whether frames are written immediately without buffering.
+    #[inline]
+    pub fn is_immediate(&self) -> bool {
+        self.max_delay == Duration::from_secs(0) || self.max_bytes == 0
+    }
+}
+
+struct Buffer<S> {
+    writer: S,
+    pending: Vec<u8>,
+    deadline: Option<Instant>,
+    // Error of a write made by the flushing thread, reported by the next call
+    error: Option<Error>,
+    closed: bool,
+}
+
+impl<S: SendFrame> Buffer<S> {
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.deadline = None;
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let res = self.writer.send_raw(&self.pending);
+        self.pending.clear();
+        res.map(|_| ())
+    }
+}
+
+struct Shared<S> {
+    config: Coalescing,
+    buffer: Mutex<Buffer<S>>,
+    wakeup: Condvar,
+}
+
+impl<S> Shared<S> {
+    fn lock(&self) -> MutexGuard<'_, Buffer<S>> {
+        self.buffer.lock().expect("poisoned coalescing buffer lock")
+    }
+}
+
+/// Flushing of pending frames by the receiving half
+trait Flush: Send + Sync {
+    fn flush(&self) -> Result<(), Error>;
+}
+
+impl<S: SendFrame + Send> Flush for Shared<S> {
+    fn flush(&self) -> Result<(), Error> { self.lock().flush() }
+}
+
+/// Sending half of a [`CoalescingConnection`]
+pub struct CoalescingSender<S>
+where
+    S: SendFrame + Send + 'static,
+{
+    shared: Arc<Shared<S>>,
+    flusher: Option<JoinHandle<()>>,
+}
+
+/// Receiving half of a [`CoalescingConnection`], which flushes frames
+/// pending in the sending half before each receive
+pub struct CoalescingReceiver<R>
+where
+    R: RecvFrame,
+{
+    reader: R,
+    output: Arc<dyn Flush>,
+}
+
+/// Connection coalescing small writes; see the [module](self) documentation
+pub struct CoalescingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame + Send + 'static,
+{
+    input: CoalescingReceiver<C::Left>,
+    output: CoalescingSender<C::Right>,
+}
+
+impl<S> CoalescingSender<S>
+where
+    S: SendFrame + Send + 'static,
+{
+    /// Wraps sending part of a connection. Unless coalescing is disabled
+    /// by the configuration, starts a thread writing buffered frames once
+    /// their deadline expires.
+    pub fn with(writer: S, config: Coalescing) -> Self {
+        let shared = Arc::new(Shared {
+            config,
+            buffer: Mutex::new(Buffer {
+                writer,
+                pending: vec![],
+                deadline: None,
+                error: None,
+                closed: false,
+            }),
+            wakeup: Condvar::new(),
+        });
+        let flusher = if config.is_immediate() {
+            None
+        } else {
+            let shared = shared.clone();
+            Some(std::thread::spawn(move || run_flusher(&shared)))
+        };
+        CoalescingSender { shared, flusher }
+    }
+
+    /// Returns configuration of the write coalescing.
+    #[inline]
+    pub fn config(&self) -> Coalescing { self.shared.config }
+
+    fn enqueue(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        let config = self.shared.config;
+        let mut buffer = self.shared.lock();
+        if let Some(err) = buffer.error.take() {
+            return Err(err);
+        }
+        if frame.len() > buffer.writer.max_frame_size() {
+            return Err(Error::OversizedFrame(frame.len()));
+        }
+        buffer.pending.extend_from_slice(frame);
+        if buffer.pending.len() >= config.max_bytes {
+            buffer.flush()?;
+        } else if buffer.deadline.is_none() {
+            buffer.deadline = Some(Instant::now() + config.max_delay);
+            self.shared.wakeup.notify_one();
+        }
+        Ok(frame.len())
+    }
+}
+
+impl<S> SendFrame for CoalescingSender<S>
+where
+    S: SendFrame + Send + 'static,
+{
+    fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        if self.shared.config.is_immediate() {
+            return self.shared.lock().writer.send_frame(frame);
+        }
+        self.enqueue(frame)
+    }
+
+    fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+        if self.shared.config.is_immediate() {
+            return self.shared.lock().writer.send_raw(raw_frame);
+        }
+        self.enqueue(raw_frame)
+    }
+
+    fn max_frame_size(&self) -> usize {
+        self.shared.lock().writer.max_frame_size()
+    }
+
+    fn send_routed(
+        &mut self,
+        source: &[u8],
+        route: &[u8],
+        dest: &[u8],
+        raw_frame: &[u8],
+    ) -> Result<usize, Error> {
+        let mut buffer = self.shared.lock();
+        buffer.flush()?;
+        buffer.writer.send_routed(source, route, dest, raw_frame)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> { self.shared.lock().flush() }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        let mut buffer = self.shared.lock();
+        buffer.flush()?;
+        buffer.writer.shutdown()
+    }
+}
+
+impl<S> Drop for CoalescingSender<S>
+where
+    S: SendFrame + Send + 'static,
+{
+    fn drop(&mut self) {
+        {
+            let mut buffer = self.shared.lock();
+            // There is no one left to report the error to
+            let _ = buffer.flush();
+            buffer.closed = true;
+        }
+        self.shared.wakeup.notify_one();
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+    }
+}
+
+fn run_flusher<S: SendFrame>(shared: &Shared<S>) {
+    let mut buffer = shared.lock();
+    while !buffer.closed {
+        buffer = match buffer.deadline {
+            None => shared
+                .wakeup
+                .wait(buffer)
+                .expect("poisoned coalescing buffer lock"),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    if let Err(err) = buffer.flush() {
+                        buffer.error = Some(err);
+                    }
+                    buffer
+                } else {
+                    shared
+                        .wakeup
+                        .wait_timeout(buffer, deadline - now)
+                        .expect("poisoned coalescing buffer lock")
+                        .0
+                }
+            }
+        };
+    }
+}
+
+impl<R> RecvFrame for CoalescingReceiver<R>
+where
+    R: RecvFrame,
+{
+    fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
+        self.output.flush()?;
+        self.reader.recv_frame()
+    }
+
+    fn recv_raw(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        self.output.flush()?;
+        self.reader.recv_raw(len)
+    }
+
+    fn recv_routed(&mut self) -> Result<RoutedFrame, Error> {
+        self.output.flush()?;
+        self.reader.recv_routed()
+    }
+}
+
+impl<C> CoalescingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame + Send + 'static,
+{
+    /// Wraps the connection, coalescing writes according to the
+    /// configuration.
+    pub fn with(connection: C, config: Coalescing) -> Self {
+        let (reader, writer) = connection.split();
+        let output = CoalescingSender::with(writer, config);
+        let input = CoalescingReceiver {
+            reader,
+            output: output.shared.clone(),
+        };
+        CoalescingConnection { input, output }
+    }
+
+    /// Writes all pending frames to the underlying connection.
+    #[inline]
+    pub fn flush(&mut self) -> Result<(), Error> { self.output.flush() }
+}
+
+impl<C> DuplexConnection for CoalescingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame + Send + 'static,
+    C::Right: SendFrame + Send + 'static,
+{
+    #[inline]
+    fn as_receiver(&mut self) -> &mut dyn RecvFrame { &mut self.input }
+
+    #[inline]
+    fn as_sender(&mut self) -> &mut dyn SendFrame { &mut self.output }
+
+    fn split(self) -> (Box<dyn RecvFrame + Send>, Box<dyn SendFrame + Send>) {
+        (Box::new(self.input), Box::new(self.output))
+    }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { self.output.max_frame_size() }
+}
+
+impl<C> Bipolar for CoalescingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame + Send + 'static,
+{
+    type Left = CoalescingReceiver<C::Left>;
+    type Right = CoalescingSender<C::Right>;
+
+    fn join(input: Self::Left, output: Self::Right) -> Self {
+        CoalescingConnection { input, output }
+    }
+
+    fn split(self) -> (Self::Left, Self::Right) { (self.input, self.output) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Transport recording each write call together with its time
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<(Instant, Vec<u8>)>>>);
+
+    impl Recorder {
+        fn writes(&self) -> Vec<(Instant, Vec<u8>)> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            self.writes()
+                .into_iter()
+                .flat_map(|(_, data)| data)
+                .collect()
+        }
+    }
+
+    impl SendFrame for Recorder {
+        fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+            self.send_raw(frame)
+        }
+
+        fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((Instant::now(), raw_frame.to_vec()));
+            Ok(raw_frame.len())
+        }
+    }
+
+    /// Transport from which nothing can be received
+    struct Silent;
+
+    impl RecvFrame for Silent {
+        fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
+            Err(Error::TimedOut)
+        }
+
+        fn recv_raw(&mut self, _: usize) -> Result<Vec<u8>, Error> {
+            Err(Error::TimedOut)
+        }
+    }
+
+    struct Mock(Silent, Recorder);
+
+    impl Bipolar for Mock {
+        type Left = Silent;
+        type Right = Recorder;
+
+        fn join(left: Silent, right: Recorder) -> Self { Mock(left, right) }
+
+        fn split(self) -> (Silent, Recorder) { (self.0, self.1) }
+    }
+
+    fn burst(connection: &mut impl DuplexConnection, count: u8) {
+        for no in 0..count {
+            connection.as_sender().send_frame(&[no; 20]).unwrap();
+        }
+    }
+
+    fn expected(count: u8) -> Vec<u8> {
+        (0..count).flat_map(|no| [no; 20]).collect()
+    }
+
+    #[test]
+    fn immediate_by_default() {
+        let recorder = Recorder::default();
+        let mut connection = CoalescingConnection::with(
+            Mock(Silent, recorder.clone()),
+            Coalescing::default(),
+        );
+        assert!(connection.output.config().is_immediate());
+        burst(&mut connection, 100);
+        assert_eq!(recorder.writes().len(), 100);
+        assert_eq!(recorder.bytes(), expected(100));
+    }
+
+    // Burst workload: 100 frames of 20 bytes with 512-byte threshold must
+    // result in 4 writes instead of 100
+    #[test]
+    fn burst_write_count() {
+        let recorder = Recorder::default();
+        let mut connection = CoalescingConnection::with(
+            Mock(Silent, recorder.clone()),
+            Coalescing::with(Duration::from_secs(10), 512),
+        );
+        burst(&mut connection, 100);
+        assert_eq!(recorder.writes().len(), 3);
+        connection.flush().unwrap();
+        assert_eq!(recorder.writes().len(), 4);
+        assert_eq!(recorder.bytes(), expected(100));
+
+        // Nothing is written when there is nothing pending
+        connection.flush().unwrap();
+        assert_eq!(recorder.writes().len(), 4);
+    }
+
+    #[test]
+    fn deadline() {
+        let max_delay = Duration::from_micros(300);
+        let recorder = Recorder::default();
+        let mut connection = CoalescingConnection::with(
+            Mock(Silent, recorder.clone()),
+            Coalescing::with(max_delay, 1 << 20),
+        );
+        for round in 0..20u8 {
+            let sent = Instant::now();
+            connection.as_sender().send_frame(&[round; 8]).unwrap();
+            let written = loop {
+                if let Some((at, data)) = recorder.writes().pop() {
+                    if data == [round; 8] {
+                        break at;
+                    }
+                }
+                assert!(sent.elapsed() < Duration::from_secs(1));
+                std::thread::yield_now();
+            };
+            // The flushing thread must not write before the deadline; the
+            // upper bound accounts for the scheduling latency
+            assert!(written.duration_since(sent) >= max_delay);
+            assert!(written.duration_since(sent) < Duration::from_millis(100));
+        }
+        assert_eq!(recorder.writes().len(), 20);
+    }
+
+    #[test]
+    fn flush_before_receive() {
+        let recorder = Recorder::default();
+        let mut connection = CoalescingConnection::with(
+            Mock(Silent, recorder.clone()),
+            Coalescing::with(Duration::from_secs(10), 1 << 20),
+        );
+        burst(&mut connection, 3);
+        assert!(recorder.writes().is_empty());
+        assert_eq!(
+            connection.as_receiver().recv_frame().unwrap_err(),
+            Error::TimedOut
+        );
+        assert_eq!(recorder.bytes(), expected(3));
+
+        // The same applies to the split halves
+        let (mut input, mut output) = Bipolar::split(connection);
+        output.send_frame(&[0xFF; 4]).unwrap();
+        assert_eq!(recorder.writes().len(), 1);
+        assert!(input.recv_raw(4).is_err());
+        assert_eq!(recorder.writes().len(), 2);
+
+        // Dropping the sender writes pending frames
+        output.send_frame(&[0xEE; 4]).unwrap();
+        drop(output);
+        assert_eq!(recorder.writes().pop().unwrap().1, [0xEE; 4]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn config_durations() {
+        use serde::de::value::{Error, MapDeserializer};
+        use serde::Deserialize;
+
+        let de = MapDeserializer::<_, Error>::new(
+            vec![("max_delay", "5ms")].into_iter(),
+        );
+        assert_eq!(
+            Coalescing::deserialize(de).unwrap(),
+            Coalescing::with(Duration::from_millis(5), 0)
+        );
+        let de = MapDeserializer::<_, Error>::new(
+            vec![("max_delay", "-5ms")].into_iter(),
+        );
+        assert!(Coalescing::deserialize(de).is_err());
+    }
+}
@@ -15,12 +15,14 @@
 
 use std::convert::TryFrom;
 use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::time::Duration;
 
 use amplify::Bipolar;
-use inet2_addr::InetSocketAddr;
+use inet2_addr::{InetSocketAddr, NodeId};
 
+use crate::consts::BRONTIDE_LEN_SIZE;
+use crate::transport::guard::ConnectGuard;
 use crate::transport::{Error, RecvFrame, SendFrame};
 use crate::DuplexConnection;
 
@@ -45,6 +47,10 @@ impl<S: Stream> Connection<S> {
             remote_addr,
         }
     }
+
+    /// Returns stream used by the connection
+    #[inline]
+    pub fn as_stream(&self) -> &S { &self.stream }
 }
 
 impl<S: Stream + DuplexConnection> DuplexConnection for Connection<S> {
@@ -60,6 +66,11 @@ impl<S: Stream + DuplexConnection> DuplexConnection for Connection<S> {
     fn split(self) -> (Box<dyn RecvFrame + Send>, Box<dyn SendFrame + Send>) {
         self.stream.split()
     }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize {
+        DuplexConnection::max_frame_size(&self.stream)
+    }
 }
 
 impl<S: Stream + Bipolar<Left = S, Right = S>> Bipolar for Connection<S> {
@@ -80,8 +91,33 @@ impl<S: Stream + Bipolar<Left = S, Right = S>> Bipolar for Connection<S> {
 /// Extensions trait for simplifying [`TcpStream`] API in working with
 /// [`InetSocketAddr`] sockets
 pub trait TcpInetStream: Sized {
+    /// Connects to a remote socket.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::InvalidPort`] before opening any socket if the
+    /// remote address uses zero port.
     fn connect_inet_socket(inet_addr: InetSocketAddr) -> Result<Self, Error>;
 
+    /// Connects to a remote socket if the `guard` allows connection to the
+    /// remote address and (optionally) node.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::ConnectionDenied`] before opening any socket if
+    /// the guard denies the connection; otherwise errors as
+    /// [`TcpInetStream::connect_inet_socket`].
+    fn connect_guarded(
+        inet_addr: InetSocketAddr,
+        node: Option<&NodeId>,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        guard
+            .allow(&inet_addr, node)
+            .map_err(Error::ConnectionDenied)?;
+        Self::connect_inet_socket(inet_addr)
+    }
+
     fn accept_inet_socket(
         listener: &TcpListener,
     ) -> Result<(Self, SocketAddr), Error>;
@@ -93,6 +129,9 @@ pub trait TcpInetStream: Sized {
 
 impl TcpInetStream for TcpStream {
     fn connect_inet_socket(inet_addr: InetSocketAddr) -> Result<Self, Error> {
+        let inet_addr = inet_addr
+            .check_remote()
+            .map_err(|_| Error::InvalidPort(0))?;
         if let Ok(socket_addr) = SocketAddr::try_from(inet_addr) {
             let stream = TcpStream::connect(socket_addr)?;
             // NB: This is how we handle ping-pong cycles
@@ -141,7 +180,7 @@ impl TcpInetStream for TcpStream {
 
 impl RecvFrame for TcpStream {
     fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
-        let mut len_buf = [0u8; 2];
+        let mut len_buf = [0u8; BRONTIDE_LEN_SIZE];
         self.read_exact(&mut len_buf)?;
         let len = u16::from_be_bytes(len_buf) as usize;
         let mut buf: Vec<u8> = vec![
@@ -149,8 +188,8 @@ impl RecvFrame for TcpStream {
             len + super::FRAME_PREFIX_SIZE
                 + super::FRAME_SUFFIX_SIZE
         ];
-        buf[0..2].copy_from_slice(&len_buf);
-        self.read_exact(&mut buf[2..])?;
+        buf[..BRONTIDE_LEN_SIZE].copy_from_slice(&len_buf);
+        self.read_exact(&mut buf[BRONTIDE_LEN_SIZE..])?;
         Ok(buf)
     }
 
@@ -175,4 +214,43 @@ impl SendFrame for TcpStream {
         self.write_all(data)?;
         Ok(data.len())
     }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { super::MAX_FRAME_SIZE }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        TcpStream::shutdown(self, Shutdown::Both)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+
+    #[test]
+    fn zero_port_connect() {
+        let addr =
+            InetSocketAddr::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+        assert_eq!(
+            TcpStream::connect_inet_socket(addr).unwrap_err(),
+            Error::InvalidPort(0)
+        );
+    }
+
+    #[test]
+    fn ephemeral_bind() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        assert_ne!(local_addr.port(), 0);
+
+        let stream =
+            TcpStream::connect_inet_socket(InetSocketAddr::from(local_addr))
+                .unwrap();
+        let (_, remote_addr) =
+            TcpStream::accept_inet_socket(&listener).unwrap();
+        assert_eq!(remote_addr, stream.local_addr().unwrap());
+    }
 }
@@ -19,8 +19,9 @@ use std::io::Read;
 use std::net::{TcpListener, TcpStream};
 
 use amplify::Bipolar;
-use inet2_addr::InetSocketAddr;
+use inet2_addr::{InetSocketAddr, NodeId};
 
+use super::guard::ConnectGuard;
 use super::{DuplexConnection, Error, RecvFrame, SendFrame};
 use crate::session::noise;
 use crate::transport::connect::{self, TcpInetStream};
@@ -37,6 +38,10 @@ pub type Connection<const LEN_SIZE: usize> =
 impl<const LEN_SIZE: usize> Stream<LEN_SIZE> {
     #[inline]
     pub fn with(stream: TcpStream) -> Stream<LEN_SIZE> { Stream::from(stream) }
+
+    /// Returns TCP stream wrapped by the type
+    #[inline]
+    pub fn as_tcp_stream(&self) -> &TcpStream { &self.0 }
 }
 
 impl<const LEN_SIZE: usize> Connection<LEN_SIZE> {
@@ -45,6 +50,17 @@ impl<const LEN_SIZE: usize> Connection<LEN_SIZE> {
         Ok(Connection::with(stream, inet_addr))
     }
 
+    /// Connects to a remote socket if the `guard` allows it; see
+    /// [`TcpInetStream::connect_guarded`].
+    pub fn connect_guarded(
+        inet_addr: InetSocketAddr,
+        node: Option<&NodeId>,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        let stream = TcpStream::connect_guarded(inet_addr, node, guard)?;
+        Ok(Connection::with(stream, inet_addr))
+    }
+
     pub fn accept(listener: &TcpListener) -> Result<Self, Error> {
         let (stream, inet_addr) = TcpStream::accept_inet_socket(listener)?;
         Ok(Connection::with(stream, inet_addr.into()))
@@ -81,6 +97,9 @@ impl<const LEN_SIZE: usize> DuplexConnection for Stream<LEN_SIZE> {
         let (r, s) = Bipolar::split(self);
         (Box::new(r), Box::new(s))
     }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { SendFrame::max_frame_size(self) }
 }
 
 impl<const LEN_SIZE: usize> RecvFrame for Stream<LEN_SIZE> {
@@ -112,4 +131,12 @@ impl<const LEN_SIZE: usize> SendFrame for Stream<LEN_SIZE> {
     fn send_raw(&mut self, data: &[u8]) -> Result<usize, Error> {
         self.0.send_raw(data)
     }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { self.0.max_frame_size() }
+
+    #[inline]
+    fn shutdown(&mut self) -> Result<(), Error> {
+        SendFrame::shutdown(&mut self.0)
+    }
 }
@@ -0,0 +1,510 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Fault-injecting transport wrappers for deterministic integration tests.
+//!
+//! [`FaultyConnection`] wraps any connection which can be split into receiving
+//! and sending parts and injects faults configured with [`Faults`]: per-frame
+//! latency, probabilistic frame drop, forced error after a number of frames
+//! and abrupt disconnection via [`KillSwitch`]. All randomness comes from a
+//! seeded generator and latency is applied through a [`Clock`], so test runs
+//! with [`MockClock`] are fully reproducible and do not actually sleep.
+
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use amplify::Bipolar;
+
+use super::{DuplexConnection, Error, RecvFrame, SendFrame};
+use crate::rng::SplitMix64;
+
+/// Latency added to each frame
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Latency {
+    /// The same latency for each frame
+    Fixed(Duration),
+
+    /// Latency uniformly distributed in `min..=max` range
+    Random {
+        /// Minimal latency
+        min: Duration,
+        /// Maximal latency
+        max: Duration,
+    },
+}
+
+/// Configuration of faults injected by [`FaultyConnection`]
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Faults {
+    /// Latency added to each sent and received frame
+    pub latency: Option<Latency>,
+
+    /// Probability of a frame being silently dropped, in `0.0..=1.0` range.
+    /// Dropping frames from a stream-based transport breaks the session
+    /// above it; this is intended for testing datagram-like transports.
+    pub drop_rate: f64,
+
+    /// Error returned for all frames after the given number of frames was
+    /// passed through the connection in both directions
+    pub fail_after: Option<(usize, Error)>,
+
+    /// Seed for the random number generator
+    pub seed: u64,
+}
+
+/// Source of time used to apply latency
+pub trait Clock: Send + Sync {
+    /// Waits for the given duration
+    fn sleep(&self, duration: Duration);
+}
+
+/// Clock using real time
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn sleep(&self, duration: Duration) { std::thread::sleep(duration) }
+}
+
+/// Clock which does not wait, but accumulates all requested sleep durations
+#[derive(Clone, Debug, Default)]
+pub struct MockClock(Arc<Mutex<Duration>>);
+
+impl MockClock {
+    /// Constructs clock with zero elapsed time
+    pub fn new() -> Self { Self::default() }
+
+    /// Returns the sum of all durations the clock was asked to sleep for
+    pub fn elapsed(&self) -> Duration {
+        *self.0.lock().expect("poisoned clock lock")
+    }
+}
+
+impl Clock for MockClock {
+    fn sleep(&self, duration: Duration) {
+        *self.0.lock().expect("poisoned clock lock") += duration;
+    }
+}
+
+/// Switch simulating abrupt disconnection of a [`FaultyConnection`]. Once
+/// killed, all operations on the connection fail with
+/// [`ErrorKind::ConnectionReset`] I/O error.
+#[derive(Clone, Debug, Default)]
+pub struct KillSwitch(Arc<AtomicBool>);
+
+impl KillSwitch {
+    /// Disconnects the connection
+    pub fn kill(&self) { self.0.store(true, Ordering::SeqCst) }
+
+    /// Checks whether the connection was disconnected
+    pub fn is_killed(&self) -> bool { self.0.load(Ordering::SeqCst) }
+}
+
+/// What should be done with a frame
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Fate {
+    Deliver,
+    Drop,
+}
+
+struct InjectorState {
+    faults: Faults,
+    rng: SplitMix64,
+    frames: usize,
+}
+
+/// Fault decisions shared by both parts of the connection
+#[derive(Clone)]
+struct Injector {
+    state: Arc<Mutex<InjectorState>>,
+    clock: Arc<dyn Clock>,
+    kill_switch: KillSwitch,
+}
+
+impl Injector {
+    fn next_frame(&self) -> Result<Fate, Error> {
+        if self.kill_switch.is_killed() {
+            return Err(Error::SocketIo(ErrorKind::ConnectionReset));
+        }
+        let (latency, fate) = {
+            let mut state = self.state.lock().expect("poisoned injector lock");
+            if let Some((after, ref err)) = state.faults.fail_after {
+                if state.frames >= after {
+                    return Err(err.clone());
+                }
+            }
+            state.frames += 1;
+            let latency = state.faults.latency;
+            let latency = match latency {
+                None => Duration::from_secs(0),
+                Some(Latency::Fixed(latency)) => latency,
+                Some(Latency::Random { min, max }) => {
+                    let range = max.saturating_sub(min);
+                    min + range.mul_f64(state.rng.next_f64())
+                }
+            };
+            let fate = if state.rng.next_f64() < state.faults.drop_rate {
+                Fate::Drop
+            } else {
+                Fate::Deliver
+            };
+            (latency, fate)
+        };
+        if latency > Duration::from_secs(0) {
+            self.clock.sleep(latency);
+        }
+        Ok(fate)
+    }
+}
+
+/// Receiving part of [`FaultyConnection`]
+pub struct FaultyRecv<R: RecvFrame> {
+    inner: R,
+    injector: Injector,
+}
+
+/// Sending part of [`FaultyConnection`]
+pub struct FaultySend<S: SendFrame> {
+    inner: S,
+    injector: Injector,
+}
+
+/// Connection wrapper injecting faults into the frames passing through it
+pub struct FaultyConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    input: FaultyRecv<C::Left>,
+    output: FaultySend<C::Right>,
+}
+
+impl<C> FaultyConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    /// Wraps connection, using real time for latency
+    pub fn new(connection: C, faults: Faults) -> Self {
+        Self::with_clock(connection, faults, SystemClock)
+    }
+
+    /// Wraps connection, using the provided clock for latency
+    pub fn with_clock(
+        connection: C,
+        faults: Faults,
+        clock: impl Clock + 'static,
+    ) -> Self {
+        let injector = Injector {
+            state: Arc::new(Mutex::new(InjectorState {
+                rng: SplitMix64::with_seed(faults.seed),
+                faults,
+                frames: 0,
+            })),
+            clock: Arc::new(clock),
+            kill_switch: KillSwitch::default(),
+        };
+        let (input, output) = connection.split();
+        FaultyConnection {
+            input: FaultyRecv {
+                inner: input,
+                injector: injector.clone(),
+            },
+            output: FaultySend {
+                inner: output,
+                injector,
+            },
+        }
+    }
+
+    /// Returns switch which disconnects the connection
+    pub fn kill_switch(&self) -> KillSwitch {
+        self.output.injector.kill_switch.clone()
+    }
+
+    /// Returns number of frames passed through the connection (including the
+    /// dropped ones)
+    pub fn frame_count(&self) -> usize {
+        self.output
+            .injector
+            .state
+            .lock()
+            .expect("poisoned injector lock")
+            .frames
+    }
+
+    /// Releases wrapped connection
+    pub fn into_inner(self) -> C {
+        C::join(self.input.inner, self.output.inner)
+    }
+}
+
+impl<R: RecvFrame> RecvFrame for FaultyRecv<R> {
+    fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            let frame = self.inner.recv_frame()?;
+            if self.injector.next_frame()? == Fate::Deliver {
+                return Ok(frame);
+            }
+        }
+    }
+
+    fn recv_raw(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        loop {
+            let data = self.inner.recv_raw(len)?;
+            if self.injector.next_frame()? == Fate::Deliver {
+                return Ok(data);
+            }
+        }
+    }
+}
+
+impl<S: SendFrame> SendFrame for FaultySend<S> {
+    fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        match self.injector.next_frame()? {
+            Fate::Deliver => self.inner.send_frame(frame),
+            Fate::Drop => Ok(frame.len()),
+        }
+    }
+
+    fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+        match self.injector.next_frame()? {
+            Fate::Deliver => self.inner.send_raw(raw_frame),
+            Fate::Drop => Ok(raw_frame.len()),
+        }
+    }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { self.inner.max_frame_size() }
+
+    #[inline]
+    fn shutdown(&mut self) -> Result<(), Error> { self.inner.shutdown() }
+}
+
+impl<C> DuplexConnection for FaultyConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame + Send + 'static,
+    C::Right: SendFrame + Send + 'static,
+{
+    #[inline]
+    fn as_receiver(&mut self) -> &mut dyn RecvFrame { &mut self.input }
+
+    #[inline]
+    fn as_sender(&mut self) -> &mut dyn SendFrame { &mut self.output }
+
+    fn split(self) -> (Box<dyn RecvFrame + Send>, Box<dyn SendFrame + Send>) {
+        (Box::new(self.input), Box::new(self.output))
+    }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { self.output.max_frame_size() }
+}
+
+impl<C> Bipolar for FaultyConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    type Left = FaultyRecv<C::Left>;
+    type Right = FaultySend<C::Right>;
+
+    fn join(input: Self::Left, output: Self::Right) -> Self {
+        FaultyConnection { input, output }
+    }
+
+    fn split(self) -> (Self::Left, Self::Right) { (self.input, self.output) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Queue of frames shared by both ends of an in-memory connection
+    #[derive(Clone, Default)]
+    struct Queue(Arc<Mutex<VecDeque<Vec<u8>>>>);
+
+    impl RecvFrame for Queue {
+        fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or(Error::ServiceOffline)
+        }
+
+        fn recv_raw(&mut self, _len: usize) -> Result<Vec<u8>, Error> {
+            self.recv_frame()
+        }
+    }
+
+    impl SendFrame for Queue {
+        fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+            self.0.lock().unwrap().push_back(frame.to_vec());
+            Ok(frame.len())
+        }
+
+        fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+            self.send_frame(raw_frame)
+        }
+    }
+
+    /// Connection receiving back everything which was sent over it
+    struct Loopback(Queue, Queue);
+
+    impl Bipolar for Loopback {
+        type Left = Queue;
+        type Right = Queue;
+
+        fn join(left: Queue, right: Queue) -> Self { Loopback(left, right) }
+
+        fn split(self) -> (Queue, Queue) { (self.0, self.1) }
+    }
+
+    fn loopback(faults: Faults) -> (FaultyConnection<Loopback>, MockClock) {
+        let queue = Queue::default();
+        let clock = MockClock::new();
+        let connection = FaultyConnection::with_clock(
+            Loopback(queue.clone(), queue),
+            faults,
+            clock.clone(),
+        );
+        (connection, clock)
+    }
+
+    #[test]
+    fn no_faults() {
+        let (mut connection, clock) = loopback(Faults::default());
+        for no in 0..10u8 {
+            connection.as_sender().send_frame(&[no]).unwrap();
+            assert_eq!(connection.as_receiver().recv_frame().unwrap(), [no]);
+        }
+        assert_eq!(connection.frame_count(), 20);
+        assert_eq!(clock.elapsed(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn fixed_latency() {
+        let (mut connection, clock) = loopback(Faults {
+            latency: Some(Latency::Fixed(Duration::from_millis(50))),
+            ..Faults::default()
+        });
+        connection.as_sender().send_frame(b"frame").unwrap();
+        connection.as_receiver().recv_frame().unwrap();
+        assert_eq!(clock.elapsed(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn random_latency() {
+        let faults = Faults {
+            latency: Some(Latency::Random {
+                min: Duration::from_millis(10),
+                max: Duration::from_millis(20),
+            }),
+            seed: 42,
+            ..Faults::default()
+        };
+        let run = |faults: Faults| {
+            let (mut connection, clock) = loopback(faults);
+            let mut elapsed = vec![];
+            for _ in 0..100 {
+                let before = clock.elapsed();
+                connection.as_sender().send_frame(b"frame").unwrap();
+                let latency = clock.elapsed() - before;
+                assert!(latency >= Duration::from_millis(10));
+                assert!(latency <= Duration::from_millis(20));
+                elapsed.push(latency);
+            }
+            elapsed
+        };
+        let first = run(faults.clone());
+        // Same seed gives the same latencies
+        assert_eq!(first, run(faults.clone()));
+        assert_ne!(first, run(Faults { seed: 43, ..faults }));
+        assert!(first.iter().any(|latency| *latency != first[0]));
+    }
+
+    #[test]
+    fn frame_drop() {
+        let (mut connection, _) = loopback(Faults {
+            drop_rate: 1.0,
+            ..Faults::default()
+        });
+        assert_eq!(connection.as_sender().send_frame(b"frame").unwrap(), 5);
+        assert_eq!(
+            connection.as_receiver().recv_frame(),
+            Err(Error::ServiceOffline)
+        );
+
+        let (mut connection, _) = loopback(Faults {
+            drop_rate: 0.5,
+            seed: 7,
+            ..Faults::default()
+        });
+        for no in 0..1000u16 {
+            connection
+                .as_sender()
+                .send_frame(&no.to_be_bytes())
+                .unwrap();
+        }
+        let mut received = 0;
+        while connection.as_receiver().recv_frame().is_ok() {
+            received += 1;
+        }
+        // Roughly a quarter of frames passes drop checks in both directions
+        assert!(received > 150 && received < 350, "{}", received);
+    }
+
+    #[test]
+    fn fail_after() {
+        let (mut connection, _) = loopback(Faults {
+            fail_after: Some((3, Error::TimedOut)),
+            ..Faults::default()
+        });
+        connection.as_sender().send_frame(b"1").unwrap();
+        connection.as_sender().send_frame(b"2").unwrap();
+        connection.as_receiver().recv_frame().unwrap();
+        assert_eq!(
+            connection.as_sender().send_frame(b"3"),
+            Err(Error::TimedOut)
+        );
+        assert_eq!(connection.as_receiver().recv_frame(), Err(Error::TimedOut));
+        assert_eq!(connection.frame_count(), 3);
+    }
+
+    #[test]
+    fn kill_switch() {
+        let (connection, _) = loopback(Faults::default());
+        let kill_switch = connection.kill_switch();
+        let (mut input, mut output) = Bipolar::split(connection);
+        output.send_frame(b"frame").unwrap();
+
+        kill_switch.kill();
+        assert!(kill_switch.is_killed());
+        assert_eq!(
+            input.recv_frame(),
+            Err(Error::SocketIo(ErrorKind::ConnectionReset))
+        );
+        assert_eq!(
+            output.send_frame(b"frame"),
+            Err(Error::SocketIo(ErrorKind::ConnectionReset))
+        );
+    }
+}
@@ -0,0 +1,369 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Pre-connect filters allowing applications to apply egress policies to
+//! outbound connections before any socket is opened.
+
+use std::fmt::{self, Display, Formatter};
+
+use inet2_addr::{InetSocketAddr, NodeId, ServiceAddr};
+
+/// Reason provided by a [`ConnectGuard`] for denying an outbound connection
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct DenyReason(String);
+
+impl DenyReason {
+    /// Constructs deny reason from a human-readable description
+    pub fn new(reason: impl ToString) -> Self { DenyReason(reason.to_string()) }
+
+    /// Returns human-readable description of the reason
+    pub fn as_str(&self) -> &str { &self.0 }
+}
+
+impl Display for DenyReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(&self.0) }
+}
+
+/// Policy consulted before opening an outbound connection.
+///
+/// Guards are passed to the `connect_guarded` family of methods (see
+/// [`super::connect::TcpInetStream::connect_guarded`]), which fail with
+/// [`super::Error::ConnectionDenied`] without creating a socket if the guard
+/// denies the target.
+pub trait ConnectGuard: Send + Sync {
+    /// Decides whether a connection to the `target` address may be opened.
+    /// The node id is provided when the connection is made to a known node
+    /// (i.e. for encrypted sessions).
+    fn allow(
+        &self,
+        target: &InetSocketAddr,
+        node: Option<&NodeId>,
+    ) -> Result<(), DenyReason>;
+}
+
+impl<F> ConnectGuard for F
+where
+    F: Fn(&InetSocketAddr, Option<&NodeId>) -> Result<(), DenyReason>
+        + Send
+        + Sync,
+{
+    #[inline]
+    fn allow(
+        &self,
+        target: &InetSocketAddr,
+        node: Option<&NodeId>,
+    ) -> Result<(), DenyReason> {
+        self(target, node)
+    }
+}
+
+/// Guard allowing all connections
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct AllowAll;
+
+impl ConnectGuard for AllowAll {
+    #[inline]
+    fn allow(
+        &self,
+        _target: &InetSocketAddr,
+        _node: Option<&NodeId>,
+    ) -> Result<(), DenyReason> {
+        Ok(())
+    }
+}
+
+/// Composite guard allowing a connection only if all of the inner guards
+/// allow it. Guards are consulted in the order they were added; the first
+/// denial is returned.
+#[derive(Default)]
+pub struct AllGuards(Vec<Box<dyn ConnectGuard>>);
+
+impl AllGuards {
+    /// Constructs composite guard without inner guards, which allows all
+    /// connections
+    #[inline]
+    pub fn new() -> Self { AllGuards::default() }
+
+    /// Adds a guard to the composite
+    pub fn with(mut self, guard: impl ConnectGuard + 'static) -> Self {
+        self.push(guard);
+        self
+    }
+
+    /// Adds a guard to the composite
+    pub fn push(&mut self, guard: impl ConnectGuard + 'static) {
+        self.0.push(Box::new(guard))
+    }
+
+    /// Returns number of the inner guards
+    #[inline]
+    pub fn len(&self) -> usize { self.0.len() }
+
+    /// Detects whether the composite has no inner guards
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+impl ConnectGuard for AllGuards {
+    fn allow(
+        &self,
+        target: &InetSocketAddr,
+        node: Option<&NodeId>,
+    ) -> Result<(), DenyReason> {
+        self.0
+            .iter()
+            .try_for_each(|guard| guard.allow(target, node))
+    }
+}
+
+/// Consults the `guard` before connecting to a ZMQ `remote` endpoint. Only
+/// TCP endpoints open outbound network connections; IPC and in-process
+/// endpoints are always allowed.
+pub(crate) fn allow_service(
+    guard: &dyn ConnectGuard,
+    remote: &ServiceAddr,
+) -> Result<(), super::Error> {
+    match remote {
+        ServiceAddr::Tcp(addr) => guard
+            .allow(&InetSocketAddr::from(*addr), None)
+            .map_err(super::Error::ConnectionDenied),
+        ServiceAddr::Ipc(_) | ServiceAddr::Inproc(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::consts::BRONTIDE_LEN_SIZE;
+    use crate::transport::connect::TcpInetStream;
+    use crate::transport::{encrypted, unencrypted, Error};
+
+    fn deny_loopback(
+        target: &InetSocketAddr,
+        _: Option<&NodeId>,
+    ) -> Result<(), DenyReason> {
+        match SocketAddr::try_from(*target) {
+            Ok(addr) if addr.ip().is_loopback() => {
+                Err(DenyReason::new("loopback targets are not allowed"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn deny_onion(
+        target: &InetSocketAddr,
+        _: Option<&NodeId>,
+    ) -> Result<(), DenyReason> {
+        if target.is_tor() {
+            return Err(DenyReason::new("onion targets are not allowed"));
+        }
+        Ok(())
+    }
+
+    /// Listener which must never see an incoming connection
+    fn listener() -> (TcpListener, InetSocketAddr) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap().into();
+        (listener, addr)
+    }
+
+    fn assert_no_connection(listener: &TcpListener) {
+        assert_eq!(
+            listener.accept().unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+    }
+
+    fn assert_denied<T>(res: Result<T, Error>) {
+        match res {
+            Err(Error::ConnectionDenied(reason)) => {
+                assert_eq!(reason.as_str(), "loopback targets are not allowed")
+            }
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("connection must be denied"),
+        }
+    }
+
+    #[test]
+    fn composite() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let calls = counter.clone();
+        let guard = AllGuards::new()
+            .with(move |_: &InetSocketAddr, _: Option<&NodeId>| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .with(deny_onion)
+            .with(deny_loopback);
+        assert_eq!(guard.len(), 3);
+
+        let remote = InetSocketAddr::from(SocketAddr::from(([1, 1, 1, 1], 80)));
+        assert_eq!(guard.allow(&remote, None), Ok(()));
+        let local =
+            InetSocketAddr::from(SocketAddr::from(([127, 0, 0, 1], 80)));
+        assert_eq!(
+            guard.allow(&local, None),
+            Err(DenyReason::new("loopback targets are not allowed"))
+        );
+        assert_eq!(counter.load(Ordering::Relaxed), 2);
+
+        assert!(AllGuards::new().is_empty());
+        assert_eq!(AllGuards::new().allow(&local, None), Ok(()));
+        assert_eq!(AllowAll.allow(&local, None), Ok(()));
+    }
+
+    #[test]
+    fn connect_paths_denied() {
+        let (listener, addr) = listener();
+
+        assert_denied(TcpStream::connect_guarded(addr, None, &deny_loopback));
+        assert_denied(unencrypted::Connection::connect_guarded(
+            addr,
+            &deny_loopback,
+        ));
+        assert_denied(
+            encrypted::Connection::<BRONTIDE_LEN_SIZE>::connect_guarded(
+                addr,
+                None,
+                &deny_loopback,
+            ),
+        );
+        assert_no_connection(&listener);
+
+        let _stream = TcpStream::connect_guarded(addr, None, &AllowAll)
+            .expect("connection must be allowed");
+        listener.set_nonblocking(false).unwrap();
+        assert!(listener.accept().is_ok());
+    }
+
+    #[cfg(feature = "keygen")]
+    #[test]
+    fn session_connect_denied() {
+        use inet2_addr::NodeAddr;
+
+        use crate::session::{
+            BrontideSession, BrontozaurSession, MinimalPeer, MinimalPeerError,
+        };
+
+        let (listener, addr) = listener();
+        let secp = secp256k1::Secp256k1::new();
+        let local_key = secp256k1::SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let remote_key = secp256k1::SecretKey::from_slice(&[2u8; 32]).unwrap();
+        let remote = NodeAddr::new(
+            secp256k1::PublicKey::from_secret_key(&secp, &remote_key).into(),
+            addr,
+        );
+
+        assert_denied(BrontideSession::connect_guarded(
+            local_key,
+            remote,
+            &deny_loopback,
+        ));
+        assert_denied(BrontozaurSession::connect_guarded(
+            local_key,
+            remote,
+            &deny_loopback,
+        ));
+        assert!(matches!(
+            MinimalPeer::connect_guarded(
+                &remote.to_string(),
+                None,
+                &[],
+                &deny_loopback
+            ),
+            Err(MinimalPeerError::Transport(Error::ConnectionDenied(_)))
+        ));
+        assert_no_connection(&listener);
+    }
+
+    #[cfg(feature = "zmq")]
+    #[test]
+    fn zmq_connect_denied() {
+        use std::time::Duration;
+
+        use crate::rpc::Multiplexer;
+        use crate::session::LocalSession;
+        use crate::transport::zeromq::{Connection, ZmqSocketType};
+
+        let (listener, _) = listener();
+        let remote = ServiceAddr::Tcp(listener.local_addr().unwrap());
+        let context = zmq::Context::new();
+
+        assert_denied(Connection::connect_guarded(
+            ZmqSocketType::Req,
+            &remote,
+            None,
+            None::<&[u8]>,
+            &context,
+            &deny_loopback,
+        ));
+        assert_denied(LocalSession::connect_guarded(
+            ZmqSocketType::RouterConnect,
+            &remote,
+            None,
+            None,
+            &context,
+            &deny_loopback,
+        ));
+        assert_denied(Multiplexer::connect_guarded(
+            &remote,
+            &context,
+            Duration::from_secs(1),
+            &deny_loopback,
+        ));
+        assert_no_connection(&listener);
+
+        // Local endpoints are not subject to the guard
+        let inproc = ServiceAddr::Inproc(s!("guard-zmq-connect-denied"));
+        let _server = Connection::connect(
+            ZmqSocketType::Rep,
+            &inproc,
+            None,
+            None::<&[u8]>,
+            &context,
+        )
+        .unwrap();
+        Connection::connect_guarded(
+            ZmqSocketType::Req,
+            &inproc,
+            None,
+            None::<&[u8]>,
+            &context,
+            &deny_loopback,
+        )
+        .expect("in-process connection must be allowed");
+    }
+
+    #[cfg(feature = "tor")]
+    #[test]
+    fn onion_denied() {
+        let onion: InetSocketAddr =
+            "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad"
+                .parse()
+                .unwrap();
+        let guard = AllGuards::new().with(deny_onion);
+        assert!(matches!(
+            unencrypted::Connection::connect_guarded(onion, &guard),
+            Err(Error::ConnectionDenied(_))
+        ));
+        assert!(matches!(
+            TcpStream::connect_guarded(onion, None, &guard),
+            Err(Error::ConnectionDenied(_))
+        ));
+    }
+}
@@ -20,8 +20,14 @@
 //! integrates with ZMQ such that the upper level can abstract for a particular
 //! transport protocol used.
 
+pub mod coalescing;
 pub mod connect;
 pub mod encrypted;
+#[cfg(any(test, feature = "testing"))]
+pub mod faulty;
+pub mod guard;
+#[cfg(any(test, feature = "testing"))]
+pub mod replay;
 pub mod unencrypted;
 #[cfg(feature = "zmq")]
 pub mod zeromq;
@@ -31,25 +37,17 @@ use std::io::ErrorKind;
 #[cfg(feature = "zmq")]
 pub use zeromq::{ZmqConnectionType, ZmqSocketType};
 
+pub use crate::consts::{
+    FRAME_PREFIX_SIZE, FRAME_SUFFIX_SIZE, MAX_FRAME_PAYLOAD_SIZE,
+    MAX_FRAME_SIZE,
+};
+use crate::session::noise::EncryptionError;
 use crate::session::HandshakeError;
 
-/// Maximum size of the transport frame; chosen in compliance with LN specs
-pub const MAX_FRAME_SIZE: usize =
-    FRAME_PREFIX_SIZE + MAX_FRAME_PAYLOAD_SIZE + FRAME_SUFFIX_SIZE;
-
-/// Size of the frame prefix which is not included into payload size, consisting
-/// of the 2-bytes message size data and 16-byte MAC of the payload length
-pub const FRAME_PREFIX_SIZE: usize = 2 + 16;
-
-/// Size of the frame suffix represented by a 16-byte MAC of the frame payload
-pub const FRAME_SUFFIX_SIZE: usize = 16;
-
-/// Maximum size of the frame payload which may be expressed by two bytes
-pub const MAX_FRAME_PAYLOAD_SIZE: usize = 0xFFFF;
-
 /// Transport protocol-level errors
-#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, From)]
 #[display(doc_comments)]
+#[non_exhaustive]
 pub enum Error {
     /// I/O socket error, generated by underlying socket implementation
     /// (POSIX or TCP). Error type is {_0:?}
@@ -75,6 +73,10 @@ pub enum Error {
     /// frame size {0} is less than minimal (34 bytes)
     FrameTooSmall(usize),
 
+    /// message of {len} bytes exceeds maximum of {max} bytes which can be sent
+    /// over the transport in a single frame
+    FrameTooLargeForTransport { len: usize, max: usize },
+
     /// frame structure broken: {0}
     FrameBroken(&'static str),
 
@@ -84,20 +86,71 @@ pub enum Error {
     /// message does not contain Noise_XK length header
     NoNoiseHeader,
 
+    /// outbound connection was denied by the connect guard: {0}
+    ConnectionDenied(guard::DenyReason),
+
     /// connections over Tor protocol are not yet supported
     TorNotSupportedYet,
 
+    /// port {0} can't be used for connecting to a remote peer; zero port is
+    /// valid only for local binds
+    InvalidPort(u16),
+
     /// read or write attempt exceeded socket timeout
     TimedOut,
 
+    /// deadline for the call has expired; the request was sent: {sent}
+    DeadlineExpired { sent: bool },
+
     /// failed Noise_XK handshake due to {0}
     #[from]
     Handshake(HandshakeError),
 
+    /// session is poisoned by a previous message authentication failure or by
+    /// a failure to send an encrypted message, and can't be used anymore
+    SessionPoisoned,
+
+    /// padding of the received message is malformed: {0}
+    InvalidPadding(&'static str),
+
+    /// multiplexed channel {0} is closed
+    ChannelClosed(u16),
+
     /// use of {0} API requires compilatino with `keygen` feature enabled
     KeygenFeatureRequired(&'static str),
 }
 
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "zmq")]
+            Error::Zmq(err) => Some(err),
+            Error::Handshake(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// Detects errors after which the session can't be used anymore: message
+    /// authentication failures, broken framing or padding and poisoned
+    /// sessions.
+    pub fn is_session_fatal(&self) -> bool {
+        matches!(
+            self,
+            Error::SessionPoisoned
+                | Error::NoNoiseHeader
+                | Error::FrameBroken(_)
+                | Error::InvalidLength { .. }
+                | Error::InvalidPadding(_)
+                | Error::Handshake(HandshakeError::Encryption(
+                    EncryptionError::MacFailure { .. }
+                        | EncryptionError::Poisoned
+                ))
+        )
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Error {
         match err.kind() {
@@ -127,6 +180,11 @@ pub trait DuplexConnection {
     fn as_receiver(&mut self) -> &mut dyn RecvFrame;
     fn as_sender(&mut self) -> &mut dyn SendFrame;
     fn split(self) -> (Box<dyn RecvFrame + Send>, Box<dyn SendFrame + Send>);
+
+    /// Maximum size of a single frame which can be sent over the connection,
+    /// including all framing and encryption overhead. See
+    /// [`SendFrame::max_frame_size`] for the details.
+    fn max_frame_size(&self) -> usize { usize::MAX }
 }
 
 /// Frame receiving type which is able to parse raw data (streamed or framed by
@@ -215,6 +273,25 @@ pub trait SendFrame {
     ///   type
     fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error>;
 
+    /// Maximum size of a single frame which can be sent over the transport,
+    /// including all framing and encryption overhead. Transports with hard
+    /// per-frame limits (datagram- or radio-based) must override the default
+    /// implementation, which does not put any limit.
+    fn max_frame_size(&self) -> usize { usize::MAX }
+
+    /// Writes frames buffered by the transport, if any. Transports which do
+    /// not buffer frames (the default) send each frame immediately and do
+    /// nothing here.
+    fn flush(&mut self) -> Result<(), Error> { Ok(()) }
+
+    /// Shuts the connection down in both directions, so the receiving half of
+    /// a split connection blocked in reading fails. Transports which can't be
+    /// shut down this way (the default) fail with [`Error::SocketIo`] of
+    /// [`ErrorKind::Unsupported`] kind.
+    fn shutdown(&mut self) -> Result<(), Error> {
+        Err(Error::SocketIo(ErrorKind::Unsupported))
+    }
+
     /// Sends a single frame of data structured as a byte string to a specific
     /// receiver with `remote_id`. Function works like [`RecvFrame::recv_frame`]
     /// and is used for the underlying protocols supporting multipeer
@@ -300,3 +377,34 @@ pub trait AsyncSendFrame {
         panic!("Multipeer sockets are not possible with the chosen transport")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn error_source() {
+        let err = Error::from(HandshakeError::SelfConnection);
+        assert_eq!(
+            err.to_string(),
+            "failed Noise_XK handshake due to self-connection attempt: remote \
+             node key matches local one"
+        );
+        assert_eq!(
+            err.source()
+                .and_then(|err| err.downcast_ref::<HandshakeError>()),
+            Some(&HandshakeError::SelfConnection)
+        );
+        assert!(Error::ServiceOffline.source().is_none());
+    }
+
+    #[test]
+    fn session_fatal() {
+        assert!(Error::SessionPoisoned.is_session_fatal());
+        assert!(Error::InvalidPadding("non-zero fill").is_session_fatal());
+        assert!(!Error::ServiceOffline.is_session_fatal());
+        assert!(!Error::TimedOut.is_session_fatal());
+    }
+}
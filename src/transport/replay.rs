@@ -0,0 +1,812 @@
+// LNP/BP Core Library implementing LNPBP specifications & standards
+// Written in 2020 by
+//     Dr. Maxim Orlovsky <orlovsky@pandoracore.com>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the MIT License
+// along with this software.
+// If not, see <https://opensource.org/licenses/MIT>.
+
+//! Recording and deterministic replay of frame streams.
+//!
+//! [`RecordingConnection`] wraps a connection and captures all frames passing
+//! through it in both directions, together with their timing, into a
+//! [`Capture`], which can be saved to a file. [`ReplayConnection`] later feeds
+//! the captured inbound frames to the code under test and checks that the
+//! frames it sends match the captured outbound ones, stopping at the first
+//! divergence. This allows reproducing protocol bugs reported from the field
+//! in a test.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use amplify::Bipolar;
+
+use super::faulty::{Clock, SystemClock};
+use super::{DuplexConnection, Error, RecvFrame, SendFrame};
+
+/// Magic bytes starting a capture file, including format version
+pub const CAPTURE_MAGIC: [u8; 8] = *b"INET2CP\x01";
+
+/// Direction of a captured frame
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum Direction {
+    /// Frame received from the remote peer
+    #[display("inbound")]
+    Inbound,
+
+    /// Frame sent to the remote peer
+    #[display("outbound")]
+    Outbound,
+}
+
+/// Frame captured by [`RecordingConnection`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CapturedFrame {
+    /// Direction of the frame
+    pub direction: Direction,
+
+    /// Time passed since the start of the capture
+    pub offset: Duration,
+
+    /// Frame data
+    pub data: Vec<u8>,
+}
+
+/// Sequence of frames captured from a connection.
+///
+/// When written to a file, capture starts with [`CAPTURE_MAGIC`] followed by
+/// the frames, each of which is serialized as a direction byte (`0` for
+/// inbound and `1` for outbound frames), a 64-bit frame offset in
+/// microseconds, a 32-bit data length and the data itself. All integers are
+/// big-endian.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Capture {
+    /// Captured frames in the order they have passed through the connection
+    pub frames: Vec<CapturedFrame>,
+}
+
+impl Capture {
+    /// Iterates over captured frames of the given direction together with
+    /// their indexes in the capture.
+    pub fn frames(
+        &self,
+        direction: Direction,
+    ) -> impl Iterator<Item = (usize, &CapturedFrame)> {
+        self.frames
+            .iter()
+            .enumerate()
+            .filter(move |(_, frame)| frame.direction == direction)
+    }
+
+    /// Writes capture in the file format.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&CAPTURE_MAGIC)?;
+        for frame in &self.frames {
+            let len = u32::try_from(frame.data.len()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "frame too large")
+            })?;
+            writer.write_all(&[match frame.direction {
+                Direction::Inbound => 0,
+                Direction::Outbound => 1,
+            }])?;
+            writer
+                .write_all(&(frame.offset.as_micros() as u64).to_be_bytes())?;
+            writer.write_all(&len.to_be_bytes())?;
+            writer.write_all(&frame.data)?;
+        }
+        Ok(())
+    }
+
+    /// Reads capture in the file format.
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != CAPTURE_MAGIC {
+            return Err(invalid("not a frame capture or unsupported version"));
+        }
+        let mut frames = vec![];
+        loop {
+            let mut direction = [0u8; 1];
+            if reader.read(&mut direction)? == 0 {
+                break;
+            }
+            let direction = match direction[0] {
+                0 => Direction::Inbound,
+                1 => Direction::Outbound,
+                _ => return Err(invalid("invalid frame direction")),
+            };
+            let mut offset = [0u8; 8];
+            reader.read_exact(&mut offset)?;
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            let mut data = vec![];
+            reader
+                .by_ref()
+                .take(u32::from_be_bytes(len) as u64)
+                .read_to_end(&mut data)?;
+            if data.len() != u32::from_be_bytes(len) as usize {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            frames.push(CapturedFrame {
+                direction,
+                offset: Duration::from_micros(u64::from_be_bytes(offset)),
+                data,
+            });
+        }
+        Ok(Capture { frames })
+    }
+}
+
+#[derive(Clone)]
+struct Recorder {
+    capture: Arc<Mutex<Capture>>,
+    started: Instant,
+}
+
+impl Recorder {
+    fn record(&self, direction: Direction, data: &[u8]) {
+        let offset = self.started.elapsed();
+        self.capture
+            .lock()
+            .expect("poisoned capture lock")
+            .frames
+            .push(CapturedFrame {
+                direction,
+                offset,
+                data: data.to_vec(),
+            });
+    }
+}
+
+/// Receiving part of [`RecordingConnection`]
+pub struct RecordingRecv<R: RecvFrame> {
+    inner: R,
+    recorder: Recorder,
+}
+
+/// Sending part of [`RecordingConnection`]
+pub struct RecordingSend<S: SendFrame> {
+    inner: S,
+    recorder: Recorder,
+}
+
+/// Connection wrapper capturing all frames passing through it
+pub struct RecordingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    input: RecordingRecv<C::Left>,
+    output: RecordingSend<C::Right>,
+}
+
+impl<C> RecordingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    /// Wraps connection, starting the capture
+    pub fn new(connection: C) -> Self {
+        let recorder = Recorder {
+            capture: Arc::new(Mutex::new(Capture::default())),
+            started: Instant::now(),
+        };
+        let (input, output) = connection.split();
+        RecordingConnection {
+            input: RecordingRecv {
+                inner: input,
+                recorder: recorder.clone(),
+            },
+            output: RecordingSend {
+                inner: output,
+                recorder,
+            },
+        }
+    }
+
+    /// Returns copy of the frames captured so far
+    pub fn capture(&self) -> Capture {
+        self.output
+            .recorder
+            .capture
+            .lock()
+            .expect("poisoned capture lock")
+            .clone()
+    }
+
+    /// Releases wrapped connection
+    pub fn into_inner(self) -> C {
+        C::join(self.input.inner, self.output.inner)
+    }
+}
+
+impl<R: RecvFrame> RecvFrame for RecordingRecv<R> {
+    fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
+        let frame = self.inner.recv_frame()?;
+        self.recorder.record(Direction::Inbound, &frame);
+        Ok(frame)
+    }
+
+    fn recv_raw(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        let data = self.inner.recv_raw(len)?;
+        self.recorder.record(Direction::Inbound, &data);
+        Ok(data)
+    }
+}
+
+impl<S: SendFrame> SendFrame for RecordingSend<S> {
+    fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        let len = self.inner.send_frame(frame)?;
+        self.recorder.record(Direction::Outbound, frame);
+        Ok(len)
+    }
+
+    fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+        let len = self.inner.send_raw(raw_frame)?;
+        self.recorder.record(Direction::Outbound, raw_frame);
+        Ok(len)
+    }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { self.inner.max_frame_size() }
+
+    #[inline]
+    fn flush(&mut self) -> Result<(), Error> { self.inner.flush() }
+
+    #[inline]
+    fn shutdown(&mut self) -> Result<(), Error> { self.inner.shutdown() }
+}
+
+impl<C> DuplexConnection for RecordingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame + Send + 'static,
+    C::Right: SendFrame + Send + 'static,
+{
+    #[inline]
+    fn as_receiver(&mut self) -> &mut dyn RecvFrame { &mut self.input }
+
+    #[inline]
+    fn as_sender(&mut self) -> &mut dyn SendFrame { &mut self.output }
+
+    fn split(self) -> (Box<dyn RecvFrame + Send>, Box<dyn SendFrame + Send>) {
+        (Box::new(self.input), Box::new(self.output))
+    }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { self.output.max_frame_size() }
+}
+
+impl<C> Bipolar for RecordingConnection<C>
+where
+    C: Bipolar,
+    C::Left: RecvFrame,
+    C::Right: SendFrame,
+{
+    type Left = RecordingRecv<C::Left>;
+    type Right = RecordingSend<C::Right>;
+
+    fn join(input: Self::Left, output: Self::Right) -> Self {
+        RecordingConnection { input, output }
+    }
+
+    fn split(self) -> (Self::Left, Self::Right) { (self.input, self.output) }
+}
+
+/// Timing of the inbound frames fed by [`ReplayConnection`]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Timing {
+    /// Frames are delayed by the original inter-frame intervals
+    Original,
+
+    /// Original inter-frame intervals are divided by the given factor
+    Compressed(u32),
+
+    /// Frames are fed without any delay
+    Ignored,
+}
+
+/// How outbound frames are compared with the captured ones
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Matching {
+    /// Frames must be byte-exact
+    Exact,
+
+    /// Only message type ids (first two bytes of the frame) must match
+    TypeId,
+}
+
+/// First outbound frame which does not match the capture
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Divergence {
+    /// Index of the expected frame in the capture, or the number of frames
+    /// in the capture if no more outbound frames were expected
+    pub index: usize,
+
+    /// Expected frame, if any
+    pub expected: Option<Vec<u8>>,
+
+    /// Frame sent by the code under test
+    pub actual: Vec<u8>,
+}
+
+impl Divergence {
+    /// Returns position of the first byte which differs between the expected
+    /// and the actual frame.
+    pub fn offset(&self) -> usize {
+        let expected = self.expected.as_deref().unwrap_or_default();
+        expected
+            .iter()
+            .zip(&self.actual)
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| expected.len().min(self.actual.len()))
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let expected = match self.expected {
+            None => {
+                return write!(
+                    f,
+                    "unexpected outbound frame after the end of capture \
+                     (frame #{}): {}",
+                    self.index,
+                    self.actual.to_hex()
+                )
+            }
+            Some(ref expected) => expected,
+        };
+        let offset = self.offset();
+        writeln!(
+            f,
+            "outbound frame #{} diverges from the capture at byte {}",
+            self.index, offset
+        )?;
+        writeln!(f, "expected: {}", expected.to_hex())?;
+        write!(f, "actual:   {}", self.actual.to_hex())
+    }
+}
+
+trait ToHex {
+    fn to_hex(&self) -> String;
+}
+
+impl ToHex for [u8] {
+    fn to_hex(&self) -> String {
+        self.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+struct ReplayState {
+    capture: Capture,
+    next_inbound: usize,
+    next_outbound: usize,
+    last_offset: Duration,
+    divergence: Option<Divergence>,
+}
+
+impl ReplayState {
+    fn next(&self, direction: Direction, from: usize) -> Option<usize> {
+        self.capture
+            .frames(direction)
+            .find(|(index, _)| *index >= from)
+            .map(|(index, _)| index)
+    }
+}
+
+/// Handle for inspecting progress of a [`ReplayConnection`], which remains
+/// available after the connection is split or moved to the code under test
+#[derive(Clone)]
+pub struct ReplayHandle(Arc<Mutex<ReplayState>>);
+
+impl ReplayHandle {
+    /// Returns first divergence of the outbound frames from the capture
+    pub fn divergence(&self) -> Option<Divergence> {
+        self.0
+            .lock()
+            .expect("poisoned replay lock")
+            .divergence
+            .clone()
+    }
+
+    /// Detects whether all captured frames were replayed without divergence
+    pub fn is_complete(&self) -> bool {
+        let state = self.0.lock().expect("poisoned replay lock");
+        state.divergence.is_none()
+            && state.next(Direction::Inbound, state.next_inbound).is_none()
+            && state
+                .next(Direction::Outbound, state.next_outbound)
+                .is_none()
+    }
+}
+
+#[derive(Clone)]
+struct Replayer {
+    state: ReplayHandle,
+    clock: Arc<dyn Clock>,
+    timing: Timing,
+    matching: Matching,
+}
+
+impl Replayer {
+    fn recv(&self) -> Result<Vec<u8>, Error> {
+        let (frame, delay) = {
+            let mut state = self.state.0.lock().expect("poisoned replay lock");
+            if state.divergence.is_some() {
+                return Err(diverged());
+            }
+            let index = match state.next(Direction::Inbound, state.next_inbound)
+            {
+                // The remote peer has disconnected at the end of the capture
+                None => return Err(Error::SocketIo(io::ErrorKind::BrokenPipe)),
+                Some(index) => index,
+            };
+            state.next_inbound = index + 1;
+            let frame = &state.capture.frames[index];
+            let (data, offset) = (frame.data.clone(), frame.offset);
+            let delay = offset.saturating_sub(state.last_offset);
+            state.last_offset = state.last_offset.max(offset);
+            (data, delay)
+        };
+        let delay = match self.timing {
+            Timing::Original => delay,
+            Timing::Compressed(factor) => delay / factor.max(1),
+            Timing::Ignored => Duration::from_secs(0),
+        };
+        if delay > Duration::from_secs(0) {
+            self.clock.sleep(delay);
+        }
+        Ok(frame)
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<usize, Error> {
+        let mut state = self.state.0.lock().expect("poisoned replay lock");
+        if state.divergence.is_some() {
+            return Err(diverged());
+        }
+        let index = state.next(Direction::Outbound, state.next_outbound);
+        let expected =
+            index.map(|index| state.capture.frames[index].data.clone());
+        let matches = match (&expected, self.matching) {
+            (None, _) => false,
+            (Some(expected), Matching::Exact) => expected == frame,
+            (Some(expected), Matching::TypeId) => {
+                expected.get(..2) == frame.get(..2)
+            }
+        };
+        match index {
+            Some(index) if matches => {
+                state.next_outbound = index + 1;
+                Ok(frame.len())
+            }
+            _ => {
+                state.divergence = Some(Divergence {
+                    index: index.unwrap_or(state.capture.frames.len()),
+                    expected,
+                    actual: frame.to_vec(),
+                });
+                Err(diverged())
+            }
+        }
+    }
+}
+
+fn diverged() -> Error {
+    Error::FrameBroken("replayed outbound frames diverge from the capture")
+}
+
+/// Receiving part of [`ReplayConnection`]
+pub struct ReplayRecv(Replayer);
+
+/// Sending part of [`ReplayConnection`]
+pub struct ReplaySend(Replayer);
+
+/// Connection replaying a [`Capture`].
+///
+/// Inbound frames are taken from the capture; once they are exhausted the
+/// connection reports that the remote peer has disconnected. Outbound frames
+/// are compared with the captured outbound frames using the provided
+/// [`Matching`]; on the first divergence the frame is rejected and all
+/// further operations fail, while the divergence is reported by
+/// [`ReplayHandle::divergence`].
+pub struct ReplayConnection {
+    input: ReplayRecv,
+    output: ReplaySend,
+}
+
+impl ReplayConnection {
+    /// Constructs connection replaying the capture, using real time to
+    /// reproduce its timing
+    pub fn new(capture: Capture, timing: Timing, matching: Matching) -> Self {
+        Self::with_clock(capture, timing, matching, SystemClock)
+    }
+
+    /// Constructs connection replaying the capture, using the provided clock
+    /// to reproduce its timing
+    pub fn with_clock(
+        capture: Capture,
+        timing: Timing,
+        matching: Matching,
+        clock: impl Clock + 'static,
+    ) -> Self {
+        let replayer = Replayer {
+            state: ReplayHandle(Arc::new(Mutex::new(ReplayState {
+                capture,
+                next_inbound: 0,
+                next_outbound: 0,
+                last_offset: Duration::from_secs(0),
+                divergence: None,
+            }))),
+            clock: Arc::new(clock),
+            timing,
+            matching,
+        };
+        ReplayConnection {
+            input: ReplayRecv(replayer.clone()),
+            output: ReplaySend(replayer),
+        }
+    }
+
+    /// Returns handle for inspecting progress of the replay
+    pub fn handle(&self) -> ReplayHandle { self.output.0.state.clone() }
+}
+
+impl RecvFrame for ReplayRecv {
+    fn recv_frame(&mut self) -> Result<Vec<u8>, Error> { self.0.recv() }
+
+    fn recv_raw(&mut self, _len: usize) -> Result<Vec<u8>, Error> {
+        self.0.recv()
+    }
+}
+
+impl SendFrame for ReplaySend {
+    fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+        self.0.send(frame)
+    }
+
+    fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+        self.0.send(raw_frame)
+    }
+}
+
+impl DuplexConnection for ReplayConnection {
+    #[inline]
+    fn as_receiver(&mut self) -> &mut dyn RecvFrame { &mut self.input }
+
+    #[inline]
+    fn as_sender(&mut self) -> &mut dyn SendFrame { &mut self.output }
+
+    fn split(self) -> (Box<dyn RecvFrame + Send>, Box<dyn SendFrame + Send>) {
+        (Box::new(self.input), Box::new(self.output))
+    }
+}
+
+impl Bipolar for ReplayConnection {
+    type Left = ReplayRecv;
+    type Right = ReplaySend;
+
+    fn join(input: Self::Left, output: Self::Right) -> Self {
+        ReplayConnection { input, output }
+    }
+
+    fn split(self) -> (Self::Left, Self::Right) { (self.input, self.output) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::super::faulty::MockClock;
+    use super::*;
+
+    /// Remote peer sending scripted frames and collecting the sent ones
+    #[derive(Clone, Default)]
+    struct Script(Arc<Mutex<VecDeque<Vec<u8>>>>, Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Script {
+        fn new(frames: &[&[u8]]) -> Self {
+            let script = Script::default();
+            script
+                .0
+                .lock()
+                .unwrap()
+                .extend(frames.iter().map(|frame| frame.to_vec()));
+            script
+        }
+    }
+
+    impl RecvFrame for Script {
+        fn recv_frame(&mut self) -> Result<Vec<u8>, Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .ok_or(Error::SocketIo(io::ErrorKind::BrokenPipe))
+        }
+
+        fn recv_raw(&mut self, _len: usize) -> Result<Vec<u8>, Error> {
+            self.recv_frame()
+        }
+    }
+
+    impl SendFrame for Script {
+        fn send_frame(&mut self, frame: &[u8]) -> Result<usize, Error> {
+            self.1.lock().unwrap().push(frame.to_vec());
+            Ok(frame.len())
+        }
+
+        fn send_raw(&mut self, raw_frame: &[u8]) -> Result<usize, Error> {
+            self.send_frame(raw_frame)
+        }
+    }
+
+    impl Bipolar for Script {
+        type Left = Script;
+        type Right = Script;
+
+        fn join(left: Script, _: Script) -> Self { left }
+
+        fn split(self) -> (Script, Script) { (self.clone(), self) }
+    }
+
+    /// Logic under test: replies to each message with the same type id and
+    /// the payload reversed, until the peer disconnects
+    fn logic(connection: &mut impl DuplexConnection) -> Result<(), Error> {
+        loop {
+            let mut frame = match connection.as_receiver().recv_frame() {
+                Err(Error::SocketIo(io::ErrorKind::BrokenPipe)) => {
+                    return Ok(())
+                }
+                res => res?,
+            };
+            frame[2..].reverse();
+            connection.as_sender().send_frame(&frame)?;
+        }
+    }
+
+    fn record() -> Capture {
+        let script =
+            Script::new(&[b"\x00\x10init", b"\x00\x12ping", b"\x00\x20"]);
+        let mut connection = RecordingConnection::new(script.clone());
+        logic(&mut connection).unwrap();
+        assert_eq!(*script.1.lock().unwrap(), vec![
+            b"\x00\x10tini".to_vec(),
+            b"\x00\x12gnip".to_vec(),
+            b"\x00\x20".to_vec(),
+        ]);
+        connection.capture()
+    }
+
+    #[test]
+    fn record_replay() {
+        let capture = record();
+        assert_eq!(capture.frames.len(), 6);
+        assert_eq!(
+            capture
+                .frames
+                .iter()
+                .map(|frame| frame.direction)
+                .collect::<Vec<_>>(),
+            [Direction::Inbound, Direction::Outbound].repeat(3)
+        );
+
+        let mut file = vec![];
+        capture.write(&mut file).unwrap();
+        assert_eq!(file[..8], CAPTURE_MAGIC);
+        let capture = Capture::read(&file[..]).unwrap();
+
+        let mut replay =
+            ReplayConnection::new(capture, Timing::Ignored, Matching::Exact);
+        let handle = replay.handle();
+        assert!(!handle.is_complete());
+        logic(&mut replay).unwrap();
+        assert_eq!(handle.divergence(), None);
+        assert!(handle.is_complete());
+    }
+
+    #[test]
+    fn divergence() {
+        let mut capture = record();
+        capture.frames[3].data = b"\x00\x12gnap".to_vec();
+
+        let mut replay = ReplayConnection::new(
+            capture.clone(),
+            Timing::Ignored,
+            Matching::Exact,
+        );
+        let handle = replay.handle();
+        assert_eq!(logic(&mut replay), Err(diverged()));
+        let divergence = handle.divergence().unwrap();
+        assert_eq!(divergence.index, 3);
+        assert_eq!(divergence.offset(), 4);
+        assert_eq!(
+            divergence.to_string(),
+            "outbound frame #3 diverges from the capture at byte 4\nexpected: \
+             0012676e6170\nactual:   0012676e6970"
+        );
+        assert!(!handle.is_complete());
+        // The replay stops at the first divergence
+        assert_eq!(replay.as_receiver().recv_frame(), Err(diverged()));
+
+        // Only type ids are checked in the tolerant mode
+        let mut replay =
+            ReplayConnection::new(capture, Timing::Ignored, Matching::TypeId);
+        let handle = replay.handle();
+        logic(&mut replay).unwrap();
+        assert!(handle.is_complete());
+    }
+
+    #[test]
+    fn unexpected_frame() {
+        let mut capture = record();
+        capture.frames.pop();
+
+        let mut replay =
+            ReplayConnection::new(capture, Timing::Ignored, Matching::Exact);
+        let handle = replay.handle();
+        assert_eq!(logic(&mut replay), Err(diverged()));
+        assert_eq!(handle.divergence().unwrap(), Divergence {
+            index: 5,
+            expected: None,
+            actual: b"\x00\x20".to_vec()
+        });
+    }
+
+    #[test]
+    fn timing() {
+        let frame = |offset, direction| CapturedFrame {
+            direction,
+            offset: Duration::from_millis(offset),
+            data: b"\x00\x10".to_vec(),
+        };
+        let capture = Capture {
+            frames: vec![
+                frame(100, Direction::Inbound),
+                frame(150, Direction::Outbound),
+                frame(400, Direction::Inbound),
+                frame(450, Direction::Outbound),
+            ],
+        };
+        let replay = |timing| {
+            let clock = MockClock::new();
+            let mut replay = ReplayConnection::with_clock(
+                capture.clone(),
+                timing,
+                Matching::Exact,
+                clock.clone(),
+            );
+            logic(&mut replay).unwrap();
+            assert!(replay.handle().is_complete());
+            clock.elapsed()
+        };
+        assert_eq!(replay(Timing::Original), Duration::from_millis(400));
+        assert_eq!(replay(Timing::Compressed(4)), Duration::from_millis(100));
+        assert_eq!(replay(Timing::Ignored), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn invalid_file() {
+        assert_eq!(
+            Capture::read(&b"INET2CP\x02"[..]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let mut file = vec![];
+        record().write(&mut file).unwrap();
+        file.pop();
+        assert_eq!(
+            Capture::read(&file[..]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
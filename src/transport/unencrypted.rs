@@ -19,6 +19,7 @@ use std::net::{TcpListener, TcpStream};
 use amplify::Bipolar;
 use inet2_addr::InetSocketAddr;
 
+use super::guard::ConnectGuard;
 use super::{DuplexConnection, Error, RecvFrame, SendFrame};
 use crate::transport::connect::{self, TcpInetStream};
 
@@ -36,6 +37,16 @@ impl Connection {
         Ok(Connection::with(stream, inet_addr))
     }
 
+    /// Connects to a remote socket if the `guard` allows it; see
+    /// [`TcpInetStream::connect_guarded`].
+    pub fn connect_guarded(
+        inet_addr: InetSocketAddr,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, Error> {
+        let stream = TcpStream::connect_guarded(inet_addr, None, guard)?;
+        Ok(Connection::with(stream, inet_addr))
+    }
+
     pub fn accept(listener: &TcpListener) -> Result<Self, Error> {
         let (stream, remote_addr) = TcpStream::accept_inet_socket(listener)?;
         Ok(Connection::with(stream, remote_addr.into()))
@@ -72,6 +83,9 @@ impl DuplexConnection for Stream {
         let (r, s) = Bipolar::split(self);
         (Box::new(r), Box::new(s))
     }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { SendFrame::max_frame_size(self) }
 }
 
 impl RecvFrame for Stream {
@@ -94,4 +108,12 @@ impl SendFrame for Stream {
     fn send_raw(&mut self, data: &[u8]) -> Result<usize, Error> {
         self.0.send_raw(data)
     }
+
+    #[inline]
+    fn max_frame_size(&self) -> usize { self.0.max_frame_size() }
+
+    #[inline]
+    fn shutdown(&mut self) -> Result<(), Error> {
+        SendFrame::shutdown(&mut self.0)
+    }
 }
@@ -11,15 +11,29 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/MIT>.
 
+use std::convert::TryFrom;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::str::FromStr;
 
 use amplify::{Bipolar, Wrapper};
-use inet2_addr::ServiceAddr;
+use inet2_addr::{NodeId, ServiceAddr};
 
+use super::guard::{allow_service, ConnectGuard};
 use super::{DuplexConnection, RecvFrame, RoutedFrame, SendFrame};
+use crate::presentation::TypeId;
 use crate::transport;
 
+/// Length of the topic frame produced by [`type_topic`]
+pub const TYPE_TOPIC_LEN: usize = 2;
+
+/// Returns topic for the messages of the given type used in the topic
+/// envelope mode of PUB/SUB connections (see
+/// [`Connection::enable_topic_envelope`]): type id as big-endian bytes.
+#[inline]
+pub fn type_topic(type_id: TypeId) -> [u8; TYPE_TOPIC_LEN] {
+    type_id.into_inner().to_be_bytes()
+}
+
 /// API type for node-to-node communications used by ZeroMQ
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
 #[repr(u8)]
@@ -118,11 +132,26 @@ pub enum ZmqSocketType {
 }
 
 /// Unknown [`ZmqSocketType`] string
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Display, Error)]
-#[display(Debug)]
-pub struct UnknownApiType;
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(
+    "unknown ZMQ socket type `{0}`; valid values are PULL, PUSH, REQ, REP, \
+     PUB, SUB, ROUTER(bind) and ROUTER(connect)"
+)]
+pub struct UnknownApiType(pub String);
 
 impl ZmqSocketType {
+    /// All known socket types
+    pub const ALL: [ZmqSocketType; 8] = [
+        ZmqSocketType::Pull,
+        ZmqSocketType::Push,
+        ZmqSocketType::Req,
+        ZmqSocketType::Rep,
+        ZmqSocketType::Pub,
+        ZmqSocketType::Sub,
+        ZmqSocketType::RouterBind,
+        ZmqSocketType::RouterConnect,
+    ];
+
     /// Returns [`zmq::SocketType`] corresponding to the given [`ZmqSocketType`]
     pub fn socket_type(&self) -> zmq::SocketType {
         match self {
@@ -155,20 +184,126 @@ impl FromStr for ZmqSocketType {
     type Err = UnknownApiType;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.to_lowercase();
-        vec![
-            ZmqSocketType::Push,
-            ZmqSocketType::Pull,
-            ZmqSocketType::Req,
-            ZmqSocketType::Rep,
-            ZmqSocketType::Pub,
-            ZmqSocketType::Sub,
-            ZmqSocketType::RouterBind,
-            ZmqSocketType::RouterConnect,
-        ]
-        .into_iter()
-        .find(|api| api.to_string() == s)
-        .ok_or(UnknownApiType)
+        ZmqSocketType::ALL
+            .into_iter()
+            .find(|api| api.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownApiType(s.to_owned()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ZmqSocketType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ZmqSocketType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        ZmqSocketType::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Errors constructing [`ZmqIdentity`]
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub enum IdentityError {
+    /// ZMQ socket identity can't be empty
+    Empty,
+
+    /// ZMQ socket identity of {0} bytes exceeds the maximum of 255 bytes
+    TooLong(usize),
+
+    /// ZMQ socket identity can't start with a zero byte, which is reserved
+    /// by libzmq for automatically generated identities
+    ZeroPrefix,
+}
+
+/// ZMQ socket identity used for addressing peers over ROUTER sockets,
+/// guaranteed to satisfy libzmq constraints: it is non-empty, at most 255
+/// bytes long and does not start with a zero byte.
+///
+/// Identities derived from [`NodeId`] consist of 33 bytes of the compressed
+/// node public key, which always starts with either `0x02` or `0x03` byte and
+/// thus is always a valid identity.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ZmqIdentity(Vec<u8>);
+
+impl ZmqIdentity {
+    /// Maximum identity length supported by libzmq
+    pub const MAX_LEN: usize = 255;
+
+    /// Constructs identity from bytes, checking libzmq constraints.
+    pub fn with(identity: impl Into<Vec<u8>>) -> Result<Self, IdentityError> {
+        let identity = identity.into();
+        match (identity.len(), identity.first()) {
+            (0, _) => Err(IdentityError::Empty),
+            (len, _) if len > Self::MAX_LEN => Err(IdentityError::TooLong(len)),
+            (_, Some(0)) => Err(IdentityError::ZeroPrefix),
+            _ => Ok(ZmqIdentity(identity)),
+        }
+    }
+
+    /// Returns byte representation of the identity.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] { &self.0 }
+
+    /// Converts identity into bytes.
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> { self.0 }
+}
+
+impl AsRef<[u8]> for ZmqIdentity {
+    #[inline]
+    fn as_ref(&self) -> &[u8] { &self.0 }
+}
+
+impl Display for ZmqIdentity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<NodeId> for ZmqIdentity {
+    fn from(node_id: NodeId) -> Self {
+        ZmqIdentity(node_id.public_key().serialize().to_vec())
+    }
+}
+
+impl TryFrom<Vec<u8>> for ZmqIdentity {
+    type Error = IdentityError;
+
+    #[inline]
+    fn try_from(identity: Vec<u8>) -> Result<Self, Self::Error> {
+        ZmqIdentity::with(identity)
+    }
+}
+
+impl TryFrom<&[u8]> for ZmqIdentity {
+    type Error = IdentityError;
+
+    #[inline]
+    fn try_from(identity: &[u8]) -> Result<Self, Self::Error> {
+        ZmqIdentity::with(identity)
+    }
+}
+
+impl From<IdentityError> for transport::Error {
+    fn from(_: IdentityError) -> Self {
+        transport::Error::from(zmq::Error::EINVAL)
     }
 }
 
@@ -230,6 +365,7 @@ impl Display for Error {
 pub struct WrappedSocket {
     api_type: ZmqSocketType,
     socket: zmq::Socket,
+    enveloped: bool,
 }
 
 pub struct Connection {
@@ -249,7 +385,8 @@ impl Connection {
     ) -> Result<Self, transport::Error> {
         let socket = context.socket(api_type.socket_type())?;
         if let Some(identity) = identity {
-            socket.set_identity(identity.as_ref())?;
+            let identity = ZmqIdentity::with(identity.as_ref())?;
+            socket.set_identity(identity.as_bytes())?;
         }
         let endpoint = remote.zmq_connect_string();
         match api_type {
@@ -287,6 +424,38 @@ impl Connection {
         })
     }
 
+    /// Connects to the `remote` endpoint as [`Connection::connect`] does, but
+    /// consults the `guard` first on each TCP endpoint the connection would
+    /// connect to (binding to a local endpoint is not checked).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`transport::Error::ConnectionDenied`] before creating any
+    /// socket if the guard denies one of the endpoints.
+    pub fn connect_guarded(
+        api_type: ZmqSocketType,
+        remote: &ServiceAddr,
+        local: Option<&ServiceAddr>,
+        identity: Option<impl AsRef<[u8]>>,
+        context: &zmq::Context,
+        guard: &dyn ConnectGuard,
+    ) -> Result<Self, transport::Error> {
+        match api_type {
+            ZmqSocketType::Pull
+            | ZmqSocketType::Rep
+            | ZmqSocketType::Pub
+            | ZmqSocketType::RouterBind => {}
+            ZmqSocketType::Push
+            | ZmqSocketType::Req
+            | ZmqSocketType::Sub
+            | ZmqSocketType::RouterConnect => allow_service(guard, remote)?,
+        }
+        if let (ZmqSocketType::Pull, Some(local)) = (api_type, local) {
+            allow_service(guard, local)?;
+        }
+        Self::connect(api_type, remote, local, identity, context)
+    }
+
     pub fn with_socket(api_type: ZmqSocketType, socket: zmq::Socket) -> Self {
         Self {
             api_type,
@@ -299,6 +468,61 @@ impl Connection {
     #[inline]
     pub(crate) fn as_socket(&self) -> &zmq::Socket { self.input.as_socket() }
 
+    /// Switches PUB or SUB connection into the topic envelope mode, where
+    /// each message is sent as a two-part ZMQ message: a topic frame followed
+    /// by the message frame. This allows subscribers to filter messages on
+    /// the publisher side (see [`Connection::subscribe`]).
+    ///
+    /// The mode must be enabled on both publisher and subscriber: peers not
+    /// using the envelope will misinterpret topic frames as messages.
+    pub fn enable_topic_envelope(&mut self) -> Result<(), transport::Error> {
+        match self.api_type {
+            ZmqSocketType::Pub | ZmqSocketType::Sub => {
+                self.input.enveloped = true;
+                Ok(())
+            }
+            _ => Err(transport::Error::from(zmq::Error::ENOTSUP)),
+        }
+    }
+
+    /// Detects whether the connection uses topic envelope mode
+    #[inline]
+    pub fn is_topic_enveloped(&self) -> bool { self.input.enveloped }
+
+    /// Subscribes SUB connection to the messages having topic starting with
+    /// `prefix`; an empty prefix subscribes to all messages.
+    pub fn subscribe(&mut self, prefix: &[u8]) -> Result<(), transport::Error> {
+        if self.api_type != ZmqSocketType::Sub {
+            return Err(transport::Error::from(zmq::Error::ENOTSUP));
+        }
+        self.input.socket.set_subscribe(prefix)?;
+        Ok(())
+    }
+
+    /// Subscribes SUB connection in topic envelope mode to the messages of
+    /// the given type
+    #[inline]
+    pub fn subscribe_type(
+        &mut self,
+        type_id: TypeId,
+    ) -> Result<(), transport::Error> {
+        self.subscribe(&type_topic(type_id))
+    }
+
+    /// Publishes frame under the given topic. Requires PUB connection in the
+    /// topic envelope mode.
+    pub fn send_topic_frame(
+        &mut self,
+        topic: &[u8],
+        data: &[u8],
+    ) -> Result<usize, transport::Error> {
+        if self.api_type != ZmqSocketType::Pub || !self.input.enveloped {
+            return Err(transport::Error::from(zmq::Error::ENOTSUP));
+        }
+        self.input.socket.send_multipart(&[topic, data], 0)?;
+        Ok(data.len())
+    }
+
     #[inline]
     pub(crate) fn as_socket_mut(&mut self) -> &mut zmq::Socket {
         self.input.as_socket_mut()
@@ -315,12 +539,14 @@ impl Connection {
         } else {
             return Err(Error::from(zmq::Error::EINVAL));
         };
+        let identity = ZmqIdentity::with(identity.as_ref())
+            .map_err(|_| Error::from(zmq::Error::EINVAL))?;
         let socket = self.input.as_socket_mut();
         let endpoint = addr.zmq_connect_string();
         socket.disconnect(&endpoint)?;
         *socket = context.socket(self.api_type.socket_type())?;
         socket
-            .set_identity(identity.as_ref())
+            .set_identity(identity.as_bytes())
             .map_err(Error::from)?;
         match self.api_type {
             ZmqSocketType::Pull
@@ -339,7 +565,11 @@ impl Connection {
 impl WrappedSocket {
     #[inline]
     fn with_socket(api_type: ZmqSocketType, socket: zmq::Socket) -> Self {
-        Self { api_type, socket }
+        Self {
+            api_type,
+            socket,
+            enveloped: false,
+        }
     }
 
     #[inline]
@@ -349,6 +579,24 @@ impl WrappedSocket {
     pub(crate) fn as_socket_mut(&mut self) -> &mut zmq::Socket {
         &mut self.socket
     }
+
+    /// Receives two-part message of the topic envelope mode, returning the
+    /// message part without the topic
+    fn recv_enveloped(&mut self) -> Result<Vec<u8>, transport::Error> {
+        let mut multipart = self.socket.recv_multipart(0)?.into_iter();
+        multipart.next().ok_or(transport::Error::FrameBroken(
+            "no topic part in ZMQ enveloped message",
+        ))?;
+        let msg = multipart.next().ok_or(transport::Error::FrameBroken(
+            "no message part in ZMQ enveloped message",
+        ))?;
+        if multipart.count() > 0 {
+            return Err(transport::Error::FrameBroken(
+                "excessive parts in ZMQ enveloped message",
+            ));
+        }
+        Ok(msg)
+    }
 }
 
 impl DuplexConnection for Connection {
@@ -426,10 +674,16 @@ impl Bipolar for Connection {
 impl RecvFrame for WrappedSocket {
     #[inline]
     fn recv_frame(&mut self) -> Result<Vec<u8>, transport::Error> {
+        if self.enveloped {
+            return self.recv_enveloped();
+        }
         Ok(self.socket.recv_bytes(0)?)
     }
 
     fn recv_raw(&mut self, _len: usize) -> Result<Vec<u8>, transport::Error> {
+        if self.enveloped {
+            return self.recv_enveloped();
+        }
         // NB: Here we can't guarantee the actual amount of bytes we receive
         Ok(self.socket.recv_bytes(0)?)
     }
@@ -461,11 +715,15 @@ impl RecvFrame for WrappedSocket {
 impl SendFrame for WrappedSocket {
     #[inline]
     fn send_frame(&mut self, data: &[u8]) -> Result<usize, transport::Error> {
-        self.socket.send(data, 0)?;
-        Ok(data.len())
+        self.send_raw(data)
     }
 
     fn send_raw(&mut self, data: &[u8]) -> Result<usize, transport::Error> {
+        if self.enveloped {
+            return Err(transport::Error::FrameBroken(
+                "topic envelope mode requires a topic for each message",
+            ));
+        }
         self.socket.send(data, 0)?;
         Ok(data.len())
     }
@@ -482,3 +740,108 @@ impl SendFrame for WrappedSocket {
         Ok(data.len())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NODE_ID: &str =
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    fn socket_type_str_roundtrip() {
+        for api in ZmqSocketType::ALL {
+            let s = api.to_string();
+            assert_eq!(ZmqSocketType::from_str(&s), Ok(api));
+            assert_eq!(ZmqSocketType::from_str(&s.to_lowercase()), Ok(api));
+            assert_eq!(ZmqSocketType::from_str(&s.to_uppercase()), Ok(api));
+        }
+        assert_eq!(
+            ZmqSocketType::from_str("router"),
+            Err(UnknownApiType(s!("router")))
+        );
+        assert_eq!(
+            UnknownApiType(s!("dealer")).to_string(),
+            "unknown ZMQ socket type `dealer`; valid values are PULL, PUSH, \
+             REQ, REP, PUB, SUB, ROUTER(bind) and ROUTER(connect)"
+        );
+    }
+
+    #[test]
+    fn identity_constraints() {
+        assert_eq!(ZmqIdentity::with(vec![]), Err(IdentityError::Empty));
+        assert_eq!(
+            ZmqIdentity::with(vec![0u8, 1]),
+            Err(IdentityError::ZeroPrefix)
+        );
+        assert_eq!(
+            ZmqIdentity::with(vec![1u8; ZmqIdentity::MAX_LEN + 1]),
+            Err(IdentityError::TooLong(256))
+        );
+        assert!(ZmqIdentity::with(vec![1u8; ZmqIdentity::MAX_LEN]).is_ok());
+        assert_eq!(ZmqIdentity::with(&[1u8][..]).unwrap().as_bytes(), [1]);
+        assert_eq!(
+            ZmqIdentity::try_from(b"rx".to_vec()).unwrap().to_string(),
+            "7278"
+        );
+    }
+
+    #[test]
+    fn identity_from_node_id() {
+        let node_id = NodeId::from_str(NODE_ID).unwrap();
+        let identity = ZmqIdentity::from(node_id);
+        assert_eq!(identity.as_bytes().len(), 33);
+        assert_eq!(identity.to_string(), node_id.to_string());
+        assert_eq!(
+            ZmqIdentity::with(identity.clone().into_bytes()),
+            Ok(identity)
+        );
+    }
+
+    #[test]
+    fn invalid_identity_rejected() {
+        let ctx = zmq::Context::new();
+        let addr = ServiceAddr::inproc_unique("invalid-identity");
+        assert_eq!(
+            Connection::connect(
+                ZmqSocketType::RouterBind,
+                &addr,
+                None,
+                Some([0u8, 1]),
+                &ctx
+            )
+            .err(),
+            Some(transport::Error::Zmq(Error::from(zmq::Error::EINVAL)))
+        );
+
+        let mut connection = Connection::connect(
+            ZmqSocketType::RouterBind,
+            &addr,
+            None,
+            Some(b"valid"),
+            &ctx,
+        )
+        .unwrap();
+        assert_eq!(
+            connection.set_identity(&[0u8; 0], &ctx),
+            Err(Error::from(zmq::Error::EINVAL))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn socket_type_serde() {
+        use serde::de::value::{Error, StrDeserializer};
+        use serde::de::IntoDeserializer;
+        use serde::Deserialize;
+
+        for api in ZmqSocketType::ALL {
+            let s = api.to_string().to_lowercase();
+            let deserializer: StrDeserializer<Error> =
+                s.as_str().into_deserializer();
+            assert_eq!(ZmqSocketType::deserialize(deserializer), Ok(api));
+        }
+        let deserializer: StrDeserializer<Error> = "esb".into_deserializer();
+        assert!(ZmqSocketType::deserialize(deserializer).is_err());
+    }
+}
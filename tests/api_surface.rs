@@ -0,0 +1,42 @@
+// Every item of the prelude is imported by name, so removing or renaming any
+// of them breaks this test. Additions to the prelude should be listed here
+// as well.
+#![allow(unused_imports)]
+
+use internet2::prelude::{
+    AddrParseError, BrontideSession, BrontozaurSession, CreateUnmarshaller,
+    DuplexConnection, HandshakeError, InetAddr, InetSocketAddr, LocalNode,
+    NodeAddr, NodeAddrParseError, NodeId, PartialNodeAddr, PartialSocketAddr,
+    Payload, PresentationError, RecvMessage, RoutedFrame, SendMessage,
+    SendRecvMessage, ServerAddr, ServiceAddr, Session, SharedSession, Split,
+    TransportError, TypeId, TypedEnum, UnknownTypeError, Unmarshall,
+    Unmarshaller, VersionError, VersionRange, VersionedSession,
+};
+#[cfg(feature = "zmq")]
+use internet2::prelude::{
+    LocalSession, RpcSession, ZmqConnectionType, ZmqSocketType,
+};
+
+#[test]
+fn prelude_aliases() {
+    fn same<T>(val: T) -> T { val }
+
+    let err: TransportError = internet2::transport::Error::ServiceOffline;
+    let _: internet2::transport::Error = same(err);
+    let err: PresentationError =
+        internet2::presentation::Error::UnknownDataType;
+    let _: internet2::presentation::Error = same(err);
+    let _: inet2_addr::InetAddr = same(InetAddr::default());
+}
+
+#[test]
+fn prelude_glob_import() {
+    use internet2::prelude::*;
+
+    let addr = InetSocketAddr::from(std::net::SocketAddr::from((
+        [127, 0, 0, 1],
+        9735,
+    )));
+    assert_eq!(addr.to_string(), "127.0.0.1:9735");
+    assert!(VersionRange::new(1, 2).is_some());
+}
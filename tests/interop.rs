@@ -0,0 +1,422 @@
+//! Conformance harness for Brontide (BOLT-8) sessions against external
+//! Lightning node implementations (lnd, Core Lightning etc).
+//!
+//! Tests talking to an external node are ignored by default and are configured
+//! with environment variables:
+//!
+//! ```sh
+//! # Connect to the external node
+//! INTEROP_PEER=<node_id>@<host>:<port> cargo test --features keygen \
+//!     --test interop -- --ignored --nocapture outbound
+//!
+//! # Listen for the inbound connection from the external node
+//! INTEROP_LISTEN=127.0.0.1:9735 cargo test --features keygen \
+//!     --test interop -- --ignored --nocapture reflector
+//! ```
+//!
+//! Other variables are optional:
+//! - `INTEROP_PINGS`: number of ping-pong round trips (600 by default, which
+//!   makes both sides pass through noise key rotation);
+//! - `INTEROP_PONG_BYTES`: size of requested pongs (1000 bytes by default);
+//! - `INTEROP_DURATION`: time limit for the session in seconds (120 by
+//!   default);
+//! - `INTEROP_FEATURES`: comma-separated feature bits sent in `init` message
+//!   (`9,13` by default, i.e. optional `var_onion_optin` and
+//!   `option_static_remotekey`).
+
+use std::net::{Ipv4Addr, TcpListener};
+use std::str::FromStr;
+
+use inet2_addr::{LocalNode, NodeAddr};
+use internet2::session::BrontideSession;
+use secp256k1::Secp256k1;
+
+#[test]
+#[ignore]
+fn outbound() {
+    let config = harness::Config::from_env();
+    let remote = std::env::var("INTEROP_PEER")
+        .expect("INTEROP_PEER must contain <node_id>@<host>:<port>");
+    let remote = NodeAddr::from_str(&remote).expect("invalid INTEROP_PEER");
+    let local = LocalNode::new(&Secp256k1::new());
+
+    println!("Connecting to {} as {}", remote, local.node_id());
+    let mut session = BrontideSession::connect(local.private_key(), remote)
+        .expect("handshake failed");
+    let report = harness::run(&mut session, &config);
+
+    println!("{}", report);
+    assert!(report.passed());
+}
+
+#[test]
+#[ignore]
+fn reflector() {
+    let mut config = harness::Config::from_env();
+    config.serve = true;
+    let addr = std::env::var("INTEROP_LISTEN")
+        .unwrap_or_else(|_| "127.0.0.1:9735".to_owned());
+    let listener = TcpListener::bind(&addr).expect("unable to bind");
+    let local = LocalNode::new(&Secp256k1::new());
+
+    println!("Waiting for connection at {}@{}", local.node_id(), addr);
+    let mut session = BrontideSession::accept(local.private_key(), &listener)
+        .expect("handshake failed");
+    let report = harness::run(&mut session, &config);
+
+    println!("{}", report);
+    assert!(report.passed());
+}
+
+/// Runs the harness against itself, so it can be checked without an external
+/// node
+#[test]
+fn self_check() {
+    let secp = Secp256k1::new();
+    let node_rx = LocalNode::new(&secp);
+    let node_tx = LocalNode::new(&secp);
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let remote =
+        NodeAddr::new(node_rx.node_id(), listener.local_addr().unwrap());
+    let config = harness::Config::default();
+
+    let reflector_config = harness::Config {
+        pings: 0,
+        serve: true,
+        ..config.clone()
+    };
+    let reflector = std::thread::spawn(move || {
+        let mut session =
+            BrontideSession::accept(node_rx.private_key(), &listener).unwrap();
+        harness::run(&mut session, &reflector_config)
+    });
+    let mut session =
+        BrontideSession::connect(node_tx.private_key(), remote).unwrap();
+    let report = harness::run(&mut session, &config);
+    drop(session);
+    let reflector = reflector.join().unwrap();
+
+    assert!(report.passed(), "{}", report);
+    assert!(reflector.passed(), "{}", reflector);
+    assert_eq!(report.pongs_received, config.pings);
+    assert_eq!(reflector.pings_answered, config.pings);
+}
+
+/// Minimal subset of BOLT-1 messages required for the session conformance
+/// checks
+mod bolt1 {
+    use std::convert::TryInto;
+
+    pub const INIT: u16 = 16;
+    pub const ERROR: u16 = 17;
+    pub const PING: u16 = 18;
+    pub const PONG: u16 = 19;
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum Message {
+        Init { features: Vec<u8> },
+        Error(String),
+        Ping { num_pong_bytes: u16 },
+        Pong { len: u16 },
+        Other(u16),
+    }
+
+    pub fn init(feature_bits: &[u16]) -> Vec<u8> {
+        let len = feature_bits
+            .iter()
+            .max()
+            .map(|bit| bit / 8 + 1)
+            .unwrap_or(0);
+        let mut features = vec![0u8; len as usize];
+        for bit in feature_bits {
+            features[(len - 1 - bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        let mut msg = INIT.to_be_bytes().to_vec();
+        // No global features
+        msg.extend(0u16.to_be_bytes());
+        msg.extend(len.to_be_bytes());
+        msg.extend(features);
+        msg
+    }
+
+    pub fn ping(num_pong_bytes: u16) -> Vec<u8> {
+        let mut msg = PING.to_be_bytes().to_vec();
+        msg.extend(num_pong_bytes.to_be_bytes());
+        msg.extend(0u16.to_be_bytes());
+        msg
+    }
+
+    pub fn pong(len: u16) -> Vec<u8> {
+        let mut msg = PONG.to_be_bytes().to_vec();
+        msg.extend(len.to_be_bytes());
+        msg.extend(vec![0u8; len as usize]);
+        msg
+    }
+
+    pub fn parse(msg: &[u8]) -> Result<Message, &'static str> {
+        let mut reader = Reader(msg);
+        Ok(match reader.u16()? {
+            INIT => {
+                let global_len = reader.u16()?;
+                let global = reader.bytes(global_len)?.to_vec();
+                let len = reader.u16()?;
+                let mut features = reader.bytes(len)?.to_vec();
+                // Global features are merged into the local ones by BOLT-1
+                if global.len() > features.len() {
+                    let mut padded = vec![0u8; global.len() - features.len()];
+                    padded.extend(features);
+                    features = padded;
+                }
+                let offset = features.len() - global.len();
+                for (no, byte) in global.into_iter().enumerate() {
+                    features[offset + no] |= byte;
+                }
+                Message::Init { features }
+            }
+            ERROR => {
+                reader.bytes(32)?;
+                let len = reader.u16()?;
+                let data = reader.bytes(len)?;
+                Message::Error(String::from_utf8_lossy(data).into_owned())
+            }
+            PING => {
+                let num_pong_bytes = reader.u16()?;
+                let len = reader.u16()?;
+                reader.bytes(len)?;
+                Message::Ping { num_pong_bytes }
+            }
+            PONG => {
+                let len = reader.u16()?;
+                reader.bytes(len)?;
+                Message::Pong { len }
+            }
+            other => Message::Other(other),
+        })
+    }
+
+    struct Reader<'a>(&'a [u8]);
+
+    impl<'a> Reader<'a> {
+        fn bytes(&mut self, len: u16) -> Result<&'a [u8], &'static str> {
+            let len = len as usize;
+            if self.0.len() < len {
+                return Err("message is truncated");
+            }
+            let (data, rest) = self.0.split_at(len);
+            self.0 = rest;
+            Ok(data)
+        }
+
+        fn u16(&mut self) -> Result<u16, &'static str> {
+            let data = self.bytes(2)?;
+            Ok(u16::from_be_bytes(data.try_into().expect("fixed size")))
+        }
+    }
+}
+
+/// Session conformance checks: `init` exchange followed by ping-pong round
+/// trips
+mod harness {
+    use std::fmt::{self, Display, Formatter};
+    use std::time::{Duration, Instant};
+
+    use internet2::SendRecvMessage;
+
+    use super::bolt1::{self, Message};
+
+    #[derive(Clone, Debug)]
+    pub struct Config {
+        /// Number of ping-pong round trips initiated by the harness
+        pub pings: usize,
+        /// Number of bytes requested in each pong
+        pub pong_bytes: u16,
+        /// Time limit for the whole session
+        pub duration: Duration,
+        /// Feature bits sent in `init` message
+        pub features: Vec<u16>,
+        /// Whether to keep answering peer pings after own round trips until
+        /// the peer disconnects, stays idle for longer than socket read
+        /// timeout, or the time limit is reached
+        pub serve: bool,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                pings: 600,
+                pong_bytes: 1000,
+                duration: Duration::from_secs(120),
+                features: vec![9, 13],
+                serve: false,
+            }
+        }
+    }
+
+    impl Config {
+        pub fn from_env() -> Self {
+            fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+                std::env::var(name).ok().map(|val| {
+                    val.parse()
+                        .unwrap_or_else(|_| panic!("invalid {} value", name))
+                })
+            }
+
+            let default = Config::default();
+            Config {
+                pings: var("INTEROP_PINGS").unwrap_or(default.pings),
+                pong_bytes: var("INTEROP_PONG_BYTES")
+                    .unwrap_or(default.pong_bytes),
+                duration: var("INTEROP_DURATION")
+                    .map(Duration::from_secs)
+                    .unwrap_or(default.duration),
+                features: std::env::var("INTEROP_FEATURES")
+                    .map(|val| {
+                        val.split(',')
+                            .map(|bit| {
+                                bit.trim()
+                                    .parse()
+                                    .expect("invalid INTEROP_FEATURES value")
+                            })
+                            .collect()
+                    })
+                    .unwrap_or(default.features),
+                serve: false,
+            }
+        }
+    }
+
+    #[derive(Clone, Default, Debug)]
+    pub struct Report {
+        /// Features received from the peer in `init` message
+        pub peer_features: Option<Vec<u8>>,
+        pub pings_sent: usize,
+        pub pongs_received: usize,
+        /// Number of pings received from the peer and answered
+        pub pings_answered: usize,
+        pub failure: Option<String>,
+    }
+
+    impl Report {
+        pub fn passed(&self) -> bool {
+            self.peer_features.is_some()
+                && self.failure.is_none()
+                && self.pongs_received == self.pings_sent
+        }
+    }
+
+    impl Display for Report {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            f.write_str(if self.passed() { "PASS" } else { "FAIL" })?;
+            if let Some(ref failure) = self.failure {
+                write!(f, ": {}", failure)?;
+            }
+            match self.peer_features {
+                Some(ref features) => {
+                    write!(f, "\n  init: received, features ")?;
+                    for byte in features {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                None => write!(f, "\n  init: not received")?,
+            }
+            write!(
+                f,
+                "\n  pongs received: {}/{}\n  peer pings answered: {}",
+                self.pongs_received, self.pings_sent, self.pings_answered
+            )
+        }
+    }
+
+    /// Runs all checks over an established session and reports the results
+    pub fn run(session: &mut impl SendRecvMessage, config: &Config) -> Report {
+        let mut report = Report::default();
+        if let Err(failure) = exchange(session, config, &mut report) {
+            report.failure = Some(failure);
+        }
+        report
+    }
+
+    fn exchange(
+        session: &mut impl SendRecvMessage,
+        config: &Config,
+        report: &mut Report,
+    ) -> Result<(), String> {
+        let start = Instant::now();
+        send(session, &bolt1::init(&config.features))?;
+        while report.peer_features.is_none() {
+            match recv(session, report)? {
+                Message::Init { features } => {
+                    report.peer_features = Some(features)
+                }
+                msg => return Err(format!("{:?} received before init", msg)),
+            }
+        }
+
+        while report.pongs_received < config.pings {
+            if start.elapsed() > config.duration {
+                return Err("time limit reached".to_owned());
+            }
+            send(session, &bolt1::ping(config.pong_bytes))?;
+            report.pings_sent += 1;
+            match recv(session, report)? {
+                Message::Pong { len } if len == config.pong_bytes => {
+                    report.pongs_received += 1
+                }
+                Message::Pong { len } => {
+                    return Err(format!(
+                        "pong of {} bytes instead of {}",
+                        len, config.pong_bytes
+                    ))
+                }
+                msg => return Err(format!("unexpected message {:?}", msg)),
+            }
+        }
+
+        while config.serve && start.elapsed() < config.duration {
+            match recv(session, report) {
+                Ok(msg) => return Err(format!("unexpected message {:?}", msg)),
+                // Peer has disconnected or stays idle
+                Err(_) => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn send(
+        session: &mut impl SendRecvMessage,
+        msg: &[u8],
+    ) -> Result<(), String> {
+        session
+            .send_raw_message(msg)
+            .map(|_| ())
+            .map_err(|err| format!("unable to send message: {}", err))
+    }
+
+    /// Receives next message answering peer pings and skipping odd messages
+    fn recv(
+        session: &mut impl SendRecvMessage,
+        report: &mut Report,
+    ) -> Result<Message, String> {
+        loop {
+            let msg = session
+                .recv_raw_message()
+                .map_err(|err| format!("unable to receive message: {}", err))?;
+            match bolt1::parse(&msg)
+                .map_err(|err| format!("malformed message: {}", err))?
+            {
+                Message::Ping { num_pong_bytes } => {
+                    // BOLT-1: pings requesting 65532 bytes or more are ignored
+                    if num_pong_bytes < 65532 {
+                        send(session, &bolt1::pong(num_pong_bytes))?;
+                        report.pings_answered += 1;
+                    }
+                }
+                Message::Error(err) => {
+                    return Err(format!("peer sent error: {}", err))
+                }
+                Message::Other(ty) if ty % 2 == 1 => {}
+                msg => return Ok(msg),
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::net::{Ipv4Addr, TcpListener};
+use std::time::Duration;
+
+use inet2_addr::LocalNode;
+use internet2::session::{MinimalPeer, MinimalPeerError};
+use secp256k1::Secp256k1;
+
+/// Custom odd message type used by the test
+const MSG_TYPE_CUSTOM: u16 = 32769;
+
+#[test]
+fn loopback() {
+    let secp = Secp256k1::new();
+    let server_node = LocalNode::new(&secp);
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let remote = format!(
+        "{}@{}",
+        server_node.node_id(),
+        listener.local_addr().unwrap()
+    );
+
+    let server = std::thread::spawn(move || {
+        let mut peer =
+            MinimalPeer::accept(&listener, server_node, &[1, 13]).unwrap();
+        let msg = peer.recv_message().unwrap();
+        peer.send_message(&msg).unwrap();
+        // The client closes the connection after receiving the echo
+        assert!(matches!(
+            peer.recv_message(),
+            Err(MinimalPeerError::Transport(_))
+        ));
+        peer.remote_feature_bits()
+    });
+
+    let mut peer = MinimalPeer::connect(&remote, None, &[9]).unwrap();
+    assert_eq!(peer.remote_id(), server_node.node_id());
+    assert_eq!(peer.remote_features(), &[0x20, 0x02]);
+    assert_eq!(peer.remote_feature_bits(), vec![1, 13]);
+
+    // Pings are answered and pongs are consumed by the peers transparently
+    peer.ping().unwrap();
+    let mut msg = MSG_TYPE_CUSTOM.to_be_bytes().to_vec();
+    msg.extend(b"custom message");
+    peer.send_message(&msg).unwrap();
+    assert_eq!(peer.recv_message().unwrap(), msg);
+    peer.shutdown().unwrap();
+
+    assert_eq!(server.join().unwrap(), vec![9]);
+}
+
+#[test]
+fn idle_keepalive() {
+    let secp = Secp256k1::new();
+    let server_node = LocalNode::new(&secp);
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let remote = format!(
+        "{}@{}",
+        server_node.node_id(),
+        listener.local_addr().unwrap()
+    );
+
+    let server = std::thread::spawn(move || {
+        let mut peer =
+            MinimalPeer::accept(&listener, server_node, &[]).unwrap();
+        peer.set_ping_interval(Duration::from_millis(50)).unwrap();
+        // The client stays idle for many ping intervals, which must not
+        // break the connection
+        let msg = peer.recv_message().unwrap();
+        peer.send_message(&msg).unwrap();
+    });
+
+    let mut peer = MinimalPeer::connect(&remote, None, &[]).unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    let mut msg = MSG_TYPE_CUSTOM.to_be_bytes().to_vec();
+    msg.extend(b"after idle");
+    peer.send_message(&msg).unwrap();
+    // Keepalive pings sent by the server meanwhile are answered and skipped
+    assert_eq!(peer.recv_message().unwrap(), msg);
+    server.join().unwrap();
+}
+
+#[test]
+fn invalid_address() {
+    assert!(matches!(
+        MinimalPeer::connect("127.0.0.1:9735", None, &[]),
+        Err(MinimalPeerError::InvalidAddress(_))
+    ));
+}
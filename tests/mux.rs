@@ -0,0 +1,217 @@
+use std::net::{Ipv4Addr, TcpListener};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use inet2_addr::{LocalNode, NodeAddr};
+use internet2::session::{
+    BrontideSession, Multiplexer, MuxError, MUX_MAGIC, MUX_MAX_PENDING_CHANNELS,
+};
+use internet2::transport::Error;
+use internet2::SendRecvMessage;
+use secp256k1::Secp256k1;
+
+fn session_pair() -> (BrontideSession, BrontideSession) {
+    let secp = Secp256k1::new();
+    let server = LocalNode::new(&secp);
+    let client = LocalNode::new(&secp);
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let remote = NodeAddr::from_str(&format!(
+        "{}@{}",
+        server.node_id(),
+        listener.local_addr().unwrap()
+    ))
+    .unwrap();
+    let accepted = std::thread::spawn(move || {
+        BrontideSession::accept(server.private_key(), &listener).unwrap()
+    });
+    let connected =
+        BrontideSession::connect(client.private_key(), remote).unwrap();
+    (connected, accepted.join().unwrap())
+}
+
+fn mux_pair(window: u32) -> (Multiplexer, Multiplexer) {
+    let (a, b) = session_pair();
+    let remote =
+        std::thread::spawn(move || Multiplexer::negotiate(b, window).unwrap());
+    let local = Multiplexer::negotiate(a, window).unwrap();
+    (local, remote.join().unwrap())
+}
+
+#[test]
+fn interleaved_channels() {
+    let (client, server) = mux_pair(16);
+    let ids = [1u16, 2, 3];
+    let mut client_channels = ids.map(|id| client.open_channel(id).unwrap());
+    let server_channels = ids.map(|id| server.open_channel(id).unwrap());
+    assert!(matches!(
+        client.open_channel(2),
+        Err(MuxError::ChannelInUse(2))
+    ));
+
+    let count = 200u8;
+    let sender = std::thread::spawn(move || {
+        for no in 0..count {
+            for channel in &mut client_channels {
+                let msg = [channel.id() as u8, no];
+                channel.send_raw_message(&msg).unwrap();
+            }
+        }
+        // Dropping the handles closes the channels
+    });
+
+    // Each channel is read by its own thread at its own pace; messages of
+    // different channels never mix and keep their order
+    let readers = server_channels.map(|mut channel| {
+        std::thread::spawn(move || {
+            for no in 0..count {
+                if channel.id() == 3 {
+                    std::thread::sleep(Duration::from_micros(500));
+                }
+                let msg = channel.recv_raw_message().unwrap();
+                assert_eq!(msg, vec![channel.id() as u8, no]);
+            }
+            channel.recv_raw_message().unwrap_err()
+        })
+    });
+    sender.join().unwrap();
+    for (id, reader) in ids.into_iter().zip(readers) {
+        assert_eq!(reader.join().unwrap(), Error::ChannelClosed(id));
+    }
+    assert_eq!(client.error(), None);
+}
+
+#[test]
+fn credit_backpressure() {
+    let (client, server) = mux_pair(4);
+    let mut busy = client.open_channel(1).unwrap();
+    let mut idle = client.open_channel(2).unwrap();
+    let mut busy_rx = server.open_channel(1).unwrap();
+    let mut idle_rx = server.open_channel(2).unwrap();
+
+    for no in 0..4u8 {
+        busy.send_raw_message(&[no]).unwrap();
+    }
+    let (done_tx, done_rx) = mpsc::channel();
+    let blocked = std::thread::spawn(move || {
+        busy.send_raw_message(&[4]).unwrap();
+        done_tx.send(()).unwrap();
+        busy
+    });
+    // The window of the busy channel is exhausted...
+    let timeout = Duration::from_millis(300);
+    assert_eq!(
+        done_rx.recv_timeout(timeout),
+        Err(RecvTimeoutError::Timeout)
+    );
+    // ...while the other channels are not affected
+    idle.send_raw_message(b"idle").unwrap();
+    assert_eq!(idle_rx.recv_raw_message().unwrap(), b"idle");
+
+    // Credits are returned once half of the window is consumed
+    assert_eq!(busy_rx.recv_raw_message().unwrap(), [0]);
+    assert_eq!(
+        done_rx.recv_timeout(timeout),
+        Err(RecvTimeoutError::Timeout)
+    );
+    assert_eq!(busy_rx.recv_raw_message().unwrap(), [1]);
+    done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    let busy = blocked.join().unwrap();
+    for no in 2..5u8 {
+        assert_eq!(busy_rx.recv_raw_message().unwrap(), [no]);
+    }
+
+    busy.close().unwrap();
+    assert_eq!(
+        busy_rx.recv_raw_message().unwrap_err(),
+        Error::ChannelClosed(1)
+    );
+    assert_eq!(
+        busy_rx.send_raw_message(b"late").unwrap_err(),
+        Error::ChannelClosed(1)
+    );
+    // The id can be reused once the channel is closed on both sides
+    drop(busy_rx);
+    assert!(server.open_channel(1).is_ok());
+}
+
+/// Waits for the multiplexer to be terminated by an error
+fn wait_error(mux: &Multiplexer) -> Error {
+    for _ in 0..500 {
+        if let Some(err) = mux.error() {
+            return err;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("multiplexer must fail")
+}
+
+#[test]
+fn drop_closes_connection() {
+    let (client, server) = mux_pair(4);
+    let client_channel = client.open_channel(1).unwrap();
+    let server_channel = server.open_channel(1).unwrap();
+
+    // Dropping the multiplexer closes the connection regardless of the
+    // channel handles and waits for the receiving thread to complete
+    let (done_tx, done_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        drop(client);
+        done_tx.send(()).unwrap();
+    });
+    done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    drop(client_channel);
+
+    // The remote receiving thread ends as well
+    wait_error(&server);
+    drop(server_channel);
+    let (done_tx, done_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        drop(server);
+        done_tx.send(()).unwrap();
+    });
+    done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn pending_channels_limited() {
+    let (a, mut b) = session_pair();
+    let local = std::thread::spawn(move || Multiplexer::negotiate(a, 4));
+    let mut hello = MUX_MAGIC.to_vec();
+    hello.extend(4u32.to_be_bytes());
+    b.send_raw_message(&hello).unwrap();
+    b.recv_raw_message().unwrap();
+    let mux = local.join().unwrap().unwrap();
+
+    // Messages for the channels not opened yet are queued...
+    for id in 0..MUX_MAX_PENDING_CHANNELS as u16 {
+        let [hi, lo] = id.to_be_bytes();
+        b.send_raw_message(&[hi, lo, 0x00, 0xFF]).unwrap();
+    }
+    let mut channel = mux.open_channel(0).unwrap();
+    assert_eq!(channel.recv_raw_message().unwrap(), [0xFF]);
+    assert_eq!(mux.error(), None);
+
+    // ...but only for a limited number of channels
+    let [hi, lo] = (MUX_MAX_PENDING_CHANNELS as u16).to_be_bytes();
+    b.send_raw_message(&[hi, lo, 0x00, 0xFF]).unwrap();
+    b.send_raw_message(&[0xFF, 0xFF, 0x00, 0xFF]).unwrap();
+    assert_eq!(
+        wait_error(&mux),
+        Error::FrameBroken("too many channels are not opened")
+    );
+}
+
+#[test]
+fn unsupported() {
+    let (a, mut b) = session_pair();
+    let peer = std::thread::spawn(move || {
+        b.send_raw_message(b"plain message").unwrap();
+        b
+    });
+    assert!(matches!(
+        Multiplexer::negotiate(a, 4),
+        Err(MuxError::Unsupported)
+    ));
+    peer.join().unwrap();
+}
@@ -0,0 +1,46 @@
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use inet2_addr::{LocalNode, NodeAddr};
+use internet2::session::{BrontideSession, BrontozaurSession, HandshakeError};
+use internet2::transport::Error;
+use secp256k1::Secp256k1;
+
+#[test]
+fn connect_to_self() {
+    let secp = Secp256k1::new();
+    let node = LocalNode::new(&secp);
+    // The listener never accepts: the connection must be rejected before any
+    // socket is opened
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let remote = NodeAddr::new(node.node_id(), listener.local_addr().unwrap());
+
+    assert!(matches!(
+        BrontideSession::connect(node.private_key(), remote),
+        Err(Error::Handshake(HandshakeError::SelfConnection))
+    ));
+}
+
+#[test]
+fn listener_to_self() {
+    let secp = Secp256k1::new();
+    let node = LocalNode::new(&secp);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let remote = NodeAddr::new(node.node_id(), listener.local_addr().unwrap());
+
+    let key = node.private_key();
+    let rx = std::thread::spawn(move || {
+        BrontozaurSession::accept(key, &listener).map(|_| ())
+    });
+    let stream =
+        TcpStream::connect(SocketAddr::try_from(remote.addr).unwrap()).unwrap();
+    let tx =
+        BrontozaurSession::connect_with(stream, node.private_key(), remote)
+            .map(|_| ());
+
+    assert_eq!(tx, Err(Error::Handshake(HandshakeError::SelfConnection)));
+    assert_eq!(
+        rx.join().unwrap(),
+        Err(Error::Handshake(HandshakeError::SelfConnection))
+    );
+}
@@ -1,10 +1,20 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+
+use amplify::Wrapper;
 use inet2_addr::ServiceAddr;
+use internet2::presentation::{self, EncodingType};
 use internet2::session::LocalSession;
-use internet2::{SendRecvMessage, ZmqSocketType};
+use internet2::{
+    SendRecvMessage, TypeId, TypedEnum, UnknownTypeError, Unmarshall,
+    UnmarshallFn, Unmarshaller, ZmqSocketType,
+};
 
 #[test]
 fn main() {
-    let node_addr1: ServiceAddr = "inproc://zmq-test-1".parse().unwrap();
+    let node_addr1 = ServiceAddr::inproc_unique("zmq-test");
     let node_addr2 = node_addr1.clone();
     let ctx = zmq::Context::new();
 
@@ -59,3 +69,162 @@ fn main() {
 
     tx.join().unwrap();
 }
+
+#[test]
+fn unique_inproc_endpoints() {
+    let ctx = zmq::Context::new();
+    let threads = (0..100)
+        .map(|no| {
+            let ctx = ctx.clone();
+            std::thread::spawn(move || {
+                let addr = ServiceAddr::inproc_unique("zmq-stress");
+                let endpoint = addr.zmq_connect_string();
+                let pull = ctx.socket(zmq::PULL).unwrap();
+                pull.bind(&endpoint).unwrap();
+                let push = ctx.socket(zmq::PUSH).unwrap();
+                push.connect(&endpoint).unwrap();
+
+                let mut rx =
+                    LocalSession::with_zmq_socket(ZmqSocketType::Pull, pull);
+                let mut tx =
+                    LocalSession::with_zmq_socket(ZmqSocketType::Push, push);
+                tx.send_raw_message(&[no]).unwrap();
+                assert_eq!(rx.recv_raw_message().unwrap(), vec![no]);
+                addr
+            })
+        })
+        .collect::<Vec<_>>();
+    let addrs = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(addrs.len(), 100);
+}
+
+const STATUS: u16 = 0x0101;
+const LOG: u16 = 0x0103;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Msg {
+    Status(u8),
+    Log(u8),
+}
+
+impl TypedEnum for Msg {
+    fn try_from_type(
+        type_id: TypeId,
+        data: &dyn Any,
+    ) -> Result<Self, UnknownTypeError> {
+        let byte = data.downcast_ref::<u8>().copied().ok_or(UnknownTypeError);
+        match type_id.into_inner() {
+            STATUS => byte.map(Msg::Status),
+            LOG => byte.map(Msg::Log),
+            _ => Err(UnknownTypeError),
+        }
+    }
+
+    fn get_type(&self) -> TypeId {
+        match self {
+            Msg::Status(_) => TypeId::from_inner(STATUS),
+            Msg::Log(_) => TypeId::from_inner(LOG),
+        }
+    }
+
+    fn get_payload(&self) -> Vec<u8> {
+        match self {
+            Msg::Status(byte) | Msg::Log(byte) => vec![*byte],
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = self.get_type().into_inner().to_be_bytes().to_vec();
+        data.extend(self.get_payload());
+        data
+    }
+}
+
+fn parse_status(
+    reader: &mut dyn Read,
+) -> Result<Arc<dyn Any>, presentation::Error> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(Arc::new(byte[0]))
+}
+
+fn parse_log(_: &mut dyn Read) -> Result<Arc<dyn Any>, presentation::Error> {
+    panic!("filtered-out message has reached the subscriber unmarshaller")
+}
+
+#[test]
+fn topic_filtering() {
+    let addr = ServiceAddr::inproc_unique("zmq-topics");
+    let ctx = zmq::Context::new();
+    let mut publisher =
+        LocalSession::connect(ZmqSocketType::Pub, &addr, None, None, &ctx)
+            .unwrap();
+    let mut subscriber =
+        LocalSession::connect(ZmqSocketType::Sub, &addr, None, None, &ctx)
+            .unwrap();
+    publisher.enable_topic_envelope().unwrap();
+    subscriber.enable_topic_envelope().unwrap();
+    subscriber
+        .subscribe_type(TypeId::from_inner(STATUS))
+        .unwrap();
+    subscriber.as_socket().set_rcvtimeo(10).unwrap();
+    assert!(publisher.subscribe(b"").is_err());
+
+    let mut known_types = BTreeMap::new();
+    known_types.insert(STATUS, parse_status as UnmarshallFn<_>);
+    known_types.insert(LOG, parse_log as UnmarshallFn<_>);
+    let unmarshaller =
+        Unmarshaller::<Msg>::new(known_types, EncodingType::Lightning);
+
+    // Subscription reaches the publisher asynchronously, so we keep
+    // publishing until the subscriber starts receiving messages
+    let mut received = vec![];
+    for no in 0..=u8::MAX {
+        publisher.send_message(&Msg::Log(no)).unwrap();
+        publisher.send_message(&Msg::Status(no)).unwrap();
+        while let Ok(msg) = subscriber.recv_raw_message() {
+            received
+                .push((*unmarshaller.unmarshall(&msg[..]).unwrap()).clone());
+        }
+        if received.len() >= 3 {
+            break;
+        }
+    }
+    assert!(received.len() >= 3);
+    let first = match received[0] {
+        Msg::Status(no) => no,
+        Msg::Log(_) => unreachable!(),
+    };
+    let expected = (first..)
+        .take(received.len())
+        .map(Msg::Status)
+        .collect::<Vec<_>>();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn topic_envelope_mode() {
+    let ctx = zmq::Context::new();
+    let addr = ServiceAddr::inproc_unique("zmq-envelope");
+    let mut publisher =
+        LocalSession::connect(ZmqSocketType::Pub, &addr, None, None, &ctx)
+            .unwrap();
+    // Without envelope messages are sent as a single frame
+    assert!(publisher.send_message(&Msg::Status(0)).is_ok());
+
+    // In envelope mode messages must be published with a topic
+    publisher.enable_topic_envelope().unwrap();
+    assert!(publisher.send_raw_message(b"no topic").is_err());
+    assert!(publisher.send_message(&Msg::Status(0)).is_ok());
+
+    // Envelope mode is available only for PUB/SUB sessions
+    let addr = ServiceAddr::inproc_unique("zmq-envelope");
+    let mut rep =
+        LocalSession::connect(ZmqSocketType::Rep, &addr, None, None, &ctx)
+            .unwrap();
+    assert!(rep.enable_topic_envelope().is_err());
+    assert!(rep.subscribe_type(TypeId::from_inner(STATUS)).is_err());
+}